@@ -1,4 +1,50 @@
+use std::{path::Path, process::Command};
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_prost_build::compile_protos("api.proto")?;
+    emit_build_metadata();
     Ok(())
 }
+
+/// Exposes git commit, build date and compiler version to the crate as
+/// `NEST_SYNC_*` environment variables.
+fn emit_build_metadata() {
+    let git_commit = command_output("git", &["rev-parse", "--short", "HEAD"])
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let build_date = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+        .and_then(|epoch| chrono::DateTime::from_timestamp(epoch, 0))
+        .unwrap_or_else(chrono::Utc::now)
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string();
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rust_version = std::env::var("RUSTC_VERSION")
+        .ok()
+        .or_else(|| command_output(&rustc, &["--version"]))
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=NEST_SYNC_GIT_COMMIT={git_commit}");
+    println!("cargo:rustc-env=NEST_SYNC_BUILD_DATE={build_date}");
+    println!("cargo:rustc-env=NEST_SYNC_RUST_VERSION={rust_version}");
+
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-env-changed=RUSTC_VERSION");
+    if Path::new(".git/HEAD").exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        if let Some(head_ref) = command_output("git", &["symbolic-ref", "-q", "HEAD"]) {
+            println!("cargo:rerun-if-changed=.git/{head_ref}");
+        }
+    }
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    Some(stdout.trim().to_string()).filter(|s| !s.is_empty())
+}
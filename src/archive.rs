@@ -0,0 +1,813 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Condvar, Mutex},
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use chrono_tz::America::Vancouver;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{layout::PathTemplate, models::CameraEvent};
+
+const CLIP_EXTENSION: &str = "mp4";
+const SIDECAR_EXTENSION: &str = "json";
+const KEEP_EXTENSION: &str = "keep";
+const NFO_EXTENSION: &str = "nfo";
+/// Optional list of pinned clips at the archive root, one path per line
+/// relative to the root.
+const KEEP_LIST_FILE: &str = "keep.txt";
+/// Threads reading the archive in a walk. Even on one core a few overlap
+/// their waits on the disk; past the upper bound, a NAS or USB disk tends to
+/// slow down rather than keep up.
+const WALK_THREADS_MIN: usize = 4;
+const WALK_THREADS_MAX: usize = 16;
+/// Reporting label for clips without a sidecar.
+pub const UNATTRIBUTED: &str = "(unattributed)";
+
+/// Metadata written next to each downloaded clip so the archive can be
+/// attributed back to devices without re-querying the API. This is the
+/// sidecar format; fields added later are optional so older sidecars still
+/// load.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VideoMetadata {
+    pub event_id: String,
+    pub device_id: String,
+    #[serde(default)]
+    pub device_name: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub duration_secs: i64,
+    /// The event's real length when it was longer than the downloaded window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_duration_secs: Option<i64>,
+    /// The manifest's identifier for the event's Period, when it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip_id: Option<String>,
+    /// Event classifications joined with `+`, when the API reports any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub downloaded_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_size_bytes: Option<u64>,
+    /// Hex-encoded SHA-256 of the clip as downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Modification time the clip should have had, recorded when setting it
+    /// failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub intended_mtime: Option<DateTime<Utc>>,
+    /// Tags from the `--tag-rules` the event matched.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The window the clip was downloaded for, when `--clip-padding-before`
+    /// or `--clip-padding-after` widened it beyond the event's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_start: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_end: Option<DateTime<Utc>>,
+    /// Ids of the back-to-back events `--merge-gap-seconds` merged into this
+    /// clip.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged_event_ids: Vec<String>,
+    /// The `--quality` variant the clip was requested with; absent for the
+    /// default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<u32>,
+    /// The identical clip this one was deleted in favour of by
+    /// `--dedup-identical skip`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<PathBuf>,
+}
+
+impl From<CameraEvent> for VideoMetadata {
+    fn from(event: CameraEvent) -> Self {
+        Self {
+            event_id: event.event_id(),
+            end_time: event.end_time(),
+            duration_secs: event.duration.num_seconds(),
+            original_duration_secs: event.original_duration.map(|d| d.num_seconds()),
+            device_id: event.device_id,
+            device_name: String::new(),
+            start_time: event.start_time,
+            clip_id: event.clip_id,
+            event_type: (!event.event_types.is_empty()).then(|| {
+                event
+                    .event_types
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("+")
+            }),
+            downloaded_at: None,
+            file_path: None,
+            file_size_bytes: None,
+            sha256: None,
+            intended_mtime: None,
+            tags: Vec::new(),
+            download_start: None,
+            download_end: None,
+            merged_event_ids: event.merged_event_ids,
+            variant: None,
+            duplicate_of: None,
+        }
+    }
+}
+
+impl VideoMetadata {
+    pub fn with_device_name(mut self, device_name: &str) -> Self {
+        self.device_name = device_name.to_string();
+        self
+    }
+
+    pub fn with_download_info(
+        mut self,
+        path: &Path,
+        size: u64,
+        sha256: &str,
+        downloaded_at: DateTime<Utc>,
+    ) -> Self {
+        self.file_path = Some(path.to_path_buf());
+        self.file_size_bytes = Some(size);
+        self.sha256 = Some(sha256.to_string());
+        self.downloaded_at = Some(downloaded_at);
+        self
+    }
+
+    pub fn with_intended_mtime(mut self, intended_mtime: Option<DateTime<Utc>>) -> Self {
+        self.intended_mtime = intended_mtime;
+        self
+    }
+
+    pub fn with_tags(mut self, tags: &[String]) -> Self {
+        self.tags = tags.to_vec();
+        self
+    }
+
+    /// Records the download window when it differs from the event's.
+    pub fn with_download_window(mut self, start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        if (start, end) != (self.start_time, self.end_time) {
+            self.download_start = Some(start);
+            self.download_end = Some(end);
+        }
+        self
+    }
+
+    pub fn with_variant(mut self, variant: Option<u32>) -> Self {
+        self.variant = variant;
+        self
+    }
+}
+
+/// A clip found on disk while walking the archive.
+#[derive(Debug, Clone)]
+pub struct ArchiveClip {
+    pub path: PathBuf,
+    pub modified: SystemTime,
+    pub size: u64,
+    /// The clip's sidecar, which attributes it to a device.
+    pub sidecar: Option<VideoMetadata>,
+    /// Pinned via a `.keep` marker or `keep.txt`; never pruned.
+    pub pinned: bool,
+}
+
+impl ArchiveClip {
+    /// Owning device, when a sidecar attributes the clip.
+    pub fn device_id(&self) -> Option<&str> {
+        self.sidecar.as_ref().map(|s| s.device_id.as_str())
+    }
+}
+
+/// Attributes clips to cameras: by sidecar, or for a clip without a readable
+/// sidecar, by the camera the archive's path template puts in its path. A
+/// camera the path only names is matched to its ID through the other clips'
+/// sidecars.
+pub struct DeviceAttribution<'a> {
+    root: &'a Path,
+    template: &'a PathTemplate,
+    /// `(device ID, device name)` of every camera a sidecar names.
+    known: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> DeviceAttribution<'a> {
+    pub fn new(root: &'a Path, template: &'a PathTemplate, clips: &'a [ArchiveClip]) -> Self {
+        let mut known: Vec<(&str, &str)> = clips
+            .iter()
+            .filter_map(|clip| clip.sidecar.as_ref())
+            .map(|s| (s.device_id.as_str(), s.device_name.as_str()))
+            .collect();
+        known.sort_unstable();
+        known.dedup_by_key(|(device_id, _)| *device_id);
+        Self {
+            root,
+            template,
+            known,
+        }
+    }
+
+    /// The clip's device ID, or the camera its path names when no sidecar
+    /// gives that camera's ID. `None` when neither says.
+    pub fn device<'c>(&'c self, clip: &'c ArchiveClip) -> Option<Cow<'c, str>> {
+        if let Some(device_id) = clip.device_id() {
+            return Some(Cow::Borrowed(device_id));
+        }
+        let relative = clip.path.strip_prefix(self.root).ok()?;
+        let parsed = relative
+            .to_str()
+            .and_then(|r| self.template.parse(r))
+            .filter(|p| p.device_id.is_some() || p.device_name.is_some())
+            .or_else(|| self.template.dir_device(relative.parent()?))?;
+        match self
+            .known
+            .iter()
+            .find(|(device_id, device_name)| parsed.is_device(device_id, device_name))
+        {
+            Some((device_id, _)) => Some(Cow::Borrowed(device_id)),
+            None => parsed.device_id.or(parsed.device_name).map(Cow::Owned),
+        }
+    }
+}
+
+/// Pinned clips seen, and their total size.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PinnedTotals {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+impl PinnedTotals {
+    pub fn add(&mut self, clip: &ArchiveClip) {
+        if clip.pinned {
+            self.count += 1;
+            self.bytes += clip.size;
+        }
+    }
+}
+
+pub fn sidecar_path(clip: &Path) -> PathBuf {
+    clip.with_extension(SIDECAR_EXTENSION)
+}
+
+pub fn write_sidecar(clip: &Path, sidecar: &VideoMetadata) -> Result<()> {
+    let json = serde_json::to_vec_pretty(sidecar).context("Failed to serialize sidecar")?;
+    fs::write(sidecar_path(clip), json).context("Failed to write sidecar")
+}
+
+pub fn read_sidecar(clip: &Path) -> Option<VideoMetadata> {
+    let data = fs::read(sidecar_path(clip)).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+pub fn keep_marker_path(clip: &Path) -> PathBuf {
+    clip.with_extension(KEEP_EXTENSION)
+}
+
+pub fn nfo_path(clip: &Path) -> PathBuf {
+    clip.with_extension(NFO_EXTENSION)
+}
+
+fn load_keep_list(root: &Path) -> HashSet<PathBuf> {
+    let Ok(contents) = fs::read_to_string(root.join(KEEP_LIST_FILE)) else {
+        return HashSet::new();
+    };
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| root.join(line))
+        .collect()
+}
+
+/// Files that may accompany a clip.
+fn companion_candidates(clip: &Path) -> [PathBuf; 3] {
+    [sidecar_path(clip), keep_marker_path(clip), nfo_path(clip)]
+}
+
+/// Files that belong to a clip and must be moved or deleted with it.
+pub fn companion_paths(clip: &Path) -> Vec<PathBuf> {
+    companion_candidates(clip)
+        .into_iter()
+        .filter(|p| p.exists())
+        .collect()
+}
+
+/// Where each companion of `from` goes when the clip moves to `to`.
+pub fn companion_moves(from: &Path, to: &Path) -> Vec<(PathBuf, PathBuf)> {
+    companion_candidates(from)
+        .into_iter()
+        .zip(companion_candidates(to))
+        .collect()
+}
+
+/// Points `keep.txt` entries for moved clips at their new paths.
+pub fn rewrite_keep_list(root: &Path, moves: &HashMap<PathBuf, PathBuf>) -> Result<()> {
+    let path = root.join(KEEP_LIST_FILE);
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Ok(());
+    };
+
+    let mut changed = false;
+    let lines: Vec<String> = contents
+        .lines()
+        .map(|line| {
+            let entry = line.trim();
+            if entry.is_empty() || entry.starts_with('#') {
+                return line.to_string();
+            }
+            match moves
+                .get(&root.join(entry))
+                .and_then(|to| to.strip_prefix(root).ok())
+            {
+                Some(relative) => {
+                    changed = true;
+                    relative.display().to_string()
+                }
+                None => line.to_string(),
+            }
+        })
+        .collect();
+
+    if changed {
+        let tmp_path = path.with_extension("txt.tmp");
+        fs::write(&tmp_path, lines.join("\n") + "\n").context("Failed to write keep list")?;
+        fs::rename(&tmp_path, &path).context("Failed to replace keep list")?;
+    }
+    Ok(())
+}
+
+/// Deletes a clip together with its companion files.
+pub fn remove_clip(clip: &Path) -> std::io::Result<()> {
+    fs::remove_file(clip)?;
+    for companion in companion_paths(clip) {
+        if let Err(e) = fs::remove_file(&companion) {
+            error!(path = %companion.display(), error = %e, "Failed to delete companion file");
+        }
+    }
+    Ok(())
+}
+
+/// Start of each camera's newest clip, keyed by device ID, read from the
+/// names of clips laid out by `template` without opening any sidecar.
+/// `devices` are `(device ID, device name)` pairs. Date directories are read
+/// newest first, and older ones skipped once every camera has a newer clip.
+pub fn newest_clip_starts(
+    root: &Path,
+    template: &PathTemplate,
+    devices: &[(&str, &str)],
+) -> HashMap<String, DateTime<Utc>> {
+    let mut newest = HashMap::new();
+    collect_newest_starts(root, root, template, devices, &mut newest);
+    newest
+}
+
+fn collect_newest_starts(
+    root: &Path,
+    dir: &Path,
+    template: &PathTemplate,
+    devices: &[(&str, &str)],
+    newest: &mut HashMap<String, DateTime<Utc>>,
+) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut dated = Vec::new();
+    let mut undated = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(relative) = path.strip_prefix(root).ok() else {
+            continue;
+        };
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            // A camera's own directory only has its clips to wait for
+            let dir_devices: Vec<(&str, &str)> = match template.dir_device(relative) {
+                Some(owner) => devices
+                    .iter()
+                    .filter(|(device_id, device_name)| owner.is_device(device_id, device_name))
+                    .copied()
+                    .collect(),
+                None => devices.to_vec(),
+            };
+            if dir_devices.is_empty() {
+                continue;
+            }
+            match template.dir_start_date(relative) {
+                Some(date) => dated.push((date, path, dir_devices)),
+                None => undated.push((path, dir_devices)),
+            }
+            continue;
+        }
+        if path.extension().is_none_or(|e| e != CLIP_EXTENSION) {
+            continue;
+        }
+        let Some(parsed) = relative.to_str().and_then(|r| template.parse(r)) else {
+            continue;
+        };
+        // Taking the earlier reading when the day is ambiguous only means
+        // rechecking a few events that are already on disk
+        let Some(start_time) = parsed.earliest_start else {
+            continue;
+        };
+        if let Some((device_id, _)) = devices
+            .iter()
+            .find(|(device_id, device_name)| parsed.is_device(device_id, device_name))
+        {
+            let entry = newest.entry(device_id.to_string()).or_insert(start_time);
+            *entry = (*entry).max(start_time);
+        }
+    }
+
+    for (dir, dir_devices) in undated {
+        collect_newest_starts(root, &dir, template, &dir_devices, newest);
+    }
+    // A date directory's clips all start before the next newer one's first
+    // day, so once every camera has a clip after that day the rest are older
+    dated.sort_by(|a, b| b.0.cmp(&a.0));
+    let mut newer_start = None;
+    for (start_date, dir, dir_devices) in dated {
+        if let Some(newer_start) = newer_start
+            && dir_devices.iter().all(|(device_id, _)| {
+                newest
+                    .get(*device_id)
+                    .is_some_and(|t| t.with_timezone(&Vancouver).date_naive() > newer_start)
+            })
+        {
+            break;
+        }
+        collect_newest_starts(root, &dir, template, &dir_devices, newest);
+        newer_start = Some(start_date);
+    }
+}
+
+/// What a walk of the archive found.
+#[derive(Debug, Default)]
+pub struct ArchiveWalk {
+    /// Every clip, in path order.
+    pub clips: Vec<ArchiveClip>,
+    /// Directories left unwalked, in path order.
+    pub skipped: Vec<PathBuf>,
+    /// Sidecars `--dedup-identical skip` left for the duplicates it deleted,
+    /// by the deleted clip's path, in path order.
+    pub duplicate_sidecars: Vec<(PathBuf, VideoMetadata)>,
+}
+
+/// Walks the archive and returns every clip with its metadata, in path
+/// order. Entries whose metadata can't be read are logged and skipped.
+pub fn walk_clips(root: &Path) -> Vec<ArchiveClip> {
+    walk_clips_skipping(root, root, |_| false).clips
+}
+
+/// Like [`walk_clips`], but only under `dir` inside the archive at `root` and
+/// leaving out the directories `skip` returns true for, which are returned
+/// unwalked. Also finds the sidecars of deleted duplicates.
+///
+/// Directories are read and clips stat'ed on several threads, since a large
+/// archive on a slow disk spends most of the walk waiting on each file.
+pub fn walk_clips_skipping(
+    root: &Path,
+    dir: &Path,
+    skip: impl Fn(&Path) -> bool + Sync,
+) -> ArchiveWalk {
+    let threads = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .clamp(WALK_THREADS_MIN, WALK_THREADS_MAX);
+    walk_on_threads(root, dir, skip, threads)
+}
+
+fn walk_on_threads(
+    root: &Path,
+    dir: &Path,
+    skip: impl Fn(&Path) -> bool + Sync,
+    threads: usize,
+) -> ArchiveWalk {
+    let keep_list = load_keep_list(root);
+    let queue = WalkQueue::new(dir.to_path_buf());
+
+    let mut walk = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| scope.spawn(|| walk_worker(&queue, &keep_list, &skip)))
+            .collect();
+        let mut walk = ArchiveWalk::default();
+        for worker in workers {
+            let worker_walk = worker.join().expect("archive walk thread panicked");
+            walk.clips.extend(worker_walk.clips);
+            walk.skipped.extend(worker_walk.skipped);
+            walk.duplicate_sidecars
+                .extend(worker_walk.duplicate_sidecars);
+        }
+        walk
+    });
+
+    // Byte order is enough for a stable result, and far cheaper than
+    // comparing paths component by component
+    walk.clips
+        .sort_unstable_by(|a, b| a.path.as_os_str().cmp(b.path.as_os_str()));
+    walk.skipped.sort_unstable();
+    walk.duplicate_sidecars
+        .sort_unstable_by(|a, b| a.0.as_os_str().cmp(b.0.as_os_str()));
+    walk
+}
+
+/// Directories waiting to be read, shared by the walk's threads.
+struct WalkQueue {
+    state: Mutex<WalkQueueState>,
+    changed: Condvar,
+}
+
+struct WalkQueueState {
+    dirs: Vec<PathBuf>,
+    /// Directories taken from the queue and still being read; more may be
+    /// queued until they're done.
+    in_progress: usize,
+}
+
+impl WalkQueue {
+    fn new(dir: PathBuf) -> Self {
+        Self {
+            state: Mutex::new(WalkQueueState {
+                dirs: vec![dir],
+                in_progress: 0,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// The next directory to read, or `None` once every directory has been.
+    fn take(&self) -> Option<PathBuf> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(dir) = state.dirs.pop() {
+                state.in_progress += 1;
+                return Some(dir);
+            }
+            if state.in_progress == 0 {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Queues a read directory's subdirectories and marks it done.
+    fn finish(&self, subdirs: Vec<PathBuf>) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.dirs.extend(subdirs);
+        state.in_progress -= 1;
+        self.changed.notify_all();
+    }
+}
+
+fn walk_worker(
+    queue: &WalkQueue,
+    keep_list: &HashSet<PathBuf>,
+    skip: &(impl Fn(&Path) -> bool + Sync),
+) -> ArchiveWalk {
+    let mut walk = ArchiveWalk::default();
+
+    while let Some(dir) = queue.take() {
+        let mut subdirs = Vec::new();
+        let mut clip_paths = HashSet::new();
+        let mut sidecar_paths = Vec::new();
+        // Unreadable directories are passed over, like unreadable entries
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                if skip(&path) {
+                    walk.skipped.push(path);
+                } else {
+                    subdirs.push(path);
+                }
+            } else {
+                match path.extension().and_then(|s| s.to_str()) {
+                    Some(CLIP_EXTENSION) => {
+                        clip_paths.insert(path);
+                    }
+                    Some(SIDECAR_EXTENSION) => sidecar_paths.push(path),
+                    _ => {}
+                }
+            }
+        }
+        queue.finish(subdirs);
+
+        // Only a sidecar without its clip is opened here, so a walk of an
+        // archive without deleted duplicates reads no extra files
+        for sidecar_path in sidecar_paths {
+            let clip_path = sidecar_path.with_extension(CLIP_EXTENSION);
+            if !clip_paths.contains(&clip_path)
+                && let Some(sidecar) = read_sidecar(&clip_path)
+                && sidecar.duplicate_of.is_some()
+            {
+                walk.duplicate_sidecars.push((clip_path, sidecar));
+            }
+        }
+        walk.clips.extend(
+            clip_paths
+                .into_iter()
+                .filter_map(|path| read_clip(path, keep_list)),
+        );
+    }
+
+    walk
+}
+
+fn read_clip(path: PathBuf, keep_list: &HashSet<PathBuf>) -> Option<ArchiveClip> {
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            error!(path = %path.display(), error = %e, "Failed to get metadata");
+            return None;
+        }
+    };
+    let modified = metadata.modified().ok()?;
+
+    let sidecar = read_sidecar(&path);
+    // Age decisions should use the event time even where it couldn't be set
+    // on the file
+    let modified = sidecar
+        .as_ref()
+        .and_then(|s| s.intended_mtime)
+        .map_or(modified, SystemTime::from);
+
+    Some(ArchiveClip {
+        pinned: keep_list.contains(&path) || keep_marker_path(&path).exists(),
+        modified,
+        size: metadata.len(),
+        sidecar,
+        path,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use chrono::TimeZone;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::layout::DayAssignment;
+
+    /// A year of clips laid out like the default template: a folder per day
+    /// and a dozen clips in each.
+    fn year_of_clips() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for month in 1..=12 {
+            for day in 1..=28 {
+                let day_dir = dir.path().join(format!("2025/{month:02}/{day:02}"));
+                fs::create_dir_all(&day_dir).unwrap();
+                for hour in 0..12 {
+                    let clip =
+                        day_dir.join(format!("2025-{month:02}-{day:02}T{hour:02}-00-00.mp4"));
+                    fs::write(&clip, b"video").unwrap();
+                }
+            }
+        }
+        fs::write(
+            dir.path().join(KEEP_LIST_FILE),
+            "2025/06/15/2025-06-15T03-00-00.mp4\n",
+        )
+        .unwrap();
+        dir
+    }
+
+    fn summary(clips: &[ArchiveClip]) -> Vec<(&Path, u64, bool)> {
+        clips
+            .iter()
+            .map(|c| (c.path.as_path(), c.size, c.pinned))
+            .collect()
+    }
+
+    /// The parallel walk has to find exactly what a walk on one thread does.
+    #[test]
+    fn parallel_walk_matches_sequential() {
+        let archive = year_of_clips();
+        let root = archive.path();
+        let skip_december = |path: &Path| path.ends_with("2025/12");
+
+        let ArchiveWalk {
+            clips: sequential,
+            skipped: sequential_skipped,
+            ..
+        } = walk_on_threads(root, root, skip_december, 1);
+        let ArchiveWalk {
+            clips: parallel,
+            skipped: parallel_skipped,
+            ..
+        } = walk_clips_skipping(root, root, skip_december);
+
+        assert_eq!(parallel.len(), 11 * 28 * 12);
+        assert_eq!(summary(&parallel), summary(&sequential));
+        assert_eq!(parallel_skipped, vec![root.join("2025/12")]);
+        assert_eq!(parallel_skipped, sequential_skipped);
+        assert_eq!(parallel.iter().filter(|c| c.pinned).count(), 1);
+        assert!(parallel.is_sorted_by(|a, b| a.path.as_os_str() <= b.path.as_os_str()));
+    }
+
+    /// Times the walk on one thread against the default number, best of a
+    /// few runs each. A benchmark rather than a check, as timings depend on
+    /// the machine and its disk cache:
+    /// `cargo test parallel_walk_speed -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "benchmark; run explicitly"]
+    fn parallel_walk_speed() {
+        let archive = year_of_clips();
+        let root = archive.path();
+        let best_of = |walk: &dyn Fn() -> ArchiveWalk| {
+            (0..5)
+                .map(|_| {
+                    let started = Instant::now();
+                    walk();
+                    started.elapsed()
+                })
+                .min()
+                .expect("timed at least once")
+        };
+
+        let sequential = best_of(&|| walk_on_threads(root, root, |_| false, 1));
+        let parallel = best_of(&|| walk_clips_skipping(root, root, |_| false));
+
+        eprintln!(
+            "walked {} clips: 1 thread {sequential:?}, parallel {parallel:?} ({:.1}x)",
+            12 * 28 * 12,
+            sequential.as_secs_f64() / parallel.as_secs_f64()
+        );
+    }
+
+    const CAMERA_TEMPLATE: &str = "{device_name}/{year}/{month}/{day}/{hour}-{minute}-{second}.mp4";
+    const CAMERAS: [(&str, &str); 2] = [("device-1", "Porch"), ("device-2", "Garage")];
+
+    /// Writes a clip where `template` puts the event, given in local time.
+    fn templated_clip(
+        root: &Path,
+        template: &PathTemplate,
+        (device_id, device_name): (&str, &str),
+        start: &str,
+        duration_secs: i64,
+    ) -> DateTime<Utc> {
+        let start = Vancouver
+            .from_local_datetime(&start.parse().unwrap())
+            .unwrap()
+            .with_timezone(&Utc);
+        let end = start + chrono::Duration::seconds(duration_secs);
+        let path = root.join(template.render(start, end, device_id, device_name, &[]));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, b"video").unwrap();
+        start
+    }
+
+    #[test]
+    fn newest_clip_starts_of_empty_archive() {
+        let archive = TempDir::new().unwrap();
+        let template: PathTemplate = CAMERA_TEMPLATE.parse().unwrap();
+
+        assert!(newest_clip_starts(archive.path(), &template, &CAMERAS).is_empty());
+    }
+
+    #[test]
+    fn newest_clip_starts_per_camera() {
+        let archive = TempDir::new().unwrap();
+        let root = archive.path();
+        let template: PathTemplate = CAMERA_TEMPLATE.parse().unwrap();
+        templated_clip(root, &template, CAMERAS[0], "2025-05-31T08:00:00", 10);
+        let porch = templated_clip(root, &template, CAMERAS[0], "2025-06-01T09:30:00", 10);
+        templated_clip(root, &template, CAMERAS[0], "2025-06-01T07:00:00", 10);
+        let garage = templated_clip(root, &template, CAMERAS[1], "2025-05-20T12:00:00", 10);
+        // Not one of the cameras asked about
+        templated_clip(
+            root,
+            &template,
+            ("device-3", "Attic"),
+            "2025-06-02T12:00:00",
+            10,
+        );
+
+        let newest = newest_clip_starts(root, &template, &CAMERAS);
+
+        assert_eq!(newest.len(), 2);
+        assert_eq!(newest["device-1"], porch);
+        assert_eq!(newest["device-2"], garage);
+    }
+
+    #[test]
+    fn newest_clip_start_of_event_filed_under_its_end_day() {
+        let archive = TempDir::new().unwrap();
+        let root = archive.path();
+        let template: PathTemplate = CAMERA_TEMPLATE.parse().unwrap();
+        let template = template.with_day_assignment(DayAssignment::End);
+        templated_clip(root, &template, CAMERAS[0], "2025-06-01T22:00:00", 10);
+        // Filed under 2025/06/02 with only the time in its name
+        let spanning = templated_clip(root, &template, CAMERAS[0], "2025-06-01T23:59:50", 30);
+        assert!(root.join("Porch/2025/06/02/23-59-50.mp4").exists());
+
+        let newest = newest_clip_starts(root, &template, &CAMERAS[..1]);
+
+        assert_eq!(newest["device-1"], spanning);
+    }
+}
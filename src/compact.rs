@@ -0,0 +1,37 @@
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use tracing::info;
+
+use crate::state::StateStore;
+
+#[derive(Debug, clap::Args)]
+pub struct CompactStateArgs {
+    /// Report what would be removed without changing the state file
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// Removes state entries for events that started before `cutoff` and devices
+/// not checked since then. A running daemon keeps its own copy of the state
+/// and would write the entries back, so stop it first or use
+/// `--auto-compact-state` instead.
+pub fn run(output_path: &Path, args: &CompactStateArgs, cutoff: DateTime<Utc>) -> Result<()> {
+    let mut store = StateStore::load(output_path);
+    let compaction = store.compact(cutoff);
+    if !args.dry_run && compaction.total() > 0 {
+        store.save()?;
+    }
+    info!(
+        cutoff = %cutoff,
+        failures = compaction.failures,
+        ignored = compaction.ignored,
+        merged = compaction.merged,
+        imported = compaction.imported,
+        devices = compaction.devices,
+        dry_run = args.dry_run,
+        "Compacted state file"
+    );
+    Ok(())
+}
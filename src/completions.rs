@@ -0,0 +1,56 @@
+use std::io::Write;
+
+use anyhow::Result;
+use clap::CommandFactory;
+use clap_complete::Shell;
+
+use crate::Args;
+
+#[derive(Debug, clap::Args)]
+pub struct CompletionsArgs {
+    /// Shell to generate the completion script for
+    shell: Shell,
+}
+
+/// Writes the completion script for `shell` to stdout, generated from the
+/// same definition that parses the command line.
+pub fn run(completions_args: &CompletionsArgs) -> Result<()> {
+    generate(completions_args.shell, &mut std::io::stdout());
+    Ok(())
+}
+
+fn generate(shell: Shell, out: &mut dyn Write) {
+    let mut command = Args::command();
+    clap_complete::generate(shell, &mut command, env!("CARGO_PKG_NAME"), out);
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+
+    use super::*;
+
+    #[test]
+    fn every_shell_completes_the_subcommands() {
+        for &shell in Shell::value_variants() {
+            let mut script = Vec::new();
+            generate(shell, &mut script);
+            let script = String::from_utf8(script).unwrap();
+
+            assert!(!script.is_empty(), "{shell} script is empty");
+            for subcommand in [
+                "prune",
+                "stats",
+                "verify",
+                "export",
+                "completions",
+                "service",
+            ] {
+                assert!(
+                    script.contains(subcommand),
+                    "{shell} script lacks {subcommand}"
+                );
+            }
+        }
+    }
+}
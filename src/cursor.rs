@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{models::CameraEvent, state::StateStore};
+
+/// Margin kept before an event the cursor stops at. Query times are sent with
+/// whole seconds, so this keeps the event inside the next window.
+const CURSOR_MARGIN_SECS: i64 = 1;
+
+/// One check's outcome for the device cursors, which record how far each
+/// device's events have been downloaded. A device's cursor advances to the end
+/// of the checked window, unless some of its events weren't downloaded: then
+/// it stops just before the earliest of them, so they are queried again while
+/// later events already on disk are skipped as usual. A device whose query
+/// was cut short advances no further than where the query ended.
+#[derive(Debug)]
+pub struct CycleCursor {
+    end_time: DateTime<Utc>,
+    earliest_pending: HashMap<String, DateTime<Utc>>,
+    held: HashMap<String, DateTime<Utc>>,
+}
+
+impl CycleCursor {
+    pub fn new(end_time: DateTime<Utc>) -> Self {
+        Self {
+            end_time,
+            earliest_pending: HashMap::new(),
+            held: HashMap::new(),
+        }
+    }
+
+    /// Keeps a device's cursor from passing `until`, the end of a query that
+    /// stopped short of the checked window.
+    pub fn hold(&mut self, device_id: &str, until: DateTime<Utc>) {
+        self.held.insert(device_id.to_string(), until);
+    }
+
+    /// Records an event that wasn't downloaded this cycle but should be
+    /// retried: it failed, was backing off or didn't fit in the cycle.
+    pub fn mark_pending(&mut self, event: &CameraEvent) {
+        self.earliest_pending
+            .entry(event.device_id.clone())
+            .and_modify(|start| *start = (*start).min(event.start_time))
+            .or_insert(event.start_time);
+    }
+
+    /// Advances the cursors of `device_ids`, the devices this cycle checked.
+    pub fn finish<'a>(self, store: &mut StateStore, device_ids: impl IntoIterator<Item = &'a str>) {
+        for device_id in device_ids {
+            let position = match self.earliest_pending.get(device_id) {
+                Some(&start) => (start - Duration::seconds(CURSOR_MARGIN_SECS)).min(self.end_time),
+                None => self.end_time,
+            };
+            let position = self
+                .held
+                .get(device_id)
+                .map_or(position, |&until| position.min(until));
+            store.set_checked_until(device_id, position);
+        }
+    }
+}
+
+/// Where a device's query should start. Normally the full lookback period,
+/// or the device's cursor with `since_last_run`. A cursor older than the
+/// lookback period means checks were missed, e.g. while the host was down, so
+/// the query reaches back to it, but no further than `max_catch_up`.
+pub fn query_start(
+    end_time: DateTime<Utc>,
+    checked_until: Option<DateTime<Utc>>,
+    lookback: Duration,
+    max_catch_up: Duration,
+    since_last_run: bool,
+) -> DateTime<Utc> {
+    let reach_back = |period: Duration| {
+        end_time
+            .checked_sub_signed(period)
+            .unwrap_or(DateTime::<Utc>::MIN_UTC)
+    };
+    let full_lookback = reach_back(lookback);
+    match checked_until {
+        Some(position) if position < full_lookback => {
+            position.max(reach_back(max_catch_up.max(lookback)))
+        }
+        Some(position) if since_last_run => position.min(end_time),
+        _ => full_lookback,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn at(minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 6, 1, 12, minute, 0).unwrap()
+    }
+
+    fn event(device_id: &str, minute: u32) -> CameraEvent {
+        CameraEvent::new(device_id.to_string(), at(minute), Duration::seconds(10))
+    }
+
+    fn finish(cursor: CycleCursor, device_ids: &[&str]) -> (TempDir, StateStore) {
+        let dir = TempDir::new().unwrap();
+        let mut store = StateStore::load(dir.path());
+        cursor.finish(&mut store, device_ids.iter().copied());
+        (dir, store)
+    }
+
+    #[test]
+    fn advances_to_window_end_when_everything_downloaded() {
+        let (_dir, store) = finish(CycleCursor::new(at(30)), &["porch"]);
+        assert_eq!(store.checked_until("porch"), Some(at(30)));
+    }
+
+    #[test]
+    fn stops_before_earliest_failed_event() {
+        // Events at 5, 10 and 20 past; the one at 10 failed, the others
+        // downloaded
+        let mut cursor = CycleCursor::new(at(30));
+        cursor.mark_pending(&event("porch", 10));
+
+        let (_dir, store) = finish(cursor, &["porch"]);
+
+        let position = store.checked_until("porch").unwrap();
+        assert_eq!(position, at(10) - Duration::seconds(CURSOR_MARGIN_SECS));
+        assert!(position > at(5), "the earlier download isn't queried again");
+        assert!(position < at(10), "the failed event is queried again");
+    }
+
+    #[test]
+    fn earliest_of_several_failures_wins() {
+        let mut cursor = CycleCursor::new(at(30));
+        cursor.mark_pending(&event("porch", 20));
+        cursor.mark_pending(&event("porch", 10));
+        cursor.mark_pending(&event("porch", 15));
+
+        let (_dir, store) = finish(cursor, &["porch"]);
+
+        assert_eq!(
+            store.checked_until("porch"),
+            Some(at(10) - Duration::seconds(CURSOR_MARGIN_SECS))
+        );
+    }
+
+    #[test]
+    fn failure_holds_back_only_its_device() {
+        let mut cursor = CycleCursor::new(at(30));
+        cursor.mark_pending(&event("porch", 10));
+
+        let (_dir, store) = finish(cursor, &["porch", "garage"]);
+
+        assert_eq!(store.checked_until("garage"), Some(at(30)));
+        assert!(store.checked_until("porch") < Some(at(30)));
+    }
+
+    #[test]
+    fn held_device_stops_at_query_end() {
+        let mut cursor = CycleCursor::new(at(30));
+        cursor.hold("porch", at(20));
+        cursor.mark_pending(&event("garage", 25));
+
+        let (_dir, store) = finish(cursor, &["porch", "garage"]);
+
+        assert_eq!(store.checked_until("porch"), Some(at(20)));
+        assert_eq!(
+            store.checked_until("garage"),
+            Some(at(25) - Duration::seconds(CURSOR_MARGIN_SECS))
+        );
+    }
+
+    #[test]
+    fn huge_catch_up_horizon_reaches_back_to_the_cursor() {
+        let lookback = Duration::hours(12);
+        let long_ago = at(0) - Duration::days(365 * 100);
+
+        let start = query_start(at(30), Some(long_ago), lookback, Duration::MAX, false);
+
+        assert_eq!(start, long_ago);
+    }
+
+    #[test]
+    fn unchecked_devices_keep_their_position() {
+        let mut cursor = CycleCursor::new(at(30));
+        cursor.mark_pending(&event("porch", 10));
+
+        let (_dir, store) = finish(cursor, &["garage"]);
+
+        assert_eq!(store.checked_until("porch"), None);
+    }
+}
@@ -0,0 +1,310 @@
+use std::{collections::HashMap, fmt::Write as _, fs, io, path::Path};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use chrono_tz::America::Vancouver;
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+use tracing::{debug, error, info, warn};
+
+use crate::archive::{self, ArchiveClip};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DedupMode {
+    /// Replace the newer copy with a hard link to the original
+    #[cfg(unix)]
+    Hardlink,
+    /// Delete the newer copy, leaving its sidecar pointing at the original
+    Skip,
+    /// Only log the duplicates
+    Report,
+}
+
+/// What a dedup pass found and did.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DedupOutcome {
+    pub duplicate_count: usize,
+    /// Bytes freed, or that would be freed in a dry run; zero when only
+    /// reporting.
+    pub reclaimed_bytes: u64,
+}
+
+/// Finds clips of the same camera and local day whose sidecars record the
+/// same SHA-256, and handles every copy after the earliest per `mode`. A
+/// match is only acted on when both files still have the same size and
+/// hash on disk; pinned clips are never touched.
+pub fn dedup_identical(clips: &[&ArchiveClip], mode: DedupMode, dry_run: bool) -> DedupOutcome {
+    let mut groups: HashMap<(&str, NaiveDate, &str), Vec<&ArchiveClip>> = HashMap::new();
+    for clip in clips {
+        let Some(sidecar) = &clip.sidecar else {
+            continue;
+        };
+        let Some(sha256) = &sidecar.sha256 else {
+            continue;
+        };
+        let day = sidecar.start_time.with_timezone(&Vancouver).date_naive();
+        groups
+            .entry((sidecar.device_id.as_str(), day, sha256.as_str()))
+            .or_default()
+            .push(clip);
+    }
+
+    let mut outcome = DedupOutcome::default();
+    for mut group in groups.into_values().filter(|group| group.len() > 1) {
+        group.sort_by_key(|clip| clip.sidecar.as_ref().map(|s| s.start_time));
+        let (original, duplicates) = group.split_first().expect("group has several clips");
+        // Hashed on the first comparison that gets that far, then reused
+        let mut original_hash = None;
+        for duplicate in duplicates {
+            if duplicate.pinned {
+                debug!(path = %duplicate.path.display(), "Leaving pinned duplicate clip");
+                continue;
+            }
+            match confirm_identical(&original.path, &mut original_hash, &duplicate.path) {
+                Ok(Some(size)) => {
+                    if handle_duplicate(&original.path, duplicate, size, mode, dry_run) {
+                        outcome.duplicate_count += 1;
+                        if mode != DedupMode::Report {
+                            outcome.reclaimed_bytes += size;
+                        }
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => warn!(
+                    path = %duplicate.path.display(),
+                    error = %format!("{e:#}"),
+                    "Failed to compare duplicate clip"
+                ),
+            }
+        }
+    }
+    outcome
+}
+
+/// The size of the two clips when they are byte-identical separate files,
+/// checked on disk rather than trusting the sidecars, which only record each
+/// clip's hash as downloaded. `original_hash` caches the original's hash
+/// across the duplicates of one group.
+fn confirm_identical(
+    original: &Path,
+    original_hash: &mut Option<String>,
+    duplicate: &Path,
+) -> Result<Option<u64>> {
+    let original_metadata = fs::metadata(original)?;
+    let duplicate_metadata = fs::metadata(duplicate)?;
+    if original_metadata.len() != duplicate_metadata.len() {
+        return Ok(None);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // Already hard-linked by an earlier pass
+        if original_metadata.dev() == duplicate_metadata.dev()
+            && original_metadata.ino() == duplicate_metadata.ino()
+        {
+            return Ok(None);
+        }
+    }
+    let original_hash = match original_hash {
+        Some(hash) => hash,
+        None => original_hash.insert(hash_file(original)?),
+    };
+    if *original_hash != hash_file(duplicate)? {
+        return Ok(None);
+    }
+    Ok(Some(original_metadata.len()))
+}
+
+fn hash_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        }))
+}
+
+/// Applies `mode` to one confirmed duplicate. Returns whether it was
+/// handled.
+fn handle_duplicate(
+    original: &Path,
+    duplicate: &ArchiveClip,
+    size: u64,
+    mode: DedupMode,
+    dry_run: bool,
+) -> bool {
+    let path = &duplicate.path;
+    if mode == DedupMode::Report || dry_run {
+        info!(
+            path = %path.display(),
+            original = %original.display(),
+            size_bytes = size,
+            ?mode,
+            dry_run,
+            "Clip is identical to an earlier clip"
+        );
+        return true;
+    }
+
+    let result = match mode {
+        #[cfg(unix)]
+        DedupMode::Hardlink => replace_with_link(original, path),
+        DedupMode::Skip => remove_duplicate(original, duplicate),
+        DedupMode::Report => unreachable!("reporting is handled above"),
+    };
+    match result {
+        Ok(()) => {
+            info!(
+                path = %path.display(),
+                original = %original.display(),
+                size_bytes = size,
+                ?mode,
+                "Deduplicated identical clip"
+            );
+            true
+        }
+        Err(e) => {
+            error!(
+                path = %path.display(),
+                error = %format!("{e:#}"),
+                "Failed to deduplicate clip"
+            );
+            false
+        }
+    }
+}
+
+/// Swaps `duplicate` for a hard link to `original` in one rename, so the
+/// clip is never missing.
+#[cfg(unix)]
+fn replace_with_link(original: &Path, duplicate: &Path) -> Result<()> {
+    let tmp_path = duplicate.with_extension("dedup-tmp");
+    let _ = fs::remove_file(&tmp_path);
+    fs::hard_link(original, &tmp_path).context("Failed to create hard link")?;
+    fs::rename(&tmp_path, duplicate).context("Failed to replace clip with hard link")?;
+    Ok(())
+}
+
+/// Deletes `duplicate`, first recording the original in its sidecar so the
+/// event reads as archived and isn't downloaded again.
+fn remove_duplicate(original: &Path, duplicate: &ArchiveClip) -> Result<()> {
+    let mut sidecar = duplicate
+        .sidecar
+        .clone()
+        .context("Duplicate clip has no sidecar")?;
+    sidecar.duplicate_of = Some(original.to_path_buf());
+    archive::write_sidecar(&duplicate.path, &sidecar)?;
+    fs::remove_file(&duplicate.path).context("Failed to delete duplicate clip")?;
+    let _ = fs::remove_file(archive::nfo_path(&duplicate.path));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{archive::VideoMetadata, models::CameraEvent};
+
+    /// Writes a clip whose sidecar records `recorded_sha256`, whatever the
+    /// content.
+    fn clip(dir: &TempDir, name: &str, minute: u32, content: &[u8], recorded_sha256: &str) {
+        let path = dir.path().join(name);
+        fs::write(&path, content).unwrap();
+        let start = Utc.with_ymd_and_hms(2025, 6, 1, 18, minute, 0).unwrap();
+        let event = CameraEvent::new("porch".to_string(), start, chrono::Duration::seconds(10));
+        let sidecar = VideoMetadata::from(event).with_download_info(
+            &path,
+            content.len() as u64,
+            recorded_sha256,
+            start,
+        );
+        archive::write_sidecar(&path, &sidecar).unwrap();
+    }
+
+    #[test]
+    fn removes_only_copies_identical_on_disk() {
+        let dir = TempDir::new().unwrap();
+        clip(&dir, "a.mp4", 0, b"video", "same");
+        clip(&dir, "b.mp4", 1, b"video", "same");
+        clip(&dir, "c.mp4", 2, b"VIDEO", "same");
+        clip(&dir, "d.mp4", 3, b"video", "same");
+        let clips = archive::walk_clips(dir.path());
+        let clips: Vec<&ArchiveClip> = clips.iter().collect();
+
+        let outcome = dedup_identical(&clips, DedupMode::Skip, false);
+
+        assert_eq!(outcome.duplicate_count, 2);
+        assert_eq!(outcome.reclaimed_bytes, 10);
+        assert!(dir.path().join("a.mp4").exists());
+        assert!(dir.path().join("c.mp4").exists(), "changed since download");
+        for removed in ["b.mp4", "d.mp4"] {
+            let path = dir.path().join(removed);
+            assert!(!path.exists());
+            let sidecar = archive::read_sidecar(&path).unwrap();
+            assert_eq!(sidecar.duplicate_of, Some(dir.path().join("a.mp4")));
+        }
+    }
+
+    fn dedup(dir: &TempDir, mode: DedupMode) -> DedupOutcome {
+        let clips = archive::walk_clips(dir.path());
+        let clips: Vec<&ArchiveClip> = clips.iter().collect();
+        dedup_identical(&clips, mode, false)
+    }
+
+    #[test]
+    fn report_changes_nothing() {
+        let dir = TempDir::new().unwrap();
+        clip(&dir, "a.mp4", 0, b"video", "same");
+        clip(&dir, "b.mp4", 1, b"video", "same");
+
+        let outcome = dedup(&dir, DedupMode::Report);
+
+        assert_eq!(outcome.duplicate_count, 1);
+        assert_eq!(outcome.reclaimed_bytes, 0);
+        assert_eq!(fs::read(dir.path().join("b.mp4")).unwrap(), b"video");
+        let sidecar = archive::read_sidecar(&dir.path().join("b.mp4")).unwrap();
+        assert_eq!(sidecar.duplicate_of, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hardlink_shares_the_original_once() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = TempDir::new().unwrap();
+        clip(&dir, "a.mp4", 0, b"video", "same");
+        clip(&dir, "b.mp4", 1, b"video", "same");
+
+        let outcome = dedup(&dir, DedupMode::Hardlink);
+
+        assert_eq!(outcome.duplicate_count, 1);
+        assert_eq!(outcome.reclaimed_bytes, 5);
+        let original = fs::metadata(dir.path().join("a.mp4")).unwrap();
+        let duplicate = fs::metadata(dir.path().join("b.mp4")).unwrap();
+        assert_eq!(duplicate.ino(), original.ino());
+        assert_eq!(original.nlink(), 2);
+        // Already linked, so a second pass finds nothing to do
+        assert_eq!(dedup(&dir, DedupMode::Hardlink).duplicate_count, 0);
+    }
+
+    #[test]
+    fn leaves_pinned_duplicate_alone() {
+        let dir = TempDir::new().unwrap();
+        clip(&dir, "a.mp4", 0, b"video", "same");
+        clip(&dir, "b.mp4", 1, b"video", "same");
+        fs::write(archive::keep_marker_path(&dir.path().join("b.mp4")), "").unwrap();
+
+        let outcome = dedup(&dir, DedupMode::Skip);
+
+        assert_eq!(outcome.duplicate_count, 0);
+        assert!(dir.path().join("b.mp4").exists());
+        let sidecar = archive::read_sidecar(&dir.path().join("b.mp4")).unwrap();
+        assert_eq!(sidecar.duplicate_of, None);
+    }
+}
@@ -0,0 +1,144 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use anyhow::{Result, bail};
+use chrono::Utc;
+use tracing::info;
+
+use crate::{Args, EVENT_HISTORY_DURATION_MINUTES, connect_google, nest_api::NestDevice};
+
+#[derive(Debug, clap::Args)]
+pub struct DiscoverTypesArgs {
+    /// Type codes to probe, as a list and/or ranges, e.g. 1-16 or 1,2,4,8
+    #[arg(long, default_value = "1-16")]
+    codes: TypeCodes,
+
+    /// Hours of history to probe, ending now (at most 12)
+    #[arg(long, default_value = "12")]
+    hours: i64,
+}
+
+/// Type codes to probe, in ascending order without repeats.
+#[derive(Debug, Clone)]
+struct TypeCodes(Vec<u32>);
+
+impl FromStr for TypeCodes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut codes = Vec::new();
+        for part in s.split(',').map(str::trim) {
+            let parse = |code: &str| {
+                code.trim()
+                    .parse::<u32>()
+                    .map_err(|_| format!("invalid type code '{code}' in '{s}'"))
+            };
+            match part.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (parse(first)?, parse(last)?);
+                    if first > last || last - first >= 256 {
+                        return Err(format!("invalid range '{part}' in '{s}'"));
+                    }
+                    codes.extend(first..=last);
+                }
+                None => codes.push(parse(part)?),
+            }
+        }
+        codes.sort_unstable();
+        codes.dedup();
+        Ok(Self(codes))
+    }
+}
+
+/// What one type code returned for one device.
+enum Probe {
+    Events {
+        count: usize,
+        /// Classifications seen, with how many events carried each.
+        event_types: BTreeMap<String, usize>,
+    },
+    Failed(String),
+}
+
+/// Queries each camera's recent history once per type code and prints how
+/// many events each code returned and how they were classified. Codes that
+/// return events are candidates for `--event-query-types`; the
+/// classifications are what `--event-types` filters on.
+pub async fn run(args: &Args, discover_args: &DiscoverTypesArgs) -> Result<()> {
+    if !(1..=EVENT_HISTORY_DURATION_MINUTES / 60).contains(&discover_args.hours) {
+        bail!(
+            "--hours must be between 1 and {}",
+            EVENT_HISTORY_DURATION_MINUTES / 60
+        );
+    }
+
+    let connection = connect_google(args)?;
+    let devices = connection.get_nest_camera_devices().await?;
+    let end_time = Utc::now();
+    let codes = &discover_args.codes.0;
+    info!(
+        device_count = devices.len(),
+        code_count = codes.len(),
+        hours = discover_args.hours,
+        "Probing event type codes"
+    );
+
+    let mut codes_with_events: BTreeMap<u32, usize> = BTreeMap::new();
+    for device in &devices {
+        let nest_device = NestDevice::new(device.device_id.clone(), device.device_name.clone());
+        println!("{} ({})", device.device_name, device.device_id);
+        for &code in codes {
+            let probe = match nest_device
+                .query_events(
+                    &connection,
+                    end_time,
+                    discover_args.hours * 60,
+                    &code.to_string(),
+                )
+                .await
+            {
+                Ok(events) => {
+                    let mut event_types = BTreeMap::new();
+                    for event_type in events.iter().flat_map(|e| &e.event_types) {
+                        *event_types.entry(event_type.to_string()).or_default() += 1;
+                    }
+                    Probe::Events {
+                        count: events.len(),
+                        event_types,
+                    }
+                }
+                Err(e) => Probe::Failed(format!("{e:#}")),
+            };
+
+            match probe {
+                Probe::Events { count: 0, .. } => println!("  {code}\t0 events"),
+                Probe::Events { count, event_types } => {
+                    *codes_with_events.entry(code).or_default() += 1;
+                    let event_types = event_types
+                        .iter()
+                        .map(|(event_type, n)| format!("{event_type} {n}"))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    println!("  {code}\t{count} events\t{event_types}");
+                }
+                Probe::Failed(error) => println!("  {code}\terror: {error}"),
+            }
+        }
+    }
+
+    let current = connection.event_query_types();
+    if codes_with_events.is_empty() {
+        println!(
+            "No code returned events in the last {} hours; try more codes or a busier period",
+            discover_args.hours
+        );
+    } else {
+        let summary = codes_with_events
+            .iter()
+            .map(|(code, device_count)| format!("{code} ({device_count} of {})", devices.len()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!("Codes returning events: {summary}");
+    }
+    println!("Currently querying with --event-query-types {current}");
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::America::Vancouver;
+use serde::Serialize;
+
+use crate::{
+    archive::{self, ArchiveClip, UNATTRIBUTED},
+    state::StateStore,
+};
+
+/// Days of download history averaged for the growth rate.
+const GROWTH_WINDOW_DAYS: i64 = 7;
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct UsageStats {
+    pub files: u64,
+    pub bytes: u64,
+    pub average_bytes: u64,
+}
+
+impl UsageStats {
+    fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+        self.average_bytes = self.bytes / self.files;
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageReport {
+    pub total: UsageStats,
+    pub by_device: BTreeMap<String, UsageStats>,
+    pub by_month: BTreeMap<String, UsageStats>,
+    /// Only clips whose sidecar records an event type.
+    pub by_event_type: BTreeMap<String, UsageStats>,
+    /// Average bytes downloaded per day over the recent download history.
+    pub daily_growth_bytes: Option<u64>,
+    pub available_bytes: Option<u64>,
+    pub projected_full_date: Option<NaiveDate>,
+}
+
+/// Builds the usage report from the same clip walk and sidecar attribution
+/// the prune pass uses.
+pub fn usage_report(output_path: &Path, store: &StateStore) -> UsageReport {
+    let clips = archive::walk_clips(output_path);
+    let mut report = UsageReport {
+        total: UsageStats::default(),
+        by_device: BTreeMap::new(),
+        by_month: BTreeMap::new(),
+        by_event_type: BTreeMap::new(),
+        daily_growth_bytes: None,
+        available_bytes: fs4::available_space(output_path).ok(),
+        projected_full_date: None,
+    };
+
+    for clip in &clips {
+        report.total.add(clip.size);
+
+        let device = clip
+            .sidecar
+            .as_ref()
+            .map_or(UNATTRIBUTED, |s| s.device_name.as_str());
+        report
+            .by_device
+            .entry(device.to_string())
+            .or_default()
+            .add(clip.size);
+        report
+            .by_month
+            .entry(clip_month(clip))
+            .or_default()
+            .add(clip.size);
+
+        if let Some(event_type) = clip.sidecar.as_ref().and_then(|s| s.event_type.as_ref()) {
+            report
+                .by_event_type
+                .entry(event_type.clone())
+                .or_default()
+                .add(clip.size);
+        }
+    }
+
+    let today = Utc::now().date_naive();
+    report.daily_growth_bytes = daily_growth(store.daily_bytes(), today);
+    if let (Some(growth), Some(available)) = (report.daily_growth_bytes, report.available_bytes)
+        && growth > 0
+    {
+        let days = i64::try_from(available / growth).unwrap_or(i64::MAX);
+        report.projected_full_date =
+            Duration::try_days(days).and_then(|d| today.checked_add_signed(d));
+    }
+
+    report
+}
+
+/// Month of the clip's event in local time, falling back to its modification
+/// time (set to the event time on download) when there is no sidecar.
+fn clip_month(clip: &ArchiveClip) -> String {
+    let start_time = clip
+        .sidecar
+        .as_ref()
+        .map_or_else(|| DateTime::<Utc>::from(clip.modified), |s| s.start_time);
+    start_time
+        .with_timezone(&Vancouver)
+        .format("%Y-%m")
+        .to_string()
+}
+
+/// Average bytes per day across the growth window, counting only days since
+/// the history began.
+fn daily_growth(history: &BTreeMap<NaiveDate, u64>, today: NaiveDate) -> Option<u64> {
+    let first = *history.keys().next()?;
+    let window_start = today - Duration::days(GROWTH_WINDOW_DAYS - 1);
+    let start = first.max(window_start);
+    let days = (today - start).num_days() + 1;
+    if days <= 0 {
+        return None;
+    }
+
+    let bytes: u64 = history.range(start..=today).map(|(_, b)| b).sum();
+    Some(bytes / days as u64)
+}
+
+/// Prints the report as tables, or as JSON when `json` is set.
+pub fn run(output_path: &Path, json: bool) -> Result<()> {
+    let store = StateStore::load(output_path);
+    let report = usage_report(output_path, &store);
+
+    if json {
+        let out =
+            serde_json::to_string_pretty(&report).context("Failed to serialize usage report")?;
+        println!("{out}");
+        return Ok(());
+    }
+
+    print_table("Device", &report.by_device);
+    print_table("Month", &report.by_month);
+    if !report.by_event_type.is_empty() {
+        print_table("Event type", &report.by_event_type);
+    }
+
+    println!(
+        "Total: {} files, {} (average {})",
+        report.total.files,
+        format_bytes(report.total.bytes),
+        format_bytes(report.total.average_bytes)
+    );
+    match report.daily_growth_bytes {
+        Some(growth) => println!("Growth: {}/day", format_bytes(growth)),
+        None => println!("Growth: unknown (no download history yet)"),
+    }
+    if let Some(available) = report.available_bytes {
+        println!("Available: {}", format_bytes(available));
+    }
+    if let Some(date) = report.projected_full_date {
+        println!("Projected full: {date}");
+    }
+
+    Ok(())
+}
+
+fn print_table(heading: &str, rows: &BTreeMap<String, UsageStats>) {
+    let width = rows
+        .keys()
+        .map(|k| k.chars().count())
+        .chain([heading.len()])
+        .max()
+        .unwrap_or(0);
+
+    println!(
+        "{heading:<width$}  {:>8}  {:>10}  {:>10}",
+        "Files", "Size", "Average"
+    );
+    for (key, stats) in rows {
+        println!(
+            "{key:<width$}  {:>8}  {:>10}  {:>10}",
+            stats.files,
+            format_bytes(stats.bytes),
+            format_bytes(stats.average_bytes)
+        );
+    }
+    println!();
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
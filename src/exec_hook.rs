@@ -0,0 +1,48 @@
+use std::{path::Path, process::Stdio, time::Duration};
+
+use anyhow::{Context, Result, bail};
+use tokio::process::Command;
+use tracing::debug;
+
+use crate::models::CameraEvent;
+
+/// A hook that runs longer than this is killed and counts as failed.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Runs `command` through `sh -c` for a downloaded clip. The clip's path is
+/// passed as `$1` and, with the event's details, in `NEST_SYNC_*` environment
+/// variables. Fails when the command can't be started, times out or exits
+/// non-zero.
+pub async fn run(command: &str, clip: &Path, event: &CameraEvent, device_name: &str) -> Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .arg("nest-sync")
+        .arg(clip)
+        .env("NEST_SYNC_PATH", clip)
+        .env("NEST_SYNC_EVENT_ID", event.event_id())
+        .env("NEST_SYNC_DEVICE_ID", &event.device_id)
+        .env("NEST_SYNC_DEVICE_NAME", device_name)
+        .env("NEST_SYNC_START_TIME", event.start_time.to_rfc3339())
+        .env("NEST_SYNC_END_TIME", event.end_time().to_rfc3339())
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to start post-download command")?;
+
+    let status = tokio::time::timeout(HOOK_TIMEOUT, child.wait())
+        .await
+        .with_context(|| {
+            format!(
+                "Post-download command timed out after {}s",
+                HOOK_TIMEOUT.as_secs()
+            )
+        })?
+        .context("Failed to wait for post-download command")?;
+
+    debug!(path = %clip.display(), %status, "Post-download command finished");
+    if !status.success() {
+        bail!("Post-download command failed with {status}");
+    }
+    Ok(())
+}
@@ -0,0 +1,576 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, BufWriter, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use chrono::{
+    DateTime, Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc,
+};
+use chrono_tz::America::Vancouver;
+use serde::Serialize;
+use tracing::{info, warn};
+use zip::{
+    CompressionMethod, ZipWriter,
+    write::{SimpleFileOptions, StreamWriter},
+};
+
+use crate::{
+    archive::{self, ArchiveClip, UNATTRIBUTED},
+    layout::PathTemplate,
+    stats::csv_field,
+};
+
+/// Name of the summary entry at the end of an exported archive.
+const MANIFEST_ENTRY: &str = "manifest.json";
+
+#[derive(Debug, clap::Args)]
+pub struct ExportCsvArgs {
+    /// File to write, or - for stdout
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    out: PathBuf,
+}
+
+/// One archived clip as exported. Times are local; duration and end are
+/// unknown for clips without a sidecar.
+struct Row {
+    device: String,
+    device_id: Option<String>,
+    start_time: DateTime<Utc>,
+    duration_secs: Option<i64>,
+    original_duration_secs: Option<i64>,
+    size: u64,
+    path: String,
+}
+
+impl Row {
+    fn new(clip: &ArchiveClip, output_path: &Path, template: &PathTemplate) -> Self {
+        let relative = clip.path.strip_prefix(output_path).unwrap_or(&clip.path);
+        let path = relative.display().to_string();
+        match &clip.sidecar {
+            Some(sidecar) => Row {
+                device: if sidecar.device_name.is_empty() {
+                    sidecar.device_id.clone()
+                } else {
+                    sidecar.device_name.clone()
+                },
+                device_id: Some(sidecar.device_id.clone()),
+                start_time: sidecar.start_time,
+                duration_secs: Some(sidecar.duration_secs),
+                original_duration_secs: sidecar.original_duration_secs,
+                size: clip.size,
+                path,
+            },
+            None => {
+                // Fall back to what the path encodes
+                let parsed = relative.to_str().and_then(|p| template.parse(p));
+                let parsed = parsed.as_ref();
+                Row {
+                    device: parsed
+                        .and_then(|p| p.device_name.clone().or_else(|| p.device_id.clone()))
+                        .unwrap_or_else(|| UNATTRIBUTED.to_string()),
+                    device_id: parsed.and_then(|p| p.device_id.clone()),
+                    start_time: parsed
+                        .and_then(|p| p.start_time)
+                        .unwrap_or_else(|| clip.modified.into()),
+                    duration_secs: None,
+                    original_duration_secs: None,
+                    size: clip.size,
+                    path,
+                }
+            }
+        }
+    }
+}
+
+/// Describes every archived clip, oldest first.
+fn rows(output_path: &Path, template: &PathTemplate) -> Vec<(ArchiveClip, Row)> {
+    let mut rows: Vec<(ArchiveClip, Row)> = archive::walk_clips(output_path)
+        .into_iter()
+        .map(|clip| {
+            let row = Row::new(&clip, output_path, template);
+            (clip, row)
+        })
+        .collect();
+    rows.sort_by(|(_, a), (_, b)| {
+        a.start_time
+            .cmp(&b.start_time)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    rows
+}
+
+/// Writes one CSV row per archived clip, oldest first.
+pub fn run(output_path: &Path, args: &ExportCsvArgs, template: &PathTemplate) -> Result<()> {
+    let rows: Vec<Row> = rows(output_path, template)
+        .into_iter()
+        .map(|(_, row)| row)
+        .collect();
+
+    let csv = render(&rows);
+    if args.out.as_os_str() == "-" {
+        print!("{csv}");
+    } else {
+        fs::write(&args.out, csv)
+            .with_context(|| format!("Failed to write {}", args.out.display()))?;
+        info!(
+            clip_count = rows.len(),
+            path = %args.out.display(),
+            "Exported download history"
+        );
+    }
+
+    Ok(())
+}
+
+fn render(rows: &[Row]) -> String {
+    let mut out = String::from(
+        "device,date,start,end,duration_secs,original_duration_secs,size_bytes,path\n",
+    );
+    for row in rows {
+        let start = row.start_time.with_timezone(&Vancouver);
+        let end = row
+            .duration_secs
+            .map(|secs| {
+                (start + chrono::Duration::seconds(secs))
+                    .format("%H:%M:%S")
+                    .to_string()
+            })
+            .unwrap_or_default();
+        let _ = writeln!(
+            out,
+            "{},{},{},{},{},{},{},{}",
+            csv_field(&row.device),
+            start.format("%Y-%m-%d"),
+            start.format("%H:%M:%S"),
+            end,
+            row.duration_secs.map(|s| s.to_string()).unwrap_or_default(),
+            row.original_duration_secs
+                .map(|s| s.to_string())
+                .unwrap_or_default(),
+            row.size,
+            csv_field(&row.path)
+        );
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ArchiveFormat {
+    Tar,
+    /// Uncompressed (clips are already compressed), for Windows recipients
+    Zip,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ExportArgs {
+    /// Only clips that started at or after this local time, YYYY-MM-DD or YYYY-MM-DDTHH:MM
+    #[arg(long, value_parser = parse_from)]
+    from: Option<NaiveDateTime>,
+
+    /// Only clips that started before this local time; a date includes the whole day
+    #[arg(long, value_parser = parse_to)]
+    to: Option<NaiveDateTime>,
+
+    /// Only clips from this device, by name or ID (repeatable)
+    #[arg(long)]
+    device: Vec<String>,
+
+    /// Archive format written to stdout
+    #[arg(long, value_enum, default_value = "tar")]
+    format: ArchiveFormat,
+}
+
+/// Parses a local time, YYYY-MM-DDTHH:MM[:SS], or a date, YYYY-MM-DD, taken
+/// as the midnight `days_after` days later.
+fn parse_local_time(s: &str, days_after: u64) -> Result<NaiveDateTime, String> {
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(s, format) {
+            return Ok(time);
+        }
+    }
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| format!("expected YYYY-MM-DD or YYYY-MM-DDTHH:MM, got '{s}'"))?;
+    date.checked_add_days(Days::new(days_after))
+        .map(|date| date.and_time(NaiveTime::MIN))
+        .ok_or_else(|| format!("date out of range: '{s}'"))
+}
+
+fn parse_from(s: &str) -> Result<NaiveDateTime, String> {
+    parse_local_time(s, 0)
+}
+
+/// Like [`parse_from`], but a bare date ends at the following midnight.
+fn parse_to(s: &str) -> Result<NaiveDateTime, String> {
+    parse_local_time(s, 1)
+}
+
+/// A local time as UTC, taking the earlier instant when clocks fall back.
+fn local_to_utc(time: NaiveDateTime) -> DateTime<Utc> {
+    Vancouver
+        .from_local_datetime(&time)
+        .earliest()
+        .unwrap_or_else(|| Vancouver.from_utc_datetime(&time))
+        .with_timezone(&Utc)
+}
+
+/// Summary written as the archive's last entry, `manifest.json`.
+#[derive(Serialize)]
+struct Manifest {
+    exported_at: DateTime<Utc>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    devices: Vec<String>,
+    clip_count: usize,
+    total_bytes: u64,
+    clips: Vec<ManifestClip>,
+    /// Clips that matched but disappeared before they could be read, e.g.
+    /// pruned during the export.
+    skipped: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ManifestClip {
+    path: String,
+    device: String,
+    start_time: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_secs: Option<i64>,
+    size_bytes: u64,
+    /// Path of the clip's sidecar in the archive, when it has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sidecar: Option<String>,
+}
+
+/// A tar or zip stream being written.
+enum ArchiveWriter<W: Write> {
+    Tar(tar::Builder<W>),
+    Zip(Box<ZipWriter<StreamWriter<W>>>),
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    fn new(format: ArchiveFormat, out: W) -> Self {
+        match format {
+            ArchiveFormat::Tar => Self::Tar(tar::Builder::new(out)),
+            ArchiveFormat::Zip => Self::Zip(Box::new(ZipWriter::new_stream(out))),
+        }
+    }
+
+    fn append(
+        &mut self,
+        name: &str,
+        data: &mut impl Read,
+        size: u64,
+        modified: DateTime<Utc>,
+    ) -> Result<()> {
+        match self {
+            Self::Tar(builder) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(size);
+                header.set_mode(0o644);
+                header.set_mtime(modified.timestamp().max(0) as u64);
+                builder.append_data(&mut header, name, data)?;
+            }
+            Self::Zip(zip) => {
+                let local = modified.with_timezone(&Vancouver);
+                let mtime = zip::DateTime::from_date_and_time(
+                    local.year().try_into().unwrap_or(1980),
+                    local.month() as u8,
+                    local.day() as u8,
+                    local.hour() as u8,
+                    local.minute() as u8,
+                    local.second() as u8,
+                )
+                .unwrap_or_default();
+                let options = SimpleFileOptions::default()
+                    .compression_method(CompressionMethod::Stored)
+                    .last_modified_time(mtime)
+                    .large_file(size > u64::from(u32::MAX));
+                zip.start_file(name, options)?;
+                io::copy(data, zip.as_mut())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        let mut out = match self {
+            Self::Tar(builder) => builder.into_inner()?,
+            Self::Zip(zip) => zip.finish()?.into_inner(),
+        };
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// The path of `path` inside the archive, relative to the archive root with
+/// `/` separators.
+fn entry_name(output_path: &Path, path: &Path) -> String {
+    path.strip_prefix(output_path)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Streams the matching clips and their sidecars to stdout as a tar or zip
+/// archive, oldest first, ending with a `manifest.json` of what it holds.
+/// Clips are read straight from the archive, so nothing is staged on disk; a
+/// clip pruned before it's opened is skipped with a warning.
+pub fn run_archive(output_path: &Path, args: &ExportArgs, template: &PathTemplate) -> Result<()> {
+    let stdout = io::stdout();
+    if stdout.is_terminal() {
+        bail!("Refusing to write an archive to a terminal; redirect stdout to a file or pipe");
+    }
+
+    let selected = select(output_path, args, template);
+    let manifest = write_archive(output_path, args, selected, BufWriter::new(stdout.lock()))?;
+
+    info!(
+        clip_count = manifest.clip_count,
+        total_bytes = manifest.total_bytes,
+        skipped_count = manifest.skipped.len(),
+        "Exported clips"
+    );
+    Ok(())
+}
+
+/// The clips matching `args`' time range and devices, oldest first.
+fn select(
+    output_path: &Path,
+    args: &ExportArgs,
+    template: &PathTemplate,
+) -> Vec<(ArchiveClip, Row)> {
+    let from = args.from.map(local_to_utc);
+    let to = args.to.map(local_to_utc);
+    rows(output_path, template)
+        .into_iter()
+        .filter(|(_, row)| from.is_none_or(|from| row.start_time >= from))
+        .filter(|(_, row)| to.is_none_or(|to| row.start_time < to))
+        .filter(|(_, row)| {
+            args.device.is_empty()
+                || args
+                    .device
+                    .iter()
+                    .any(|device| *device == row.device || row.device_id.as_ref() == Some(device))
+        })
+        .collect()
+}
+
+/// Writes the `selected` clips, their sidecars and the manifest to `out`.
+fn write_archive(
+    output_path: &Path,
+    args: &ExportArgs,
+    selected: Vec<(ArchiveClip, Row)>,
+    out: impl Write,
+) -> Result<Manifest> {
+    let mut writer = ArchiveWriter::new(args.format, out);
+    let mut manifest = Manifest {
+        exported_at: Utc::now(),
+        from: args.from.map(local_to_utc),
+        to: args.to.map(local_to_utc),
+        devices: args.device.clone(),
+        clip_count: 0,
+        total_bytes: 0,
+        clips: Vec::new(),
+        skipped: Vec::new(),
+    };
+
+    for (clip, row) in selected {
+        let name = entry_name(output_path, &clip.path);
+        // Reading from the open file, a clip pruned from here on still
+        // exports whole
+        let opened = fs::File::open(&clip.path).and_then(|file| {
+            let metadata = file.metadata()?;
+            Ok((file, metadata))
+        });
+        let (mut file, metadata) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                warn!(path = %clip.path.display(), error = %e, "Skipping clip that can't be read, it may have been pruned");
+                manifest.skipped.push(name);
+                continue;
+            }
+        };
+        let modified = metadata.modified().map_or(row.start_time, DateTime::from);
+        writer
+            .append(&name, &mut file, metadata.len(), modified)
+            .with_context(|| format!("Failed to export {}", clip.path.display()))?;
+
+        let sidecar_path = archive::sidecar_path(&clip.path);
+        let sidecar = match fs::read(&sidecar_path) {
+            Ok(data) => {
+                let sidecar_name = entry_name(output_path, &sidecar_path);
+                writer
+                    .append(
+                        &sidecar_name,
+                        &mut data.as_slice(),
+                        data.len() as u64,
+                        modified,
+                    )
+                    .with_context(|| format!("Failed to export {}", sidecar_path.display()))?;
+                Some(sidecar_name)
+            }
+            Err(_) => None,
+        };
+
+        manifest.clip_count += 1;
+        manifest.total_bytes += metadata.len();
+        manifest.clips.push(ManifestClip {
+            path: name,
+            device: row.device,
+            start_time: row.start_time,
+            duration_secs: row.duration_secs,
+            size_bytes: metadata.len(),
+            sidecar,
+        });
+    }
+
+    let json = serde_json::to_vec_pretty(&manifest).context("Failed to serialize manifest")?;
+    writer.append(
+        MANIFEST_ENTRY,
+        &mut json.as_slice(),
+        json.len() as u64,
+        manifest.exported_at,
+    )?;
+    writer.finish().context("Failed to finish the archive")?;
+
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::Duration;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{layout::DEFAULT_PATH_TEMPLATE, models::CameraEvent};
+
+    fn template() -> PathTemplate {
+        DEFAULT_PATH_TEMPLATE.parse().unwrap()
+    }
+
+    fn args(format: ArchiveFormat) -> ExportArgs {
+        ExportArgs {
+            from: None,
+            to: None,
+            device: Vec::new(),
+            format,
+        }
+    }
+
+    /// Writes a clip of `device_name` starting `hour` hours into 2025-06-01
+    /// UTC, with a sidecar, and returns its path.
+    fn clip(root: &Path, device_name: &str, hour: u32) -> PathBuf {
+        let start = Utc.with_ymd_and_hms(2025, 6, 1, hour, 0, 0).unwrap();
+        let event = CameraEvent::new(device_name.to_lowercase(), start, Duration::seconds(10));
+        let path = root.join(template().render(
+            start,
+            event.end_time(),
+            &event.device_id,
+            device_name,
+            &[],
+        ));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, format!("{device_name} video")).unwrap();
+        let sidecar = archive::VideoMetadata::from(event).with_device_name(device_name);
+        archive::write_sidecar(&path, &sidecar).unwrap();
+        path
+    }
+
+    /// An archive of three clips, one of which is pruned after being
+    /// selected, exported in `format`.
+    fn export(format: ArchiveFormat) -> (Vec<u8>, Manifest, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        clip(root, "Porch", 12);
+        let pruned = clip(root, "Garage", 13);
+        clip(root, "Porch", 14);
+        let args = args(format);
+        let selected = select(root, &args, &template());
+        archive::remove_clip(&pruned).unwrap();
+
+        let mut out = Vec::new();
+        let manifest = write_archive(root, &args, selected, &mut out).unwrap();
+        (
+            out,
+            manifest,
+            pruned.strip_prefix(root).unwrap().to_path_buf(),
+        )
+    }
+
+    /// Checks the entries read back from an export against its manifest.
+    fn check_entries(entries: BTreeMap<String, Vec<u8>>, manifest: &Manifest, pruned: &Path) {
+        let pruned = pruned.to_str().unwrap();
+        assert_eq!(manifest.clip_count, 2);
+        assert_eq!(manifest.skipped, [pruned]);
+        assert_eq!(manifest.total_bytes, 2 * "Porch video".len() as u64);
+
+        let json: serde_json::Value = serde_json::from_slice(&entries[MANIFEST_ENTRY]).unwrap();
+        assert_eq!(json["clip_count"], 2);
+        assert_eq!(json["skipped"][0], pruned);
+        let clips = json["clips"].as_array().unwrap();
+        assert_eq!(clips.len(), 2);
+        for clip in clips {
+            assert_eq!(clip["device"], "Porch");
+            let path = clip["path"].as_str().unwrap();
+            assert_eq!(entries[path], b"Porch video");
+            assert!(entries.contains_key(clip["sidecar"].as_str().unwrap()));
+        }
+        assert!(clips[0]["start_time"].as_str() < clips[1]["start_time"].as_str());
+        assert!(!entries.contains_key(pruned));
+        // Two clips, their sidecars and the manifest
+        assert_eq!(entries.len(), 5);
+    }
+
+    #[test]
+    fn exports_tar_with_manifest() {
+        let (out, manifest, pruned) = export(ArchiveFormat::Tar);
+
+        let mut entries = BTreeMap::new();
+        for entry in tar::Archive::new(out.as_slice()).entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_str().unwrap().to_string();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            entries.insert(name, data);
+        }
+
+        check_entries(entries, &manifest, &pruned);
+    }
+
+    #[test]
+    fn exports_zip_with_manifest() {
+        let (out, manifest, pruned) = export(ArchiveFormat::Zip);
+
+        let mut zip = zip::ZipArchive::new(io::Cursor::new(out)).unwrap();
+        let mut entries = BTreeMap::new();
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i).unwrap();
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).unwrap();
+            entries.insert(file.name().to_string(), data);
+        }
+
+        check_entries(entries, &manifest, &pruned);
+    }
+
+    #[test]
+    fn selects_by_device() {
+        let dir = TempDir::new().unwrap();
+        clip(dir.path(), "Porch", 12);
+        clip(dir.path(), "Garage", 13);
+        let mut args = args(ArchiveFormat::Tar);
+        args.device = vec!["garage".to_string()];
+
+        let selected = select(dir.path(), &args, &template());
+
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].1.device, "Garage");
+    }
+}
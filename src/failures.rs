@@ -0,0 +1,87 @@
+use std::path::Path;
+
+use anyhow::Result;
+use clap::Subcommand;
+use tracing::info;
+
+use crate::state::StateStore;
+
+#[derive(Debug, clap::Args)]
+pub struct FailuresArgs {
+    #[command(subcommand)]
+    action: FailuresAction,
+}
+
+#[derive(Debug, Subcommand)]
+enum FailuresAction {
+    /// List ignored events with the error they were given up on
+    List,
+    /// Remove events from the ignore list so they are retried; clears every event if none are given
+    Clear {
+        /// Event IDs as shown by `failures list`
+        event_ids: Vec<String>,
+    },
+}
+
+/// Lists or clears the ignore list in the state file. A running daemon keeps
+/// its own copy of the state, so stop it before clearing.
+pub fn run(output_path: &Path, args: &FailuresArgs) -> Result<()> {
+    let mut store = StateStore::load(output_path);
+
+    match &args.action {
+        FailuresAction::List => {
+            for (event_id, ignored) in store.ignored() {
+                println!(
+                    "{event_id}\t{}\t{} failures\t{}",
+                    ignored.ignored_at.to_rfc3339(),
+                    ignored.failures,
+                    ignored.reason
+                );
+            }
+            println!("{} ignored events", store.ignored().len());
+        }
+        FailuresAction::Clear { event_ids } => {
+            let cleared_count = store.clear_ignored(event_ids);
+            store.save()?;
+            info!(
+                cleared_count,
+                "Cleared ignored events; they are retried at the next check"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn clear(output_path: &Path, event_ids: &[&str]) {
+        let args = FailuresArgs {
+            action: FailuresAction::Clear {
+                event_ids: event_ids.iter().map(ToString::to_string).collect(),
+            },
+        };
+        run(output_path, &args).unwrap();
+    }
+
+    #[test]
+    fn clear_round_trips_through_the_state_file() {
+        let dir = TempDir::new().unwrap();
+        let mut store = StateStore::load(dir.path());
+        store.ignore_event("porch-1", "expired");
+        store.ignore_event("porch-2", "expired");
+        store.save().unwrap();
+
+        clear(dir.path(), &["porch-1"]);
+        let store = StateStore::load(dir.path());
+        assert!(!store.is_ignored("porch-1"));
+        assert!(store.is_ignored("porch-2"));
+
+        clear(dir.path(), &[]);
+        assert!(StateStore::load(dir.path()).ignored().is_empty());
+    }
+}
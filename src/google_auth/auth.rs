@@ -0,0 +1,580 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{debug, error, warn};
+
+use super::connection::ConnectionOptions;
+use crate::metrics::{METRICS, TokenKind};
+
+const ACCESS_TOKEN_DURATION: Duration = Duration::from_secs(3600);
+pub(super) const AUTH_URL: &str = "https://android.clients.google.com/auth";
+const USER_AGENT: &str = "GoogleAuth/1.4";
+const ACCESS_TOKEN_APP_NAME: &str = "com.google.android.apps.chromecast.app";
+const ACCESS_TOKEN_CLIENT_SIGNATURE: &str = "24bb24c05e47e0aefa68a58a766179d9b613a600";
+const ACCESS_TOKEN_SERVICE: &str = "oauth2:https://www.google.com/accounts/OAuthLogin";
+const NEST_SCOPE: &str = "oauth2:https://www.googleapis.com/auth/nest-account";
+pub(super) const DEFAULT_PLAY_SERVICES_VERSION: &str = "240913000";
+
+#[derive(Debug, Error)]
+pub enum OAuthError {
+    /// Google's generic rejection, most often caused by an outdated
+    /// `google_play_services_version` or a revoked master token.
+    #[error(
+        "Google rejected the OAuth request (BadAuthentication) reporting play services version {play_services_version}"
+    )]
+    BadAuthentication { play_services_version: String },
+    #[error("Google rejected the OAuth request: {0}")]
+    Rejected(String),
+    #[error("No Auth token in OAuth response: {0}")]
+    MissingToken(String),
+}
+
+/// Whether something fetched at `date` is older than `max_age` at `now`. A
+/// `date` after `now` means the wall clock jumped back, e.g. an NTP correction
+/// after waking from sleep, so its age is unknown and it counts as stale.
+pub(super) fn is_stale(now: SystemTime, date: SystemTime, max_age: Duration) -> bool {
+    !matches!(now.duration_since(date), Ok(age) if age <= max_age)
+}
+
+/// Source of the current time for token expiry checks, so expiry can be
+/// driven without waiting on the wall clock.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A token as kept in the token cache file.
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredToken {
+    token: String,
+    /// Seconds since the Unix epoch.
+    fetched_at: u64,
+}
+
+impl StoredToken {
+    fn new(token: Option<&String>, date: Option<SystemTime>) -> Option<Self> {
+        let fetched_at = date?.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+        Some(Self {
+            token: token?.clone(),
+            fetched_at: fetched_at.as_secs(),
+        })
+    }
+
+    fn into_parts(token: Option<Self>) -> (Option<String>, Option<SystemTime>) {
+        match token {
+            Some(stored) => (
+                Some(stored.token),
+                Some(SystemTime::UNIX_EPOCH + Duration::from_secs(stored.fetched_at)),
+            ),
+            None => (None, None),
+        }
+    }
+}
+
+/// An account's token cache file, which keeps its tokens and Android ID
+/// across restarts.
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredTokens {
+    android_id: String,
+    #[serde(default)]
+    access_token: Option<StoredToken>,
+    #[serde(default)]
+    nest_access_token: Option<StoredToken>,
+}
+
+/// The account's token cache file in `dir`, named after the username.
+fn token_cache_path(dir: &Path, username: &str) -> PathBuf {
+    let name: String = username
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '@' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    dir.join(format!("tokens-{name}.json"))
+}
+
+/// Reads a token cache file. A missing file is no cache; an unreadable one is
+/// logged and replaced on the next refresh.
+fn load_stored_tokens(path: &Path) -> Option<StoredTokens> {
+    let bytes = fs::read(path).ok()?;
+    serde_json::from_slice(&bytes)
+        .inspect_err(|e| {
+            warn!(path = %path.display(), error = %e, "Ignoring unreadable token cache");
+        })
+        .ok()
+}
+
+/// Writes a token cache file atomically, readable only by its owner.
+fn save_stored_tokens(path: &Path, tokens: &StoredTokens) -> Result<()> {
+    let json = serde_json::to_vec_pretty(tokens).context("Failed to serialize tokens")?;
+    let tmp_path = path.with_extension("json.tmp");
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(&tmp_path)
+        .context("Failed to write token cache")?;
+    file.write_all(&json)
+        .context("Failed to write token cache")?;
+    fs::rename(&tmp_path, path).context("Failed to replace token cache")?;
+    Ok(())
+}
+
+/// Exchanges the master token for service-scoped access tokens and caches
+/// them until they expire.
+pub struct TokenCache {
+    client: Client,
+    master_token: String,
+    username: String,
+    android_id: String,
+    auth_url: String,
+    accept_encoding: String,
+    play_services_version: String,
+    clock: Arc<dyn Clock>,
+    /// How long before their nominal expiry tokens are refreshed.
+    expiry_grace: Duration,
+    /// File the tokens are kept in between runs.
+    cache_path: Option<PathBuf>,
+    access_token: Option<String>,
+    access_token_date: Option<SystemTime>,
+    nest_access_token: Option<String>,
+    nest_access_token_date: Option<SystemTime>,
+}
+
+impl TokenCache {
+    pub fn new(
+        client: Client,
+        master_token: String,
+        username: String,
+        options: &ConnectionOptions,
+    ) -> Self {
+        let cache_path = options
+            .token_cache_dir
+            .as_deref()
+            .map(|dir| token_cache_path(dir, &username));
+        let stored = cache_path.as_deref().and_then(load_stored_tokens);
+        // Keep the cached Android ID so Google sees the same device across
+        // restarts; otherwise generate a random 16-character one
+        let android_id = options
+            .android_id
+            .clone()
+            .or_else(|| stored.as_ref().map(|stored| stored.android_id.clone()))
+            .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+        let (access, nest) = match stored {
+            Some(stored) => (stored.access_token, stored.nest_access_token),
+            None => (None, None),
+        };
+        let (access_token, access_token_date) = StoredToken::into_parts(access);
+        let (nest_access_token, nest_access_token_date) = StoredToken::into_parts(nest);
+
+        Self {
+            client,
+            master_token,
+            username,
+            android_id,
+            auth_url: options.auth_url.clone(),
+            accept_encoding: options.oauth_accept_encoding.clone(),
+            play_services_version: options.play_services_version.clone(),
+            clock: options.clock.clone(),
+            expiry_grace: options.token_expiry_grace,
+            cache_path,
+            access_token,
+            access_token_date,
+            nest_access_token,
+            nest_access_token_date,
+        }
+    }
+
+    /// Writes the tokens to the token cache file, if there is one. A failed
+    /// write is logged: the tokens still work, the next run just fetches new
+    /// ones.
+    fn persist(&self) {
+        let Some(path) = &self.cache_path else {
+            return;
+        };
+        let tokens = StoredTokens {
+            android_id: self.android_id.clone(),
+            access_token: StoredToken::new(self.access_token.as_ref(), self.access_token_date),
+            nest_access_token: StoredToken::new(
+                self.nest_access_token.as_ref(),
+                self.nest_access_token_date,
+            ),
+        };
+        if let Err(e) = save_stored_tokens(path, &tokens) {
+            warn!(path = %path.display(), error = %format!("{e:#}"), "Failed to save token cache");
+        }
+    }
+
+    async fn perform_oauth(&self, service: &str) -> Result<String> {
+        let mut params = HashMap::new();
+        params.insert("accountType", "HOSTED_OR_GOOGLE");
+        params.insert("Email", self.username.as_str());
+        params.insert("has_permission", "1");
+        params.insert("EncryptedPasswd", self.master_token.as_str());
+        params.insert("service", service);
+        params.insert("source", "android");
+        params.insert("androidId", self.android_id.as_str());
+        params.insert("app", ACCESS_TOKEN_APP_NAME);
+        params.insert("client_sig", ACCESS_TOKEN_CLIENT_SIGNATURE);
+        params.insert("device_country", "us");
+        params.insert("operatorCountry", "us");
+        params.insert("lang", "en");
+        params.insert("sdk_version", "17");
+        params.insert(
+            "google_play_services_version",
+            self.play_services_version.as_str(),
+        );
+
+        debug!(
+            service,
+            accept_encoding = %self.accept_encoding,
+            "Requesting OAuth token"
+        );
+
+        let response = self
+            .client
+            .post(&self.auth_url)
+            .header("Accept-Encoding", &self.accept_encoding)
+            .header("Content-type", "application/x-www-form-urlencoded")
+            .header("User-Agent", USER_AGENT)
+            .form(&params)
+            .send()
+            .await
+            .context("Failed to send OAuth request")?;
+
+        let text = response
+            .text()
+            .await
+            .context("Failed to read OAuth response")?;
+
+        // Parse the response (format: key=value\nkey=value)
+        let fields: HashMap<&str, &str> = text.lines().filter_map(|l| l.split_once('=')).collect();
+        if let Some(token) = fields.get("Auth") {
+            return Ok(token.to_string());
+        }
+
+        match fields.get("Error").copied() {
+            Some("BadAuthentication") => {
+                error!(
+                    play_services_version = %self.play_services_version,
+                    "Google rejected authentication. This usually means the reported Google Play services \
+                     version is too old: pass a newer one with --play-services-version. If that doesn't help, \
+                     the master token may have been revoked and needs regenerating"
+                );
+                Err(OAuthError::BadAuthentication {
+                    play_services_version: self.play_services_version.clone(),
+                }
+                .into())
+            }
+            Some(error) => Err(OAuthError::Rejected(error.to_string()).into()),
+            None => Err(OAuthError::MissingToken(text).into()),
+        }
+    }
+
+    /// The cached token if it was fetched at `date` and hasn't expired, going
+    /// by the wall clock, which keeps counting through a suspend.
+    fn unexpired(&self, token: Option<&String>, date: Option<SystemTime>) -> Option<String> {
+        let max_age = ACCESS_TOKEN_DURATION.saturating_sub(self.expiry_grace);
+        token
+            .zip(date)
+            .filter(|&(_, date)| !is_stale(self.clock.now(), date, max_age))
+            .map(|(token, _)| token.clone())
+    }
+
+    /// Returns the cached access token, refreshing it once expired. A failed
+    /// refresh leaves the previous token in place.
+    pub async fn get_access_token(&mut self) -> Result<String> {
+        if let Some(token) = self.unexpired(self.access_token.as_ref(), self.access_token_date) {
+            return Ok(token);
+        }
+        let token = self.perform_oauth(ACCESS_TOKEN_SERVICE).await?;
+        self.access_token = Some(token.clone());
+        self.access_token_date = Some(self.clock.now());
+        self.persist();
+        METRICS.record_token_refresh(TokenKind::Access);
+        Ok(token)
+    }
+
+    pub async fn get_nest_access_token(&mut self) -> Result<String> {
+        if let Some(token) =
+            self.unexpired(self.nest_access_token.as_ref(), self.nest_access_token_date)
+        {
+            return Ok(token);
+        }
+        let token = self.perform_oauth(NEST_SCOPE).await?;
+        self.nest_access_token = Some(token.clone());
+        self.nest_access_token_date = Some(self.clock.now());
+        self.persist();
+        METRICS.record_token_refresh(TokenKind::Nest);
+        Ok(token)
+    }
+
+    /// Drops the cached Nest token so the next call fetches a fresh one.
+    pub fn invalidate_nest_access_token(&mut self) {
+        self.nest_access_token = None;
+        self.nest_access_token_date = None;
+        self.persist();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::google_auth::GoogleConnection;
+
+    #[derive(Debug)]
+    struct MockClock(Mutex<SystemTime>);
+
+    impl MockClock {
+        fn new() -> Arc<Self> {
+            Arc::new(Self(Mutex::new(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1_750_000_000),
+            )))
+        }
+
+        fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> SystemTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    /// Answers every request with `Auth=token-<n>`, or with an error while
+    /// `failing` is set.
+    struct MockOAuthServer {
+        url: String,
+        requests: Arc<AtomicUsize>,
+        failing: Arc<AtomicBool>,
+    }
+
+    impl MockOAuthServer {
+        async fn start(delay: Duration) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/auth", listener.local_addr().unwrap());
+            let requests = Arc::new(AtomicUsize::new(0));
+            let failing = Arc::new(AtomicBool::new(false));
+            let (counter, fail) = (requests.clone(), failing.clone());
+            tokio::spawn(async move {
+                loop {
+                    let (mut stream, _) = listener.accept().await.unwrap();
+                    let (counter, fail) = (counter.clone(), fail.clone());
+                    tokio::spawn(async move {
+                        read_request(&mut stream).await;
+                        let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                        tokio::time::sleep(delay).await;
+                        let body = if fail.load(Ordering::SeqCst) {
+                            "Error=ServiceUnavailable".to_string()
+                        } else {
+                            format!("Auth=token-{n}")
+                        };
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                            body.len()
+                        );
+                        let _ = stream.write_all(response.as_bytes()).await;
+                    });
+                }
+            });
+            Self {
+                url,
+                requests,
+                failing,
+            }
+        }
+
+        fn request_count(&self) -> usize {
+            self.requests.load(Ordering::SeqCst)
+        }
+    }
+
+    /// Reads the request head and its form body.
+    async fn read_request(stream: &mut tokio::net::TcpStream) {
+        let mut request = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|l| {
+                        l.to_ascii_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|v| v.trim().to_string())
+                    })
+                    .and_then(|v| v.parse::<usize>().ok())
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    return;
+                }
+            }
+            if n == 0 {
+                return;
+            }
+        }
+    }
+
+    fn connection(server: &MockOAuthServer, clock: Arc<MockClock>) -> GoogleConnection {
+        GoogleConnection::builder("master-token".to_string(), "user@example.com".to_string())
+            .auth_url(server.url.clone())
+            .clock(clock)
+            .build()
+            .unwrap()
+    }
+
+    async fn access_token(connection: &GoogleConnection) -> Result<String> {
+        connection.tokens.lock().await.get_access_token().await
+    }
+
+    #[tokio::test]
+    async fn fetches_token_on_first_call() {
+        let server = MockOAuthServer::start(Duration::ZERO).await;
+        let connection = connection(&server, MockClock::new());
+
+        assert_eq!(access_token(&connection).await.unwrap(), "token-1");
+        assert_eq!(server.request_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn returns_cached_token_within_ttl() {
+        let server = MockOAuthServer::start(Duration::ZERO).await;
+        let clock = MockClock::new();
+        let connection = connection(&server, clock.clone());
+
+        access_token(&connection).await.unwrap();
+        clock.advance(Duration::from_secs(30 * 60));
+        assert_eq!(access_token(&connection).await.unwrap(), "token-1");
+        assert_eq!(server.request_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn refreshes_token_after_ttl() {
+        let server = MockOAuthServer::start(Duration::ZERO).await;
+        let clock = MockClock::new();
+        let connection = connection(&server, clock.clone());
+
+        access_token(&connection).await.unwrap();
+        clock.advance(ACCESS_TOKEN_DURATION);
+        assert_eq!(access_token(&connection).await.unwrap(), "token-2");
+        assert_eq!(server.request_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_refresh_keeps_cached_token() {
+        let server = MockOAuthServer::start(Duration::ZERO).await;
+        let clock = MockClock::new();
+        let connection = connection(&server, clock.clone());
+
+        access_token(&connection).await.unwrap();
+        clock.advance(ACCESS_TOKEN_DURATION);
+        server.failing.store(true, Ordering::SeqCst);
+        let error = access_token(&connection).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<OAuthError>(),
+            Some(OAuthError::Rejected(reason)) if reason == "ServiceUnavailable"
+        ));
+
+        let tokens = connection.tokens.lock().await;
+        assert_eq!(tokens.access_token.as_deref(), Some("token-1"));
+    }
+
+    #[tokio::test]
+    async fn cached_tokens_survive_a_restart() {
+        let server = MockOAuthServer::start(Duration::ZERO).await;
+        let clock = MockClock::new();
+        let dir = tempfile::TempDir::new().unwrap();
+        let connect = || {
+            GoogleConnection::builder("master-token".to_string(), "user@example.com".to_string())
+                .auth_url(server.url.clone())
+                .clock(clock.clone())
+                .token_cache_dir(dir.path())
+                .build()
+                .unwrap()
+        };
+
+        let first = connect();
+        access_token(&first).await.unwrap();
+        let android_id = first.tokens.lock().await.android_id.clone();
+        drop(first);
+        let restarted = connect();
+
+        assert_eq!(access_token(&restarted).await.unwrap(), "token-1");
+        assert_eq!(server.request_count(), 1);
+        assert_eq!(restarted.tokens.lock().await.android_id, android_id);
+    }
+
+    #[tokio::test]
+    async fn configured_android_id_overrides_cached_one() {
+        let server = MockOAuthServer::start(Duration::ZERO).await;
+        let dir = tempfile::TempDir::new().unwrap();
+        let connect = |android_id: Option<&str>| {
+            let builder = GoogleConnection::builder(
+                "master-token".to_string(),
+                "user@example.com".to_string(),
+            )
+            .auth_url(server.url.clone())
+            .token_cache_dir(dir.path());
+            match android_id {
+                Some(id) => builder.android_id(id),
+                None => builder,
+            }
+            .build()
+            .unwrap()
+        };
+
+        access_token(&connect(None)).await.unwrap();
+        let pinned = connect(Some("0123456789abcdef"));
+
+        assert_eq!(pinned.tokens.lock().await.android_id, "0123456789abcdef");
+    }
+
+    #[tokio::test]
+    async fn concurrent_calls_refresh_once() {
+        let server = MockOAuthServer::start(Duration::from_millis(200)).await;
+        let connection = connection(&server, MockClock::new());
+
+        let calls = (0..8).map(|_| {
+            let connection = connection.clone();
+            tokio::spawn(async move { access_token(&connection).await })
+        });
+        for call in calls.collect::<Vec<_>>() {
+            assert_eq!(call.await.unwrap().unwrap(), "token-1");
+        }
+        assert_eq!(server.request_count(), 1);
+    }
+}
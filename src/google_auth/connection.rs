@@ -0,0 +1,612 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use reqwest::{Client, StatusCode};
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tonic::transport::Uri;
+use tracing::warn;
+
+#[cfg(feature = "hickory-dns")]
+use super::resolver::DnsServer;
+use super::{
+    auth::{AUTH_URL, Clock, DEFAULT_PLAY_SERVICES_VERSION, SystemClock, TokenCache},
+    homegraph::{DEFAULT_FOYER_ENDPOINT, DiscoveredDevice, DiscoveryOptions, HomegraphClient},
+    resolver::{HostResolver, IpVersion, ResolveOverride},
+};
+use crate::metrics::METRICS;
+
+const NEST_API_ORIGIN: &str = "https://nest-camera-frontend.googleapis.com";
+pub const DEFAULT_NEST_API_NAMESPACE: &str = "nest-phoenix-prod";
+/// `types` sent with event manifest requests; `nest-sync discover-types`
+/// finds others when this stops returning events.
+pub const DEFAULT_EVENT_QUERY_TYPES: &str = "4";
+
+/// Nest API failures that callers handle differently from a generic error.
+#[derive(Debug, Error)]
+pub enum ApiError {
+    /// The host name didn't resolve. Retrying at once is futile, so callers
+    /// back off the whole cycle instead of retrying the request.
+    #[error("DNS resolution failed for {host}")]
+    DnsResolution { host: String },
+    /// 404 Not Found. For a manifest this usually means the namespace is wrong.
+    #[error(
+        "Nest API returned 404 Not Found for {url}; check that --nest-api-namespace ('{namespace}') is right for this camera"
+    )]
+    NotFound { url: String, namespace: String },
+    /// 410 Gone: the resource existed but has been removed for good.
+    #[error("Nest API returned 410 Gone for {url}")]
+    Gone { url: String },
+    /// The clip endpoint answered 404, 410 or with no footage for an event its
+    /// manifest listed: the footage aged out of the camera's history before it
+    /// was downloaded.
+    #[error("Clip is no longer available ({response}); it expired before download")]
+    ClipExpired { response: &'static str },
+}
+
+/// The [`ApiError`] behind `error`, if any.
+pub fn api_error(error: &anyhow::Error) -> Option<&ApiError> {
+    error.downcast_ref::<ApiError>()
+}
+
+/// Whether `error` came from a failed DNS lookup.
+pub fn is_dns_failure(error: &anyhow::Error) -> bool {
+    matches!(api_error(error), Some(ApiError::DnsResolution { .. }))
+}
+
+/// reqwest doesn't classify resolver errors, so look for hyper's `dns error`
+/// or the resolver's own message in the source chain.
+fn is_dns_error(error: &reqwest::Error) -> bool {
+    if !error.is_connect() {
+        return false;
+    }
+    let mut source = std::error::Error::source(error);
+    while let Some(e) = source {
+        let message = e.to_string();
+        if message.contains("dns error") || message.contains("failed to lookup address") {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// Tunables for how a [`GoogleConnection`] talks to Google, set through
+/// [`GoogleConnectionBuilder`].
+#[derive(Debug, Clone)]
+pub(super) struct ConnectionOptions {
+    /// Endpoint the master token is exchanged against.
+    pub auth_url: String,
+    /// `Accept-Encoding` sent with the OAuth request. Google expects
+    /// `identity`, but some transforming proxies need something else.
+    pub oauth_accept_encoding: String,
+    /// `google_play_services_version` reported during OAuth. Google rejects
+    /// versions it considers too old.
+    pub play_services_version: String,
+    /// Google Home Foyer API endpoint the home graph is fetched from.
+    pub foyer_endpoint: Uri,
+    /// Scheme and host of the Nest API, substituted into `{origin}` in
+    /// request URLs.
+    pub nest_api_origin: String,
+    /// Nest API namespace substituted into `{namespace}` in request URLs.
+    /// Camera generations may live in different namespaces.
+    pub nest_api_namespace: String,
+    /// `types` parameter of event manifest requests.
+    pub event_query_types: String,
+    /// How long pooled HTTP connections may sit idle before they're closed.
+    pub http_pool_idle_timeout: Duration,
+    /// Idle HTTP connections kept open per host for reuse.
+    pub http_pool_max_idle_per_host: usize,
+    /// Extra PEM root certificate trusted for HTTP and gRPC, e.g. for a TLS
+    /// intercepting proxy.
+    pub ca_cert_pem: Option<Vec<u8>>,
+    /// Skip certificate verification for HTTP and gRPC, for inspecting
+    /// traffic through an intercepting proxy. Overrides `ca_cert_pem`.
+    #[cfg(feature = "insecure-tls")]
+    pub danger_insecure_tls: bool,
+    /// Address family for HTTP and gRPC connections.
+    pub ip_version: IpVersion,
+    /// Hosts pinned to fixed addresses instead of being looked up.
+    pub resolve_overrides: Vec<ResolveOverride>,
+    /// DNS server used instead of the system resolver.
+    #[cfg(feature = "hickory-dns")]
+    pub dns_server: Option<DnsServer>,
+    /// Time source for token expiry.
+    pub clock: Arc<dyn Clock>,
+    /// How long before their nominal expiry tokens are refreshed.
+    pub token_expiry_grace: Duration,
+    /// Android ID reported during OAuth; a random one is generated, or the
+    /// token cache's reused, when unset.
+    pub android_id: Option<String>,
+    /// Directory the account's tokens are kept in between runs, so a restart
+    /// doesn't need a fresh OAuth exchange.
+    pub token_cache_dir: Option<PathBuf>,
+    /// Cameras served as the home graph instead of asking the Foyer API.
+    #[cfg(test)]
+    pub home_graph: Option<Arc<std::sync::Mutex<Vec<DiscoveredDevice>>>>,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        Self {
+            auth_url: AUTH_URL.to_string(),
+            oauth_accept_encoding: "identity".to_string(),
+            play_services_version: DEFAULT_PLAY_SERVICES_VERSION.to_string(),
+            foyer_endpoint: Uri::from_static(DEFAULT_FOYER_ENDPOINT),
+            nest_api_origin: NEST_API_ORIGIN.to_string(),
+            nest_api_namespace: DEFAULT_NEST_API_NAMESPACE.to_string(),
+            event_query_types: DEFAULT_EVENT_QUERY_TYPES.to_string(),
+            http_pool_idle_timeout: Duration::from_secs(90),
+            http_pool_max_idle_per_host: 10,
+            ca_cert_pem: None,
+            #[cfg(feature = "insecure-tls")]
+            danger_insecure_tls: false,
+            ip_version: IpVersion::Auto,
+            resolve_overrides: Vec::new(),
+            #[cfg(feature = "hickory-dns")]
+            dns_server: None,
+            clock: Arc::new(SystemClock),
+            token_expiry_grace: Duration::from_secs(60),
+            android_id: None,
+            token_cache_dir: None,
+            #[cfg(test)]
+            home_graph: None,
+        }
+    }
+}
+
+/// Authenticated access to the Google Home and Nest APIs for one account.
+///
+/// Clones share the HTTP client and token cache, so concurrent tasks reuse the
+/// same tokens instead of each performing their own OAuth exchange.
+#[derive(Clone)]
+pub struct GoogleConnection {
+    client: Client,
+    pub(super) tokens: Arc<Mutex<TokenCache>>,
+    homegraph: Arc<Mutex<HomegraphClient>>,
+    discovery: DiscoveryOptions,
+    nest_api_origin: String,
+    nest_api_namespace: String,
+    event_query_types: String,
+    resolver: HostResolver,
+}
+
+/// Builds a [`GoogleConnection`] from an account's credentials, every other
+/// option keeping its default unless overridden.
+#[must_use]
+pub struct GoogleConnectionBuilder {
+    master_token: String,
+    username: String,
+    options: ConnectionOptions,
+}
+
+impl GoogleConnectionBuilder {
+    pub fn oauth_accept_encoding(mut self, encoding: impl Into<String>) -> Self {
+        self.options.oauth_accept_encoding = encoding.into();
+        self
+    }
+
+    pub fn play_services_version(mut self, version: impl Into<String>) -> Self {
+        self.options.play_services_version = version.into();
+        self
+    }
+
+    pub fn foyer_endpoint(mut self, endpoint: Uri) -> Self {
+        self.options.foyer_endpoint = endpoint;
+        self
+    }
+
+    pub fn nest_api_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.options.nest_api_namespace = namespace.into();
+        self
+    }
+
+    pub fn event_query_types(mut self, types: impl Into<String>) -> Self {
+        self.options.event_query_types = types.into();
+        self
+    }
+
+    pub fn http_pool(mut self, idle_timeout: Duration, max_idle_per_host: usize) -> Self {
+        self.options.http_pool_idle_timeout = idle_timeout;
+        self.options.http_pool_max_idle_per_host = max_idle_per_host;
+        self
+    }
+
+    pub fn token_expiry_grace(mut self, grace: Duration) -> Self {
+        self.options.token_expiry_grace = grace;
+        self
+    }
+
+    /// Reports `android_id` during OAuth instead of a generated one.
+    pub fn android_id(mut self, android_id: impl Into<String>) -> Self {
+        self.options.android_id = Some(android_id.into());
+        self
+    }
+
+    /// Keeps tokens in `dir` between runs. The directory is created when the
+    /// connection is built.
+    pub fn token_cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.options.token_cache_dir = Some(dir.into());
+        self
+    }
+
+    /// Trusts the PEM root certificate at `path` as well as the system's.
+    /// The file is read and checked now rather than on first use.
+    pub fn ca_cert_file(mut self, path: &Path) -> Result<Self> {
+        let pem = std::fs::read(path)
+            .with_context(|| format!("Failed to read CA certificate {}", path.display()))?;
+        check_ca_cert(&pem)
+            .with_context(|| format!("Invalid CA certificate {}", path.display()))?;
+        self.options.ca_cert_pem = Some(pem);
+        Ok(self)
+    }
+
+    #[cfg(feature = "insecure-tls")]
+    pub fn danger_insecure_tls(mut self, insecure: bool) -> Self {
+        self.options.danger_insecure_tls = insecure;
+        self
+    }
+
+    pub fn ip_version(mut self, ip_version: IpVersion) -> Self {
+        self.options.ip_version = ip_version;
+        self
+    }
+
+    pub fn resolve_overrides(mut self, overrides: Vec<ResolveOverride>) -> Self {
+        self.options.resolve_overrides = overrides;
+        self
+    }
+
+    #[cfg(feature = "hickory-dns")]
+    pub fn dns_server(mut self, server: Option<DnsServer>) -> Self {
+        self.options.dns_server = server;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.options.clock = clock;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn auth_url(mut self, url: impl Into<String>) -> Self {
+        self.options.auth_url = url.into();
+        self
+    }
+
+    /// Serves `cameras` as the home graph; changes show on the next fetch.
+    #[cfg(test)]
+    pub fn home_graph(mut self, cameras: Arc<std::sync::Mutex<Vec<DiscoveredDevice>>>) -> Self {
+        self.options.home_graph = Some(cameras);
+        self
+    }
+
+    #[cfg(test)]
+    pub fn nest_api_origin(mut self, origin: impl Into<String>) -> Self {
+        self.options.nest_api_origin = origin.into();
+        self
+    }
+
+    /// Checks the options fit together and builds the connection. No request
+    /// is made until the connection is first used.
+    pub fn build(self) -> Result<GoogleConnection> {
+        if self.master_token.trim().is_empty() {
+            bail!("Google master token is empty");
+        }
+        if self.username.trim().is_empty() {
+            bail!("Google username is empty");
+        }
+        // Tokens last an hour, so a longer grace would refresh on every request
+        if self.options.token_expiry_grace >= Duration::from_secs(60 * 60) {
+            bail!(
+                "Token expiry grace of {}s leaves no time to use a token",
+                self.options.token_expiry_grace.as_secs()
+            );
+        }
+        if let Some(android_id) = &self.options.android_id {
+            check_android_id(android_id)?;
+        }
+        if let Some(dir) = &self.options.token_cache_dir {
+            std::fs::create_dir_all(dir).with_context(|| {
+                format!("Failed to create token cache directory {}", dir.display())
+            })?;
+        }
+        GoogleConnection::with_options(self.master_token, self.username, self.options)
+    }
+}
+
+/// Checks that `pem` holds at least one certificate. rustls only parses it
+/// when the client is built, and then silently skips anything that isn't one.
+pub fn check_ca_cert(pem: &[u8]) -> Result<()> {
+    if reqwest::Certificate::from_pem_bundle(pem)?.is_empty() {
+        bail!("no PEM certificate found");
+    }
+    Ok(())
+}
+
+/// Checks that `android_id` looks like one: 16 hex digits, as Android reports it.
+pub fn check_android_id(android_id: &str) -> Result<()> {
+    if android_id.len() != 16 || !android_id.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("Android ID '{android_id}' isn't 16 hex digits");
+    }
+    Ok(())
+}
+
+impl GoogleConnection {
+    /// Starts a [`GoogleConnectionBuilder`] for the account.
+    pub fn builder(master_token: String, username: String) -> GoogleConnectionBuilder {
+        GoogleConnectionBuilder {
+            master_token,
+            username,
+            options: ConnectionOptions::default(),
+        }
+    }
+
+    fn with_options(
+        master_token: String,
+        username: String,
+        options: ConnectionOptions,
+    ) -> Result<Self> {
+        // One pool serves OAuth, event queries and every download, so keeping
+        // connections warm saves a handshake on most requests
+        let resolver = HostResolver::new(&options);
+        let mut builder = Client::builder()
+            .pool_idle_timeout(options.http_pool_idle_timeout)
+            .pool_max_idle_per_host(options.http_pool_max_idle_per_host)
+            .dns_resolver(resolver.clone());
+        #[cfg(feature = "insecure-tls")]
+        if options.danger_insecure_tls {
+            builder = builder.tls_danger_accept_invalid_certs(true);
+        }
+        if let Some(pem) = &options.ca_cert_pem {
+            let cert = reqwest::Certificate::from_pem(pem).context("Invalid CA certificate")?;
+            builder = builder.add_root_certificate(cert);
+        }
+        let client = builder.build().context("Failed to build HTTP client")?;
+
+        Ok(Self {
+            tokens: Arc::new(Mutex::new(TokenCache::new(
+                client.clone(),
+                master_token,
+                username,
+                &options,
+            ))),
+            homegraph: Arc::new(Mutex::new(HomegraphClient::new(&options, resolver.clone()))),
+            discovery: DiscoveryOptions::default(),
+            nest_api_origin: options.nest_api_origin,
+            nest_api_namespace: options.nest_api_namespace,
+            event_query_types: options.event_query_types,
+            resolver,
+            client,
+        })
+    }
+
+    pub fn event_query_types(&self) -> &str {
+        &self.event_query_types
+    }
+
+    pub async fn make_nest_get_request(
+        &self,
+        device_id: &str,
+        url: &str,
+        params: &[(&str, String)],
+    ) -> Result<Vec<u8>> {
+        let url = url
+            .replace("{origin}", &self.nest_api_origin)
+            .replace("{namespace}", &self.nest_api_namespace)
+            .replace("{device_id}", device_id);
+        let access_token = self.tokens.lock().await.get_nest_access_token().await?;
+
+        let mut response = self
+            .send_nest_get_request(&url, params, &access_token)
+            .await?;
+
+        // A 401 means the cached token was revoked early; refresh once and retry
+        if response.status() == StatusCode::UNAUTHORIZED {
+            warn!(%url, "Nest API rejected access token; forcing refresh");
+            METRICS.record_forced_token_refresh();
+            let access_token = {
+                let mut tokens = self.tokens.lock().await;
+                tokens.invalidate_nest_access_token();
+                tokens.get_nest_access_token().await?
+            };
+            response = self
+                .send_nest_get_request(&url, params, &access_token)
+                .await?;
+        }
+
+        // A wrong namespace surfaces as a 404 rather than a bad manifest
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(ApiError::NotFound {
+                url,
+                namespace: self.nest_api_namespace.clone(),
+            }
+            .into());
+        }
+        if response.status() == StatusCode::GONE {
+            return Err(ApiError::Gone { url }.into());
+        }
+
+        let bytes = response
+            .error_for_status()
+            .context("Request returned error status")?
+            .bytes()
+            .await
+            .context("Failed to read response body")?;
+
+        Ok(bytes.to_vec())
+    }
+
+    async fn send_nest_get_request(
+        &self,
+        url: &str,
+        params: &[(&str, String)],
+        access_token: &str,
+    ) -> Result<reqwest::Response> {
+        self.client
+            .get(url)
+            .query(params)
+            .header("Authorization", format!("Bearer {}", access_token))
+            .send()
+            .await
+            .map_err(|e| {
+                let host = e
+                    .url()
+                    .and_then(|u| u.host_str())
+                    .unwrap_or_default()
+                    .to_string();
+                if is_dns_error(&e) {
+                    anyhow::Error::new(e).context(ApiError::DnsResolution { host })
+                } else if e.is_connect() {
+                    let families = self.resolver.families(&host);
+                    anyhow::Error::new(e)
+                        .context(format!("Failed to connect to {host} (tried {families})"))
+                } else {
+                    anyhow::Error::new(e).context("Failed to send request")
+                }
+            })
+    }
+
+    pub async fn get_nest_camera_devices(&self) -> Result<Vec<DiscoveredDevice>> {
+        let mut homegraph = self.homegraph.lock().await;
+        let mut tokens = self.tokens.lock().await;
+        homegraph
+            .get_nest_camera_devices(&mut tokens, &self.discovery)
+            .await
+    }
+
+    /// Like [`Self::get_nest_camera_devices`], but fetches the home graph
+    /// afresh rather than reusing a cached copy.
+    pub async fn refresh_nest_camera_devices(&self) -> Result<Vec<DiscoveredDevice>> {
+        self.homegraph.lock().await.invalidate();
+        self.get_nest_camera_devices().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Self-signed P-256 root, standing in for an intercepting proxy's CA.
+    const TEST_CA_PEM: &str = "-----BEGIN CERTIFICATE-----
+MIIBjzCCATWgAwIBAgIUTpuFTAfYOhiRz0pEf6MIiv2+iqMwCgYIKoZIzj0EAwIw
+HDEaMBgGA1UEAwwRbmVzdC1zeW5jIHRlc3QgQ0EwIBcNMjYxMDE3MDU1NTQ3WhgP
+MjEyNjA5MjMwNTU1NDdaMBwxGjAYBgNVBAMMEW5lc3Qtc3luYyB0ZXN0IENBMFkw
+EwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEzoy9/4zojAV/x+ANFrPJQnPfORpk2wmr
+sYsMMoND/0d93AJ+M/a7xzZBe059w6g3AVmMGT+6yBgrdC7WHre706NTMFEwHQYD
+VR0OBBYEFO+WLnm+Z89WCsx3mMn/A9yAcUVNMB8GA1UdIwQYMBaAFO+WLnm+Z89W
+Csx3mMn/A9yAcUVNMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIg
+RELH+hVrQiTLJKq0ROjeo2ZrPQt1pW0Z4CAro2mRRscCIQCWclIBJu+kVLl11VHK
+EuOGPBwiZXwxXq200n76RUZmUQ==
+-----END CERTIFICATE-----
+";
+
+    fn builder() -> GoogleConnectionBuilder {
+        GoogleConnection::builder("aas_et/token".to_string(), "user@example.com".to_string())
+    }
+
+    /// Builds the HTTP client and the gRPC endpoint, the two TLS stacks,
+    /// with whichever backend feature is enabled.
+    async fn assert_tls_stacks_build(builder: GoogleConnectionBuilder) {
+        let connection = builder.build().expect("HTTP client");
+        connection
+            .homegraph
+            .lock()
+            .await
+            .tls_endpoint()
+            .expect("gRPC endpoint");
+    }
+
+    #[tokio::test]
+    async fn builds_tls_clients_with_platform_roots() {
+        assert_tls_stacks_build(builder()).await;
+    }
+
+    #[tokio::test]
+    async fn builds_tls_clients_with_extra_ca_cert() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("ca.pem");
+        std::fs::write(&path, TEST_CA_PEM).unwrap();
+
+        assert_tls_stacks_build(builder().ca_cert_file(&path).unwrap()).await;
+    }
+
+    #[test]
+    fn rejects_invalid_ca_cert() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("ca.pem");
+        std::fs::write(&path, "not a certificate").unwrap();
+
+        assert!(builder().ca_cert_file(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_empty_credentials() {
+        let empty_token =
+            GoogleConnection::builder(" ".to_string(), "user@example.com".to_string());
+        let empty_username = GoogleConnection::builder("aas_et/token".to_string(), String::new());
+
+        assert!(empty_token.build().is_err());
+        assert!(empty_username.build().is_err());
+    }
+
+    #[test]
+    fn rejects_grace_of_an_hour_or_more() {
+        assert!(
+            builder()
+                .token_expiry_grace(Duration::from_secs(60 * 60))
+                .build()
+                .is_err()
+        );
+        assert!(
+            builder()
+                .token_expiry_grace(Duration::from_secs(59 * 60))
+                .build()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_android_id() {
+        assert!(builder().android_id("0123456789abcdef").build().is_ok());
+        assert!(builder().android_id("0123456789abcde").build().is_err());
+        assert!(builder().android_id("0123456789abcdeg").build().is_err());
+    }
+
+    #[test]
+    fn creates_token_cache_dir() {
+        let dir = TempDir::new().unwrap();
+        let cache_dir = dir.path().join("cache").join("tokens");
+
+        builder().token_cache_dir(&cache_dir).build().unwrap();
+
+        assert!(cache_dir.is_dir());
+    }
+
+    #[test]
+    fn rejects_token_cache_dir_that_cannot_be_created() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+
+        assert!(
+            builder()
+                .token_cache_dir(file.join("tokens"))
+                .build()
+                .is_err()
+        );
+    }
+
+    #[cfg(feature = "insecure-tls")]
+    #[tokio::test]
+    async fn builds_tls_clients_without_verification() {
+        assert_tls_stacks_build(builder().danger_insecure_tls(true)).await;
+    }
+}
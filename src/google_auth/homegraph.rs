@@ -0,0 +1,411 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use hyper_util::client::legacy::connect::HttpConnector;
+use tonic::{
+    Request,
+    metadata::MetadataValue,
+    transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Uri},
+};
+
+use super::{
+    auth::{TokenCache, is_stale},
+    connection::ConnectionOptions,
+    foyer::{
+        GetHomeGraphRequest, GetHomeGraphResponse,
+        structures_service_client::StructuresServiceClient,
+    },
+    resolver::HostResolver,
+};
+
+/// Google Home Foyer API endpoint the home graph is fetched from.
+pub const DEFAULT_FOYER_ENDPOINT: &str = "https://googlehomefoyer-pa.googleapis.com";
+const HOMEGRAPH_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+const CAMERA_STREAM_TRAIT: &str = "action.devices.traits.CameraStream";
+
+/// Parses a Foyer API endpoint: an `https` URL with a host and no path, since
+/// gRPC methods are requested at fixed paths on it.
+pub fn parse_foyer_endpoint(s: &str) -> Result<Uri, String> {
+    let uri: Uri = s
+        .parse()
+        .map_err(|e| format!("invalid endpoint URL '{s}': {e}"))?;
+    if uri.scheme_str() != Some("https") {
+        return Err(format!("expected an https:// URL, got '{s}'"));
+    }
+    if uri.host().is_none_or(str::is_empty) {
+        return Err(format!("missing host in '{s}'"));
+    }
+    if !matches!(uri.path(), "" | "/") || uri.query().is_some() {
+        return Err(format!("expected no path in '{s}'"));
+    }
+    Ok(uri)
+}
+
+/// gRPC client for the Google Home Foyer API, caching the home graph between
+/// calls.
+pub struct HomegraphClient {
+    homegraph: Option<GetHomeGraphResponse>,
+    homegraph_date: Option<SystemTime>,
+    endpoint: Uri,
+    tls_config: ClientTlsConfig,
+    resolver: HostResolver,
+    #[cfg(feature = "insecure-tls")]
+    danger_insecure_tls: bool,
+    #[cfg(test)]
+    served_cameras: Option<std::sync::Arc<std::sync::Mutex<Vec<DiscoveredDevice>>>>,
+}
+
+impl HomegraphClient {
+    /// `ca_cert_pem` is trusted in addition to the platform's roots (and, with
+    /// the `rustls` feature, the bundled webpki roots). Connections resolve
+    /// through `resolver`, so they use the same address family as HTTP.
+    pub fn new(options: &ConnectionOptions, resolver: HostResolver) -> Self {
+        let mut tls_config = ClientTlsConfig::new().with_native_roots();
+        #[cfg(feature = "rustls")]
+        {
+            tls_config = tls_config.with_webpki_roots();
+        }
+        if let Some(pem) = &options.ca_cert_pem {
+            tls_config = tls_config.ca_certificate(Certificate::from_pem(pem));
+        }
+
+        Self {
+            homegraph: None,
+            homegraph_date: None,
+            endpoint: options.foyer_endpoint.clone(),
+            tls_config,
+            resolver,
+            #[cfg(feature = "insecure-tls")]
+            danger_insecure_tls: options.danger_insecure_tls,
+            #[cfg(test)]
+            served_cameras: options.home_graph.clone(),
+        }
+    }
+
+    /// Drops the cached home graph so the next call fetches it again.
+    pub fn invalidate(&mut self) {
+        self.homegraph = None;
+        self.homegraph_date = None;
+    }
+
+    pub async fn get_home_graph(
+        &mut self,
+        tokens: &mut TokenCache,
+    ) -> Result<GetHomeGraphResponse> {
+        let cached = self
+            .homegraph
+            .as_ref()
+            .zip(self.homegraph_date)
+            .filter(|&(_, date)| !is_stale(SystemTime::now(), date, HOMEGRAPH_DURATION));
+        if let Some((homegraph, _)) = cached {
+            return Ok(homegraph.clone());
+        }
+
+        #[cfg(test)]
+        if let Some(cameras) = &self.served_cameras {
+            let homegraph = camera_home_graph(&cameras.lock().unwrap());
+            self.homegraph = Some(homegraph.clone());
+            self.homegraph_date = Some(SystemTime::now());
+            return Ok(homegraph);
+        }
+
+        let access_token = tokens.get_access_token().await?;
+
+        let channel = self.connect().await.with_context(|| {
+            let host = self.endpoint.host().unwrap_or_default();
+            format!(
+                "Failed to connect to Google Home Foyer API at {} (tried {})",
+                self.endpoint,
+                self.resolver.families(host)
+            )
+        })?;
+
+        let token: MetadataValue<_> = format!("Bearer {}", access_token)
+            .parse()
+            .context("Failed to parse access token")?;
+
+        let mut client =
+            StructuresServiceClient::with_interceptor(channel, move |mut req: Request<()>| {
+                req.metadata_mut().insert("authorization", token.clone());
+                Ok(req)
+            });
+
+        let request = Request::new(GetHomeGraphRequest {
+            string1: String::new(),
+            num2: String::new(),
+        });
+
+        let response = client
+            .get_home_graph(request)
+            .await
+            .context("Failed to get home graph")?;
+
+        let homegraph = response.into_inner();
+        self.homegraph = Some(homegraph.clone());
+        self.homegraph_date = Some(SystemTime::now());
+        Ok(homegraph)
+    }
+
+    async fn connect(&self) -> Result<Channel> {
+        #[cfg(feature = "insecure-tls")]
+        if self.danger_insecure_tls {
+            return super::insecure::connect(&self.endpoint, self.resolver.clone()).await;
+        }
+
+        let mut connector = HttpConnector::new_with_resolver(self.resolver.clone());
+        connector.enforce_http(false);
+        connector.set_nodelay(true);
+        Ok(self
+            .tls_endpoint()?
+            .connect_with_connector(connector)
+            .await?)
+    }
+
+    /// The Foyer endpoint with TLS set up; root certificates are loaded here.
+    pub(super) fn tls_endpoint(&self) -> Result<Endpoint> {
+        Ok(Channel::builder(self.endpoint.clone()).tls_config(self.tls_config.clone())?)
+    }
+
+    pub async fn get_nest_camera_devices(
+        &mut self,
+        tokens: &mut TokenCache,
+        opts: &DiscoveryOptions,
+    ) -> Result<Vec<DiscoveredDevice>> {
+        let homegraph = self.get_home_graph(tokens).await?;
+        Ok(select_camera_devices(&homegraph, opts))
+    }
+}
+
+/// Criteria a home graph device must meet to be synced as a camera.
+#[derive(Debug, Clone)]
+pub struct DiscoveryOptions {
+    pub required_trait: String,
+    pub model_substring: String,
+}
+
+impl Default for DiscoveryOptions {
+    fn default() -> Self {
+        Self {
+            required_trait: CAMERA_STREAM_TRAIT.to_string(),
+            model_substring: "Nest".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredDevice {
+    pub device_id: String,
+    pub device_name: String,
+}
+
+/// Picks the camera devices out of a home graph. Devices must expose the
+/// required trait, report a hardware model containing the configured
+/// substring, and carry a non-empty agent `unique_id`.
+pub fn select_camera_devices(
+    resp: &GetHomeGraphResponse,
+    opts: &DiscoveryOptions,
+) -> Vec<DiscoveredDevice> {
+    let Some(home) = resp.home.as_ref() else {
+        return Vec::new();
+    };
+
+    home.devices
+        .iter()
+        .filter(|device| device.traits.contains(&opts.required_trait))
+        .filter(|device| {
+            device
+                .hardware
+                .as_ref()
+                .is_some_and(|h| h.model.contains(&opts.model_substring))
+        })
+        .filter_map(|device| {
+            let device_id = device
+                .device_info
+                .as_ref()
+                .and_then(|di| di.agent_info.as_ref())
+                .map(|ai| ai.unique_id.clone())
+                .unwrap_or_default();
+
+            (!device_id.is_empty()).then(|| DiscoveredDevice {
+                device_id,
+                device_name: device.device_name.clone(),
+            })
+        })
+        .collect()
+}
+
+/// A home graph holding `cameras` as Nest cameras.
+#[cfg(test)]
+fn camera_home_graph(cameras: &[DiscoveredDevice]) -> GetHomeGraphResponse {
+    use super::foyer::get_home_graph_response::{
+        Home,
+        home::{
+            Device,
+            device::{DeviceInfo, Hardware, device_info::AgentInfo},
+        },
+    };
+
+    let devices = cameras
+        .iter()
+        .map(|camera| Device {
+            device_info: Some(DeviceInfo {
+                agent_info: Some(AgentInfo {
+                    unique_id: camera.device_id.clone(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            device_name: camera.device_name.clone(),
+            traits: vec![CAMERA_STREAM_TRAIT.to_string()],
+            hardware: Some(Hardware {
+                model: "Nest Cam".to_string(),
+            }),
+            ..Default::default()
+        })
+        .collect();
+    GetHomeGraphResponse {
+        home: Some(Home {
+            devices,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::google_auth::foyer::get_home_graph_response::{
+        Home,
+        home::{
+            Device,
+            device::{DeviceInfo, Hardware, device_info::AgentInfo},
+        },
+    };
+
+    fn device(name: &str, model: &str, unique_id: Option<&str>, traits: &[&str]) -> Device {
+        Device {
+            device_info: Some(DeviceInfo {
+                agent_info: unique_id.map(|id| AgentInfo {
+                    unique_id: id.to_string(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            device_name: name.to_string(),
+            traits: traits.iter().map(|t| t.to_string()).collect(),
+            hardware: Some(Hardware {
+                model: model.to_string(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn home_graph(devices: Vec<Device>) -> GetHomeGraphResponse {
+        GetHomeGraphResponse {
+            home: Some(Home {
+                devices,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn selected_names(resp: &GetHomeGraphResponse) -> Vec<String> {
+        select_camera_devices(resp, &DiscoveryOptions::default())
+            .into_iter()
+            .map(|d| d.device_name)
+            .collect()
+    }
+
+    #[test]
+    fn selects_nest_camera() {
+        let resp = home_graph(vec![device(
+            "Backyard",
+            "Nest Cam (battery)",
+            Some("cam-1"),
+            &[CAMERA_STREAM_TRAIT],
+        )]);
+        assert_eq!(
+            select_camera_devices(&resp, &DiscoveryOptions::default()),
+            vec![DiscoveredDevice {
+                device_id: "cam-1".to_string(),
+                device_name: "Backyard".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn skips_non_nest_model() {
+        let resp = home_graph(vec![device(
+            "Porch",
+            "Wyze Cam v3",
+            Some("cam-2"),
+            &[CAMERA_STREAM_TRAIT],
+        )]);
+        assert!(selected_names(&resp).is_empty());
+    }
+
+    #[test]
+    fn skips_device_without_agent_info() {
+        let resp = home_graph(vec![device(
+            "Garage",
+            "Nest Cam (wired)",
+            None,
+            &[CAMERA_STREAM_TRAIT],
+        )]);
+        assert!(selected_names(&resp).is_empty());
+    }
+
+    #[test]
+    fn selects_doorbell() {
+        let resp = home_graph(vec![device(
+            "Front Door",
+            "Nest Doorbell (battery)",
+            Some("doorbell-1"),
+            &["action.devices.traits.OnOff", CAMERA_STREAM_TRAIT],
+        )]);
+        assert_eq!(selected_names(&resp), ["Front Door"]);
+    }
+
+    #[test]
+    fn skips_nest_device_without_camera_stream() {
+        let resp = home_graph(vec![device(
+            "Hallway",
+            "Nest Thermostat",
+            Some("thermostat-1"),
+            &["action.devices.traits.TemperatureSetting"],
+        )]);
+        assert!(selected_names(&resp).is_empty());
+    }
+
+    #[test]
+    fn no_home_selects_nothing() {
+        assert!(selected_names(&GetHomeGraphResponse::default()).is_empty());
+    }
+
+    #[test]
+    fn keeps_home_graph_order() {
+        let resp = home_graph(vec![
+            device(
+                "Backyard",
+                "Nest Cam",
+                Some("cam-1"),
+                &[CAMERA_STREAM_TRAIT],
+            ),
+            device(
+                "Porch",
+                "Wyze Cam v3",
+                Some("cam-2"),
+                &[CAMERA_STREAM_TRAIT],
+            ),
+            device(
+                "Front Door",
+                "Nest Doorbell",
+                Some("doorbell-1"),
+                &[CAMERA_STREAM_TRAIT],
+            ),
+        ]);
+        assert_eq!(selected_names(&resp), ["Backyard", "Front Door"]);
+    }
+}
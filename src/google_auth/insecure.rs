@@ -0,0 +1,137 @@
+//! gRPC connections that skip certificate verification, for inspecting
+//! traffic through an intercepting proxy. tonic's TLS settings can't turn
+//! verification off, so the channel speaks plain HTTP/2 to a connector that
+//! does its own TLS handshake, while requests keep the real `https` origin.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use anyhow::{Context as _, Result};
+use hyper_util::{client::legacy::connect::HttpConnector, rt::TokioIo};
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    TlsConnector,
+    client::TlsStream,
+    rustls::{
+        ClientConfig, DigitallySignedStruct, SignatureScheme,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{CryptoProvider, ring},
+        pki_types::{CertificateDer, ServerName, UnixTime},
+    },
+};
+use tonic::transport::{Channel, Uri};
+
+use super::resolver::HostResolver;
+
+/// Accepts any certificate. Handshake signatures are still checked, so the
+/// connection is encrypted to whoever presented the certificate.
+#[derive(Debug)]
+struct NoVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        tokio_rustls::rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        tokio_rustls::rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// TCP through the shared resolver, then an unverified TLS handshake.
+#[derive(Clone)]
+struct InsecureConnector {
+    http: HttpConnector<HostResolver>,
+    tls: TlsConnector,
+    server_name: ServerName<'static>,
+}
+
+impl tower_service::Service<Uri> for InsecureConnector {
+    type Response = TokioIo<TlsStream<TcpStream>>;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connect = self.http.call(uri);
+        let tls = self.tls.clone();
+        let server_name = self.server_name.clone();
+        Box::pin(async move {
+            let tcp = connect.await?.into_inner();
+            let stream = tls.connect(server_name, tcp).await?;
+            Ok(TokioIo::new(stream))
+        })
+    }
+}
+
+/// Connects to the `https` URL `origin` without verifying its certificate.
+pub async fn connect(origin: &Uri, resolver: HostResolver) -> Result<Channel> {
+    let host = origin.host().context("URL has no host")?.to_string();
+    let port = origin.port_u16().unwrap_or(443);
+
+    let provider = Arc::new(ring::default_provider());
+    let mut config = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .context("Failed to configure TLS")?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(NoVerifier(provider)))
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec()];
+
+    let mut http = HttpConnector::new_with_resolver(resolver);
+    http.set_nodelay(true);
+    let connector = InsecureConnector {
+        http,
+        tls: TlsConnector::from(Arc::new(config)),
+        server_name: ServerName::try_from(host.clone()).context("Invalid TLS server name")?,
+    };
+
+    Channel::from_shared(format!("http://{host}:{port}"))
+        .context("Invalid endpoint")?
+        .origin(origin.clone())
+        .connect_with_connector(connector)
+        .await
+        .context("Failed to connect without certificate verification")
+}
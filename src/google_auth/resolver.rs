@@ -0,0 +1,371 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use hyper_util::client::legacy::connect::dns::Name;
+use tracing::debug;
+
+use super::connection::ConnectionOptions;
+
+/// Address family used for connections to Google.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum IpVersion {
+    /// Whichever addresses the resolver returns
+    #[default]
+    Auto,
+    /// IPv4 only
+    V4,
+    /// IPv6 only
+    V6,
+}
+
+impl IpVersion {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Auto => "usable",
+            Self::V4 => "IPv4",
+            Self::V6 => "IPv6",
+        }
+    }
+
+    fn allows(self, ip: IpAddr) -> bool {
+        match self {
+            Self::Auto => true,
+            Self::V4 => ip.is_ipv4(),
+            Self::V6 => ip.is_ipv6(),
+        }
+    }
+}
+
+fn family_name(ip: IpAddr) -> &'static str {
+    if ip.is_ipv4() { "IPv4" } else { "IPv6" }
+}
+
+/// Parses an IP address, optionally in brackets like `[2001:db8::1]`.
+fn parse_ip(s: &str) -> Option<IpAddr> {
+    let s = s.trim();
+    s.strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .unwrap_or(s)
+        .parse()
+        .ok()
+}
+
+/// A host name pinned to fixed addresses, bypassing DNS: `HOST=ADDR[,ADDR...]`
+/// or curl's `HOST:PORT:ADDR[,ADDR...]`, whose port is ignored since every
+/// connection to a host is pinned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolveOverride {
+    host: String,
+    addrs: Vec<IpAddr>,
+}
+
+impl FromStr for ResolveOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, addrs) = match s.split_once('=') {
+            Some(parts) => parts,
+            None => {
+                let mut parts = s.splitn(3, ':');
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(host), Some(port), Some(addrs)) if port.parse::<u16>().is_ok() => {
+                        (host, addrs)
+                    }
+                    _ => return Err(format!("expected HOST=ADDR or HOST:PORT:ADDR, got '{s}'")),
+                }
+            }
+        };
+        let host = host.trim();
+        if host.is_empty() {
+            return Err(format!("missing host in '{s}'"));
+        }
+        let addrs = addrs
+            .split(',')
+            .map(|addr| parse_ip(addr).ok_or_else(|| format!("invalid address '{addr}' in '{s}'")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            host: host.to_ascii_lowercase(),
+            addrs,
+        })
+    }
+}
+
+/// A DNS server to resolve through instead of the system resolver:
+/// `ADDR[:PORT]` or `udp://ADDR[:PORT]` for plain DNS, or
+/// `https://HOST[:PORT][/PATH]` for DNS over HTTPS.
+#[cfg(feature = "hickory-dns")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsServer {
+    /// Plain DNS, over UDP with TCP for truncated answers.
+    Udp(SocketAddr),
+    /// DNS over HTTPS. A host name is looked up once, through the overrides
+    /// or the system resolver, and also serves as the TLS server name.
+    Https {
+        host: String,
+        port: u16,
+        path: String,
+    },
+}
+
+#[cfg(feature = "hickory-dns")]
+impl FromStr for DnsServer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("https://") {
+            let url = reqwest::Url::parse(s).map_err(|e| format!("invalid URL '{s}': {e}"))?;
+            let host = url
+                .host_str()
+                .ok_or_else(|| format!("missing host in '{s}'"))?;
+            let path = match url.path() {
+                "/" => "/dns-query",
+                path => path,
+            };
+            return Ok(Self::Https {
+                host: host.trim_matches(['[', ']']).to_string(),
+                port: url.port().unwrap_or(443),
+                path: path.to_string(),
+            });
+        }
+
+        let addr = s.strip_prefix("udp://").unwrap_or(s);
+        addr.parse::<SocketAddr>()
+            .ok()
+            .or_else(|| parse_ip(addr).map(|ip| SocketAddr::new(ip, 53)))
+            .map(Self::Udp)
+            .ok_or_else(|| format!("expected ADDR[:PORT] or https://HOST[/PATH], got '{s}'"))
+    }
+}
+
+/// Resolves host names for both the HTTP client and the gRPC channel:
+/// pinned hosts first, then the configured DNS server or the system resolver,
+/// keeping only addresses of the configured family. Each host's last addresses
+/// are remembered so a failed connection can say which families it tried.
+#[derive(Debug, Clone)]
+pub struct HostResolver {
+    ip_version: IpVersion,
+    overrides: Arc<HashMap<String, Vec<IpAddr>>>,
+    #[cfg(feature = "hickory-dns")]
+    dns_server: Option<Arc<dns_server::DnsServerResolver>>,
+    resolved: Arc<Mutex<HashMap<String, Vec<IpAddr>>>>,
+}
+
+impl HostResolver {
+    pub fn new(options: &ConnectionOptions) -> Self {
+        Self {
+            ip_version: options.ip_version,
+            overrides: Arc::new(
+                options
+                    .resolve_overrides
+                    .iter()
+                    .map(|o| (o.host.clone(), o.addrs.clone()))
+                    .collect(),
+            ),
+            #[cfg(feature = "hickory-dns")]
+            dns_server: options
+                .dns_server
+                .clone()
+                .map(|server| Arc::new(dns_server::DnsServerResolver::new(server))),
+            resolved: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    async fn lookup_ips(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        if let Some(addrs) = self.overrides.get(&host.to_ascii_lowercase()) {
+            return Ok(addrs.clone());
+        }
+        #[cfg(feature = "hickory-dns")]
+        if let Some(dns_server) = &self.dns_server {
+            return dns_server
+                .lookup(host, self.ip_version, &self.overrides)
+                .await;
+        }
+        system_lookup(host).await
+    }
+
+    async fn lookup(self, host: String) -> io::Result<std::vec::IntoIter<SocketAddr>> {
+        let addrs: Vec<SocketAddr> = self
+            .lookup_ips(&host)
+            .await?
+            .into_iter()
+            .filter(|ip| self.ip_version.allows(*ip))
+            .map(|ip| SocketAddr::new(ip, 0))
+            .collect();
+        debug!(%host, ip_version = ?self.ip_version, ?addrs, "Resolved host");
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no {} addresses for {host}", self.ip_version.label()),
+            ));
+        }
+
+        self.resolved
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(host, addrs.iter().map(SocketAddr::ip).collect());
+        Ok(addrs.into_iter())
+    }
+
+    /// The address families `host` last resolved to, in the order they are
+    /// tried, e.g. "IPv6, then IPv4".
+    pub fn families(&self, host: &str) -> String {
+        let resolved = self.resolved.lock().unwrap_or_else(|e| e.into_inner());
+        let mut families: Vec<&str> = Vec::new();
+        for ip in resolved.get(host).into_iter().flatten() {
+            let family = family_name(*ip);
+            if !families.contains(&family) {
+                families.push(family);
+            }
+        }
+        if families.is_empty() {
+            "no resolved addresses".to_string()
+        } else {
+            families.join(", then ")
+        }
+    }
+}
+
+async fn system_lookup(host: &str) -> io::Result<Vec<IpAddr>> {
+    Ok(tokio::net::lookup_host((host, 0))
+        .await?
+        .map(|addr| addr.ip())
+        .collect())
+}
+
+#[cfg(feature = "hickory-dns")]
+mod dns_server {
+    use std::{collections::HashMap, io, net::IpAddr, sync::Arc};
+
+    use hickory_resolver::{
+        TokioResolver,
+        config::{LookupIpStrategy, NameServerConfig, ResolverConfig},
+        net::runtime::TokioRuntimeProvider,
+    };
+    use tokio::sync::OnceCell;
+    use tracing::info;
+
+    use super::{DnsServer, IpVersion, parse_ip, system_lookup};
+
+    /// A resolver for `--dns-server`, built on first use so a DNS over HTTPS
+    /// host name can be looked up inside the runtime.
+    #[derive(Debug)]
+    pub struct DnsServerResolver {
+        server: DnsServer,
+        resolver: OnceCell<TokioResolver>,
+    }
+
+    impl DnsServerResolver {
+        pub fn new(server: DnsServer) -> Self {
+            Self {
+                server,
+                resolver: OnceCell::new(),
+            }
+        }
+
+        pub async fn lookup(
+            &self,
+            host: &str,
+            ip_version: IpVersion,
+            overrides: &HashMap<String, Vec<IpAddr>>,
+        ) -> io::Result<Vec<IpAddr>> {
+            let resolver = self
+                .resolver
+                .get_or_try_init(|| self.build(ip_version, overrides))
+                .await?;
+            let lookup = resolver.lookup_ip(host).await.map_err(io::Error::other)?;
+            Ok(lookup.iter().collect())
+        }
+
+        async fn build(
+            &self,
+            ip_version: IpVersion,
+            overrides: &HashMap<String, Vec<IpAddr>>,
+        ) -> io::Result<TokioResolver> {
+            let name_server = match &self.server {
+                DnsServer::Udp(addr) => {
+                    let mut name_server = NameServerConfig::udp_and_tcp(addr.ip());
+                    for connection in &mut name_server.connections {
+                        connection.port = addr.port();
+                    }
+                    name_server
+                }
+                DnsServer::Https { host, port, path } => {
+                    let candidates = match parse_ip(host) {
+                        Some(ip) => vec![ip],
+                        None => match overrides.get(&host.to_ascii_lowercase()) {
+                            Some(addrs) => addrs.clone(),
+                            None => system_lookup(host).await?,
+                        },
+                    };
+                    let ip = candidates
+                        .into_iter()
+                        .find(|ip| ip_version.allows(*ip))
+                        .ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::NotFound,
+                                format!(
+                                    "no {} addresses for DNS server {host}",
+                                    ip_version.label()
+                                ),
+                            )
+                        })?;
+                    let mut name_server = NameServerConfig::https(
+                        ip,
+                        Arc::from(host.as_str()),
+                        Some(Arc::from(path.as_str())),
+                    );
+                    for connection in &mut name_server.connections {
+                        connection.port = *port;
+                    }
+                    name_server
+                }
+            };
+            info!(server = ?self.server, ip = %name_server.ip, "Resolving host names through DNS server");
+
+            let mut builder = TokioResolver::builder_with_config(
+                ResolverConfig::from_name_servers(vec![name_server]),
+                TokioRuntimeProvider::default(),
+            );
+            builder.options_mut().ip_strategy = match ip_version {
+                IpVersion::Auto => LookupIpStrategy::default(),
+                IpVersion::V4 => LookupIpStrategy::Ipv4Only,
+                IpVersion::V6 => LookupIpStrategy::Ipv6Only,
+            };
+            builder.build().map_err(io::Error::other)
+        }
+    }
+}
+
+impl reqwest::dns::Resolve for HostResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let lookup = self.clone().lookup(name.as_str().to_string());
+        Box::pin(async move {
+            let addrs: reqwest::dns::Addrs = Box::new(lookup.await?);
+            Ok(addrs)
+        })
+    }
+}
+
+/// Resolver for the gRPC channel's hyper connector.
+impl tower_service::Service<Name> for HostResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        Box::pin(self.clone().lookup(name.as_str().to_string()))
+    }
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use reqwest::{Client, Url};
+use serde::Serialize;
+use tracing::{debug, warn};
+
+/// A heartbeat that doesn't answer within this long counts as failed, so a
+/// slow monitoring service can't hold up the next check.
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HeartbeatMethod {
+    Get,
+    Post,
+}
+
+/// What a check did, sent as the POST body with `--heartbeat-summary`.
+#[derive(Debug, Serialize)]
+pub struct CycleSummary {
+    pub cycle_id: String,
+    pub cycle_secs: f64,
+    pub device_count: usize,
+    pub downloaded_count: usize,
+    pub expired_count: usize,
+}
+
+/// Pings `--heartbeat-url` after every successful check, for dead man's
+/// switch monitoring such as healthchecks.io.
+pub struct Heartbeat {
+    client: Client,
+    url: Url,
+    method: HeartbeatMethod,
+    include_summary: bool,
+}
+
+impl Heartbeat {
+    pub fn new(url: Url, method: HeartbeatMethod, include_summary: bool) -> Result<Self> {
+        let client = Client::builder()
+            .timeout(PING_TIMEOUT)
+            .build()
+            .context("Failed to build heartbeat HTTP client")?;
+        Ok(Self {
+            client,
+            url,
+            method,
+            include_summary,
+        })
+    }
+
+    /// Sends the heartbeat. A failed ping is only logged; the monitoring
+    /// service alerts on the missing heartbeat itself.
+    pub async fn ping(&self, summary: &CycleSummary) {
+        let request = match self.method {
+            HeartbeatMethod::Get => self.client.get(self.url.clone()),
+            HeartbeatMethod::Post if self.include_summary => {
+                self.client.post(self.url.clone()).json(summary)
+            }
+            HeartbeatMethod::Post => self.client.post(self.url.clone()),
+        };
+
+        match request.send().await.and_then(|r| r.error_for_status()) {
+            Ok(response) => debug!(status = %response.status(), "Sent heartbeat"),
+            Err(e) => warn!(
+                host = self.url.host_str().unwrap_or_default(),
+                error = %format!("{e:#}"),
+                "Failed to send heartbeat"
+            ),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use tracing::{debug, info, warn};
+
+use crate::{
+    archive,
+    layout::PathTemplate,
+    state::{ImportedClip, StateStore},
+};
+
+#[derive(Debug, clap::Args)]
+pub struct ImportExistingArgs {
+    /// Directory of clips to import, e.g. another tool's archive
+    #[arg(value_hint = clap::ValueHint::DirPath)]
+    dir: PathBuf,
+
+    /// Template the clips' paths follow, relative to DIR (default: --path-template)
+    #[arg(long)]
+    template: Option<PathTemplate>,
+
+    /// Camera, by name or ID, of clips whose path doesn't name one; without it they match any
+    /// camera's event starting at the same time
+    #[arg(long)]
+    device: Option<String>,
+
+    /// Report what would be imported without changing the state file
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// Records the clips under `args.dir` in the state file, so events starting
+/// when one of them did are skipped as already downloaded. A clip's start
+/// and camera come from its sidecar, or else from its path. A running daemon
+/// keeps its own copy of the state and would overwrite the entries, so stop
+/// it first.
+pub fn run(output_path: &Path, args: &ImportExistingArgs, template: &PathTemplate) -> Result<()> {
+    if !args.dir.is_dir() {
+        bail!("{} is not a directory", args.dir.display());
+    }
+    let template = args.template.as_ref().unwrap_or(template);
+    let mut store = StateStore::load(output_path);
+
+    let mut imported_count = 0;
+    let mut known_count = 0;
+    let mut unrecognized_count = 0;
+    for clip in archive::walk_clips(&args.dir) {
+        let imported = match &clip.sidecar {
+            Some(sidecar) => Some(ImportedClip {
+                start_time: sidecar.start_time,
+                device: Some(sidecar.device_id.clone()),
+            }),
+            None => clip
+                .path
+                .strip_prefix(&args.dir)
+                .ok()
+                .and_then(Path::to_str)
+                .and_then(|relative| template.parse(relative))
+                .and_then(|parsed| {
+                    Some(ImportedClip {
+                        start_time: parsed.start_time?,
+                        device: parsed
+                            .device_id
+                            .or(parsed.device_name)
+                            .or_else(|| args.device.clone()),
+                    })
+                }),
+        };
+        let Some(imported) = imported else {
+            debug!(path = %clip.path.display(), "No start time in clip path");
+            unrecognized_count += 1;
+            continue;
+        };
+        if store.import_clip(imported) {
+            imported_count += 1;
+        } else {
+            known_count += 1;
+        }
+    }
+
+    if unrecognized_count > 0 {
+        warn!(
+            unrecognized_count,
+            template = %template,
+            "Skipped clips without a sidecar whose path doesn't match the template"
+        );
+    }
+    if !args.dry_run && imported_count > 0 {
+        store.save()?;
+    }
+    info!(
+        dir = %args.dir.display(),
+        imported_count,
+        known_count,
+        dry_run = args.dry_run,
+        "Imported existing clips"
+    );
+    Ok(())
+}
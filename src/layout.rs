@@ -0,0 +1,710 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::{
+    DateTime, Datelike, Days, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc,
+};
+use chrono_tz::America::Vancouver;
+
+use crate::models::MAX_EVENT_DURATION_SECS;
+
+/// The archive layout used before templates were configurable.
+pub const DEFAULT_PATH_TEMPLATE: &str =
+    "{year}/{month}/{day}/{year}-{month}-{day}T{hour}-{minute}-{second}.mp4";
+/// What `{tags}` becomes for an event without tags.
+const UNTAGGED: &str = "untagged";
+
+/// How to keep clips from different cameras apart when the path template
+/// doesn't name the camera.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DeviceCollisionPolicy {
+    /// Append the camera's name to the file name of a clip whose path another
+    /// camera's clip already has
+    Suffix,
+    /// Refuse to start unless the template has {device_id} or {device_name}
+    Refuse,
+}
+
+/// Which day's directory an event that spans midnight goes into. Only date
+/// fields in directory names follow it; the file name keeps the start time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DayAssignment {
+    /// The day the event starts
+    #[default]
+    Start,
+    /// The day the event ends
+    End,
+    /// The day holding most of the event, the start day on a tie
+    Majority,
+}
+
+impl DayAssignment {
+    /// The local date of the directory for an event from `start_time` to
+    /// `end_time`.
+    fn folder_date(self, start_time: DateTime<Utc>, end_time: DateTime<Utc>) -> NaiveDate {
+        self.folder_date_in(&Vancouver, start_time, end_time)
+    }
+
+    /// [`Self::folder_date`] with dates in `tz`.
+    fn folder_date_in<Tz: TimeZone>(
+        self,
+        tz: &Tz,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> NaiveDate {
+        let start_date = start_time.with_timezone(tz).date_naive();
+        // An event ending exactly at midnight is all in the day before
+        let last = (end_time - chrono::Duration::milliseconds(1)).max(start_time);
+        let end_date = last.with_timezone(tz).date_naive();
+        if start_date == end_date {
+            return start_date;
+        }
+        match self {
+            Self::Start => start_date,
+            Self::End => end_date,
+            Self::Majority => {
+                let Some(midnight) = end_date
+                    .and_hms_opt(0, 0, 0)
+                    .and_then(|naive| tz.from_local_datetime(&naive).earliest())
+                else {
+                    return start_date;
+                };
+                let midnight = midnight.with_timezone(&Utc);
+                if end_time - midnight > midnight - start_time {
+                    end_date
+                } else {
+                    start_date
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Field {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    DeviceId,
+    DeviceName,
+    Tags,
+    /// The local UTC offset, e.g. `-0700`.
+    Offset,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "year" => Self::Year,
+            "month" => Self::Month,
+            "day" => Self::Day,
+            "hour" => Self::Hour,
+            "minute" => Self::Minute,
+            "second" => Self::Second,
+            "device_id" => Self::DeviceId,
+            "device_name" => Self::DeviceName,
+            "tags" => Self::Tags,
+            "offset" => Self::Offset,
+            _ => return None,
+        })
+    }
+
+    /// Width of fixed-width fields; device and tag fields are free-form.
+    fn width(self) -> Option<usize> {
+        match self {
+            Self::Year => Some(4),
+            Self::Month | Self::Day | Self::Hour | Self::Minute | Self::Second => Some(2),
+            Self::Offset => Some(5),
+            Self::DeviceId | Self::DeviceName | Self::Tags => None,
+        }
+    }
+
+    /// Whether `value` can be this field's text in a path.
+    fn accepts(self, value: &str) -> bool {
+        match self {
+            Self::Offset => parse_offset(value).is_some(),
+            Self::DeviceId | Self::DeviceName | Self::Tags => true,
+            _ => value.bytes().all(|b| b.is_ascii_digit()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    /// A field, and whether it's in a directory name rather than the file
+    /// name.
+    Field(Field, bool),
+}
+
+/// Where a clip lives relative to the output directory, e.g.
+/// `{device_name}/{year}/{month}/{day}/{hour}-{minute}-{second}.mp4`. Times
+/// are local.
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+    raw: String,
+    parts: Vec<Part>,
+    day_assignment: DayAssignment,
+}
+
+/// Clip attributes recovered from a path laid out by a template.
+#[derive(Debug, Clone)]
+pub struct ParsedPath {
+    pub start_time: Option<DateTime<Utc>>,
+    /// The earliest the clip can have started: a day before `start_time`
+    /// when it may be filed under the day its event ended.
+    pub earliest_start: Option<DateTime<Utc>>,
+    pub device_id: Option<String>,
+    pub device_name: Option<String>,
+}
+
+impl ParsedPath {
+    /// Whether the path names the camera, by ID or by its name as rendered
+    /// into paths.
+    pub fn is_device(&self, device_id: &str, device_name: &str) -> bool {
+        match (&self.device_id, &self.device_name) {
+            (Some(id), _) => id == device_id,
+            (None, Some(name)) => *name == sanitize_component(device_name),
+            (None, None) => false,
+        }
+    }
+}
+
+impl fmt::Display for PathTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+impl FromStr for PathTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                parts.push(Part::Literal(rest[..open].to_string()));
+            }
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed '{{' in path template '{s}'"))?;
+            let name = &rest[open + 1..open + close];
+            let field = Field::from_name(name)
+                .ok_or_else(|| format!("unknown placeholder '{{{name}}}' in path template"))?;
+            parts.push(Part::Field(field, false));
+            rest = &rest[open + close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        // Fields can't contain '/', so those before the last one are in
+        // directory names
+        let last_slash = parts
+            .iter()
+            .rposition(|part| matches!(part, Part::Literal(literal) if literal.contains('/')));
+        for part in parts.iter_mut().take(last_slash.unwrap_or(0)) {
+            if let Part::Field(_, in_dir) = part {
+                *in_dir = true;
+            }
+        }
+
+        if !s.ends_with(".mp4") {
+            return Err("path template must end in .mp4".to_string());
+        }
+        if s.starts_with('/') || s.split('/').any(|c| c == "..") {
+            return Err("path template must stay inside the output directory".to_string());
+        }
+
+        Ok(Self {
+            raw: s.to_string(),
+            parts,
+            day_assignment: DayAssignment::default(),
+        })
+    }
+}
+
+impl PathTemplate {
+    fn uses(&self, field: Field) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Field(f, _) if *f == field))
+    }
+
+    pub fn uses_device_id(&self) -> bool {
+        self.uses(Field::DeviceId)
+    }
+
+    pub fn uses_device_name(&self) -> bool {
+        self.uses(Field::DeviceName)
+    }
+
+    /// The template with `{offset}` appended to the file name, e.g.
+    /// `...T{hour}-{minute}-{second}{offset}.mp4`, unless it's already there.
+    pub fn with_offset(&self) -> Self {
+        if self.uses(Field::Offset) {
+            return self.clone();
+        }
+        let base = self.raw.strip_suffix(".mp4").unwrap_or(&self.raw);
+        format!("{base}{{offset}}.mp4").parse().map_or_else(
+            |_| self.clone(),
+            |template: Self| template.with_day_assignment(self.day_assignment),
+        )
+    }
+
+    /// The template with events that span midnight put in the directory of
+    /// the day `day_assignment` picks.
+    pub fn with_day_assignment(&self, day_assignment: DayAssignment) -> Self {
+        Self {
+            day_assignment,
+            ..self.clone()
+        }
+    }
+
+    /// The layouts earlier settings may have archived clips in: the other day
+    /// assignments, and each without `{offset}`.
+    pub fn earlier_layouts(&self) -> Vec<Self> {
+        let templates = std::iter::once(self.clone()).chain(self.without_offset());
+        templates
+            .flat_map(|template| {
+                [
+                    DayAssignment::Start,
+                    DayAssignment::End,
+                    DayAssignment::Majority,
+                ]
+                .map(|day_assignment| template.with_day_assignment(day_assignment))
+            })
+            .filter(|template| {
+                template.raw != self.raw || template.day_assignment != self.day_assignment
+            })
+            .collect()
+    }
+
+    /// The template as it was before `{offset}` was added, with the offset
+    /// and any separator just before it removed, so clips archived without
+    /// an offset can still be recognized. `None` without `{offset}`.
+    pub fn without_offset(&self) -> Option<Self> {
+        let (before, after) = self.raw.split_once("{offset}")?;
+        let before = before.trim_end_matches(['-', '_', '.', ' ']);
+        format!("{before}{}", after.replace("{offset}", ""))
+            .parse()
+            .ok()
+            .map(|template: Self| template.with_day_assignment(self.day_assignment))
+    }
+
+    /// Whether clips from different cameras always get different paths.
+    pub fn separates_devices(&self) -> bool {
+        self.uses_device_id() || self.uses_device_name()
+    }
+
+    /// Whether every time field down to the second is part of the path, so
+    /// clips from one camera never share a path.
+    pub fn resolves_seconds(&self) -> bool {
+        [
+            Field::Year,
+            Field::Month,
+            Field::Day,
+            Field::Hour,
+            Field::Minute,
+            Field::Second,
+        ]
+        .into_iter()
+        .all(|field| self.uses(field))
+    }
+
+    /// Where the template's date directories are: how many directories deep
+    /// `{year}` starts, and how many of `{year}`, `{month}` and `{day}` follow
+    /// it as whole directory names, in that order. `None` when the template
+    /// has no `{year}` directory, or a time field comes before it.
+    fn date_dirs(&self) -> Option<(usize, usize)> {
+        let components: Vec<&str> = self.raw.split('/').collect();
+        // The file itself isn't a directory
+        let dirs = &components[..components.len() - 1];
+        let depth = dirs.iter().position(|c| *c == "{year}")?;
+        let time_fields = ["{year", "{month", "{day", "{hour", "{minute", "{second"];
+        let earlier_time_field = dirs[..depth]
+            .iter()
+            .any(|component| time_fields.iter().any(|field| component.contains(field)));
+        if earlier_time_field {
+            return None;
+        }
+        let levels = dirs[depth..]
+            .iter()
+            .zip(["{year}", "{month}", "{day}"])
+            .take_while(|&(component, field)| *component == field)
+            .count();
+        Some((depth, levels))
+    }
+
+    /// The first day a date directory in the layout this template produces
+    /// can hold clips from, e.g. 1 September 2025 for `2025/09`, given its
+    /// path relative to the output directory; a day earlier when events that
+    /// span midnight may go in the directory of the day they end. `None` for
+    /// any other path, or when the template has no date directories.
+    pub fn dir_start_date(&self, relative: &Path) -> Option<NaiveDate> {
+        let (depth, levels) = self.date_dirs()?;
+        let components: Vec<&str> = relative
+            .components()
+            .map(|c| c.as_os_str().to_str())
+            .collect::<Option<_>>()?;
+        if components.len() <= depth || components.len() > depth + levels {
+            return None;
+        }
+        let number = |i: usize, width: usize| match components.get(depth + i) {
+            Some(value) => (value.len() == width && value.bytes().all(|b| b.is_ascii_digit()))
+                .then(|| value.parse::<u32>().ok())
+                .flatten(),
+            None => Some(1),
+        };
+        let date = NaiveDate::from_ymd_opt(number(0, 4)? as i32, number(1, 2)?, number(2, 2)?)?;
+        match self.day_assignment {
+            DayAssignment::Start => Some(date),
+            DayAssignment::End | DayAssignment::Majority => date.pred_opt(),
+        }
+    }
+
+    /// The camera every clip under a directory belongs to, given its path
+    /// relative to the output directory, when a directory above it is named
+    /// by `{device_id}` or `{device_name}` alone.
+    pub fn dir_device(&self, relative: &Path) -> Option<ParsedPath> {
+        let components: Vec<&str> = self.raw.split('/').collect();
+        // The file itself isn't a directory
+        let dirs = &components[..components.len() - 1];
+        let relative: Vec<&str> = relative
+            .components()
+            .map(|c| c.as_os_str().to_str())
+            .collect::<Option<_>>()?;
+        dirs.iter()
+            .zip(relative)
+            .find_map(|(component, value)| match *component {
+                "{device_id}" => Some(ParsedPath {
+                    start_time: None,
+                    earliest_start: None,
+                    device_id: Some(value.to_string()),
+                    device_name: None,
+                }),
+                "{device_name}" => Some(ParsedPath {
+                    start_time: None,
+                    earliest_start: None,
+                    device_id: None,
+                    device_name: Some(value.to_string()),
+                }),
+                _ => None,
+            })
+    }
+
+    /// The path for a clip, relative to the output directory. `{tags}` joins
+    /// the tags with `+`.
+    pub fn render(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        device_id: &str,
+        device_name: &str,
+        tags: &[String],
+    ) -> PathBuf {
+        let local = start_time.with_timezone(&Vancouver);
+        let folder_date = self.day_assignment.folder_date(start_time, end_time);
+        let mut out = String::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => out.push_str(literal),
+                Part::Field(field, in_dir) => {
+                    let date = if *in_dir {
+                        folder_date
+                    } else {
+                        local.date_naive()
+                    };
+                    match field {
+                        Field::Year => out.push_str(&format!("{:04}", date.year())),
+                        Field::Month => out.push_str(&format!("{:02}", date.month())),
+                        Field::Day => out.push_str(&format!("{:02}", date.day())),
+                        Field::Hour => out.push_str(&format!("{:02}", local.hour())),
+                        Field::Minute => out.push_str(&format!("{:02}", local.minute())),
+                        Field::Second => out.push_str(&format!("{:02}", local.second())),
+                        Field::DeviceId => out.push_str(&sanitize_component(device_id)),
+                        Field::DeviceName => out.push_str(&sanitize_component(device_name)),
+                        Field::Tags if tags.is_empty() => out.push_str(UNTAGGED),
+                        Field::Tags => out.push_str(&sanitize_component(&tags.join("+"))),
+                        Field::Offset => out.push_str(&local.format("%z").to_string()),
+                    }
+                }
+            }
+        }
+
+        PathBuf::from(out)
+    }
+
+    /// Matches a path relative to the output directory against the template,
+    /// or against the template without `{offset}` for clips archived before
+    /// it was added. Returns `None` when the path isn't laid out by this
+    /// template. With an offset, a start time in the hour repeated when
+    /// daylight saving time ends is exact rather than the earlier hour. The
+    /// file name's date comes before the directories', which may be a day
+    /// late for an event that spans midnight.
+    pub fn parse(&self, relative: &str) -> Option<ParsedPath> {
+        let captures = match match_parts(&self.parts, relative, HashMap::new()) {
+            Some(captures) => captures,
+            None => return self.without_offset()?.parse(relative),
+        };
+        let capture = |field| {
+            captures
+                .get(&(field, false))
+                .or_else(|| captures.get(&(field, true)))
+        };
+        let offset = capture(Field::Offset).and_then(|v| parse_offset(v));
+        let number = |field| capture(field).and_then(|v| v.parse::<u32>().ok());
+
+        let naive = match (
+            number(Field::Year),
+            number(Field::Month),
+            number(Field::Day),
+        ) {
+            (Some(year), Some(month), Some(day)) => {
+                NaiveDate::from_ymd_opt(year as i32, month, day).and_then(|date| {
+                    date.and_hms_opt(
+                        number(Field::Hour).unwrap_or(0),
+                        number(Field::Minute).unwrap_or(0),
+                        number(Field::Second).unwrap_or(0),
+                    )
+                })
+            }
+            _ => None,
+        };
+        let to_utc = |naive: NaiveDateTime| match offset {
+            Some(offset) => offset
+                .from_local_datetime(&naive)
+                .single()
+                .map(|local| local.with_timezone(&Utc)),
+            None => Vancouver
+                .from_local_datetime(&naive)
+                .earliest()
+                .map(|local| local.with_timezone(&Utc)),
+        };
+        let start_time = naive.and_then(to_utc);
+        // Without a date in the file name, a clip close enough to midnight
+        // may be filed under the day after it started
+        let earliest_start = naive.and_then(|naive| {
+            let seconds_left = 24 * 60 * 60 - i64::from(naive.num_seconds_from_midnight());
+            let maybe_late = self.day_assignment != DayAssignment::Start
+                && !captures.contains_key(&(Field::Day, false))
+                && seconds_left <= MAX_EVENT_DURATION_SECS;
+            if maybe_late {
+                to_utc(naive.checked_sub_days(Days::new(1))?)
+            } else {
+                to_utc(naive)
+            }
+        });
+
+        Some(ParsedPath {
+            start_time,
+            earliest_start,
+            device_id: capture(Field::DeviceId).cloned(),
+            device_name: capture(Field::DeviceName).cloned(),
+        })
+    }
+}
+
+/// The path with the camera appended to the file name, `<name>-<device>.mp4`,
+/// for a clip whose path another camera's clip already has. The camera is
+/// named by its ID when it has no name.
+pub fn device_suffixed(path: &Path, device_id: &str, device_name: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(
+        "{stem}-{}.{extension}",
+        device_suffix(device_id, device_name)
+    ))
+}
+
+/// Undoes [`device_suffixed`] on a path relative to the output directory, or
+/// `None` when the file name doesn't end in the camera's suffix.
+pub fn strip_device_suffix(relative: &str, device_id: &str, device_name: &str) -> Option<String> {
+    let base = relative.strip_suffix(".mp4")?;
+    let base = base.strip_suffix(&device_suffix(device_id, device_name))?;
+    Some(format!("{}.mp4", base.strip_suffix('-')?))
+}
+
+fn device_suffix(device_id: &str, device_name: &str) -> String {
+    sanitize_component(if device_name.is_empty() {
+        device_id
+    } else {
+        device_name
+    })
+}
+
+/// Reads a `{offset}` value, `+HHMM` or `-HHMM`.
+fn parse_offset(value: &str) -> Option<FixedOffset> {
+    let sign = match value.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let digits = &value[1..];
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Keeps device names and tags from introducing extra path components.
+fn sanitize_component(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Backtracking matcher; a field that appears twice in directory names, or
+/// twice in the file name, must match the same text both times.
+fn match_parts(
+    parts: &[Part],
+    input: &str,
+    captures: HashMap<(Field, bool), String>,
+) -> Option<HashMap<(Field, bool), String>> {
+    let Some((part, rest)) = parts.split_first() else {
+        return input.is_empty().then_some(captures);
+    };
+
+    match part {
+        Part::Literal(literal) => {
+            match_parts(rest, input.strip_prefix(literal.as_str())?, captures)
+        }
+        Part::Field(field, in_dir) => {
+            let key = (*field, *in_dir);
+            let candidates: Vec<usize> = match field.width() {
+                Some(width) => vec![width],
+                None => {
+                    let component_end = input.find('/').unwrap_or(input.len());
+                    (1..=component_end)
+                        .rev()
+                        .filter(|&end| input.is_char_boundary(end))
+                        .collect()
+                }
+            };
+
+            candidates.into_iter().find_map(|end| {
+                let (value, tail) = input.split_at_checked(end)?;
+                if !field.accepts(value) {
+                    return None;
+                }
+                if captures.get(&key).is_some_and(|prev| prev != value) {
+                    return None;
+                }
+
+                let mut captures = captures.clone();
+                captures.insert(key, value.to_string());
+                match_parts(rest, tail, captures)
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::{America::Santiago, Asia::Kolkata, Tz};
+
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    /// The UTC time of a local time in `tz`.
+    fn local(tz: &Tz, date: NaiveDate, hour: u32, minute: u32) -> DateTime<Utc> {
+        tz.from_local_datetime(&date.and_hms_opt(hour, minute, 0).unwrap())
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn folder_dates(tz: &Tz, start: DateTime<Utc>, end: DateTime<Utc>) -> [NaiveDate; 3] {
+        [
+            DayAssignment::Start,
+            DayAssignment::End,
+            DayAssignment::Majority,
+        ]
+        .map(|assignment| assignment.folder_date_in(tz, start, end))
+    }
+
+    #[test]
+    fn event_within_a_day_stays_there() {
+        for tz in [Vancouver, Kolkata] {
+            let day = date(2025, 3, 4);
+            let start = local(&tz, day, 23, 0);
+            let end = local(&tz, day, 23, 59);
+            assert_eq!(folder_dates(&tz, start, end), [day; 3], "{tz}");
+        }
+    }
+
+    #[test]
+    fn event_ending_at_midnight_belongs_to_the_day_before() {
+        for tz in [Vancouver, Kolkata] {
+            let day = date(2025, 3, 4);
+            let start = local(&tz, day, 23, 58);
+            let end = local(&tz, date(2025, 3, 5), 0, 0);
+            assert_eq!(folder_dates(&tz, start, end), [day; 3], "{tz}");
+        }
+    }
+
+    #[test]
+    fn event_spanning_midnight_follows_the_assignment() {
+        for tz in [Vancouver, Kolkata] {
+            let (day, next) = (date(2025, 3, 4), date(2025, 3, 5));
+
+            // One minute before midnight, two after
+            let start = local(&tz, day, 23, 59);
+            let end = local(&tz, next, 0, 2);
+            assert_eq!(folder_dates(&tz, start, end), [day, next, next], "{tz}");
+
+            // An even split goes to the start day
+            let end = local(&tz, next, 0, 1);
+            assert_eq!(folder_dates(&tz, start, end), [day, next, day], "{tz}");
+        }
+    }
+
+    #[test]
+    fn majority_uses_the_real_length_across_a_dst_change() {
+        // Vancouver springs forward at 02:00 on 2025-03-09, so 21:30 to 03:00
+        // is three wall-clock hours after midnight but only two real ones,
+        // against two and a half before
+        let (day, next) = (date(2025, 3, 8), date(2025, 3, 9));
+        let start = local(&Vancouver, day, 21, 30);
+        let end = local(&Vancouver, next, 3, 0);
+        assert_eq!(
+            DayAssignment::Majority.folder_date_in(&Vancouver, start, end),
+            day
+        );
+    }
+
+    #[test]
+    fn majority_falls_back_to_start_day_when_midnight_is_skipped() {
+        // Santiago's clocks jumped from 00:00 to 01:00 on 2024-09-08
+        let (day, next) = (date(2024, 9, 7), date(2024, 9, 8));
+        let start = local(&Santiago, day, 23, 50);
+        let end = local(&Santiago, next, 2, 0);
+        assert_eq!(
+            [
+                DayAssignment::Start,
+                DayAssignment::End,
+                DayAssignment::Majority
+            ]
+            .map(|assignment| assignment.folder_date_in(&Santiago, start, end)),
+            [day, next, day]
+        );
+    }
+}
@@ -0,0 +1,185 @@
+//! Where logs go: the console and, where built in, syslog or the systemd
+//! journal.
+
+#[cfg(feature = "syslog")]
+mod syslog;
+
+use std::sync::{
+    OnceLock,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use tracing::{info, warn};
+use tracing_subscriber::{
+    EnvFilter, Registry, fmt, fmt::writer::BoxMakeWriter, layer::SubscriberExt, reload,
+    util::SubscriberInitExt,
+};
+
+#[cfg(feature = "syslog")]
+pub use self::syslog::{DEFAULT_SYSLOG_ADDR, SyslogAddr};
+use crate::{Args, version};
+
+/// The filters SIGUSR2 steps through after the startup one, before wrapping
+/// back to it.
+const FILTER_PRESETS: &[&str] = &["info,nest_sync=debug", "info,nest_sync=trace"];
+
+/// Swaps the log filter of the installed subscriber.
+static FILTER: OnceLock<FilterControl> = OnceLock::new();
+
+struct FilterControl {
+    handle: reload::Handle<EnvFilter, Registry>,
+    /// `RUST_LOG`, or `info` when it's unset or invalid.
+    initial: String,
+    /// Where SIGUSR2 is in the cycle; 0 is the startup filter.
+    preset: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogTarget {
+    /// Standard output for the daemon, standard error for subcommands
+    #[value(alias = "stderr")]
+    Console,
+    /// RFC 5424 messages to --syslog-addr
+    #[cfg(feature = "syslog")]
+    Syslog,
+    /// The systemd journal's native protocol, with log fields as journal fields
+    #[cfg(feature = "journald")]
+    Journald,
+}
+
+/// Installs the global tracing subscriber for `--log-target`, with `console`
+/// as the console. A target that can't be reached is replaced by the console
+/// and warned about. `args` is `None` when the flags couldn't be read, which
+/// logs to the console only.
+pub fn init(args: Option<&Args>, console: BoxMakeWriter, ansi: bool) {
+    let targets = args.map_or(&[LogTarget::Console][..], |args| &args.log_target);
+    #[cfg_attr(not(any(feature = "syslog", feature = "journald")), allow(unused_mut))]
+    let mut unavailable: Vec<String> = Vec::new();
+
+    // Syslog stamps its own time and severity
+    #[cfg(feature = "syslog")]
+    let syslog = args
+        .filter(|args| args.log_target.contains(&LogTarget::Syslog))
+        .and_then(|args| match syslog::Syslog::connect(&args.syslog_addr) {
+            Ok(syslog) => Some(
+                fmt::layer()
+                    .without_time()
+                    .with_level(false)
+                    .with_ansi(false)
+                    .with_writer(syslog),
+            ),
+            Err(e) => {
+                unavailable.push(format!("syslog at {}: {e}", args.syslog_addr));
+                None
+            }
+        });
+
+    #[cfg(feature = "journald")]
+    let journald = if targets.contains(&LogTarget::Journald) {
+        match tracing_journald::layer() {
+            Ok(layer) => Some(
+                layer
+                    .with_field_prefix(None)
+                    .with_syslog_identifier(env!("CARGO_PKG_NAME").to_string()),
+            ),
+            Err(e) => {
+                unavailable.push(format!("journald: {e}"));
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let console = (targets.contains(&LogTarget::Console) || !unavailable.is_empty())
+        .then(|| fmt::layer().with_ansi(ansi).with_writer(console));
+
+    let (filter, initial) = match EnvFilter::try_from_default_env() {
+        Ok(filter) => (
+            filter,
+            std::env::var(EnvFilter::DEFAULT_ENV).unwrap_or_default(),
+        ),
+        Err(_) => (EnvFilter::new("info"), "info".to_string()),
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+    let _ = FILTER.set(FilterControl {
+        handle,
+        initial,
+        preset: AtomicUsize::new(0),
+    });
+
+    let subscriber = tracing_subscriber::registry().with(filter).with(console);
+    #[cfg(feature = "syslog")]
+    let subscriber = subscriber.with(syslog);
+    #[cfg(feature = "journald")]
+    let subscriber = subscriber.with(journald);
+    // Error-level events become Sentry issues; lower levels ride along as
+    // breadcrumbs
+    #[cfg(feature = "sentry")]
+    let subscriber = subscriber.with(
+        args.is_some_and(|args| args.sentry_dsn.is_some())
+            .then(sentry::integrations::tracing::layer),
+    );
+    subscriber.init();
+
+    for target in unavailable {
+        warn!(%target, "Log target unavailable; logging to the console instead");
+    }
+
+    let build = version::NEST_SYNC_VERSION;
+    info!(
+        git_commit = build.git_commit,
+        build_date = build.build_date,
+        rust_version = build.rust_version,
+        "Application: {}, Version: {}",
+        env!("CARGO_PKG_NAME"),
+        build.version
+    );
+}
+
+/// The log filter in effect, in `RUST_LOG` syntax.
+pub fn current_filter() -> Option<String> {
+    FILTER
+        .get()?
+        .handle
+        .with_current(|filter| filter.to_string())
+        .ok()
+}
+
+/// Steps to the next filter preset on each SIGUSR2, so debug logging can be
+/// turned on and off without restarting the daemon.
+#[cfg(unix)]
+pub async fn cycle_filter_on_sigusr2() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut sigusr2 = match signal(SignalKind::user_defined2()) {
+        Ok(sigusr2) => sigusr2,
+        Err(e) => {
+            warn!(error = %e, "Failed to listen for SIGUSR2; the log filter can't be changed at runtime");
+            return;
+        }
+    };
+    while sigusr2.recv().await.is_some() {
+        cycle_filter();
+    }
+}
+
+#[cfg(unix)]
+fn cycle_filter() {
+    let Some(control) = FILTER.get() else {
+        return;
+    };
+    let preset = (control.preset.load(Ordering::Relaxed) + 1) % (FILTER_PRESETS.len() + 1);
+    let directives = match preset {
+        0 => control.initial.as_str(),
+        n => FILTER_PRESETS[n - 1],
+    };
+    let filter = EnvFilter::try_new(directives).unwrap_or_else(|_| EnvFilter::new("info"));
+    match control.handle.reload(filter) {
+        Ok(()) => {
+            control.preset.store(preset, Ordering::Relaxed);
+            info!(log_filter = directives, "Changed log filter");
+        }
+        Err(e) => warn!(error = %e, "Failed to change log filter"),
+    }
+}
@@ -0,0 +1,421 @@
+//! RFC 5424 syslog output. The fmt layer renders each event into a
+//! [`Message`], which goes out as one datagram or TCP frame when it's dropped,
+//! with a severity taken from the event's level.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    str::FromStr,
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::{SecondsFormat, Utc};
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// The `daemon` facility.
+const FACILITY: u8 = 3;
+const APP_NAME: &str = env!("CARGO_PKG_NAME");
+/// How long connecting to a TCP syslog server may take.
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long one TCP frame may take to write before the connection is dropped.
+const TCP_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Pause after a failed reconnect, doubling up to `TCP_MAX_RECONNECT_BACKOFF`.
+const TCP_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const TCP_MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+/// TCP frames queued for the writer thread; more are dropped.
+const TCP_QUEUE_LEN: usize = 1024;
+
+/// Where `--syslog-addr` points by default.
+#[cfg(unix)]
+pub const DEFAULT_SYSLOG_ADDR: &str = "/dev/log";
+#[cfg(not(unix))]
+pub const DEFAULT_SYSLOG_ADDR: &str = "udp://127.0.0.1:514";
+
+/// Where syslog messages go: a local socket path, `udp://HOST:PORT` or
+/// `tcp://HOST:PORT`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogAddr {
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+    Udp(String),
+    Tcp(String),
+}
+
+impl fmt::Display for SyslogAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "{}", path.display()),
+            Self::Udp(addr) => write!(f, "udp://{addr}"),
+            Self::Tcp(addr) => write!(f, "tcp://{addr}"),
+        }
+    }
+}
+
+impl FromStr for SyslogAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("udp://") {
+            return Ok(Self::Udp(addr.to_string()));
+        }
+        if let Some(addr) = s.strip_prefix("tcp://") {
+            return Ok(Self::Tcp(addr.to_string()));
+        }
+        #[cfg(unix)]
+        if let Some(path) = s
+            .strip_prefix("unix://")
+            .or(s.starts_with('/').then_some(s))
+        {
+            return Ok(Self::Unix(path.into()));
+        }
+        Err(format!(
+            "expected a socket path, udp://HOST:PORT or tcp://HOST:PORT, got '{s}'"
+        ))
+    }
+}
+
+enum Socket {
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixDatagram),
+    Udp(UdpSocket),
+    /// Frames are length-prefixed (RFC 6587 octet counting) and written by a
+    /// background thread, so a slow or unreachable server never holds up
+    /// logging. See [`write_tcp_frames`].
+    Tcp(SyncSender<String>),
+}
+
+/// Sends formatted events to a syslog server.
+pub struct Syslog {
+    socket: Socket,
+    hostname: String,
+    pid: u32,
+}
+
+impl Syslog {
+    /// Connects to `addr`, failing when nothing is listening there.
+    pub fn connect(addr: &SyslogAddr) -> io::Result<Self> {
+        let socket = match addr {
+            #[cfg(unix)]
+            SyslogAddr::Unix(path) => {
+                let socket = std::os::unix::net::UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Socket::Unix(socket)
+            }
+            SyslogAddr::Udp(addr) => {
+                let target = addr.to_socket_addrs()?.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("no addresses for {addr}"))
+                })?;
+                let local = match target {
+                    SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                    SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+                };
+                let socket = UdpSocket::bind(local)?;
+                socket.connect(target)?;
+                Socket::Udp(socket)
+            }
+            SyslogAddr::Tcp(addr) => {
+                let stream = connect_tcp(addr)?;
+                let (frames, queue) = mpsc::sync_channel(TCP_QUEUE_LEN);
+                let addr = addr.clone();
+                thread::Builder::new()
+                    .name("syslog-tcp".to_string())
+                    .spawn(move || write_tcp_frames(&addr, stream, &queue))?;
+                Socket::Tcp(frames)
+            }
+        };
+        Ok(Self {
+            socket,
+            hostname: hostname(),
+            pid: std::process::id(),
+        })
+    }
+
+    fn make_writer_for_level(&self, level: Level) -> Message<'_> {
+        Message {
+            syslog: self,
+            severity: severity(level),
+            buf: Vec::new(),
+        }
+    }
+
+    fn send(&self, severity: u8, text: &[u8]) {
+        let text = String::from_utf8_lossy(text);
+        let message = format!(
+            "<{}>1 {} {} {APP_NAME} {} - - {}",
+            FACILITY * 8 + severity,
+            Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.hostname,
+            self.pid,
+            text.trim_end()
+        );
+        // Nowhere to report a failure from inside the logger, so a message
+        // that can't be sent is dropped
+        match &self.socket {
+            #[cfg(unix)]
+            Socket::Unix(socket) => {
+                let _ = socket.send(message.as_bytes());
+            }
+            Socket::Udp(socket) => {
+                let _ = socket.send(message.as_bytes());
+            }
+            Socket::Tcp(frames) => {
+                let _ = frames.try_send(format!("{} {message}", message.len()));
+            }
+        }
+    }
+}
+
+/// Connects to a TCP syslog server, giving each of its addresses
+/// `TCP_CONNECT_TIMEOUT`.
+fn connect_tcp(addr: &str) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for target in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&target, TCP_CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_write_timeout(Some(TCP_WRITE_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("no addresses for {addr}"))
+    }))
+}
+
+/// Writes queued frames to the server until the [`Syslog`] is dropped. A
+/// broken connection is made again for the next frame; while that fails,
+/// reconnects back off and frames are dropped.
+fn write_tcp_frames(addr: &str, stream: TcpStream, queue: &Receiver<String>) {
+    let mut stream = Some(stream);
+    let mut backoff = TCP_RECONNECT_BACKOFF;
+    let mut retry_at = Instant::now();
+    for frame in queue {
+        if stream.is_none() && Instant::now() >= retry_at {
+            match connect_tcp(addr) {
+                Ok(connected) => {
+                    stream = Some(connected);
+                    backoff = TCP_RECONNECT_BACKOFF;
+                }
+                Err(_) => {
+                    retry_at = Instant::now() + backoff;
+                    backoff = (backoff * 2).min(TCP_MAX_RECONNECT_BACKOFF);
+                }
+            }
+        }
+        if let Some(Err(_)) = stream.as_mut().map(|s| s.write_all(frame.as_bytes())) {
+            stream = None;
+        }
+    }
+}
+
+/// The RFC 5424 severity for a tracing level.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// The host name, or the nil value `-` when it can't be read.
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty() && !name.contains(' '))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// One event being formatted; sent when dropped.
+pub struct Message<'a> {
+    syslog: &'a Syslog,
+    severity: u8,
+    buf: Vec<u8>,
+}
+
+impl Write for Message<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Message<'_> {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() {
+            self.syslog.send(self.severity, &self.buf);
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = Message<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.make_writer_for_level(Level::INFO)
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.make_writer_for_level(*meta.level())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, net::TcpListener};
+
+    use chrono::DateTime;
+
+    use super::*;
+
+    /// A syslog server on a local UDP port and a [`Syslog`] sending to it.
+    fn udp_syslog() -> (UdpSocket, Syslog) {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let addr = SyslogAddr::Udp(server.local_addr().unwrap().to_string());
+        (server, Syslog::connect(&addr).unwrap())
+    }
+
+    /// Logs `text` at `level` and returns the datagram the server received.
+    fn send(server: &UdpSocket, syslog: &Syslog, level: Level, text: &str) -> String {
+        let mut message = syslog.make_writer_for_level(level);
+        message.write_all(text.as_bytes()).unwrap();
+        drop(message);
+        let mut buf = [0; 4096];
+        let n = server.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..n].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn priority_combines_daemon_facility_with_level_severity() {
+        let (server, syslog) = udp_syslog();
+        for (level, pri) in [
+            (Level::ERROR, 27),
+            (Level::WARN, 28),
+            (Level::INFO, 30),
+            (Level::DEBUG, 31),
+            (Level::TRACE, 31),
+        ] {
+            let message = send(&server, &syslog, level, "event");
+            assert!(message.starts_with(&format!("<{pri}>1 ")), "{message}");
+        }
+    }
+
+    #[test]
+    fn header_has_every_rfc5424_field() {
+        let (server, syslog) = udp_syslog();
+
+        let message = send(&server, &syslog, Level::INFO, "Checking for new events\n");
+
+        let fields: Vec<&str> = message.splitn(8, ' ').collect();
+        assert_eq!(fields[0], "<30>1");
+        let timestamp = DateTime::parse_from_rfc3339(fields[1]).unwrap();
+        assert!(fields[1].ends_with('Z'));
+        assert!((Utc::now() - timestamp.with_timezone(&Utc)).num_seconds() < 60);
+        assert_eq!(fields[2], syslog.hostname);
+        assert_eq!(fields[3], "nest-sync");
+        assert_eq!(fields[4], std::process::id().to_string());
+        // No MSGID, and the trailing newline is dropped
+        assert_eq!(fields[5..], ["-", "-", "Checking for new events"]);
+    }
+
+    #[test]
+    fn message_is_never_read_as_structured_data() {
+        let (server, syslog) = udp_syslog();
+        let text = r#"[meta key="va\"l]ue"] device_name="Front \ Door""#;
+
+        let message = send(&server, &syslog, Level::WARN, text);
+
+        // Structured data is always the nil value, so whatever follows it is
+        // the message, passed through as is
+        let fields: Vec<&str> = message.splitn(8, ' ').collect();
+        assert_eq!(fields[6], "-");
+        assert_eq!(fields[7], text);
+    }
+
+    #[test]
+    fn tcp_frames_are_octet_counted() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = SyslogAddr::Tcp(listener.local_addr().unwrap().to_string());
+        let syslog = Syslog::connect(&addr).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+
+        let mut message = syslog.make_writer_for_level(Level::ERROR);
+        message
+            .write_all("Download error ünïcode".as_bytes())
+            .unwrap();
+        drop(message);
+        drop(syslog);
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+
+        let (length, frame) = received.split_once(' ').unwrap();
+        assert_eq!(length.parse::<usize>().unwrap(), frame.len());
+        assert!(frame.starts_with("<27>1 "));
+        assert!(frame.ends_with(" - - Download error ünïcode"));
+    }
+
+    #[test]
+    fn tcp_reconnects_once_the_server_is_back() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let local = listener.local_addr().unwrap();
+        let syslog = Syslog::connect(&SyslogAddr::Tcp(local.to_string())).unwrap();
+        drop(listener.accept().unwrap());
+        drop(listener);
+
+        // Sent while the server is down, which mustn't block the caller
+        let started = Instant::now();
+        for _ in 0..10 {
+            syslog.send(6, b"lost");
+        }
+        assert!(started.elapsed() < TCP_CONNECT_TIMEOUT);
+
+        let listener = TcpListener::bind(local).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let deadline = Instant::now() + TCP_RECONNECT_BACKOFF * 10;
+        let mut stream = loop {
+            syslog.send(6, b"back");
+            if let Ok((stream, _)) = listener.accept() {
+                break stream;
+            }
+            assert!(Instant::now() < deadline, "never reconnected");
+            thread::sleep(Duration::from_millis(100));
+        };
+        drop(syslog);
+        stream.set_nonblocking(false).unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+
+        assert!(received.contains(" - - back"), "{received}");
+    }
+
+    #[test]
+    fn parses_addresses() {
+        assert_eq!(
+            "udp://127.0.0.1:514".parse(),
+            Ok(SyslogAddr::Udp("127.0.0.1:514".to_string()))
+        );
+        assert_eq!(
+            "tcp://logs:601".parse(),
+            Ok(SyslogAddr::Tcp("logs:601".to_string()))
+        );
+        #[cfg(unix)]
+        assert_eq!("/dev/log".parse(), Ok(SyslogAddr::Unix("/dev/log".into())));
+        assert!("logs:514".parse::<SyslogAddr>().is_err());
+    }
+}
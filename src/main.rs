@@ -1,60 +1,307 @@
+mod archive;
+mod compact;
+mod completions;
+mod cursor;
+mod dedup;
+mod discover;
+mod du;
+mod exec_hook;
+mod export;
+mod failures;
 mod google_auth;
+mod heartbeat;
+mod import;
+mod layout;
+mod logging;
+mod metrics;
+mod migrate;
 mod models;
+mod mp4;
+mod mqtt;
 mod nest_api;
+mod nfo;
+mod playlist;
+mod profile;
+mod prune;
+mod retention;
+#[cfg(windows)]
+mod service;
+mod silence;
+mod state;
+mod stats;
+mod storage;
+mod tags;
+mod thinning;
+mod validate;
+mod verify;
+mod version;
+mod xattrs;
 
 use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Write as _,
     fs,
     io::Write,
+    net::SocketAddr,
     path::{Path, PathBuf},
-    sync::Arc,
-    time::{Duration, SystemTime},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
 };
 
 use anyhow::{Context, Result};
-use chrono::{DateTime, Utc};
+use archive::VideoMetadata;
+use chrono::{DateTime, NaiveDate, SubsecRound, Utc};
 use chrono_tz::America::Vancouver;
-use clap::Parser;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueHint};
+use cursor::CycleCursor;
 use filetime::FileTime;
-use google_auth::GoogleConnection;
-use nest_api::NestDevice;
-use tokio::{sync::Semaphore, task::JoinSet, time};
-use tracing::{debug, error, info};
+use google_auth::{
+    ApiError, DEFAULT_EVENT_QUERY_TYPES, DEFAULT_FOYER_ENDPOINT, DEFAULT_NEST_API_NAMESPACE,
+    DiscoveredDevice, GoogleConnection, IpVersion, ResolveOverride, api_error, is_dns_failure,
+    parse_foyer_endpoint,
+};
+use heartbeat::{CycleSummary, Heartbeat, HeartbeatMethod};
+use layout::{DEFAULT_PATH_TEMPLATE, DayAssignment, DeviceCollisionPolicy, PathTemplate};
+use logging::LogTarget;
+#[cfg(feature = "syslog")]
+use logging::{DEFAULT_SYSLOG_ADDR, SyslogAddr};
+use metrics::{DownloadQuota, EmergencyPrune, METRICS};
+use models::{CameraEvent, EventTypeFilter};
+use mqtt::MqttPublisher;
+use nest_api::{DeviceQuality, NestDevice};
+use prune::{EmergencyThresholds, PrunePolicy};
+use reqwest::Url;
+use retention::FreeSpaceTier;
+use sha2::{Digest, Sha256};
+use silence::{DeviceSilenceThreshold, SilenceMonitor};
+use state::{FailureBackoff, SharedStateStore, StateStore};
+use storage::{StorageMonitor, is_storage_error};
+use tags::TagRules;
+use thinning::ThinTier;
+use tokio::{
+    sync::{Mutex, Semaphore},
+    task::{self, JoinHandle, JoinSet},
+    time::{self, Instant},
+};
+use tonic::transport::Uri;
+use tracing::{Instrument, debug, error, info, info_span, trace, warn};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use uuid::Uuid;
 
 const EVENT_HISTORY_DURATION_MINUTES: i64 = 12 * 60;
+/// Pause before retrying a device whose event query failed DNS resolution.
+const DNS_FAILURE_BACKOFF: Duration = Duration::from_secs(30);
+/// How often the daemon checks free space against `--emergency-free-gb`.
+const EMERGENCY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// How often `--auto-compact-state` compacts the state file.
+const STATE_COMPACTION_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How often `--quiet-empty` summarizes the zero-event checks it suppressed.
+const QUIET_SUMMARY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[cfg(all(feature = "rustls", feature = "native-tls"))]
+compile_error!("enable only one TLS backend: `rustls` or `native-tls`");
+#[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+compile_error!("enable a TLS backend: `rustls` or `native-tls`");
 
 struct AppState {
     google_connection: GoogleConnection,
-    nest_camera_devices: Vec<(String, String)>,
-    google_master_token: String,
-    google_username: String,
+    nest_camera_devices: Vec<DiscoveredDevice>,
     output_path: PathBuf,
+    mqtt_publisher: Option<MqttPublisher>,
+    heartbeat: Option<Heartbeat>,
+    storage: StorageMonitor,
+    state: SharedStateStore,
+    idle_log: IdleLog,
+    silence: SilenceMonitor,
+    tag_rules: TagRules,
+    devices_refreshed_at: Instant,
+    /// Rediscover devices before the next check, e.g. after a manifest
+    /// request failed because a camera may have been removed.
+    refresh_devices: bool,
+    /// Devices that have disappeared from the account since startup.
+    removed_device_ids: HashSet<String>,
+    /// The local day `--max-downloads-per-day` was last reported reached.
+    quota_reached_on: Option<NaiveDate>,
+    /// Existing clips `--refresh-incomplete` found whole.
+    verified_clips: mp4::VerifiedClips,
 }
 
-async fn initialize(args: &Args) -> Option<AppState> {
-    let google_master_token = match std::env::var("GOOGLE_MASTER_TOKEN") {
-        Ok(token) => token,
-        Err(e) => {
-            error!(error = %e, "GOOGLE_MASTER_TOKEN environment variable not set");
-            return None;
+impl AppState {
+    fn devices_due_for_refresh(&self, args: &Args) -> bool {
+        self.refresh_devices
+            || (args.device_refresh_interval > 0
+                && self.devices_refreshed_at.elapsed()
+                    >= Duration::from_secs(args.device_refresh_interval * 60))
+    }
+
+    /// Rediscovers the account's cameras from a freshly fetched home graph.
+    /// Devices that vanish stop being polled, but their clips are kept so
+    /// retention still applies to their footage. A re-added camera's check
+    /// position is reset, so its tracking starts afresh.
+    async fn refresh_devices(&mut self) {
+        self.refresh_devices = false;
+        self.devices_refreshed_at = Instant::now();
+        let devices = match self.google_connection.refresh_nest_camera_devices().await {
+            Ok(devices) => devices,
+            Err(e) => {
+                warn!(error = %format!("{e:#}"), "Failed to refresh camera devices; keeping the current list");
+                return;
+            }
+        };
+
+        for device in &self.nest_camera_devices {
+            if devices.iter().any(|d| d.device_id == device.device_id) {
+                continue;
+            }
+            warn!(
+                device_name = %device.device_name,
+                device_id = %device.device_id,
+                "Camera is no longer in the account; stopped polling it, archived clips are kept"
+            );
+            METRICS.set_device_removed(&device.device_id, &device.device_name, true);
+            self.silence.forget(&device.device_id);
+            self.removed_device_ids.insert(device.device_id.clone());
+        }
+
+        for device in &devices {
+            if self
+                .nest_camera_devices
+                .iter()
+                .any(|d| d.device_id == device.device_id)
+            {
+                continue;
+            }
+            if self.removed_device_ids.remove(&device.device_id) {
+                info!(device_name = %device.device_name, "Camera is back in the account; resuming polling");
+                METRICS.set_device_removed(&device.device_id, &device.device_name, false);
+                self.state.lock().reset_device(&device.device_id);
+            } else {
+                info!(device_name = %device.device_name, "Discovered new camera");
+            }
+        }
+
+        self.nest_camera_devices = devices;
+    }
+}
+
+/// Tracks zero-event checks for `--quiet-empty`.
+struct IdleLog {
+    /// Whether each device's previous check returned any events.
+    had_events: HashMap<String, bool>,
+    suppressed: usize,
+    last_summary: Instant,
+}
+
+impl IdleLog {
+    fn new() -> Self {
+        Self {
+            had_events: HashMap::new(),
+            suppressed: 0,
+            last_summary: Instant::now(),
+        }
+    }
+
+    fn log_events_received(&mut self, device: &DiscoveredDevice, count: usize, quiet: bool) {
+        let device_name = &device.device_name;
+        let had_events = self.had_events.insert(device.device_id.clone(), count > 0);
+
+        if count > 0 || !quiet {
+            info!(count, %device_name, "Received camera events");
+        } else if had_events == Some(true) {
+            info!(%device_name, "No new camera events; further empty checks are summarized");
+        } else {
+            trace!(%device_name, "Received no camera events");
+            self.suppressed += 1;
+        }
+    }
+
+    fn maybe_log_summary(&mut self) {
+        if self.last_summary.elapsed() < QUIET_SUMMARY_INTERVAL {
+            return;
         }
+
+        let idle_devices = self.had_events.values().filter(|had| !**had).count();
+        info!(
+            suppressed_checks = self.suppressed,
+            idle_devices, "Empty event checks since last summary"
+        );
+        self.suppressed = 0;
+        self.last_summary = Instant::now();
+    }
+}
+
+fn resolve_output_path(args: &Args) -> PathBuf {
+    PathBuf::from(shellexpand::tilde(&args.output.to_string_lossy()).to_string())
+}
+
+/// Sets up the Google connection from the environment's credentials and the
+/// connection flags.
+fn connect_google(args: &Args) -> Result<GoogleConnection> {
+    let google_master_token = std::env::var("GOOGLE_MASTER_TOKEN")
+        .context("GOOGLE_MASTER_TOKEN environment variable not set")?;
+    let google_username =
+        std::env::var("GOOGLE_USERNAME").context("GOOGLE_USERNAME environment variable not set")?;
+
+    let builder = GoogleConnection::builder(google_master_token, google_username)
+        .oauth_accept_encoding(&args.oauth_accept_encoding)
+        .play_services_version(&args.play_services_version)
+        .foyer_endpoint(args.foyer_endpoint.clone())
+        .nest_api_namespace(&args.nest_api_namespace)
+        .event_query_types(&args.event_query_types)
+        .http_pool(
+            Duration::from_secs(args.http_pool_idle_timeout_secs),
+            args.http_pool_max_idle_per_host,
+        )
+        .token_expiry_grace(Duration::from_secs(args.token_expiry_grace_secs))
+        .ip_version(args.ip_version)
+        .resolve_overrides(args.resolve.clone());
+    let builder = match &args.token_cache_dir {
+        Some(dir) => builder.token_cache_dir(dir),
+        None => builder,
     };
-    let google_username = match std::env::var("GOOGLE_USERNAME") {
-        Ok(username) => username,
+    let builder = match &args.android_id {
+        Some(android_id) => builder.android_id(android_id),
+        None => builder,
+    };
+    #[cfg(feature = "insecure-tls")]
+    let builder = builder.danger_insecure_tls(args.danger_insecure_tls);
+    #[cfg(feature = "hickory-dns")]
+    let builder = builder.dns_server(args.dns_server.clone());
+    let builder = match &args.ca_cert {
+        Some(ca_cert) => builder.ca_cert_file(ca_cert)?,
+        None => builder,
+    };
+    builder.build()
+}
+
+async fn initialize(args: &Args, state: SharedStateStore) -> Option<AppState> {
+    let google_connection = match connect_google(args) {
+        Ok(connection) => connection,
         Err(e) => {
-            error!(error = %e, "GOOGLE_USERNAME environment variable not set");
+            error!(error = %format!("{e:#}"), "Failed to set up Google connection");
             return None;
         }
     };
 
-    let output_path = shellexpand::tilde(&args.output.to_string_lossy()).to_string();
-    let output_path = PathBuf::from(output_path);
+    let output_path = resolve_output_path(args);
     if let Err(e) = fs::create_dir_all(&output_path) {
         error!(error = %e, "Failed to create output directory");
         return None;
     }
 
-    let mut google_connection =
-        GoogleConnection::new(google_master_token.clone(), google_username.clone());
+    let tag_rules = match &args.tag_rules {
+        Some(path) => match TagRules::load(path) {
+            Ok(rules) => rules,
+            Err(e) => {
+                error!(error = %format!("{e:#}"), "Failed to load tag rules");
+                return None;
+            }
+        },
+        None => TagRules::default(),
+    };
 
     let nest_camera_devices = match google_connection.get_nest_camera_devices().await {
         Ok(devices) => {
@@ -68,318 +315,2810 @@ async fn initialize(args: &Args) -> Option<AppState> {
         }
     };
 
+    let mqtt_publisher = match args.mqtt_url.as_deref() {
+        Some(url) => match MqttPublisher::connect(url, args.mqtt_topic.clone()) {
+            Ok(publisher) => Some(publisher),
+            Err(e) => {
+                error!(error = %e, "Failed to set up MQTT publisher");
+                return None;
+            }
+        },
+        None => None,
+    };
+
+    let heartbeat = match &args.heartbeat_url {
+        Some(url) => {
+            match Heartbeat::new(url.clone(), args.heartbeat_method, args.heartbeat_summary) {
+                Ok(heartbeat) => Some(heartbeat),
+                Err(e) => {
+                    error!(error = %format!("{e:#}"), "Failed to set up heartbeat");
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
     Some(AppState {
         google_connection,
         nest_camera_devices,
-        google_master_token,
-        google_username,
+        storage: StorageMonitor::new(&output_path),
         output_path,
+        mqtt_publisher,
+        heartbeat,
+        state,
+        idle_log: IdleLog::new(),
+        silence: SilenceMonitor::new(
+            args.silence_threshold,
+            args.device_silence_threshold.clone(),
+        ),
+        tag_rules,
+        devices_refreshed_at: Instant::now(),
+        refresh_devices: false,
+        removed_device_ids: HashSet::new(),
+        quota_reached_on: None,
+        verified_clips: mp4::VerifiedClips::default(),
     })
 }
 
-async fn prune_old_videos(
-    output_path: &Path,
-    retention_period: u64,
-    use_hours: bool,
-) -> Result<()> {
-    if retention_period == 0 {
-        // No pruning
-        return Ok(());
+fn failure_backoff(args: &Args) -> FailureBackoff {
+    FailureBackoff {
+        base: chrono::Duration::minutes(args.retry_backoff_minutes),
+        max: chrono::Duration::hours(24),
+        max_failures: args.max_download_failures,
     }
+}
 
-    let unit = if use_hours { "hours" } else { "days" };
-    info!(
-        retention_period,
-        unit, "Pruning videos older than specified period"
-    );
+/// Where a clip for the event was saved under an earlier layout of the path
+/// template, e.g. before `{offset}` was added or with another
+/// `--day-assignment`, if it's there: a path one of `layouts` renders, or that
+/// path with the camera appended. A clip counts when its sidecar names the
+/// event, or when it has no sidecar.
+fn archived_under_earlier_layout(
+    layouts: &[PathTemplate],
+    output_path: &Path,
+    event: &CameraEvent,
+    device_name: &str,
+    tags: &[String],
+    filepath: &Path,
+) -> Option<PathBuf> {
+    layouts
+        .iter()
+        .map(|layout| {
+            output_path.join(layout.render(
+                event.start_time,
+                event.end_time(),
+                &event.device_id,
+                device_name,
+                tags,
+            ))
+        })
+        .flat_map(|path| {
+            let suffixed = layout::device_suffixed(&path, &event.device_id, device_name);
+            [path, suffixed]
+        })
+        .find(|path| {
+            path != filepath
+                && path.exists()
+                && archive::read_sidecar(path)
+                    .is_none_or(|sidecar| sidecar.event_id == event.event_id())
+        })
+}
 
-    let retention_seconds = if use_hours {
-        retention_period * 60 * 60
-    } else {
-        retention_period * 24 * 60 * 60
-    };
-    let cutoff_time = SystemTime::now() - Duration::from_secs(retention_seconds);
-    let mut deleted_count = 0;
-    let mut kept_count = 0;
+/// Whether a clip path belongs to another camera's clip, queued this cycle or
+/// already downloaded, when the path template doesn't name the camera.
+fn path_taken_by_other_device(
+    path: &Path,
+    device_id: &str,
+    claimed_paths: &HashMap<PathBuf, String>,
+) -> bool {
+    match claimed_paths.get(path) {
+        Some(owner) => owner != device_id,
+        None => archive::read_sidecar(path).is_some_and(|sidecar| sidecar.device_id != device_id),
+    }
+}
 
-    // Walk through all .mp4 files in the directory tree
-    for entry in walkdir::WalkDir::new(output_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.path().extension().and_then(|s| s.to_str()) == Some("mp4"))
+/// Where the event's clip goes: the rendered template, with the camera
+/// appended when another camera's clip already has that path.
+fn clip_path(
+    template: &PathTemplate,
+    output_path: &Path,
+    event: &CameraEvent,
+    device: &DiscoveredDevice,
+    tags: &[String],
+    claimed_paths: &HashMap<PathBuf, String>,
+) -> PathBuf {
+    let filepath = output_path.join(template.render(
+        event.start_time,
+        event.end_time(),
+        &device.device_id,
+        &device.device_name,
+        tags,
+    ));
+    if !template.separates_devices()
+        && path_taken_by_other_device(&filepath, &device.device_id, claimed_paths)
     {
-        let path = entry.path();
-
-        match fs::metadata(path) {
-            Ok(metadata) => {
-                if let Ok(modified) = metadata.modified() {
-                    if modified < cutoff_time {
-                        match fs::remove_file(path) {
-                            Ok(_) => {
-                                info!(path = %path.display(), "Deleted old video");
-                                deleted_count += 1;
-                            }
-                            Err(e) => {
-                                error!(path = %path.display(), error = %e, "Failed to delete video");
-                            }
-                        }
-                    } else {
-                        kept_count += 1;
-                    }
-                }
-            }
-            Err(e) => {
-                error!(path = %path.display(), error = %e, "Failed to get metadata");
-            }
-        }
+        return layout::device_suffixed(&filepath, &device.device_id, &device.device_name);
     }
+    filepath
+}
 
-    info!(deleted_count, kept_count, "Pruning complete");
+/// The order a check's downloads are started in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum DownloadOrder {
+    /// Event order across all cameras
+    Time,
+    /// One event from each camera in turn, each camera's in event order
+    RoundRobin,
+}
 
-    Ok(())
+/// Orders a check's downloads, newest events first if asked.
+fn order_jobs(
+    mut jobs: Vec<DownloadJob>,
+    order: DownloadOrder,
+    newest_first: bool,
+) -> Vec<DownloadJob> {
+    jobs.sort_by_key(|job| job.event.start_time);
+    if newest_first {
+        jobs.reverse();
+    }
+    if order == DownloadOrder::Time {
+        return jobs;
+    }
+
+    // Cameras take turns in the order of their first event
+    let mut queues: Vec<VecDeque<DownloadJob>> = Vec::new();
+    for job in jobs {
+        match queues
+            .iter_mut()
+            .find(|queue| queue[0].event.device_id == job.event.device_id)
+        {
+            Some(queue) => queue.push_back(job),
+            None => queues.push(VecDeque::from([job])),
+        }
+    }
+    let mut ordered = Vec::new();
+    while !queues.is_empty() {
+        ordered.extend(queues.iter_mut().filter_map(VecDeque::pop_front));
+        queues.retain(|queue| !queue.is_empty());
+    }
+    ordered
 }
 
-async fn check_and_download_events(
-    google_connection: &mut GoogleConnection,
-    nest_camera_devices: &[(String, String)],
-    output_path: &Path,
-    semaphore: &Arc<Semaphore>,
-    google_master_token: &str,
-    google_username: &str,
-    interval_minutes: u64,
-) -> Result<()> {
-    info!("Checking for new events");
-    let mut join_set = JoinSet::new();
-    let mut completed_count = 0;
-    let mut total_count = 0;
+/// Writes a downloaded clip to `path` with its modification time set to the
+/// event's start. The clip is written next to `path` and renamed into place,
+/// so a failed or interrupted download never leaves a truncated clip that the
+/// next check would skip as already downloaded.
+///
+/// Some filesystems (e.g. exFAT) reject setting the time intermittently; the
+/// clip itself is fine, so unless `strict` it is kept and the intended time
+/// returned for the sidecar instead.
+fn write_clip(
+    path: &Path,
+    video_data: &[u8],
+    start_time: DateTime<Utc>,
+    strict: bool,
+) -> Result<Option<DateTime<Utc>>> {
+    let tmp_path = path.with_extension("mp4.tmp");
+    let result = write_clip_file(&tmp_path, video_data, start_time, strict).and_then(|intended| {
+        fs::rename(&tmp_path, path).context("Failed to move clip into place")?;
+        Ok(intended)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
 
-    for (device_id, device_name) in nest_camera_devices {
-        let nest_device = NestDevice::new(device_id.clone(), device_name.clone());
+fn write_clip_file(
+    path: &Path,
+    video_data: &[u8],
+    start_time: DateTime<Utc>,
+    strict: bool,
+) -> Result<Option<DateTime<Utc>>> {
+    let mut file = fs::File::create(path).context("Failed to create file")?;
+    file.write_all(video_data)
+        .context("Failed to write video data")?;
+    drop(file);
+    set_clip_times(path, start_time, strict)
+}
 
-        let end_time: DateTime<Utc> = Utc::now();
-        let events = nest_device
-            .get_events(google_connection, end_time, EVENT_HISTORY_DURATION_MINUTES)
-            .await?;
-        info!(count = events.len(), device_name, "Received camera events");
+fn set_clip_times(
+    path: &Path,
+    start_time: DateTime<Utc>,
+    strict: bool,
+) -> Result<Option<DateTime<Utc>>> {
+    let filetime = FileTime::from_unix_time(start_time.timestamp(), 0);
+    match filetime::set_file_times(path, filetime, filetime) {
+        Ok(()) => Ok(None),
+        Err(e) if strict => Err(anyhow::Error::new(e).context("Failed to set file times")),
+        Err(e) => {
+            warn!(path = %path.display(), error = %e, "Failed to set file times; keeping clip");
+            Ok(Some(start_time))
+        }
+    }
+}
 
-        for event in events {
-            let event_local_time = event.start_time.with_timezone(&Vancouver);
+/// Everything a spawned task needs to download one event.
+struct DownloadJob {
+    nest_device: NestDevice,
+    connection: GoogleConnection,
+    event: CameraEvent,
+    /// The window to download, the event's padded by --clip-padding-*.
+    window: (DateTime<Utc>, DateTime<Utc>),
+    filepath: PathBuf,
+    device_name: String,
+    tags: Vec<String>,
+    mqtt_publisher: Option<MqttPublisher>,
+    strict_file_times: bool,
+    xattrs: bool,
+    nfo: bool,
+    playlist: bool,
+    embed_creation_time: bool,
+    exec_on_download: Option<String>,
+    delete_on_exec_failure: bool,
+}
 
-            // Create folder structure: YEAR/MONTH/DAY
-            let year = event_local_time.format("%Y").to_string();
-            let month = event_local_time.format("%m").to_string();
-            let day = event_local_time.format("%d").to_string();
-            let date_folder = output_path.join(&year).join(&month).join(&day);
+impl DownloadJob {
+    /// Downloads the clip and returns its size in bytes.
+    async fn run(self) -> Result<u64> {
+        let mut video_data = self
+            .nest_device
+            .download_camera_event(&self.connection, self.window.0, self.window.1)
+            .await?;
 
-            fs::create_dir_all(&date_folder).context("Failed to create date folder structure")?;
+        if self.embed_creation_time
+            && let Err(e) = mp4::set_creation_time(&mut video_data, self.event.start_time)
+        {
+            warn!(
+                path = %self.filepath.display(),
+                error = %e,
+                "Unexpected MP4 layout; leaving the embedded creation time as downloaded"
+            );
+        }
 
-            let filename = event_local_time.format("%Y-%m-%dT%H-%M-%S.mp4").to_string();
-            let filepath = date_folder.join(&filename);
+        let intended_mtime = write_clip(
+            &self.filepath,
+            &video_data,
+            self.event.start_time,
+            self.strict_file_times,
+        )?;
 
-            if filepath.exists() {
-                debug!(
-                    event_id = %event.event_id(),
-                    path = %filepath.display(),
-                    "Skipping camera event, file already exists"
-                );
-                continue;
+        let sha256 = Sha256::digest(&video_data)
+            .iter()
+            .fold(String::new(), |mut hex, byte| {
+                let _ = write!(hex, "{byte:02x}");
+                hex
+            });
+        let metadata = VideoMetadata::from(self.event.clone())
+            .with_device_name(&self.device_name)
+            .with_download_info(&self.filepath, video_data.len() as u64, &sha256, Utc::now())
+            .with_intended_mtime(intended_mtime)
+            .with_tags(&self.tags)
+            .with_download_window(self.window.0, self.window.1)
+            .with_variant(self.nest_device.variant);
+        if let Err(e) = archive::write_sidecar(&self.filepath, &metadata) {
+            warn!(path = %self.filepath.display(), error = %e, "Failed to write sidecar");
+        }
+        if self.xattrs {
+            xattrs::write_or_warn(&self.filepath, &metadata);
+        }
+        if self.nfo
+            && let Err(e) = nfo::write(&self.filepath, &metadata)
+        {
+            warn!(path = %self.filepath.display(), error = %e, "Failed to write NFO");
+        }
+        if let Some(command) = &self.exec_on_download
+            && let Err(e) =
+                exec_hook::run(command, &self.filepath, &self.event, &self.device_name).await
+        {
+            if self.delete_on_exec_failure {
+                // Remove the rejected clip so the event is retried rather than skipped as existing
+                if let Err(remove_error) = archive::remove_clip(&self.filepath) {
+                    error!(path = %self.filepath.display(), error = %remove_error, "Failed to delete rejected clip");
+                }
+                return Err(e);
             }
+            warn!(path = %self.filepath.display(), error = %format!("{e:#}"), "Post-download command failed; keeping clip");
+        }
+        if self.playlist
+            && let Err(e) = playlist::add_clip(&self.filepath, &metadata)
+        {
+            warn!(path = %self.filepath.display(), error = %e, "Failed to update playlist");
+        }
 
-            info!(
-                event_id = %event.event_id(),
-                path = %filepath.display(),
-                "Downloading camera event"
-            );
+        if let Some(publisher) = &self.mqtt_publisher {
+            publisher.publish_download(&self.event, &self.device_name, &self.filepath);
+        }
 
-            // Acquire permit before spawning to prevent unbounded task creation
-            let permit = match semaphore.clone().acquire_owned().await {
-                Ok(permit) => permit,
-                Err(e) => {
-                    error!(error = %e, "Failed to acquire semaphore permit");
-                    continue;
-                }
-            };
+        Ok(video_data.len() as u64)
+    }
+}
 
-            let nest_device_clone = nest_device.clone();
-            let google_master_token_clone = google_master_token.to_string();
-            let google_username_clone = google_username.to_string();
-            let event_clone = event.clone();
-            let filepath_clone = filepath.clone();
+/// How one camera fared in a check.
+#[derive(Debug)]
+enum DeviceOutcome {
+    Checked,
+    /// Its events couldn't be listed; the other cameras were still checked.
+    Failed {
+        error: String,
+    },
+}
 
-            total_count += 1;
+#[derive(Debug)]
+struct DeviceReport {
+    device_id: String,
+    device_name: String,
+    outcome: DeviceOutcome,
+}
 
-            join_set.spawn(async move {
-                let _permit = permit;
+/// Each camera's outcome in a check, in device order, and how its downloads
+/// went.
+#[derive(Debug, Default)]
+struct CycleReport {
+    devices: Vec<DeviceReport>,
+    downloads: DownloadProgress,
+}
 
-                // Create a new GoogleConnection for this task
-                let mut task_google_connection =
-                    GoogleConnection::new(google_master_token_clone, google_username_clone);
+impl CycleReport {
+    fn record(&mut self, device: &DiscoveredDevice, outcome: DeviceOutcome) {
+        self.devices.push(DeviceReport {
+            device_id: device.device_id.clone(),
+            device_name: device.device_name.clone(),
+            outcome,
+        });
+    }
 
-                let video_data = nest_device_clone
-                    .download_camera_event(&mut task_google_connection, &event_clone)
-                    .await?;
+    /// IDs of the cameras whose events were listed.
+    fn checked_device_ids(&self) -> impl Iterator<Item = &str> {
+        self.devices
+            .iter()
+            .filter(|device| matches!(device.outcome, DeviceOutcome::Checked))
+            .map(|device| device.device_id.as_str())
+    }
 
-                let mut file =
-                    fs::File::create(&filepath_clone).context("Failed to create file")?;
-                file.write_all(&video_data)
-                    .context("Failed to write video data")?;
+    /// Names of the cameras that couldn't be checked, with the errors.
+    fn failures(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.devices
+            .iter()
+            .filter_map(|device| match &device.outcome {
+                DeviceOutcome::Failed { error } => {
+                    Some((device.device_name.as_str(), error.as_str()))
+                }
+                DeviceOutcome::Checked => None,
+            })
+    }
+}
 
-                let event_local_time = event_clone.start_time.with_timezone(&Vancouver);
-                let timestamp = event_local_time.timestamp();
-                let filetime = FileTime::from_unix_time(timestamp, 0);
-                filetime::set_file_times(&filepath_clone, filetime, filetime)
-                    .context("Failed to set file times")?;
+#[derive(Debug, Default)]
+struct DownloadProgress {
+    completed_count: usize,
+    total_count: usize,
+    /// Clips that aged out of the camera's history before they were reached.
+    expired_count: usize,
+}
 
-                Ok::<(), anyhow::Error>(())
-            });
+/// Handles a finished download task. `in_flight` maps each running task to
+/// its event, so a task that panicked or was cancelled can still be retried.
+fn handle_download_result(
+    result: Result<(task::Id, Result<u64>), task::JoinError>,
+    in_flight: &mut HashMap<task::Id, CameraEvent>,
+    progress: &mut DownloadProgress,
+    state: &SharedStateStore,
+    backoff: &FailureBackoff,
+    cycle_cursor: &mut CycleCursor,
+    storage: &mut StorageMonitor,
+) {
+    let id = match &result {
+        Ok((id, _)) => *id,
+        Err(e) => e.id(),
+    };
+    let Some(event) = in_flight.remove(&id) else {
+        error!(task_id = %id, "Finished download task has no event");
+        return;
+    };
+    let result = result.map(|(_, result)| result);
 
-            // Drain completed tasks to avoid accumulating all tasks in memory
-            while let Some(result) = join_set.try_join_next() {
-                match result {
-                    Ok(Ok(())) => {
-                        completed_count += 1;
-                        info!(completed_count, total_count, "Download progress");
-                    }
-                    Ok(Err(e)) => error!(error = %e, "Download error"),
-                    Err(e) => error!(error = %e, "Task join error"),
-                }
+    let mut store = state.lock();
+    match result {
+        Ok(Ok(bytes)) => {
+            storage.record_success();
+            store.record_success(&event.event_id(), bytes);
+            if !event.merged_event_ids.is_empty() {
+                store.record_merged(&event.event_id(), &event.merged_event_ids);
             }
+            METRICS.record_device_download(&event.device_id, event.start_time);
+            progress.completed_count += 1;
+            info!(
+                completed_count = progress.completed_count,
+                total_count = progress.total_count,
+                "Download progress"
+            );
         }
-    }
-
-    // Wait for all remaining downloads to complete
-    while let Some(result) = join_set.join_next().await {
-        match result {
-            Ok(Ok(())) => {
-                completed_count += 1;
-                info!(completed_count, total_count, "Download progress");
+        // Not the event's fault, so it doesn't count towards its backoff; it
+        // is retried next cycle
+        Ok(Err(e)) if is_dns_failure(&e) => {
+            cycle_cursor.mark_pending(&event);
+            METRICS.record_device_error(&event.device_id, format!("{e:#}"));
+            warn!(event_id = %event.event_id(), error = %format!("{e:#}"), "Download failed to resolve DNS; retrying next cycle");
+        }
+        // The output filesystem is down, not the event; once downloads are
+        // paused nothing counts, so in-flight downloads don't use up retries
+        Ok(Err(e)) if is_storage_error(&e) || storage.is_unavailable() => {
+            storage.record_failure(&e);
+            cycle_cursor.mark_pending(&event);
+            if storage.is_unavailable() {
+                debug!(event_id = %event.event_id(), error = %format!("{e:#}"), "Download failed while storage is unavailable; retrying later");
+            } else {
+                warn!(event_id = %event.event_id(), error = %format!("{e:#}"), "Download failed to write to storage; retrying next cycle");
+            }
+        }
+        // Retrying can't bring the footage back, so it isn't a failure either
+        Ok(Err(e)) if matches!(api_error(&e), Some(ApiError::ClipExpired { .. })) => {
+            let event_id = event.event_id();
+            progress.expired_count += 1;
+            METRICS.record_clip_expired();
+            warn!(%event_id, "Clip expired before it could be downloaded; footage lost to timing");
+            store.ignore_event(&event_id, &e.to_string());
+        }
+        Ok(Err(e)) => {
+            let event_id = event.event_id();
+            error!(%event_id, error = %e, "Download error");
+            METRICS.record_device_error(&event.device_id, format!("{e:#}"));
+            let record = store.record_failure(&event_id, &e.to_string(), backoff);
+            if record.gave_up {
+                warn!(
+                    %event_id,
+                    failures = record.count,
+                    "Giving up on event after repeated download failures"
+                );
+            } else {
+                debug!(%event_id, failures = record.count, retry_after = %record.retry_after, "Backing off event");
+                cycle_cursor.mark_pending(&event);
             }
-            Ok(Err(e)) => error!(error = %e, "Download error"),
-            Err(e) => error!(error = %e, "Task join error"),
+        }
+        // A panicked download says nothing about the event; retry it next cycle
+        Err(e) => {
+            cycle_cursor.mark_pending(&event);
+            error!(event_id = %event.event_id(), error = %e, "Download task failed");
         }
     }
-
-    info!(completed_count, total_count, "All downloads complete");
-    info!(interval_minutes, "Waiting before next check");
-
-    Ok(())
 }
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// Output directory for downloaded videos
-    #[arg(short, long, default_value = ".")]
-    output: PathBuf,
+/// The `--quality` variant for `device`, its `--device-quality` if it has one.
+fn device_variant(args: &Args, device: &DiscoveredDevice) -> Option<u32> {
+    args.device_quality
+        .iter()
+        .rev()
+        .find(|o| o.device == device.device_name || o.device == device.device_id)
+        .map(|o| o.variant)
+        .or(args.quality)
+}
 
-    /// Number of concurrent downloads
-    #[arg(short, long, default_value = "10")]
-    concurrency: usize,
+/// Fetches a device's events from `start_time` to `end_time`, one lookback
+/// period per request, holding a discovery permit for each request.
+async fn fetch_events(
+    nest_device: &NestDevice,
+    connection: &GoogleConnection,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    discovery_semaphore: &Semaphore,
+) -> Result<Vec<CameraEvent>> {
+    let mut events = Vec::new();
+    let mut window_end = end_time;
+    loop {
+        let window_start =
+            start_time.max(window_end - chrono::Duration::minutes(EVENT_HISTORY_DURATION_MINUTES));
+        // Round up so the window reaches back to its start
+        let minutes = ((window_end - window_start).num_seconds() + 59) / 60;
+        events.extend(
+            fetch_window(
+                nest_device,
+                connection,
+                window_end,
+                minutes.max(1),
+                discovery_semaphore,
+            )
+            .await?,
+        );
+        if window_start <= start_time {
+            break;
+        }
+        window_end = window_start;
+    }
 
-    /// Interval in minutes to check for new events
-    #[arg(short = 'i', long, default_value = "5")]
-    check_interval: u64,
+    // Events spanning a window boundary are listed by both windows
+    events.sort_by_key(|e| e.start_time);
+    events.dedup_by(|a, b| a.event_id() == b.event_id());
+    Ok(events)
+}
 
-    /// Run once and exit instead of running continuously
-    #[arg(long)]
-    once: bool,
+/// Fetches one window of a device's events. A DNS failure is retried once
+/// after [`DNS_FAILURE_BACKOFF`], without holding a permit while waiting.
+async fn fetch_window(
+    nest_device: &NestDevice,
+    connection: &GoogleConnection,
+    end_time: DateTime<Utc>,
+    duration_minutes: i64,
+    discovery_semaphore: &Semaphore,
+) -> Result<Vec<CameraEvent>> {
+    let result = {
+        let _permit = discovery_semaphore.acquire().await?;
+        nest_device
+            .get_events(connection, end_time, duration_minutes)
+            .await
+    };
 
-    /// Number of days to keep videos (0 = keep forever, no pruning)
-    #[arg(long, default_value = "60")]
-    retention_days: u64,
+    match result {
+        Err(e) if is_dns_failure(&e) => {
+            warn!(
+                device_name = %nest_device.device_name,
+                error = %format!("{e:#}"),
+                delay_secs = DNS_FAILURE_BACKOFF.as_secs(),
+                "DNS resolution failed; backing off before retrying the device"
+            );
+            time::sleep(DNS_FAILURE_BACKOFF).await;
+            let _permit = discovery_semaphore.acquire().await?;
+            nest_device
+                .get_events(connection, end_time, duration_minutes)
+                .await
+                .context("DNS still failing after backing off; ending this cycle")
+        }
+        result => result,
+    }
+}
 
-    /// Use hours instead of days for retention period (for testing)
-    #[arg(long)]
-    retention_hours: bool,
+/// Logs a device's catch-up when its query reaches back past the usual
+/// lookback period, and any part of the gap too old to catch up on.
+fn log_catch_up(
+    device: &DiscoveredDevice,
+    checked_until: Option<DateTime<Utc>>,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    args: &Args,
+) {
+    let lookback = chrono::Duration::minutes(EVENT_HISTORY_DURATION_MINUTES);
+    let Some(checked_until) = checked_until.filter(|&c| c < end_time - lookback) else {
+        return;
+    };
+    let device_name = &device.device_name;
+    let gap_hours = (end_time - checked_until).num_minutes() as f64 / 60.0;
+    let catch_up_hours = (end_time - start_time).num_minutes() as f64 / 60.0;
+    info!(
+        %device_name,
+        checked_until = %checked_until,
+        gap_hours = format!("{gap_hours:.1}"),
+        catch_up_hours = format!("{catch_up_hours:.1}"),
+        "Catching up on events missed since the last check"
+    );
+    if checked_until < start_time {
+        warn!(
+            %device_name,
+            lost_hours = format!("{:.1}", (start_time - checked_until).num_minutes() as f64 / 60.0),
+            max_catch_up_hours = args.max_catch_up_hours,
+            "Part of the gap is beyond --max-catch-up-hours; events in it won't be downloaded"
+        );
+    }
+}
 
-    /// Interval in minutes to prune old videos
-    #[arg(long, default_value = "10")]
-    prune_interval: u64,
+/// Where a catching-up device's query from `start_time` should end so it
+/// takes no more 12-hour windows than are left in `budget`, oldest first.
+/// The windows are taken from `budget`; with none left, the query ends where
+/// it starts.
+fn budgeted_query_end(
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    budget: &mut usize,
+) -> DateTime<Utc> {
+    let window_secs = EVENT_HISTORY_DURATION_MINUTES * 60;
+    let needed = ((end_time - start_time).num_seconds() + window_secs - 1) / window_secs;
+    let allowed = (*budget).min(needed as usize);
+    *budget -= allowed;
+    if allowed as i64 == needed {
+        end_time
+    } else {
+        start_time + chrono::Duration::seconds(window_secs * allowed as i64)
+    }
 }
 
-#[tokio::main]
-async fn main() {
-    // Initialize tracing subscriber
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
-        )
-        .init();
+/// The windows to download a camera's clips for, by event ID, when
+/// `--clip-padding-before` or `--clip-padding-after` widen them. Padding
+/// only reaches into the gaps between events, the gap before an event going
+/// to its own padding first, so no footage is downloaded twice. Windows end
+/// by `query_end`, as later footage may not be indexed yet.
+fn padded_windows(
+    events: &[CameraEvent],
+    args: &Args,
+    query_end: DateTime<Utc>,
+) -> HashMap<String, (DateTime<Utc>, DateTime<Utc>)> {
+    let before = chrono::Duration::seconds(args.clip_padding_before as i64);
+    let after = chrono::Duration::seconds(args.clip_padding_after as i64);
+    if before.is_zero() && after.is_zero() {
+        return HashMap::new();
+    }
 
-    info!(
-        "Application: {}, Version: {}",
-        env!("CARGO_PKG_NAME"),
-        env!("CARGO_PKG_VERSION")
-    );
+    let mut sorted: Vec<&CameraEvent> = events.iter().collect();
+    sorted.sort_by_key(|event| event.start_time);
+    // Where an event's window may start, given the latest end before it
+    let padded_start = |event: &CameraEvent, latest_end: Option<DateTime<Utc>>| {
+        let start = event.start_time - before;
+        latest_end
+            .map_or(start, |end| start.max(end))
+            .min(event.start_time)
+    };
 
-    dotenvy::dotenv().ok();
+    let mut windows = HashMap::new();
+    let mut latest_end: Option<DateTime<Utc>> = None;
+    for (index, event) in sorted.iter().enumerate() {
+        let start = padded_start(event, latest_end);
+        latest_end = latest_end.max(Some(event.end_time()));
+        let next_start = sorted
+            .get(index + 1)
+            .map(|next| padded_start(next, latest_end));
+        let end = (event.end_time() + after)
+            .min(next_start.unwrap_or(query_end))
+            .min(query_end)
+            .max(event.end_time());
+        windows.insert(event.event_id(), (start, end));
+    }
+    windows
+}
 
-    let args = Args::parse();
+/// Merges a camera's back-to-back events with `--merge-gap-seconds`. Events
+/// already downloaded as part of a merged clip are left as they are, to be
+/// skipped, so an event listed after its neighbours were merged is
+/// downloaded on its own rather than merged into a clip that exists.
+fn merge_back_to_back(
+    events: Vec<CameraEvent>,
+    max_gap_secs: u64,
+    store: &StateStore,
+) -> Vec<CameraEvent> {
+    let (mut events, mut pending): (Vec<_>, Vec<_>) = events
+        .into_iter()
+        .partition(|event| store.is_merged(&event.event_id()));
+    pending.sort_by_key(|event| event.start_time);
+    events.extend(models::merge_adjacent(
+        pending,
+        chrono::Duration::seconds(max_gap_secs as i64),
+    ));
+    events.sort_by_key(|event| event.start_time);
+    events
+}
 
-    let mut app_state = None;
+async fn check_and_download_events(
+    app: &mut AppState,
+    semaphore: &Arc<Semaphore>,
+    discovery_semaphore: &Arc<Semaphore>,
+    args: &Args,
+    cycle_id: Uuid,
+) -> Result<CycleReport> {
+    if app.devices_due_for_refresh(args) {
+        app.refresh_devices().await;
+    }
+    app.storage.check().await;
 
-    let semaphore = Arc::new(Semaphore::new(args.concurrency));
-    let mut check_events_interval = time::interval(Duration::from_secs(args.check_interval * 60));
-    check_events_interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+    info!("Checking for new events");
+    let google_connection = &app.google_connection;
+    let output_path = app.output_path.as_path();
+    let mqtt_publisher = app.mqtt_publisher.as_ref();
+    let state = app.state.clone();
+    let backoff = failure_backoff(args);
+    let permit_timeout = Duration::from_secs(args.permit_timeout_secs);
+    let mut join_set = JoinSet::new();
+    let mut in_flight = HashMap::new();
+    let mut progress = DownloadProgress::default();
+    let mut report = CycleReport::default();
 
-    let mut prune_interval = time::interval(Duration::from_secs(args.prune_interval * 60));
-    prune_interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+    // Stop short of now: the most recent events may not be indexed yet and
+    // would otherwise be missed until a later check
+    let end_time: DateTime<Utc> = Utc::now() - chrono::Duration::seconds(args.index_lag_secs);
 
-    if !args.once {
-        info!(
-            check_interval = args.check_interval,
-            "Checking for events at regular intervals"
-        );
-        if args.retention_days > 0 {
-            let unit = if args.retention_hours {
-                "hours"
-            } else {
-                "days"
-            };
-            info!(
-                retention_days = args.retention_days,
-                unit,
-                prune_interval = args.prune_interval,
-                "Video pruning enabled"
+    // Fetch every device's manifest up front, at most --discovery-concurrency
+    // at a time, then queue downloads in device order
+    let mut fetches = JoinSet::new();
+    let mut cycle_cursor = CycleCursor::new(end_time);
+    let mut fetched: Vec<Option<Result<Vec<CameraEvent>>>> =
+        (0..app.nest_camera_devices.len()).map(|_| None).collect();
+    // Requests left for devices catching up; event lists take at most half,
+    // so downloads always get some and the backfill moves forward
+    let mut catch_up_budget = args.catch_up_budget;
+    let mut list_budget = args.catch_up_budget.map(|budget| budget.div_ceil(2));
+    let mut catching_up: HashSet<String> = HashSet::new();
+    if !args.continuous {
+        for (index, device) in app.nest_camera_devices.iter().enumerate() {
+            let nest_device = NestDevice::new(device.device_id.clone(), device.device_name.clone())
+                .with_variant(device_variant(args, device));
+            let connection = google_connection.clone();
+            let discovery_semaphore = discovery_semaphore.clone();
+            let checked_until = state.lock().checked_until(&device.device_id);
+            let start_time = cursor::query_start(
+                end_time,
+                checked_until,
+                chrono::Duration::minutes(EVENT_HISTORY_DURATION_MINUTES),
+                chrono::Duration::hours(args.max_catch_up_hours as i64),
+                args.since_last_run,
             );
-        } else {
-            info!("Video pruning disabled (retention_days = 0)");
-        }
-    }
+            log_catch_up(device, checked_until, start_time, end_time, args);
 
-    loop {
-        tokio::select! {
-            _ = check_events_interval.tick() => {
-                if app_state.is_none() {
-                    app_state = initialize(&args).await;
+            let mut query_end = end_time;
+            if let Some(list_budget) = &mut list_budget
+                && start_time < end_time - chrono::Duration::minutes(EVENT_HISTORY_DURATION_MINUTES)
+            {
+                catching_up.insert(device.device_id.clone());
+                let before = *list_budget;
+                query_end = budgeted_query_end(start_time, end_time, list_budget);
+                if let Some(budget) = &mut catch_up_budget {
+                    *budget -= before - *list_budget;
+                }
+                if query_end < end_time {
+                    cycle_cursor.hold(&device.device_id, query_end);
+                    info!(
+                        device_name = %device.device_name,
+                        caught_up_until = %query_end,
+                        "Reached --catch-up-budget; the rest of the catch-up waits for a later check"
+                    );
+                }
+                if query_end == start_time {
+                    fetched[index] = Some(Ok(Vec::new()));
+                    continue;
                 }
-
-                if let Some(ref mut state) = app_state
-                    && let Err(e) = check_and_download_events(
-                        &mut state.google_connection,
-                        &state.nest_camera_devices,
-                        &state.output_path,
-                        &semaphore,
-                        &state.google_master_token,
-                        &state.google_username,
-                        args.check_interval,
-                    ).await {
-                        error!(error = %e, "Error checking events");
-                    }
-            }
-            _ = prune_interval.tick() => {
-                if let Some(ref state) = app_state
-                    && let Err(e) = prune_old_videos(&state.output_path, args.retention_days, args.retention_hours).await {
-                        error!(error = %e, "Error pruning videos");
-                    }
             }
-            // Add more branches here as needed
-            // _ = some_signal => { ... }
+            let device_span = info_span!("device", device_name = %device.device_name);
+            fetches.spawn(
+                async move {
+                    let events = fetch_events(
+                        &nest_device,
+                        &connection,
+                        start_time,
+                        query_end,
+                        &discovery_semaphore,
+                    );
+                    (index, events.await)
+                }
+                .instrument(device_span),
+            );
         }
+    }
+    while let Some(result) = fetches.join_next().await {
+        let (index, events) = result.context("Event fetch task failed")?;
+        fetched[index] = Some(events);
+    }
 
-        if args.once {
-            break;
-        }
+    let newest_on_disk = if args.incremental_by_filename {
+        let output_path = output_path.to_path_buf();
+        let template = args.path_template.clone();
+        let devices: Vec<(String, String)> = app
+            .nest_camera_devices
+            .iter()
+            .map(|d| (d.device_id.clone(), d.device_name.clone()))
+            .collect();
+        tokio::task::spawn_blocking(move || {
+            let devices: Vec<(&str, &str)> = devices
+                .iter()
+                .map(|(id, name)| (id.as_str(), name.as_str()))
+                .collect();
+            archive::newest_clip_starts(&output_path, &template, &devices)
+        })
+        .await
+        .context("Archive scan failed")?
+    } else {
+        HashMap::new()
+    };
+
+    // The store is held only while queueing, which never awaits
+    let (jobs, existing) = {
+        let mut store = state.lock();
+        let mut jobs = Vec::new();
+        // Queued clips already on disk, for --refresh-incomplete to check
+        let mut existing: HashSet<PathBuf> = HashSet::new();
+        // Paths queued this cycle, by device ID
+        let mut claimed_paths: HashMap<PathBuf, String> = HashMap::new();
+        let earlier_layouts = args.path_template.earlier_layouts();
+        for (device, fetched) in app.nest_camera_devices.iter().zip(fetched) {
+            let device_name = &device.device_name;
+            let _span = info_span!("device", %device_name).entered();
+            let nest_device = NestDevice::new(device.device_id.clone(), device_name.clone())
+                .with_variant(device_variant(args, device));
+
+            let events = match fetched {
+                Some(Ok(events)) => events,
+                // The network is down for every camera, not just this one
+                Some(Err(e)) if is_dns_failure(&e) => {
+                    METRICS.record_device_check_error(
+                        &device.device_id,
+                        device_name,
+                        format!("{e:#}"),
+                    );
+                    return Err(e);
+                }
+                Some(Err(e)) => {
+                    // The camera may have been removed from the account
+                    app.refresh_devices = true;
+                    let error = format!("{e:#}");
+                    error!(%error, "Failed to check camera; continuing with the others");
+                    METRICS.record_device_check_error(
+                        &device.device_id,
+                        device_name,
+                        error.clone(),
+                    );
+                    report.record(device, DeviceOutcome::Failed { error });
+                    continue;
+                }
+                None => nest_device.timeline_chunks(
+                    end_time,
+                    EVENT_HISTORY_DURATION_MINUTES,
+                    args.chunk_minutes,
+                ),
+            };
+            METRICS.record_device_check(&device.device_id, device_name);
+            report.record(device, DeviceOutcome::Checked);
+            app.idle_log
+                .log_events_received(device, events.len(), args.quiet_empty);
+            if !args.continuous {
+                // Every event in the manifest counts, before any type filter
+                let newest_event = events.iter().map(|e| e.start_time).max();
+                let last_event = store.record_last_event(&device.device_id, newest_event);
+                app.silence.check(device, last_event);
+            }
+
+            let events = match args.merge_gap_seconds {
+                Some(max_gap_secs) => merge_back_to_back(events, max_gap_secs, &store),
+                None => events,
+            };
+
+            // Padded against every listed event, so footage isn't duplicated
+            // even into clips already on disk or filtered out
+            let windows = padded_windows(&events, args, end_time);
+
+            let events = match &args.event_types {
+                Some(filter) if !args.continuous => {
+                    let received_count = events.len();
+                    let events: Vec<CameraEvent> =
+                        events.into_iter().filter(|e| filter.matches(e)).collect();
+                    debug!(
+                        %device_name,
+                        filtered_count = received_count - events.len(),
+                        "Filtered camera events by type"
+                    );
+                    events
+                }
+                _ => events,
+            };
+
+            let events = if args.min_event_secs > 0 && !args.continuous {
+                let min_duration = chrono::Duration::seconds(args.min_event_secs as i64);
+                let received_count = events.len();
+                let events: Vec<CameraEvent> = events
+                    .into_iter()
+                    .filter(|e| e.original_duration.unwrap_or(e.duration) >= min_duration)
+                    .collect();
+                debug!(
+                    %device_name,
+                    filtered_count = received_count - events.len(),
+                    "Filtered out short camera events"
+                );
+                events
+            } else {
+                events
+            };
+
+            // File names only resolve whole seconds
+            let events = match newest_on_disk.get(&device.device_id) {
+                Some(&newest) => {
+                    let received_count = events.len();
+                    let events: Vec<CameraEvent> = events
+                        .into_iter()
+                        .filter(|e| e.start_time.trunc_subsecs(0) > newest)
+                        .collect();
+                    debug!(
+                        %device_name,
+                        %newest,
+                        filtered_count = received_count - events.len(),
+                        "Skipped events no newer than the newest clip on disk"
+                    );
+                    events
+                }
+                None => events,
+            };
+
+            for event in events {
+                let tags = app
+                    .tag_rules
+                    .tags_for(&event, &device.device_id, device_name);
+                let filepath = clip_path(
+                    &args.path_template,
+                    output_path,
+                    &event,
+                    device,
+                    &tags,
+                    &claimed_paths,
+                );
+
+                if !filepath.exists()
+                    && let Some(original) =
+                        archive::read_sidecar(&filepath).and_then(|s| s.duplicate_of)
+                {
+                    debug!(
+                        event_id = %event.event_id(),
+                        path = %filepath.display(),
+                        original = %original.display(),
+                        "Skipping camera event, clip was identical to an archived one"
+                    );
+                    continue;
+                }
+                let exists = filepath.exists();
+                if exists && !args.refresh_incomplete {
+                    debug!(
+                        event_id = %event.event_id(),
+                        path = %filepath.display(),
+                        "Skipping camera event, file already exists"
+                    );
+                    continue;
+                }
+                if let Some(path) = archived_under_earlier_layout(
+                    &earlier_layouts,
+                    output_path,
+                    &event,
+                    device_name,
+                    &tags,
+                    &filepath,
+                ) {
+                    debug!(
+                        event_id = %event.event_id(),
+                        path = %path.display(),
+                        "Skipping camera event, already archived under an earlier layout"
+                    );
+                    continue;
+                }
+
+                let event_id = event.event_id();
+                if store.is_ignored(&event_id) {
+                    debug!(%event_id, "Skipping ignored camera event");
+                    continue;
+                }
+                if store.is_merged(&event_id) {
+                    debug!(%event_id, "Skipping camera event, already archived in a merged clip");
+                    continue;
+                }
+                if store.is_imported(event.start_time, &device.device_id, device_name) {
+                    debug!(%event_id, "Skipping camera event, imported from an existing archive");
+                    continue;
+                }
+                if let Some(failure) = store.failure(&event_id)
+                    && (failure.gave_up || failure.retry_after > Utc::now())
+                {
+                    if !failure.gave_up {
+                        cycle_cursor.mark_pending(&event);
+                    }
+                    debug!(
+                        %event_id,
+                        failures = failure.count,
+                        gave_up = failure.gave_up,
+                        "Skipping camera event in failure backoff"
+                    );
+                    continue;
+                }
+
+                claimed_paths.insert(filepath.clone(), device.device_id.clone());
+                if exists {
+                    existing.insert(filepath.clone());
+                }
+                let window = windows
+                    .get(&event_id)
+                    .copied()
+                    .unwrap_or((event.start_time, event.end_time()));
+                jobs.push(DownloadJob {
+                    nest_device: nest_device.clone(),
+                    connection: google_connection.clone(),
+                    event,
+                    window,
+                    filepath,
+                    device_name: device_name.clone(),
+                    tags,
+                    mqtt_publisher: mqtt_publisher.cloned(),
+                    strict_file_times: args.strict_file_times,
+                    xattrs: args.xattrs,
+                    nfo: args.nfo,
+                    playlist: args.playlist,
+                    embed_creation_time: args.embed_creation_time,
+                    exec_on_download: args.exec_on_download.clone(),
+                    delete_on_exec_failure: args.delete_on_exec_failure,
+                });
+            }
+        }
+        (jobs, existing)
+    };
+
+    // Parsing clips is blocking file I/O, kept off the store's lock
+    let jobs = if existing.is_empty() {
+        jobs
+    } else {
+        let mut verified_clips = std::mem::take(&mut app.verified_clips);
+        let paths = existing.clone();
+        let (verified_clips, problems) = task::spawn_blocking(move || {
+            let problems = verified_clips.check(paths);
+            (verified_clips, problems)
+        })
+        .await
+        .context("Clip check failed")?;
+        app.verified_clips = verified_clips;
+        jobs.into_iter()
+            .filter(|job| {
+                if !existing.contains(&job.filepath) {
+                    return true;
+                }
+                let event_id = job.event.event_id();
+                let path = job.filepath.display();
+                match problems.get(&job.filepath) {
+                    Some(problem) => {
+                        info!(%event_id, %path, %problem, "Re-downloading incomplete clip");
+                        true
+                    }
+                    None => {
+                        debug!(%event_id, %path, "Skipping camera event, file already exists");
+                        false
+                    }
+                }
+            })
+            .collect()
+    };
+
+    let mut jobs = order_jobs(jobs, args.download_order, args.newest_first);
+    if let Some(max_events) = args.max_events_per_cycle
+        && jobs.len() > max_events
+    {
+        info!(
+            max_events,
+            skipped_count = jobs.len() - max_events,
+            "Reached per-cycle event limit; skipping remaining events"
+        );
+        for job in jobs.drain(max_events..) {
+            cycle_cursor.mark_pending(&job.event);
+        }
+    }
+    if let Some(mut budget) = catch_up_budget {
+        let queued_count = jobs.len();
+        jobs.retain(|job| {
+            if !catching_up.contains(&job.event.device_id) {
+                return true;
+            }
+            if budget == 0 {
+                cycle_cursor.mark_pending(&job.event);
+                return false;
+            }
+            budget -= 1;
+            true
+        });
+        if jobs.len() < queued_count {
+            info!(
+                catch_up_budget = args.catch_up_budget,
+                skipped_count = queued_count - jobs.len(),
+                "Reached --catch-up-budget; skipping remaining catch-up downloads until a later check"
+            );
+        }
+    }
+
+    // Clips already fetched today, e.g. retries, don't count again
+    let quota = args.max_downloads_per_day.filter(|_| !args.ignore_quota);
+    let today = Utc::now().with_timezone(&Vancouver).date_naive();
+    if let Some(limit) = quota {
+        let store = state.lock();
+        let mut remaining = limit.saturating_sub(store.downloads_on(today));
+        let queued_count = jobs.len();
+        jobs.retain(|job| {
+            if store.downloaded_on(&job.event.event_id(), today) {
+                return true;
+            }
+            if remaining == 0 {
+                cycle_cursor.mark_pending(&job.event);
+                return false;
+            }
+            remaining -= 1;
+            true
+        });
+        if jobs.len() < queued_count && app.quota_reached_on != Some(today) {
+            app.quota_reached_on = Some(today);
+            warn!(
+                max_downloads_per_day = limit,
+                deferred_count = queued_count - jobs.len(),
+                "Reached --max-downloads-per-day; deferring further events until tomorrow"
+            );
+        }
+    }
+
+    let mut storage_deferred_count = 0;
+    for job in jobs {
+        // Left pending rather than failed, so they download once the
+        // filesystem is back without having used up any retries
+        if app.storage.is_unavailable() {
+            cycle_cursor.mark_pending(&job.event);
+            storage_deferred_count += 1;
+            continue;
+        }
+        if let Some(date_folder) = job.filepath.parent()
+            && let Err(e) =
+                fs::create_dir_all(date_folder).context("Failed to create date folder structure")
+        {
+            if !is_storage_error(&e) {
+                return Err(e);
+            }
+            app.storage.record_failure(&e);
+            cycle_cursor.mark_pending(&job.event);
+            storage_deferred_count += 1;
+            continue;
+        }
+
+        info!(
+            event_id = %job.event.event_id(),
+            path = %job.filepath.display(),
+            "Downloading camera event"
+        );
+
+        // Acquire permit before spawning to prevent unbounded task creation.
+        // Stuck downloads can hold every permit, so don't wait forever.
+        let permit = match time::timeout(permit_timeout, semaphore.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => permit,
+            Ok(Err(e)) => {
+                error!(error = %e, "Failed to acquire semaphore permit");
+                cycle_cursor.mark_pending(&job.event);
+                continue;
+            }
+            Err(_) => {
+                cycle_cursor.mark_pending(&job.event);
+                warn!(
+                    event_id = %job.event.event_id(),
+                    timeout_secs = args.permit_timeout_secs,
+                    "Timed out waiting for a download slot; skipping event this cycle"
+                );
+                continue;
+            }
+        };
+
+        progress.total_count += 1;
+        if quota.is_some() {
+            state.lock().count_download(&job.event.event_id(), today);
+        }
+
+        let download_span = info_span!("download", event_id = %job.event.event_id());
+        let event = job.event.clone();
+        let handle = join_set.spawn(
+            async move {
+                let _permit = permit;
+                job.run().await
+            }
+            .instrument(download_span),
+        );
+        in_flight.insert(handle.id(), event);
+
+        // Drain completed tasks to avoid accumulating all tasks in memory
+        while let Some(result) = join_set.try_join_next_with_id() {
+            handle_download_result(
+                result,
+                &mut in_flight,
+                &mut progress,
+                &state,
+                &backoff,
+                &mut cycle_cursor,
+                &mut app.storage,
+            );
+        }
+    }
+
+    if storage_deferred_count > 0 {
+        info!(
+            deferred_count = storage_deferred_count,
+            "Deferred downloads until output storage is writable"
+        );
+    }
+
+    if args.quiet_empty {
+        app.idle_log.maybe_log_summary();
+    }
+
+    // Wait for all remaining downloads to complete
+    while let Some(result) = join_set.join_next_with_id().await {
+        handle_download_result(
+            result,
+            &mut in_flight,
+            &mut progress,
+            &state,
+            &backoff,
+            &mut cycle_cursor,
+            &mut app.storage,
+        );
+    }
+
+    if !args.continuous {
+        // A camera that couldn't be checked keeps its cursor, so the next
+        // check covers the window it missed
+        cycle_cursor.finish(&mut state.lock(), report.checked_device_ids());
+    }
+    if let Some(limit) = quota {
+        let used = state.lock().downloads_on(today);
+        METRICS.set_download_quota(DownloadQuota {
+            date: today,
+            limit,
+            used,
+            remaining: limit.saturating_sub(used),
+        });
+    }
+    state.flush();
+
+    info!(
+        completed_count = progress.completed_count,
+        total_count = progress.total_count,
+        expired_count = progress.expired_count,
+        %cycle_id,
+        "All downloads complete"
+    );
+    info!(
+        interval_minutes = args.check_interval,
+        "Waiting before next check"
+    );
+
+    report.downloads = progress;
+    Ok(report)
+}
+
+/// Clears an activity's running flag when it ends, even by panic.
+struct RunningGuard(Arc<AtomicBool>);
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// One polling cycle, run in its own task so a slow cycle doesn't stall the
+/// main loop. Connects first if the previous attempt failed.
+async fn run_check_cycle(
+    app_state: Arc<Mutex<Option<AppState>>>,
+    state: SharedStateStore,
+    semaphore: Arc<Semaphore>,
+    discovery_semaphore: Arc<Semaphore>,
+    args: Arc<Args>,
+    cycle_running: Arc<AtomicBool>,
+) {
+    let _guard = RunningGuard(cycle_running);
+    let started = Instant::now();
+    // Every log line of one cycle carries its id, down to each download
+    let cycle_id = Uuid::new_v4();
+    let cycle_span = info_span!("check_cycle", %cycle_id);
+    let mut app_state = app_state.lock().await;
+    async {
+        if app_state.is_none() {
+            *app_state = initialize(&args, state).await;
+        }
+
+        if let Some(state) = app_state.as_mut() {
+            match check_and_download_events(
+                state,
+                &semaphore,
+                &discovery_semaphore,
+                &args,
+                cycle_id,
+            )
+            .await
+            {
+                Ok(report) => {
+                    let failures: Vec<String> = report
+                        .failures()
+                        .map(|(device_name, error)| format!("{device_name}: {error}"))
+                        .collect();
+                    if !failures.is_empty() {
+                        warn!(
+                            failed_count = failures.len(),
+                            device_count = report.devices.len(),
+                            failures = %failures.join("; "),
+                            "Some cameras couldn't be checked this cycle"
+                        );
+                    } else if let Some(heartbeat) = &state.heartbeat {
+                        heartbeat
+                            .ping(&CycleSummary {
+                                cycle_id: cycle_id.to_string(),
+                                cycle_secs: started.elapsed().as_secs_f64(),
+                                device_count: report.devices.len(),
+                                downloaded_count: report.downloads.completed_count,
+                                expired_count: report.downloads.expired_count,
+                            })
+                            .await;
+                    }
+                }
+                Err(e) => error!(error = %e, "Error checking events"),
+            }
+        }
+    }
+    .instrument(cycle_span.clone())
+    .await;
+    let _span = cycle_span.entered();
+
+    let elapsed = started.elapsed();
+    let interval = Duration::from_secs(args.check_interval * 60);
+    let overran = !args.once && elapsed > interval;
+    let overrun_count = METRICS.record_check_cycle(elapsed, overran);
+    if overran {
+        // Missed ticks are skipped, so every overrun quietly lowers the
+        // polling frequency
+        warn!(
+            cycle_secs = elapsed.as_secs(),
+            check_interval_secs = interval.as_secs(),
+            overrun_count,
+            concurrency = args.concurrency,
+            "Check cycle overran the check interval; consider raising --concurrency, capping \
+             --max-events-per-cycle or lengthening --check-interval"
+        );
+    } else {
+        debug!(cycle_secs = elapsed.as_secs_f64(), "Check cycle finished");
+    }
+
+    if let Some(path) = &args.status_file
+        && let Err(e) = METRICS.write_status_file(path)
+    {
+        warn!(path = %path.display(), error = %format!("{e:#}"), "Failed to write status file");
+    }
+}
+
+/// One prune pass, run in its own task so pruning keeps to its interval
+/// while a long download cycle is in progress.
+async fn run_prune_pass(output_path: PathBuf, policy: PrunePolicy, prune_running: Arc<AtomicBool>) {
+    let _guard = RunningGuard(prune_running);
+    if let Err(e) = prune::prune_old_videos(&output_path, &policy).await {
+        error!(error = %e, "Error pruning videos");
+    }
+}
+
+/// Where state compaction stops keeping entries: events older than this are
+/// past the retention period and the catch-up window alike, so they can't be
+/// queried again and, with a retention period, their clips are pruned.
+fn state_compaction_cutoff(args: &Args) -> DateTime<Utc> {
+    let retention = match args.retention_days as i64 {
+        0 => chrono::Duration::zero(),
+        n if args.retention_hours => chrono::Duration::hours(n),
+        n => chrono::Duration::days(n),
+    };
+    let horizon = chrono::Duration::minutes(EVENT_HISTORY_DURATION_MINUTES)
+        .max(chrono::Duration::hours(args.max_catch_up_hours as i64))
+        .max(retention);
+    Utc::now()
+        .checked_sub_signed(horizon)
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+/// An emergency prune pass, run as a prune pass so it never overlaps one.
+async fn run_emergency_prune(
+    output_path: PathBuf,
+    policy: PrunePolicy,
+    thresholds: EmergencyThresholds,
+    available: u64,
+    prune_running: Arc<AtomicBool>,
+) {
+    let _guard = RunningGuard(prune_running);
+    error!(
+        available_bytes = available,
+        floor_bytes = thresholds.floor,
+        recovery_bytes = thresholds.recovery,
+        "Free space is below the emergency floor; deleting the oldest clips regardless of retention. \
+         Something else is filling the disk, or retention needs tuning"
+    );
+    let outcome = match prune::emergency_prune(&output_path, &policy, thresholds, available).await {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            error!(error = %format!("{e:#}"), "Emergency pruning failed");
+            return;
+        }
+    };
+    METRICS.record_emergency_prune(EmergencyPrune {
+        at: Utc::now(),
+        deleted_count: outcome.deleted_count,
+        freed_bytes: outcome.freed_bytes,
+        recovered: outcome.recovered,
+    });
+    if outcome.recovered {
+        warn!(
+            deleted_count = outcome.deleted_count,
+            freed_bytes = outcome.freed_bytes,
+            dry_run = policy.dry_run,
+            "Emergency pruning complete"
+        );
+    } else {
+        error!(
+            deleted_count = outcome.deleted_count,
+            freed_bytes = outcome.freed_bytes,
+            dry_run = policy.dry_run,
+            "Emergency pruning couldn't free enough space; only pinned and protected clips are left. \
+             Not trying again until free space is back above the floor"
+        );
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version = version::LONG_VERSION, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Output directory for downloaded videos
+    #[arg(short, long, default_value = ".", global = true, value_hint = ValueHint::DirPath)]
+    output: PathBuf,
+
+    /// Clip path relative to the output directory; placeholders {year} {month} {day} {hour} {minute}
+    /// {second} (local time), {offset} (its UTC offset, e.g. -0700), {device_id}, {device_name}
+    /// and {tags}
+    #[arg(long, default_value = DEFAULT_PATH_TEMPLATE, global = true)]
+    path_template: PathTemplate,
+
+    /// Append the UTC offset to each clip's file name, e.g. 2024-05-12T14-03-22-0700.mp4, so
+    /// local times stay unambiguous; clips named without it are still recognized
+    #[arg(long, global = true)]
+    filename_offset: bool,
+
+    /// Which day's directory an event that spans midnight goes into
+    #[arg(long, value_enum, default_value = "start", global = true)]
+    day_assignment: DayAssignment,
+
+    /// How to keep clips from different cameras apart when --path-template doesn't name the camera
+    #[arg(long, value_enum, default_value = "suffix")]
+    device_collisions: DeviceCollisionPolicy,
+
+    /// JSON rules tagging events by local time of day and device; tags go in the sidecar and {tags}
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    tag_rules: Option<PathBuf>,
+
+    /// Number of concurrent downloads
+    #[arg(short, long, default_value = "10")]
+    concurrency: usize,
+
+    /// Number of devices whose event manifests are fetched at once, independent of --concurrency
+    #[arg(long, default_value = "4")]
+    discovery_concurrency: usize,
+
+    /// Interval in minutes to rediscover the account's cameras (0 = only at startup)
+    #[arg(long, default_value = "60")]
+    device_refresh_interval: u64,
+
+    /// Interval in minutes to check for new events
+    #[arg(short = 'i', long, default_value = "5")]
+    check_interval: u64,
+
+    /// Delay each event check by a random amount up to this many seconds, so instances polling
+    /// the same account don't all hit Google at once (0 = check exactly on the interval)
+    #[arg(long, default_value = "0")]
+    check_jitter_secs: u64,
+
+    /// Seconds to stop event queries short of now, leaving time for Nest to index recent events
+    #[arg(long, default_value = "30", value_parser = clap::value_parser!(i64).range(0..=3600))]
+    index_lag_secs: i64,
+
+    /// Seconds to wait before the first event check, e.g. while the network comes up at boot
+    #[arg(long, default_value = "0")]
+    startup_delay_secs: u64,
+
+    /// Where logs go, comma-separated or repeated: console, plus syslog or journald where built in;
+    /// an unreachable target falls back to the console
+    #[arg(long, value_delimiter = ',', default_value = "console")]
+    log_target: Vec<LogTarget>,
+
+    /// Syslog destination for --log-target syslog: a socket path, udp://HOST:PORT or tcp://HOST:PORT
+    #[cfg(feature = "syslog")]
+    #[arg(long, default_value = DEFAULT_SYSLOG_ADDR)]
+    syslog_addr: SyslogAddr,
+
+    /// Log per-device checks that find no events at trace level, with an hourly summary instead
+    #[arg(long)]
+    quiet_empty: bool,
+
+    /// Run once and exit instead of running continuously
+    #[arg(long)]
+    once: bool,
+
+    /// Write event metadata to user.nest.* extended attributes on each clip (Linux/macOS)
+    #[arg(long)]
+    xattrs: bool,
+
+    /// Write a Kodi/Jellyfin `<name>.nfo` file next to each clip
+    #[arg(long)]
+    nfo: bool,
+
+    /// Keep a playlist.m3u of each folder's clips in start time order
+    #[arg(long)]
+    playlist: bool,
+
+    /// Set the creation and modification times in each clip's MP4 headers to the event start
+    #[arg(long)]
+    embed_creation_time: bool,
+
+    /// Treat a failure to set a clip's modification time as a failed download
+    #[arg(long)]
+    strict_file_times: bool,
+
+    /// Re-download clips that already exist but are truncated or too small to be valid MP4s
+    #[arg(long)]
+    refresh_incomplete: bool,
+
+    /// Seconds to wait for a free download slot before skipping an event until the next check
+    #[arg(long, default_value = "300")]
+    permit_timeout_secs: u64,
+
+    /// With --once, download the newest events first, e.g. to stop a long backfill early
+    #[arg(long, requires = "once")]
+    newest_first: bool,
+
+    /// Order of a check's downloads: event order across cameras, or cameras taking turns so a
+    /// busy camera's backlog doesn't hold up the others
+    #[arg(long, value_enum, default_value = "time")]
+    download_order: DownloadOrder,
+
+    /// Download at most this many events per check; the rest wait for a later check
+    #[arg(long)]
+    max_events_per_cycle: Option<usize>,
+
+    /// Defaults for a kind of camera: event types, variant, padding and minimum event length;
+    /// flags passed explicitly still win
+    #[arg(long, value_enum)]
+    profile: Option<profile::Profile>,
+
+    /// Seconds of footage to download before each event, where the gap before it allows (max 600)
+    #[arg(long, default_value = "0", value_parser = clap::value_parser!(u64).range(0..=600))]
+    clip_padding_before: u64,
+
+    /// Seconds of footage to download after each event, where the gap after it allows (max 600)
+    #[arg(long, default_value = "0", value_parser = clap::value_parser!(u64).range(0..=600))]
+    clip_padding_after: u64,
+
+    /// Only download events newer than each camera's newest clip on disk, going by file names
+    /// under --path-template, which must name the camera
+    #[arg(long)]
+    incremental_by_filename: bool,
+
+    /// Skip events shorter than this many seconds (ignored with --continuous)
+    #[arg(long, default_value = "0")]
+    min_event_secs: u64,
+
+    /// Download events on the same camera at most this many seconds apart as one clip, up to
+    /// the 10 minute download cap
+    #[arg(long, conflicts_with = "continuous")]
+    merge_gap_seconds: Option<u64>,
+
+    /// Fetch at most this many clips per local calendar day, retries of a clip counting once;
+    /// the rest wait for the next day
+    #[arg(long)]
+    max_downloads_per_day: Option<usize>,
+
+    /// Download past --max-downloads-per-day, e.g. for a one-off backfill with --once
+    #[arg(long, requires = "max_downloads_per_day")]
+    ignore_quota: bool,
+
+    /// Number of days to keep videos (0 = keep forever, no pruning)
+    #[arg(long, default_value = "60")]
+    retention_days: u64,
+
+    /// Use hours instead of days for retention period (for testing)
+    #[arg(long)]
+    retention_hours: bool,
+
+    /// Interval in minutes to prune old videos
+    #[arg(long, default_value = "10")]
+    prune_interval: u64,
+
+    /// Free-space retention tier FREE_PERCENT:PERIOD: below FREE_PERCENT free space, keep videos for
+    /// at most PERIOD days (or hours), e.g. --retention-free-tier 20:30 --retention-free-tier 10:7
+    #[arg(long)]
+    retention_free_tier: Vec<FreeSpaceTier>,
+
+    /// Thinning tier AGE:BUCKET[:MAX]: past AGE keep at most MAX (default 1) clips per device per
+    /// BUCKET, e.g. --thin 14d:1h --thin 60d:1d (repeatable)
+    #[arg(long)]
+    thin: Vec<ThinTier>,
+
+    /// Log what pruning would delete without deleting anything
+    #[arg(long)]
+    prune_dry_run: bool,
+
+    /// After each prune pass, find byte-identical clips of a camera on the same day and handle every
+    /// copy after the first: hardlink (unix), skip (delete it) or report
+    #[arg(long, value_enum)]
+    dedup_identical: Option<dedup::DedupMode>,
+
+    /// Delete the oldest clips until the archive is at most this many GB
+    #[arg(long)]
+    max_disk_gb: Option<f64>,
+
+    /// When the output filesystem has less than this many GB free, delete the oldest clips regardless
+    /// of retention until --emergency-recovery-gb is free. Pinned clips and --keep-min-per-device stay
+    #[arg(long)]
+    emergency_free_gb: Option<f64>,
+
+    /// Free space in GB an emergency prune frees up to (default: twice --emergency-free-gb)
+    #[arg(long, requires = "emergency_free_gb")]
+    emergency_recovery_gb: Option<f64>,
+
+    /// Keep at most this many clips per device, deleting the oldest
+    #[arg(long)]
+    max_clips_per_device: Option<usize>,
+
+    /// Never prune a device's newest N clips, even past the retention period
+    #[arg(long, default_value = "5")]
+    keep_min_per_device: usize,
+
+    /// Only download events of these types: `,` separates alternatives, `+` requires all of the types,
+    /// e.g. person+motion,sound (ignored with --continuous)
+    #[arg(long)]
+    event_types: Option<EventTypeFilter>,
+
+    /// Download the full timeline in fixed-size chunks instead of discrete events
+    #[arg(long)]
+    continuous: bool,
+
+    /// Query each camera only from where the previous check left off, stopping before any event
+    /// that wasn't downloaded, instead of the full 12 hours every time
+    #[arg(long, conflicts_with = "continuous")]
+    since_last_run: bool,
+
+    /// After downtime, query back at most this many hours to catch up on missed events (values
+    /// below 12 keep the usual 12-hour window; max 1440, Nest Aware's 60 days of history)
+    #[arg(long, default_value = "72", value_parser = clap::value_parser!(u64).range(0..=1440))]
+    max_catch_up_hours: u64,
+
+    /// Spend at most this many API requests per check on cameras catching up, event lists
+    /// included, so a long backfill is spread over several checks
+    #[arg(long, alias = "catchup-budget", conflicts_with = "continuous")]
+    catch_up_budget: Option<usize>,
+
+    /// Chunk length in minutes for continuous mode (capped at the maximum clip length)
+    #[arg(long, default_value = "5", value_parser = clap::value_parser!(i64).range(1..=10))]
+    chunk_minutes: i64,
+
+    /// Warn when a camera has reported no events for this long, e.g. 48h or 7d (0 disables)
+    #[arg(long, default_value = "48h", value_parser = thinning::parse_duration_spec)]
+    silence_threshold: chrono::Duration,
+
+    /// Per-device silence threshold DEVICE=DURATION, by name or ID, e.g. "Side Gate=7d" (repeatable)
+    #[arg(long)]
+    device_silence_threshold: Vec<DeviceSilenceThreshold>,
+
+    /// Manifest and clip `variant` to request, selecting a rendition set; unset requests the
+    /// manifest's default (2) and sends no variant with clip requests
+    #[arg(long, value_name = "VARIANT")]
+    quality: Option<u32>,
+
+    /// Per-device --quality DEVICE=VARIANT, by name or ID, e.g. "Attic=1" (repeatable)
+    #[arg(long)]
+    device_quality: Vec<DeviceQuality>,
+
+    /// Accept-Encoding header sent with OAuth requests (change only if a proxy breaks auth)
+    #[arg(long, default_value = "identity")]
+    oauth_accept_encoding: String,
+
+    /// google_play_services_version reported to Google's auth endpoint; bump it if auth fails with
+    /// BadAuthentication
+    #[arg(long, default_value = "240913000")]
+    play_services_version: String,
+
+    /// Refresh OAuth tokens this many seconds before their hour is up, so one that's about to
+    /// expire, e.g. after waking from sleep, isn't sent (max 3000)
+    #[arg(long, default_value = "60", value_parser = clap::value_parser!(u64).range(0..=3000))]
+    token_expiry_grace_secs: u64,
+
+    /// Google Home Foyer API endpoint for device discovery, e.g. a regional endpoint or a gRPC proxy
+    #[arg(
+        long,
+        env = "NEST_SYNC_FOYER_ENDPOINT",
+        default_value = DEFAULT_FOYER_ENDPOINT,
+        value_parser = parse_foyer_endpoint
+    )]
+    foyer_endpoint: Uri,
+
+    /// Nest API namespace used in event and clip URLs; other camera generations may need another one
+    #[arg(long, default_value = DEFAULT_NEST_API_NAMESPACE)]
+    nest_api_namespace: String,
+
+    /// `types` parameter of event manifest requests; see `discover-types` if events stop showing up
+    #[arg(long, default_value = DEFAULT_EVENT_QUERY_TYPES)]
+    event_query_types: String,
+
+    /// PEM root certificate to trust in addition to the system's, e.g. for a TLS intercepting proxy
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    ca_cert: Option<PathBuf>,
+
+    /// Directory to keep OAuth tokens in between runs, so restarts don't each need a fresh exchange
+    #[arg(long, env = "NEST_SYNC_TOKEN_CACHE_DIR", value_hint = ValueHint::DirPath)]
+    token_cache_dir: Option<PathBuf>,
+
+    /// Android ID (16 hex digits) reported to Google's auth endpoint instead of a generated one
+    #[arg(long, env = "NEST_SYNC_ANDROID_ID")]
+    android_id: Option<String>,
+
+    /// Skip TLS certificate verification, to inspect traffic through an intercepting proxy such as
+    /// mitmproxy. Refused in daemon mode or without a terminal unless NEST_SYNC_ALLOW_INSECURE_TLS=1
+    #[cfg(feature = "insecure-tls")]
+    #[arg(long)]
+    danger_insecure_tls: bool,
+
+    /// Address family for connections to Google, e.g. v4 when the IPv6 route is unreliable
+    #[arg(long, value_enum, default_value_t = IpVersion::Auto)]
+    ip_version: IpVersion,
+
+    /// Pin a host to fixed addresses, HOST=ADDR[,ADDR...] or curl's HOST:PORT:ADDR (repeatable)
+    #[arg(long, value_name = "HOST=ADDR")]
+    resolve: Vec<ResolveOverride>,
+
+    /// Resolve host names through this DNS server instead of the system's: ADDR[:PORT] or
+    /// https://HOST[/PATH] for DNS over HTTPS
+    #[cfg(feature = "hickory-dns")]
+    #[arg(long)]
+    dns_server: Option<google_auth::DnsServer>,
+
+    /// Seconds an idle pooled HTTP connection is kept open for reuse
+    #[arg(long, default_value = "90")]
+    http_pool_idle_timeout_secs: u64,
+
+    /// Idle HTTP connections kept open per host
+    #[arg(long, default_value = "10")]
+    http_pool_max_idle_per_host: usize,
+
+    /// Seconds between saves of changed download state while a cycle runs; it is also saved at
+    /// the end of each cycle and on shutdown
+    #[arg(long, default_value = "60")]
+    state_flush_interval_secs: u64,
+
+    /// Compact the state file at startup and daily, as the compact-state subcommand does
+    #[arg(long)]
+    auto_compact_state: bool,
+
+    /// Minutes to wait before retrying a failed download; doubles with each failure (max 24h)
+    #[arg(long, default_value = "5")]
+    retry_backoff_minutes: i64,
+
+    /// Give up on an event permanently after this many failed downloads
+    #[arg(long)]
+    max_download_failures: Option<u32>,
+
+    /// Shell command to run for every downloaded clip; the clip's path is passed as $1 and in
+    /// NEST_SYNC_PATH
+    #[arg(long, value_hint = ValueHint::CommandString)]
+    exec_on_download: Option<String>,
+
+    /// Delete the clip and retry the event later when the --exec-on-download command fails
+    #[arg(long, requires = "exec_on_download")]
+    delete_on_exec_failure: bool,
+
+    /// MQTT broker to publish a message to for every downloaded clip (e.g. mqtt://broker:1883)
+    #[arg(long)]
+    mqtt_url: Option<String>,
+
+    /// MQTT topic template; supports {device_id} and {device_name}
+    #[arg(long, default_value = "nest-sync/{device_id}/events")]
+    mqtt_topic: String,
+
+    /// Address to serve Prometheus metrics (/metrics) and JSON status (/status) on
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Write the /status report to this file after each check cycle, replacing it atomically
+    #[arg(long)]
+    status_file: Option<PathBuf>,
+
+    /// URL to ping after every check in which each camera was checked, for dead man's switch
+    /// monitoring such as healthchecks.io
+    #[arg(long, value_hint = ValueHint::Url)]
+    heartbeat_url: Option<Url>,
+
+    /// HTTP method of the heartbeat
+    #[arg(long, value_enum, default_value = "get", requires = "heartbeat_url")]
+    heartbeat_method: HeartbeatMethod,
+
+    /// Send the check's summary as the JSON body of a POST heartbeat
+    #[arg(long, requires = "heartbeat_url")]
+    heartbeat_summary: bool,
+
+    /// Sentry DSN to report error-level logs and panics to
+    #[cfg(feature = "sentry")]
+    #[arg(long, env = "SENTRY_DSN")]
+    sentry_dsn: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Report archive disk usage by device, month and event type, with a projected full date
+    Du {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Summarize archived clip counts and recorded minutes per day, hour or device
+    Stats(stats::StatsArgs),
+    /// Move existing clips from one path template to another
+    Migrate(migrate::MigrateArgs),
+    /// Write every archived clip to a CSV file: device, date, start, end, duration, size and path
+    ExportCsv(export::ExportCsvArgs),
+    /// Stream matching clips and their sidecars to stdout as a tar or zip archive
+    Export(export::ExportArgs),
+    /// Prune the archive once with the configured retention policy and exit
+    Prune(prune::PruneArgs),
+    /// List or clear events that are no longer retried because they can't be downloaded
+    Failures(failures::FailuresArgs),
+    /// Remove state entries for events too old to be retried or kept, and for cameras not checked
+    /// since; stop the daemon first
+    CompactState(compact::CompactStateArgs),
+    /// Record clips already archived by another tool in the state file so their events aren't
+    /// downloaded again; stop the daemon first
+    ImportExisting(import::ImportExistingArgs),
+    /// Check clips against their sidecars and report drift
+    Verify(verify::VerifyArgs),
+    /// Check the flags and environment for mistakes without touching the network
+    ValidateConfig,
+    /// Rewrite the NFO file of every clip from its sidecar
+    RegenNfo,
+    /// Rebuild the playlist.m3u of every folder of clips
+    Playlist(playlist::PlaylistArgs),
+    /// Probe which event type codes return events for each camera, to pick --event-query-types
+    DiscoverTypes(discover::DiscoverTypesArgs),
+    /// Print a shell completion script, e.g. `nest-sync completions bash > /etc/bash_completion.d/nest-sync`
+    Completions(completions::CompletionsArgs),
+    /// Install, uninstall or run nest-sync as a Windows service
+    #[cfg(windows)]
+    Service(service::ServiceArgs),
+}
+
+/// Set to `1` to allow `--danger-insecure-tls` in daemon mode or without a
+/// terminal, where nobody sees the warning.
+#[cfg(feature = "insecure-tls")]
+const ALLOW_INSECURE_TLS_ENV: &str = "NEST_SYNC_ALLOW_INSECURE_TLS";
+
+/// Warns loudly about `--danger-insecure-tls`, and refuses it where the
+/// warning would go unseen unless [`ALLOW_INSECURE_TLS_ENV`] confirms it.
+#[cfg(feature = "insecure-tls")]
+fn confirm_insecure_tls(args: &Args) -> Result<()> {
+    use std::io::IsTerminal;
+
+    if !args.danger_insecure_tls {
+        return Ok(());
+    }
+
+    let message = "WARNING: --danger-insecure-tls disables TLS certificate verification. Anyone on the \
+                   network path can read and change traffic to Google, including your tokens.";
+    if std::io::stderr().is_terminal() {
+        eprintln!("\x1b[1;31m{message}\x1b[0m");
+    } else {
+        eprintln!("{message}");
+    }
+    warn!("TLS certificate verification is disabled (--danger-insecure-tls)");
+    if args.ca_cert.is_some() {
+        warn!("--ca-cert is redundant with --danger-insecure-tls, which accepts any certificate");
+    }
+
+    let allowed = std::env::var(ALLOW_INSECURE_TLS_ENV).is_ok_and(|v| v == "1");
+    let daemon = args.command.is_none() && !args.once;
+    let interactive = std::io::stdin().is_terminal() && std::io::stderr().is_terminal();
+    if !allowed && daemon {
+        anyhow::bail!(
+            "Refusing to run the daemon with --danger-insecure-tls; use --once, or set {ALLOW_INSECURE_TLS_ENV}=1"
+        );
+    }
+    if !allowed && !interactive {
+        anyhow::bail!(
+            "Refusing --danger-insecure-tls without a terminal; set {ALLOW_INSECURE_TLS_ENV}=1 to allow it"
+        );
+    }
+    Ok(())
+}
+
+/// Exits with status 1, first giving Sentry a moment to send pending events
+/// since `exit` skips the client guard's flush on drop.
+fn exit_failure() -> ! {
+    #[cfg(feature = "sentry")]
+    if let Some(client) = sentry::Hub::current().client() {
+        client.flush(Some(Duration::from_secs(2)));
+    }
+    std::process::exit(1);
+}
+
+#[cfg(feature = "sentry")]
+fn init_sentry(args: &Args) -> Option<sentry::ClientInitGuard> {
+    args.sentry_dsn.as_deref().map(|dsn| {
+        sentry::init((
+            dsn,
+            sentry::ClientOptions {
+                release: Some(version::NEST_SYNC_VERSION.version.into()),
+                ..Default::default()
+            },
+        ))
+    })
+}
+
+impl Args {
+    /// Builds the arguments from parsed `matches`, filling in `--profile`
+    /// defaults and resolving the path template.
+    fn from_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        let args = <Self as FromArgMatches>::from_arg_matches(matches)?;
+        Ok(profile::apply(args, matches).resolve_path_template())
+    }
+
+    /// Applies `--filename-offset` and `--day-assignment` to the path
+    /// template.
+    fn resolve_path_template(mut self) -> Self {
+        if self.filename_offset {
+            self.path_template = self.path_template.with_offset();
+        }
+        self.path_template = self.path_template.with_day_assignment(self.day_assignment);
+        self
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // Loaded before parsing so flags backed by environment variables see .env
+    dotenvy::dotenv().ok();
+    let matches = Args::command().get_matches();
+    let args = Arc::new(Args::from_matches(&matches).unwrap_or_else(|e| e.exit()));
+
+    // The service reads its flags from its own configuration and logs to a
+    // file, so it sets up everything itself
+    #[cfg(windows)]
+    if let Some(Command::Service(service_args)) = &args.command {
+        if let Err(e) = service::run(service_args) {
+            eprintln!("Error: {e:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    #[cfg(feature = "sentry")]
+    let _sentry = init_sentry(&args);
+
+    // Subcommands print their report on stdout, so their logs go to stderr
+    let writer = if args.command.is_some() {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+    logging::init(Some(&args), writer, true);
+
+    #[cfg(feature = "insecure-tls")]
+    if let Err(e) = confirm_insecure_tls(&args) {
+        error!(error = %e, "Insecure TLS not confirmed");
+        exit_failure();
+    }
+
+    if let Some(command) = &args.command {
+        let output_path = resolve_output_path(&args);
+        let result = match command {
+            Command::Du { json } => du::run(&output_path, *json),
+            Command::Stats(stats_args) => stats::run(&output_path, stats_args, &args.path_template),
+            Command::Migrate(migrate_args) => {
+                migrate::run(&output_path, migrate_args, &args.path_template)
+            }
+            Command::ExportCsv(export_args) => {
+                export::run(&output_path, export_args, &args.path_template)
+            }
+            Command::Export(export_args) => {
+                export::run_archive(&output_path, export_args, &args.path_template)
+            }
+            Command::Prune(prune_args) => {
+                let policy = PrunePolicy::for_subcommand(&args, prune_args);
+                prune::prune_old_videos(&output_path, &policy).await
+            }
+            Command::ImportExisting(import_args) => {
+                import::run(&output_path, import_args, &args.path_template)
+            }
+            Command::Verify(verify_args) => verify::run(&output_path, verify_args),
+            Command::ValidateConfig => validate::run(&args, &output_path),
+            Command::Failures(failures_args) => failures::run(&output_path, failures_args),
+            Command::CompactState(compact_args) => {
+                compact::run(&output_path, compact_args, state_compaction_cutoff(&args))
+            }
+            Command::RegenNfo => nfo::regenerate(&output_path),
+            Command::Playlist(playlist_args) => playlist::run(&output_path, playlist_args),
+            Command::DiscoverTypes(discover_args) => discover::run(&args, discover_args).await,
+            Command::Completions(completions_args) => completions::run(completions_args),
+            #[cfg(windows)]
+            Command::Service(_) => unreachable!("handled before logging is set up"),
+        };
+        if let Err(e) = result {
+            error!(error = %e, "Command failed");
+            exit_failure();
+        }
+        return;
+    }
+
+    run_daemon(args, shutdown_signal()).await;
+}
+
+/// Checks for events until `shutdown` resolves, then waits for the running
+/// cycle and prune pass and saves the state. With `--once`, runs one cycle
+/// and one prune pass instead.
+async fn run_daemon(args: Arc<Args>, shutdown: impl Future<Output = ()>) {
+    let output_path = resolve_output_path(&args);
+    let mut config_valid = true;
+    for finding in validate::check(&args, &output_path) {
+        match finding.severity {
+            validate::Severity::Error => {
+                error!(problem = %finding.message, "Invalid configuration");
+                config_valid = false;
+            }
+            validate::Severity::Warning => {
+                warn!(problem = %finding.message, "Questionable configuration")
+            }
+        }
+    }
+    if !config_valid {
+        exit_failure();
+    }
+
+    if let Some(addr) = args.metrics_addr {
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(addr).await {
+                error!(error = %e, "Metrics endpoint stopped");
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    tokio::spawn(logging::cycle_filter_on_sigusr2());
+
+    let app_state = Arc::new(Mutex::new(None));
+    let state = SharedStateStore::load(&output_path);
+    let cycle_running = Arc::new(AtomicBool::new(false));
+    let mut cycle_started = Instant::now();
+    let prune_running = Arc::new(AtomicBool::new(false));
+    let mut prune_started = Instant::now();
+
+    let semaphore = Arc::new(Semaphore::new(args.concurrency));
+    let discovery_semaphore = Arc::new(Semaphore::new(args.discovery_concurrency));
+    let startup_delay = Duration::from_secs(args.startup_delay_secs);
+    if !startup_delay.is_zero() {
+        info!(
+            startup_delay_secs = args.startup_delay_secs,
+            "Delaying first event check"
+        );
+    }
+    let mut check_schedule = CheckSchedule::new(
+        Instant::now() + startup_delay,
+        Duration::from_secs(args.check_interval * 60),
+        Duration::from_secs(args.check_jitter_secs),
+    );
+
+    let mut prune_interval = time::interval(Duration::from_secs(args.prune_interval * 60));
+    prune_interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+
+    if !args.once {
+        info!(
+            check_interval = args.check_interval,
+            check_jitter_secs = args.check_jitter_secs,
+            "Checking for events at regular intervals"
+        );
+        if args.retention_days > 0 {
+            let unit = if args.retention_hours {
+                "hours"
+            } else {
+                "days"
+            };
+            info!(
+                retention_days = args.retention_days,
+                unit,
+                prune_interval = args.prune_interval,
+                "Video pruning enabled"
+            );
+        } else {
+            info!("Video pruning disabled (retention_days = 0)");
+        }
+    }
+
+    if args.once {
+        // One cycle, then one prune pass, honoring the startup delay and jitter
+        check_schedule.tick().await;
+        cycle_running.store(true, Ordering::Release);
+        run_check_cycle(
+            app_state,
+            state.clone(),
+            semaphore,
+            discovery_semaphore,
+            args.clone(),
+            cycle_running,
+        )
+        .await;
+        state.flush();
+        prune_running.store(true, Ordering::Release);
+        run_prune_pass(output_path, PrunePolicy::from_args(&args), prune_running).await;
+        return;
+    }
+
+    // Failure records written mid-cycle reach disk without waiting for the
+    // cycle to end
+    let mut state_flush_interval =
+        time::interval(Duration::from_secs(args.state_flush_interval_secs));
+    state_flush_interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+
+    let mut compaction_interval = time::interval(STATE_COMPACTION_INTERVAL);
+    compaction_interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+
+    let emergency = EmergencyThresholds::from_args(&args);
+    let mut free_space_interval = time::interval(EMERGENCY_CHECK_INTERVAL);
+    free_space_interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+    let mut emergency_fired = false;
+
+    let mut cycle_task: Option<JoinHandle<()>> = None;
+    let mut prune_task: Option<JoinHandle<()>> = None;
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = check_schedule.tick() => {
+                if cycle_running.load(Ordering::Acquire) {
+                    warn!(
+                        running_secs = cycle_started.elapsed().as_secs(),
+                        "Previous polling cycle is still running; skipping this tick."
+                    );
+                    continue;
+                }
+
+                cycle_running.store(true, Ordering::Release);
+                cycle_started = Instant::now();
+                cycle_task = Some(tokio::spawn(run_check_cycle(
+                    app_state.clone(),
+                    state.clone(),
+                    semaphore.clone(),
+                    discovery_semaphore.clone(),
+                    args.clone(),
+                    cycle_running.clone(),
+                )));
+            }
+            _ = prune_interval.tick() => {
+                if prune_running.load(Ordering::Acquire) {
+                    warn!(
+                        running_secs = prune_started.elapsed().as_secs(),
+                        "Previous prune pass is still running; skipping this tick."
+                    );
+                    continue;
+                }
+
+                prune_running.store(true, Ordering::Release);
+                prune_started = Instant::now();
+                prune_task = Some(tokio::spawn(run_prune_pass(
+                    output_path.clone(),
+                    PrunePolicy::from_args(&args),
+                    prune_running.clone(),
+                )));
+            }
+            _ = free_space_interval.tick(), if emergency.is_some() => {
+                let Some(thresholds) = emergency else {
+                    continue;
+                };
+                let available = match fs4::available_space(&output_path) {
+                    Ok(available) => available,
+                    Err(e) => {
+                        warn!(error = %e, "Failed to read free space for emergency pruning");
+                        continue;
+                    }
+                };
+                if available >= thresholds.floor {
+                    emergency_fired = false;
+                    continue;
+                }
+                // One pass per drop below the floor, so clips aren't deleted
+                // one by one as they arrive when nothing else can be freed
+                if emergency_fired || prune_running.load(Ordering::Acquire) {
+                    continue;
+                }
+
+                emergency_fired = true;
+                prune_running.store(true, Ordering::Release);
+                prune_started = Instant::now();
+                prune_task = Some(tokio::spawn(run_emergency_prune(
+                    output_path.clone(),
+                    PrunePolicy::from_args(&args),
+                    thresholds,
+                    available,
+                    prune_running.clone(),
+                )));
+            }
+            _ = state_flush_interval.tick() => state.flush(),
+            _ = compaction_interval.tick(), if args.auto_compact_state => {
+                let cutoff = state_compaction_cutoff(&args);
+                let compaction = state.lock().compact(cutoff);
+                if compaction.total() > 0 {
+                    info!(
+                        cutoff = %cutoff,
+                        failures = compaction.failures,
+                        ignored = compaction.ignored,
+                        merged = compaction.merged,
+                        imported = compaction.imported,
+                        devices = compaction.devices,
+                        "Compacted state file"
+                    );
+                    state.flush();
+                }
+            }
+            _ = &mut shutdown => {
+                info!("Shutting down; waiting for the running cycle and prune pass to finish");
+                break;
+            }
+        }
+    }
+
+    for task in [cycle_task, prune_task].into_iter().flatten() {
+        if let Err(e) = task.await {
+            error!(error = %e, "Task panicked during shutdown");
+        }
+    }
+    state.flush();
+    info!("Shutdown complete");
+}
+
+/// When to run event checks: every `period` from `start`, each check delayed
+/// by a fresh random amount up to `jitter`. Jitter doesn't accumulate, since
+/// each delay is measured from the unjittered slot, and slots that have
+/// already passed are skipped like a `time::interval` with
+/// `MissedTickBehavior::Skip`.
+struct CheckSchedule {
+    slot: Instant,
+    period: Duration,
+    jitter: Duration,
+    deadline: Instant,
+}
+
+impl CheckSchedule {
+    fn new(start: Instant, period: Duration, jitter: Duration) -> Self {
+        let mut schedule = Self {
+            slot: start,
+            period,
+            jitter,
+            deadline: start,
+        };
+        schedule.deadline = schedule.jittered(start);
+        schedule
+    }
+
+    fn jittered(&self, slot: Instant) -> Instant {
+        if self.jitter.is_zero() {
+            return slot;
+        }
+        let jitter_ms = rand::random_range(0..=self.jitter.as_millis() as u64);
+        let deadline = slot + Duration::from_millis(jitter_ms);
+        debug!(delay_ms = jitter_ms, "Jittered next event check");
+        deadline
+    }
+
+    /// Waits for the next check, then schedules the one after it.
+    async fn tick(&mut self) {
+        time::sleep_until(self.deadline).await;
+        let now = Instant::now();
+        self.slot += self.period;
+        while self.slot <= now {
+            self.slot += self.period;
+        }
+        self.deadline = self.jittered(self.slot);
+    }
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM where there is one.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                warn!(error = %e, "Failed to listen for SIGTERM; only Ctrl-C shuts down cleanly");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn start_time() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 6, 1, 12, 0, 0).unwrap()
+    }
+
+    fn mtime(path: &Path) -> i64 {
+        FileTime::from_last_modification_time(&fs::metadata(path).unwrap()).unix_seconds()
+    }
+
+    #[test]
+    fn writes_clip_with_event_time() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("clip.mp4");
+
+        let intended = write_clip(&path, b"video", start_time(), false).unwrap();
+
+        assert_eq!(intended, None);
+        assert_eq!(fs::read(&path).unwrap(), b"video");
+        assert_eq!(mtime(&path), start_time().timestamp());
+        assert!(!path.with_extension("mp4.tmp").exists());
+    }
+
+    #[test]
+    fn replaces_truncated_clip_whole() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("clip.mp4");
+        fs::write(&path, b"vid").unwrap();
+
+        write_clip(&path, b"video", start_time(), false).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"video");
+    }
+
+    #[test]
+    fn failed_write_leaves_nothing_behind() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("missing").join("clip.mp4");
+
+        assert!(write_clip(&path, b"video", start_time(), false).is_err());
+        assert!(!path.exists());
+        assert!(!path.with_extension("mp4.tmp").exists());
+    }
+
+    #[test]
+    fn read_only_clip_gets_event_time() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("clip.mp4");
+        fs::write(&path, b"video").unwrap();
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions).unwrap();
+
+        let intended = set_clip_times(&path, start_time(), true).unwrap();
+
+        assert_eq!(intended, None);
+        assert_eq!(mtime(&path), start_time().timestamp());
+    }
+
+    #[test]
+    fn failed_time_set_records_intended_time() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("gone.mp4");
+
+        let intended = set_clip_times(&path, start_time(), false).unwrap();
+
+        assert_eq!(intended, Some(start_time()));
+    }
+
+    #[test]
+    fn strict_time_set_failure_is_an_error() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("gone.mp4");
+
+        assert!(set_clip_times(&path, start_time(), true).is_err());
+    }
+
+    #[tokio::test]
+    async fn panicked_download_is_retried_next_cycle() {
+        let dir = TempDir::new().unwrap();
+        let state = SharedStateStore::load(dir.path());
+        let backoff = FailureBackoff {
+            base: chrono::Duration::minutes(5),
+            max: chrono::Duration::hours(24),
+            max_failures: None,
+        };
+        let mut storage = StorageMonitor::new(dir.path());
+        let end_time = start_time() + chrono::Duration::hours(1);
+        let mut cycle_cursor = CycleCursor::new(end_time);
+        let mut progress = DownloadProgress::default();
+
+        let event = CameraEvent::new(
+            "porch".to_string(),
+            start_time(),
+            chrono::Duration::seconds(10),
+        );
+        let mut join_set: JoinSet<Result<u64>> = JoinSet::new();
+        let mut in_flight = HashMap::new();
+        let handle = join_set.spawn(async { panic!("download panicked") });
+        in_flight.insert(handle.id(), event);
+
+        let result = join_set.join_next_with_id().await.unwrap();
+        assert!(result.is_err());
+        handle_download_result(
+            result,
+            &mut in_flight,
+            &mut progress,
+            &state,
+            &backoff,
+            &mut cycle_cursor,
+            &mut storage,
+        );
+
+        assert!(in_flight.is_empty());
+        assert_eq!(progress.completed_count, 0);
+        cycle_cursor.finish(&mut state.lock(), ["porch"]);
+        assert!(state.lock().checked_until("porch") < Some(start_time()));
+    }
+
+    #[test]
+    fn padded_windows_stay_between_neighbours_and_before_query_end() {
+        let args = Args::try_parse_from([
+            "nest-sync",
+            "--clip-padding-before",
+            "600",
+            "--clip-padding-after",
+            "600",
+        ])
+        .unwrap();
+        let event = |offset_secs, duration_secs| {
+            CameraEvent::new(
+                "porch".to_string(),
+                start_time() + chrono::Duration::seconds(offset_secs),
+                chrono::Duration::seconds(duration_secs),
+            )
+        };
+        let events = vec![event(0, 10), event(40, 10), event(300, 20)];
+        let query_end = start_time() + chrono::Duration::seconds(330);
+
+        let windows = padded_windows(&events, &args, query_end);
+
+        let mut spans: Vec<_> = events
+            .iter()
+            .map(|event| (event, windows[&event.event_id()]))
+            .collect();
+        spans.sort_by_key(|(event, _)| event.start_time);
+        for (event, (start, end)) in &spans {
+            assert!(*start <= event.start_time && *end >= event.end_time());
+            assert!(*end <= query_end);
+        }
+        for pair in spans.windows(2) {
+            let ((_, (_, end)), (next, (next_start, _))) = (pair[0], pair[1]);
+            assert!(end <= next_start && next_start <= next.start_time);
+        }
+        assert_eq!(spans[0].1.0, start_time() - chrono::Duration::seconds(600));
+        assert_eq!(spans[2].1.1, query_end);
+    }
+
+    #[test]
+    fn clip_padding_over_ten_minutes_is_rejected() {
+        for flag in ["--clip-padding-before", "--clip-padding-after"] {
+            assert!(Args::try_parse_from(["nest-sync", flag, "601"]).is_err());
+            assert!(Args::try_parse_from(["nest-sync", flag, &u64::MAX.to_string()]).is_err());
+        }
+    }
+
+    #[test]
+    fn max_catch_up_beyond_sixty_days_is_rejected() {
+        assert!(Args::try_parse_from(["nest-sync", "--max-catch-up-hours", "1440"]).is_ok());
+        assert!(Args::try_parse_from(["nest-sync", "--max-catch-up-hours", "1441"]).is_err());
+        assert!(Args::try_parse_from(["nest-sync", "--max-catch-up-hours", "3000000000"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn expired_clip_is_ignored_without_backing_off() {
+        let dir = TempDir::new().unwrap();
+        let state = SharedStateStore::load(dir.path());
+        let backoff = FailureBackoff {
+            base: chrono::Duration::minutes(5),
+            max: chrono::Duration::hours(24),
+            max_failures: None,
+        };
+        let mut storage = StorageMonitor::new(dir.path());
+        let mut cycle_cursor = CycleCursor::new(start_time() + chrono::Duration::hours(1));
+        let mut progress = DownloadProgress::default();
+
+        let event = CameraEvent::new(
+            "porch".to_string(),
+            start_time(),
+            chrono::Duration::seconds(10),
+        );
+        let event_id = event.event_id();
+        let mut join_set: JoinSet<Result<u64>> = JoinSet::new();
+        let mut in_flight = HashMap::new();
+        let handle = join_set.spawn(async {
+            Err(ApiError::ClipExpired {
+                response: "404 Not Found",
+            }
+            .into())
+        });
+        in_flight.insert(handle.id(), event);
+
+        let result = join_set.join_next_with_id().await.unwrap();
+        handle_download_result(
+            result,
+            &mut in_flight,
+            &mut progress,
+            &state,
+            &backoff,
+            &mut cycle_cursor,
+            &mut storage,
+        );
+
+        assert_eq!(progress.expired_count, 1);
+        let store = state.lock();
+        assert!(store.is_ignored(&event_id));
+        assert!(store.failure(&event_id).is_none());
+        drop(store);
+        // Not retried: the cursor moves past the event
+        cycle_cursor.finish(&mut state.lock(), ["porch"]);
+        assert!(state.lock().checked_until("porch") > Some(start_time()));
+    }
+
+    fn camera(device_id: &str, device_name: &str) -> DiscoveredDevice {
+        DiscoveredDevice {
+            device_id: device_id.to_string(),
+            device_name: device_name.to_string(),
+        }
+    }
+
+    fn paths_for_simultaneous_events(template: &str, output_path: &Path) -> (PathBuf, PathBuf) {
+        let template: PathTemplate = template.parse().unwrap();
+        let mut claimed_paths = HashMap::new();
+        let mut paths = Vec::new();
+        for device in [camera("device-1", "Porch"), camera("device-2", "Garage")] {
+            let event = CameraEvent::new(
+                device.device_id.clone(),
+                start_time(),
+                chrono::Duration::seconds(10),
+            );
+            let path = clip_path(&template, output_path, &event, &device, &[], &claimed_paths);
+            claimed_paths.insert(path.clone(), device.device_id);
+            paths.push(path);
+        }
+        (paths.remove(0), paths.remove(0))
+    }
+
+    #[test]
+    fn simultaneous_events_on_two_cameras_get_separate_paths() {
+        let dir = TempDir::new().unwrap();
+
+        let (porch, garage) = paths_for_simultaneous_events(DEFAULT_PATH_TEMPLATE, dir.path());
+
+        assert_eq!(porch.file_name().unwrap(), "2025-06-01T05-00-00.mp4");
+        assert_eq!(
+            garage.file_name().unwrap(),
+            "2025-06-01T05-00-00-Garage.mp4"
+        );
+        assert_eq!(porch.parent(), garage.parent());
+    }
+
+    #[test]
+    fn template_naming_the_camera_needs_no_suffix() {
+        let dir = TempDir::new().unwrap();
+
+        let (porch, garage) = paths_for_simultaneous_events(
+            "{device_name}/{year}-{month}-{day}T{hour}-{minute}-{second}.mp4",
+            dir.path(),
+        );
+
+        assert_eq!(porch, dir.path().join("Porch/2025-06-01T05-00-00.mp4"));
+        assert_eq!(garage, dir.path().join("Garage/2025-06-01T05-00-00.mp4"));
+    }
+
+    #[test]
+    fn clip_downloaded_in_an_earlier_cycle_claims_its_path() {
+        let dir = TempDir::new().unwrap();
+        let template: PathTemplate = DEFAULT_PATH_TEMPLATE.parse().unwrap();
+        let porch = camera("device-1", "Porch");
+        let garage = camera("device-2", "");
+        let porch_event = CameraEvent::new(
+            porch.device_id.clone(),
+            start_time(),
+            chrono::Duration::seconds(10),
+        );
+        let garage_event = CameraEvent::new(
+            garage.device_id.clone(),
+            start_time(),
+            chrono::Duration::seconds(10),
+        );
+        let no_claims = HashMap::new();
+
+        let archived = clip_path(&template, dir.path(), &porch_event, &porch, &[], &no_claims);
+        fs::create_dir_all(archived.parent().unwrap()).unwrap();
+        fs::write(&archived, b"video").unwrap();
+        let sidecar = archive::VideoMetadata::from(porch_event.clone()).with_device_name("Porch");
+        archive::write_sidecar(&archived, &sidecar).unwrap();
+
+        // The same camera finds its own clip; another is named apart, by ID
+        // as it has no name
+        assert_eq!(
+            clip_path(&template, dir.path(), &porch_event, &porch, &[], &no_claims),
+            archived
+        );
+        assert_eq!(
+            clip_path(
+                &template,
+                dir.path(),
+                &garage_event,
+                &garage,
+                &[],
+                &no_claims
+            ),
+            archived.with_file_name("2025-06-01T05-00-00-device-2.mp4")
+        );
+    }
+
+    /// Answers OAuth, event manifest and clip requests on a local port, with
+    /// one event per camera at `event_start`, except that listing
+    /// `failing_device`'s events fails. Returns the origin to point the
+    /// connection at and the paths requested so far.
+    async fn mock_google(
+        failing_device: &'static str,
+        event_start: DateTime<Utc>,
+    ) -> (String, Arc<std::sync::Mutex<Vec<String>>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let manifest = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static">
+  <Period id="event-1" programDateTime="{}" duration="PT10S" type="motion"/>
+</MPD>"#,
+            event_start.format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        );
+        let log = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let (log, manifest) = (log.clone(), manifest.clone());
+                tokio::spawn(async move {
+                    // Requests are small enough to arrive in one read, apart
+                    // from the OAuth form, whose content doesn't matter
+                    let mut buf = [0; 8192];
+                    let n = stream.read(&mut buf).await.unwrap();
+                    let request = String::from_utf8_lossy(&buf[..n]);
+                    let path = request.split(' ').nth(1).unwrap_or_default();
+                    let path = path.split('?').next().unwrap_or_default().to_string();
+                    log.lock().unwrap().push(path.clone());
+                    let (status, body) = if path == "/auth" {
+                        ("200 OK", "Auth=token".to_string())
+                    } else if path.ends_with(failing_device) {
+                        ("503 Service Unavailable", String::new())
+                    } else if path.starts_with("/dashmanifest/") {
+                        ("200 OK", manifest)
+                    } else if path.starts_with("/mp4clip/") {
+                        ("200 OK", "video".to_string())
+                    } else {
+                        ("404 Not Found", String::new())
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        (origin, requests)
+    }
+
+    #[tokio::test]
+    async fn failing_camera_does_not_stop_the_others_downloading() {
+        let dir = TempDir::new().unwrap();
+        let event_start = (Utc::now() - chrono::Duration::minutes(30)).trunc_subsecs(0);
+        let (origin, requests) = mock_google("device-1", event_start).await;
+        let args =
+            Args::try_parse_from(["nest-sync", "--output", dir.path().to_str().unwrap()]).unwrap();
+        let google_connection =
+            GoogleConnection::builder("master-token".to_string(), "user@example.com".to_string())
+                .auth_url(format!("{origin}/auth"))
+                .nest_api_origin(origin)
+                .build()
+                .unwrap();
+        let mut app = AppState {
+            google_connection,
+            nest_camera_devices: vec![camera("device-1", "Porch"), camera("device-2", "Garage")],
+            output_path: dir.path().to_path_buf(),
+            mqtt_publisher: None,
+            heartbeat: None,
+            storage: StorageMonitor::new(dir.path()),
+            state: SharedStateStore::load(dir.path()),
+            idle_log: IdleLog::new(),
+            silence: SilenceMonitor::new(args.silence_threshold, Vec::new()),
+            tag_rules: TagRules::default(),
+            devices_refreshed_at: Instant::now(),
+            refresh_devices: false,
+            removed_device_ids: HashSet::new(),
+            quota_reached_on: None,
+            verified_clips: mp4::VerifiedClips::default(),
+        };
+
+        let report = check_and_download_events(
+            &mut app,
+            &Arc::new(Semaphore::new(2)),
+            &Arc::new(Semaphore::new(2)),
+            &args,
+            Uuid::new_v4(),
+        )
+        .await
+        .unwrap();
+
+        let failures: Vec<_> = report.failures().map(|(name, _)| name).collect();
+        assert_eq!(failures, ["Porch"]);
+        assert_eq!(
+            report.checked_device_ids().collect::<Vec<_>>(),
+            ["device-2"]
+        );
+        let clips = archive::walk_clips(dir.path());
+        assert_eq!(clips.len(), 1);
+        let sidecar = clips[0].sidecar.as_ref().unwrap();
+        assert_eq!(sidecar.device_id, "device-2");
+        assert_eq!(sidecar.start_time, event_start);
+        let requests = requests.lock().unwrap();
+        assert!(requests.iter().any(|path| {
+            path.ends_with("/dashmanifest/namespace/nest-phoenix-prod/device/device-1")
+        }));
+        assert!(
+            requests
+                .iter()
+                .any(|path| path.ends_with("/mp4clip/namespace/nest-phoenix-prod/device/device-2"))
+        );
+        // The failed camera is checked again from where it was; the other
+        // moves on
+        let store = app.state.lock();
+        assert_eq!(store.checked_until("device-1"), None);
+        assert!(store.checked_until("device-2").is_some());
+    }
+
+    #[tokio::test]
+    async fn removed_camera_is_dropped_and_reset_when_re_added() {
+        let dir = TempDir::new().unwrap();
+        let args =
+            Args::try_parse_from(["nest-sync", "--output", dir.path().to_str().unwrap()]).unwrap();
+        let cameras = Arc::new(std::sync::Mutex::new(vec![
+            camera("device-1", "Porch"),
+            camera("device-2", "Garage"),
+        ]));
+        let google_connection =
+            GoogleConnection::builder("master-token".to_string(), "user@example.com".to_string())
+                .home_graph(cameras.clone())
+                .build()
+                .unwrap();
+        let mut app = AppState {
+            nest_camera_devices: google_connection.get_nest_camera_devices().await.unwrap(),
+            google_connection,
+            output_path: dir.path().to_path_buf(),
+            mqtt_publisher: None,
+            heartbeat: None,
+            storage: StorageMonitor::new(dir.path()),
+            state: SharedStateStore::load(dir.path()),
+            idle_log: IdleLog::new(),
+            silence: SilenceMonitor::new(args.silence_threshold, Vec::new()),
+            tag_rules: TagRules::default(),
+            devices_refreshed_at: Instant::now(),
+            refresh_devices: false,
+            removed_device_ids: HashSet::new(),
+            quota_reached_on: None,
+            verified_clips: mp4::VerifiedClips::default(),
+        };
+        app.state.lock().set_checked_until("device-2", start_time());
+
+        cameras.lock().unwrap().pop();
+        // The cached home graph still lists the camera; a refresh refetches it
+        assert_eq!(
+            app.google_connection
+                .get_nest_camera_devices()
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        app.refresh_devices().await;
+        assert_eq!(app.nest_camera_devices, [camera("device-1", "Porch")]);
+        assert!(app.removed_device_ids.contains("device-2"));
+        assert_eq!(
+            app.state.lock().checked_until("device-2"),
+            Some(start_time())
+        );
+
+        cameras.lock().unwrap().push(camera("device-2", "Garage"));
+        app.refresh_devices().await;
+        assert_eq!(
+            app.nest_camera_devices,
+            [camera("device-1", "Porch"), camera("device-2", "Garage")]
+        );
+        assert!(app.removed_device_ids.is_empty());
+        assert_eq!(app.state.lock().checked_until("device-2"), None);
     }
 }
@@ -0,0 +1,681 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write as _,
+    fs,
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+    },
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::America::Vancouver;
+use serde::Serialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, error, info, warn};
+
+use crate::version::{NEST_SYNC_VERSION, NestSyncVersion};
+
+/// Refreshes within this window count towards the abnormal-rate warning.
+const REFRESH_RATE_WINDOW_SECS: i64 = 60 * 60;
+/// More refreshes than this within the window usually means connections are
+/// being re-created in a loop.
+const REFRESH_RATE_WARN_THRESHOLD: usize = 10;
+
+pub static METRICS: Metrics = Metrics::new();
+
+#[derive(Debug, Clone, Copy)]
+pub enum TokenKind {
+    Access,
+    Nest,
+}
+
+/// Process-wide counters, exported via the metrics endpoint.
+pub struct Metrics {
+    access_token_refreshes: AtomicU64,
+    nest_token_refreshes: AtomicU64,
+    forced_token_refreshes: AtomicU64,
+    // Unix timestamps of the last refresh, 0 when never refreshed
+    access_token_refreshed_at: AtomicI64,
+    nest_token_refreshed_at: AtomicI64,
+    recent_refreshes: Mutex<VecDeque<i64>>,
+    refresh_rate_warned: AtomicBool,
+    check_cycles: AtomicU64,
+    check_cycle_overruns: AtomicU64,
+    last_check_cycle_ms: AtomicU64,
+    clips_expired: AtomicU64,
+    manifest_parse_failures: AtomicU64,
+    emergency_prunes: AtomicU64,
+    storage_unavailable: AtomicBool,
+    last_emergency_prune: Mutex<Option<EmergencyPrune>>,
+    download_quota: Mutex<Option<DownloadQuota>>,
+    // Keyed by device ID
+    devices: Mutex<BTreeMap<String, DeviceStatus>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenStats {
+    pub access_token_refreshes: u64,
+    pub nest_token_refreshes: u64,
+    pub forced_refreshes: u64,
+    pub last_refresh: Option<DateTime<Utc>>,
+    pub access_token_age_secs: Option<i64>,
+    pub nest_token_age_secs: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CycleStats {
+    pub cycles: u64,
+    /// Cycles that took longer than the check interval.
+    pub overruns: u64,
+    pub last_cycle_secs: Option<f64>,
+    /// Clips that expired before they could be downloaded.
+    pub clips_expired: u64,
+    /// Manifest Periods dropped because they couldn't be parsed.
+    pub manifest_parse_failures: u64,
+}
+
+/// The latest emergency prune pass, run because free space fell below
+/// `--emergency-free-gb`.
+#[derive(Debug, Clone, Serialize)]
+pub struct EmergencyPrune {
+    pub at: DateTime<Utc>,
+    pub deleted_count: usize,
+    pub freed_bytes: u64,
+    /// Whether free space got back to the recovery threshold.
+    pub recovered: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EmergencyPruneStats {
+    pub passes: u64,
+    pub last: Option<EmergencyPrune>,
+}
+
+/// Use of `--max-downloads-per-day` on the local day of the latest check.
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadQuota {
+    pub date: NaiveDate,
+    pub limit: usize,
+    /// Events whose clips were fetched that day.
+    pub used: usize,
+    pub remaining: usize,
+}
+
+/// When a camera last reported an event, as of its latest check.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceSilence {
+    /// Start of the newest event, or when tracking began if there's been none.
+    pub silent_since: DateTime<Utc>,
+    /// Silence threshold for this device; 0 when alerts are disabled.
+    pub threshold_secs: i64,
+    /// Whether the device is currently past its threshold.
+    pub alerting: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceStatus {
+    pub device_id: String,
+    pub device_name: String,
+    /// The device has disappeared from the account and is no longer polled;
+    /// its archived clips are kept.
+    pub removed: bool,
+    #[serde(flatten)]
+    pub silence: Option<DeviceSilence>,
+    pub silent_secs: Option<i64>,
+    /// When the device's events were last checked.
+    pub last_checked_at: Option<DateTime<Utc>>,
+    /// Start of the newest event downloaded since startup.
+    pub last_downloaded_event: Option<DateTime<Utc>>,
+    /// Failed event checks and downloads since the last one that succeeded.
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    /// Clips downloaded since local midnight.
+    pub clips_today: u64,
+    /// The local day `clips_today` counts.
+    #[serde(skip)]
+    clips_date: Option<NaiveDate>,
+}
+
+impl DeviceStatus {
+    fn record_error(&mut self, error: String) {
+        self.consecutive_failures += 1;
+        self.last_error = Some(error);
+        self.last_error_at = Some(Utc::now());
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusReport {
+    pub version: NestSyncVersion,
+    pub tokens: TokenStats,
+    pub check_cycles: CycleStats,
+    pub devices: Vec<DeviceStatus>,
+    /// A pass here means retention doesn't keep up with the disk.
+    pub emergency_prunes: EmergencyPruneStats,
+    /// The log filter in effect, which SIGUSR2 changes.
+    pub log_filter: Option<String>,
+    pub download_quota: Option<DownloadQuota>,
+    /// Downloads are paused because the output can't be written.
+    pub storage_unavailable: bool,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            access_token_refreshes: AtomicU64::new(0),
+            nest_token_refreshes: AtomicU64::new(0),
+            forced_token_refreshes: AtomicU64::new(0),
+            access_token_refreshed_at: AtomicI64::new(0),
+            nest_token_refreshed_at: AtomicI64::new(0),
+            recent_refreshes: Mutex::new(VecDeque::new()),
+            refresh_rate_warned: AtomicBool::new(false),
+            check_cycles: AtomicU64::new(0),
+            check_cycle_overruns: AtomicU64::new(0),
+            last_check_cycle_ms: AtomicU64::new(0),
+            clips_expired: AtomicU64::new(0),
+            manifest_parse_failures: AtomicU64::new(0),
+            emergency_prunes: AtomicU64::new(0),
+            storage_unavailable: AtomicBool::new(false),
+            last_emergency_prune: Mutex::new(None),
+            download_quota: Mutex::new(None),
+            devices: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn record_token_refresh(&self, kind: TokenKind) {
+        let now = Utc::now().timestamp();
+        let (counter, refreshed_at) = match kind {
+            TokenKind::Access => (
+                &self.access_token_refreshes,
+                &self.access_token_refreshed_at,
+            ),
+            TokenKind::Nest => (&self.nest_token_refreshes, &self.nest_token_refreshed_at),
+        };
+        let total = counter.fetch_add(1, Ordering::Relaxed) + 1;
+        refreshed_at.store(now, Ordering::Relaxed);
+        debug!(?kind, total, "Refreshed OAuth token");
+
+        let recent = {
+            let mut recent = self
+                .recent_refreshes
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            recent.push_back(now);
+            while recent
+                .front()
+                .is_some_and(|&t| now - t > REFRESH_RATE_WINDOW_SECS)
+            {
+                recent.pop_front();
+            }
+            recent.len()
+        };
+
+        if recent > REFRESH_RATE_WARN_THRESHOLD
+            && !self.refresh_rate_warned.swap(true, Ordering::Relaxed)
+        {
+            warn!(
+                refreshes_last_hour = recent,
+                "Abnormal OAuth token refresh rate; connections may be re-created in a loop"
+            );
+        }
+    }
+
+    pub fn record_forced_token_refresh(&self) {
+        self.forced_token_refreshes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a finished check cycle; returns the total overrun count.
+    pub fn record_check_cycle(&self, duration: std::time::Duration, overran: bool) -> u64 {
+        self.check_cycles.fetch_add(1, Ordering::Relaxed);
+        self.last_check_cycle_ms
+            .store(duration.as_millis() as u64, Ordering::Relaxed);
+        if overran {
+            self.check_cycle_overruns.fetch_add(1, Ordering::Relaxed) + 1
+        } else {
+            self.check_cycle_overruns.load(Ordering::Relaxed)
+        }
+    }
+
+    pub fn record_clip_expired(&self) {
+        self.clips_expired.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_manifest_parse_failures(&self, count: usize) {
+        self.manifest_parse_failures
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_emergency_prune(&self, prune: EmergencyPrune) {
+        self.emergency_prunes.fetch_add(1, Ordering::Relaxed);
+        *self
+            .last_emergency_prune
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(prune);
+    }
+
+    pub fn emergency_prune_stats(&self) -> EmergencyPruneStats {
+        EmergencyPruneStats {
+            passes: self.emergency_prunes.load(Ordering::Relaxed),
+            last: self
+                .last_emergency_prune
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+        }
+    }
+
+    pub fn set_storage_unavailable(&self, unavailable: bool) {
+        self.storage_unavailable
+            .store(unavailable, Ordering::Relaxed);
+    }
+
+    pub fn set_download_quota(&self, quota: DownloadQuota) {
+        *self
+            .download_quota
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(quota);
+    }
+
+    pub fn download_quota(&self) -> Option<DownloadQuota> {
+        self.download_quota
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    pub fn cycle_stats(&self) -> CycleStats {
+        let cycles = self.check_cycles.load(Ordering::Relaxed);
+        CycleStats {
+            cycles,
+            overruns: self.check_cycle_overruns.load(Ordering::Relaxed),
+            last_cycle_secs: (cycles > 0)
+                .then(|| self.last_check_cycle_ms.load(Ordering::Relaxed) as f64 / 1000.0),
+            clips_expired: self.clips_expired.load(Ordering::Relaxed),
+            manifest_parse_failures: self.manifest_parse_failures.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn record_device_silence(
+        &self,
+        device_id: &str,
+        device_name: &str,
+        silence: DeviceSilence,
+    ) {
+        self.update_device(device_id, device_name, |status| {
+            status.silence = Some(silence)
+        });
+    }
+
+    pub fn set_device_removed(&self, device_id: &str, device_name: &str, removed: bool) {
+        self.update_device(device_id, device_name, |status| status.removed = removed);
+    }
+
+    /// Records that a device's events were checked successfully.
+    pub fn record_device_check(&self, device_id: &str, device_name: &str) {
+        self.update_device(device_id, device_name, |status| {
+            status.last_checked_at = Some(Utc::now());
+            status.consecutive_failures = 0;
+        });
+    }
+
+    /// Records a failed event check for a device.
+    pub fn record_device_check_error(&self, device_id: &str, device_name: &str, error: String) {
+        self.update_device(device_id, device_name, |status| status.record_error(error));
+    }
+
+    /// Records a failed download for a device checked before.
+    pub fn record_device_error(&self, device_id: &str, error: String) {
+        self.update_known_device(device_id, |status| status.record_error(error));
+    }
+
+    /// Records a downloaded clip of the event starting at `start_time`, for a
+    /// device checked before.
+    pub fn record_device_download(&self, device_id: &str, start_time: DateTime<Utc>) {
+        let today = Utc::now().with_timezone(&Vancouver).date_naive();
+        self.update_known_device(device_id, |status| {
+            status.consecutive_failures = 0;
+            status.last_downloaded_event = status.last_downloaded_event.max(Some(start_time));
+            if status.clips_date != Some(today) {
+                status.clips_date = Some(today);
+                status.clips_today = 0;
+            }
+            status.clips_today += 1;
+        });
+    }
+
+    fn update_device(
+        &self,
+        device_id: &str,
+        device_name: &str,
+        update: impl FnOnce(&mut DeviceStatus),
+    ) {
+        let mut devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        let status = devices
+            .entry(device_id.to_string())
+            .or_insert_with(|| DeviceStatus {
+                device_id: device_id.to_string(),
+                device_name: device_name.to_string(),
+                removed: false,
+                silence: None,
+                silent_secs: None,
+                last_checked_at: None,
+                last_downloaded_event: None,
+                consecutive_failures: 0,
+                last_error: None,
+                last_error_at: None,
+                clips_today: 0,
+                clips_date: None,
+            });
+        status.device_name = device_name.to_string();
+        update(status);
+    }
+
+    fn update_known_device(&self, device_id: &str, update: impl FnOnce(&mut DeviceStatus)) {
+        let mut devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(status) = devices.get_mut(device_id) {
+            update(status);
+        }
+    }
+
+    pub fn device_statuses(&self) -> Vec<DeviceStatus> {
+        let now = Utc::now();
+        let today = now.with_timezone(&Vancouver).date_naive();
+        self.devices
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .map(|status| DeviceStatus {
+                silent_secs: status
+                    .silence
+                    .as_ref()
+                    .map(|silence| (now - silence.silent_since).num_seconds()),
+                clips_today: if status.clips_date == Some(today) {
+                    status.clips_today
+                } else {
+                    0
+                },
+                ..status.clone()
+            })
+            .collect()
+    }
+
+    pub fn token_stats(&self) -> TokenStats {
+        let now = Utc::now().timestamp();
+        let access_at = self.access_token_refreshed_at.load(Ordering::Relaxed);
+        let nest_at = self.nest_token_refreshed_at.load(Ordering::Relaxed);
+        let age = |at: i64| (at > 0).then(|| now - at);
+
+        TokenStats {
+            access_token_refreshes: self.access_token_refreshes.load(Ordering::Relaxed),
+            nest_token_refreshes: self.nest_token_refreshes.load(Ordering::Relaxed),
+            forced_refreshes: self.forced_token_refreshes.load(Ordering::Relaxed),
+            last_refresh: DateTime::from_timestamp(access_at.max(nest_at), 0)
+                .filter(|_| access_at.max(nest_at) > 0),
+            access_token_age_secs: age(access_at),
+            nest_token_age_secs: age(nest_at),
+        }
+    }
+
+    pub fn status_report(&self) -> StatusReport {
+        StatusReport {
+            version: NEST_SYNC_VERSION,
+            tokens: self.token_stats(),
+            check_cycles: self.cycle_stats(),
+            devices: self.device_statuses(),
+            emergency_prunes: self.emergency_prune_stats(),
+            log_filter: crate::logging::current_filter(),
+            download_quota: self.download_quota(),
+            storage_unavailable: self.storage_unavailable.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Writes the status report to `path` atomically (temp file + rename),
+    /// for `--status-file`.
+    pub fn write_status_file(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_vec_pretty(&self.status_report())
+            .context("Failed to serialize status")?;
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, json).context("Failed to write status file")?;
+        fs::rename(&tmp_path, path).context("Failed to replace status file")?;
+        Ok(())
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render_prometheus(&self) -> String {
+        let tokens = self.token_stats();
+        let mut out = String::new();
+
+        write_metric(
+            &mut out,
+            "nest_sync_token_refreshes_total",
+            "counter",
+            "OAuth token refreshes by token kind",
+            &[
+                ("kind=\"access\"", tokens.access_token_refreshes as f64),
+                ("kind=\"nest\"", tokens.nest_token_refreshes as f64),
+            ],
+        );
+        write_metric(
+            &mut out,
+            "nest_sync_token_forced_refreshes_total",
+            "counter",
+            "Token refreshes forced by a 401 response",
+            &[("", tokens.forced_refreshes as f64)],
+        );
+
+        let mut ages = Vec::new();
+        if let Some(age) = tokens.access_token_age_secs {
+            ages.push(("kind=\"access\"", age as f64));
+        }
+        if let Some(age) = tokens.nest_token_age_secs {
+            ages.push(("kind=\"nest\"", age as f64));
+        }
+        write_metric(
+            &mut out,
+            "nest_sync_token_age_seconds",
+            "gauge",
+            "Seconds since the current token was issued",
+            &ages,
+        );
+
+        let cycles = self.cycle_stats();
+        write_metric(
+            &mut out,
+            "nest_sync_check_cycles_total",
+            "counter",
+            "Completed event check cycles",
+            &[("", cycles.cycles as f64)],
+        );
+        write_metric(
+            &mut out,
+            "nest_sync_check_cycle_overruns_total",
+            "counter",
+            "Event check cycles that took longer than the check interval",
+            &[("", cycles.overruns as f64)],
+        );
+        write_metric(
+            &mut out,
+            "nest_sync_clips_expired_total",
+            "counter",
+            "Clips that aged out of the camera's history before they were downloaded",
+            &[("", cycles.clips_expired as f64)],
+        );
+        write_metric(
+            &mut out,
+            "nest_sync_manifest_parse_failures_total",
+            "counter",
+            "Event manifest Periods dropped because their timestamp or duration couldn't be parsed",
+            &[("", cycles.manifest_parse_failures as f64)],
+        );
+        if let Some(secs) = cycles.last_cycle_secs {
+            write_metric(
+                &mut out,
+                "nest_sync_check_cycle_duration_seconds",
+                "gauge",
+                "Wall time of the last event check cycle",
+                &[("", secs)],
+            );
+        }
+
+        write_metric(
+            &mut out,
+            "nest_sync_emergency_prunes_total",
+            "counter",
+            "Prune passes run because free space fell below the emergency floor",
+            &[("", self.emergency_prunes.load(Ordering::Relaxed) as f64)],
+        );
+        write_metric(
+            &mut out,
+            "nest_sync_storage_unavailable",
+            "gauge",
+            "1 while downloads are paused because the output can't be written",
+            &[(
+                "",
+                f64::from(u8::from(self.storage_unavailable.load(Ordering::Relaxed))),
+            )],
+        );
+
+        if let Some(quota) = self.download_quota() {
+            write_metric(
+                &mut out,
+                "nest_sync_download_quota_limit",
+                "gauge",
+                "Clips that may be fetched per local day",
+                &[("", quota.limit as f64)],
+            );
+            write_metric(
+                &mut out,
+                "nest_sync_download_quota_remaining",
+                "gauge",
+                "Clips that may still be fetched today",
+                &[("", quota.remaining as f64)],
+            );
+        }
+
+        // Removed devices are no longer checked, so their silence is moot
+        let silences: Vec<(String, f64, f64)> = self
+            .device_statuses()
+            .into_iter()
+            .filter(|d| !d.removed)
+            .filter_map(|d| {
+                let alerting = d.silence.as_ref()?.alerting;
+                let labels = format!(
+                    "device_id=\"{}\",device_name=\"{}\"",
+                    escape_label(&d.device_id),
+                    escape_label(&d.device_name)
+                );
+                Some((labels, d.silent_secs? as f64, f64::from(u8::from(alerting))))
+            })
+            .collect();
+        if !silences.is_empty() {
+            let silent: Vec<(&str, f64)> = silences
+                .iter()
+                .map(|(labels, secs, _)| (labels.as_str(), *secs))
+                .collect();
+            let alerting: Vec<(&str, f64)> = silences
+                .iter()
+                .map(|(labels, _, alerting)| (labels.as_str(), *alerting))
+                .collect();
+            write_metric(
+                &mut out,
+                "nest_sync_device_silent_seconds",
+                "gauge",
+                "Seconds since the camera's newest event",
+                &silent,
+            );
+            write_metric(
+                &mut out,
+                "nest_sync_device_silence_alert",
+                "gauge",
+                "1 while the camera has been silent for longer than its threshold",
+                &alerting,
+            );
+        }
+
+        out
+    }
+}
+
+/// Escapes a Prometheus label value.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, f64)]) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{name} {value}");
+        } else {
+            let _ = writeln!(out, "{name}{{{labels}}} {value}");
+        }
+    }
+}
+
+/// Serves `/metrics` (Prometheus text) and `/status` (JSON) until the process
+/// exits.
+pub async fn serve(addr: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind metrics endpoint on {addr}"))?;
+    info!(%addr, "Serving metrics");
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!(error = %e, "Failed to accept metrics connection");
+                continue;
+            }
+        };
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream).await {
+                debug!(%peer, error = %e, "Metrics request failed");
+            }
+        });
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream) -> Result<()> {
+    let mut buf = [0u8; 1024];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+    let (status, content_type, body) = match path {
+        "/metrics" => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            METRICS.render_prometheus(),
+        ),
+        "/status" => (
+            "200 OK",
+            "application/json",
+            serde_json::to_string_pretty(&METRICS.status_report())?,
+        ),
+        _ => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
@@ -0,0 +1,556 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::{
+    archive,
+    layout::{self, DEFAULT_PATH_TEMPLATE, PathTemplate},
+};
+
+/// Plan of an in-progress migration at the output root, removed once every
+/// move has completed.
+const JOURNAL_FILE: &str = ".nest-sync-migration.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CollisionPolicy {
+    /// Leave the clip where it is
+    Skip,
+    /// Move it to the first free `<name>-N.mp4`
+    Suffix,
+    /// Abort before moving anything
+    Error,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct MigrateArgs {
+    /// Template the archive is currently laid out in
+    #[arg(long, default_value = DEFAULT_PATH_TEMPLATE)]
+    from_template: PathTemplate,
+
+    /// Template to move clips to (default: --path-template)
+    #[arg(long)]
+    to_template: Option<PathTemplate>,
+
+    /// Print the planned moves without moving anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// What to do when a clip's new path is already taken
+    #[arg(long, value_enum, default_value = "skip")]
+    on_collision: CollisionPolicy,
+
+    /// Remove directories the migration leaves empty
+    #[arg(long)]
+    delete_empty_dirs: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Journal {
+    from_template: String,
+    to_template: String,
+    moves: Vec<PlannedMove>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PlannedMove {
+    from: PathBuf,
+    to: PathBuf,
+}
+
+/// Moves every clip laid out by the source template to its path under the
+/// target template, together with its sidecar and keep marker. The plan is
+/// journaled first, so rerunning after an interruption finishes the same
+/// moves.
+pub fn run(output_path: &Path, args: &MigrateArgs, current_template: &PathTemplate) -> Result<()> {
+    let to_template = args.to_template.as_ref().unwrap_or(current_template);
+    let journal_path = output_path.join(JOURNAL_FILE);
+
+    let journal = match load_journal(&journal_path)? {
+        Some(journal) => {
+            if journal.from_template != args.from_template.to_string()
+                || journal.to_template != to_template.to_string()
+            {
+                bail!(
+                    "An unfinished migration from '{}' to '{}' exists; rerun it with those templates or delete {}",
+                    journal.from_template,
+                    journal.to_template,
+                    journal_path.display()
+                );
+            }
+            info!(
+                moves = journal.moves.len(),
+                "Resuming interrupted migration"
+            );
+            journal
+        }
+        None => Journal {
+            from_template: args.from_template.to_string(),
+            to_template: to_template.to_string(),
+            moves: plan_moves(
+                output_path,
+                &args.from_template,
+                to_template,
+                args.on_collision,
+            )?,
+        },
+    };
+
+    if args.dry_run {
+        for planned in &journal.moves {
+            println!("{} -> {}", planned.from.display(), planned.to.display());
+        }
+        println!("{} clips would be moved", journal.moves.len());
+        return Ok(());
+    }
+
+    if journal.moves.is_empty() {
+        info!("Nothing to migrate");
+        return Ok(());
+    }
+
+    save_journal(&journal_path, &journal)?;
+
+    let mut moved = HashMap::new();
+    let mut skipped_count = 0;
+    for planned in &journal.moves {
+        if execute_move(planned)
+            .with_context(|| format!("Failed to move {}", planned.from.display()))?
+        {
+            moved.insert(planned.from.clone(), planned.to.clone());
+            if args.delete_empty_dirs {
+                remove_empty_parents(output_path, &planned.from);
+            }
+        } else {
+            skipped_count += 1;
+        }
+    }
+
+    archive::rewrite_keep_list(output_path, &moved)?;
+    fs::remove_file(&journal_path).context("Failed to remove migration journal")?;
+
+    info!(
+        moved_count = moved.len(),
+        skipped_count, "Migration complete"
+    );
+
+    Ok(())
+}
+
+fn plan_moves(
+    root: &Path,
+    from: &PathTemplate,
+    to: &PathTemplate,
+    on_collision: CollisionPolicy,
+) -> Result<Vec<PlannedMove>> {
+    let mut planned_targets = HashSet::new();
+    let mut moves = Vec::new();
+
+    for clip in archive::walk_clips(root) {
+        let Some(relative) = clip.path.strip_prefix(root).ok().and_then(|p| p.to_str()) else {
+            continue;
+        };
+        // A clip renamed apart from another camera's has the camera after its
+        // templated name
+        let parsed = from.parse(relative).or_else(|| {
+            let sidecar = clip.sidecar.as_ref()?;
+            let relative =
+                layout::strip_device_suffix(relative, &sidecar.device_id, &sidecar.device_name)?;
+            from.parse(&relative)
+        });
+        let Some(parsed) = parsed else {
+            debug!(path = %clip.path.display(), "Not laid out by the source template; leaving in place");
+            continue;
+        };
+
+        // The sidecar is authoritative; the old path fills in the gaps
+        let sidecar = clip.sidecar.as_ref();
+        let start_time = sidecar.map(|s| s.start_time).or(parsed.start_time);
+        let device_id = sidecar.map(|s| s.device_id.clone()).or(parsed.device_id);
+        let device_name = sidecar
+            .map(|s| s.device_name.clone())
+            .or(parsed.device_name);
+
+        let Some(start_time) = start_time else {
+            warn!(path = %clip.path.display(), "No timestamp for clip; leaving in place");
+            continue;
+        };
+        if (to.uses_device_id() && device_id.is_none())
+            || (to.uses_device_name() && device_name.is_none())
+        {
+            warn!(path = %clip.path.display(), "Clip can't be attributed to a device; leaving in place");
+            continue;
+        }
+
+        let mut target = root.join(to.render(
+            start_time,
+            sidecar.map_or(start_time, |s| s.end_time),
+            device_id.as_deref().unwrap_or_default(),
+            device_name.as_deref().unwrap_or_default(),
+            sidecar.map_or(&[][..], |s| &s.tags),
+        ));
+        if target == clip.path {
+            continue;
+        }
+
+        let taken = |p: &Path| p.exists() || planned_targets.contains(p);
+        if taken(&target) {
+            match on_collision {
+                CollisionPolicy::Skip => {
+                    warn!(
+                        from = %clip.path.display(),
+                        to = %target.display(),
+                        "Destination already taken; leaving clip in place"
+                    );
+                    continue;
+                }
+                CollisionPolicy::Suffix => {
+                    target = (1..)
+                        .map(|n| suffixed_path(&target, n))
+                        .find(|p| !taken(p))
+                        .expect("a free suffix exists");
+                }
+                CollisionPolicy::Error => bail!(
+                    "Destination {} for {} is already taken",
+                    target.display(),
+                    clip.path.display()
+                ),
+            }
+        }
+
+        planned_targets.insert(target.clone());
+        moves.push(PlannedMove {
+            from: clip.path,
+            to: target,
+        });
+    }
+
+    Ok(moves)
+}
+
+fn suffixed_path(path: &Path, n: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{stem}-{n}.{extension}"))
+}
+
+/// Moves a clip and its companions. Safe to repeat: files already at their
+/// destination are left alone. Returns whether the clip ended up moved.
+fn execute_move(planned: &PlannedMove) -> Result<bool> {
+    if planned.from.exists() {
+        if planned.to.exists() {
+            warn!(
+                from = %planned.from.display(),
+                to = %planned.to.display(),
+                "Destination appeared since planning; leaving clip in place"
+            );
+            return Ok(false);
+        }
+        if let Some(parent) = planned.to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Renames keep the modification time the archive relies on
+        fs::rename(&planned.from, &planned.to)?;
+    } else if !planned.to.exists() {
+        warn!(path = %planned.from.display(), "Clip disappeared since planning; skipping");
+        return Ok(false);
+    }
+
+    for (from, to) in archive::companion_moves(&planned.from, &planned.to) {
+        if from.exists() && !to.exists() {
+            fs::rename(&from, &to)?;
+        }
+    }
+
+    if let Some(mut metadata) = archive::read_sidecar(&planned.to)
+        && metadata
+            .file_path
+            .as_ref()
+            .is_some_and(|path| *path != planned.to)
+    {
+        metadata.file_path = Some(planned.to.clone());
+        archive::write_sidecar(&planned.to, &metadata)?;
+    }
+
+    Ok(true)
+}
+
+/// Removes directories left empty by a move, up to the output root.
+fn remove_empty_parents(root: &Path, moved_from: &Path) {
+    let mut dir = moved_from.parent();
+    while let Some(current) = dir
+        && current != root
+        && current.starts_with(root)
+    {
+        if fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+fn load_journal(path: &Path) -> Result<Option<Journal>> {
+    match fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .with_context(|| format!("Failed to parse migration journal {}", path.display())),
+        Err(_) => Ok(None),
+    }
+}
+
+fn save_journal(path: &Path, journal: &Journal) -> Result<()> {
+    let json =
+        serde_json::to_vec_pretty(journal).context("Failed to serialize migration journal")?;
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, json).context("Failed to write migration journal")?;
+    fs::rename(&tmp_path, path).context("Failed to replace migration journal")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::models::CameraEvent;
+
+    const CAMERA_TEMPLATE: &str = "{device_name}/{year}/{month}/{day}/{hour}-{minute}-{second}.mp4";
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 6, 1, hour, 0, 0).unwrap()
+    }
+
+    fn template(template: &str) -> PathTemplate {
+        template.parse().unwrap()
+    }
+
+    fn args(on_collision: CollisionPolicy) -> MigrateArgs {
+        MigrateArgs {
+            from_template: template(DEFAULT_PATH_TEMPLATE),
+            to_template: Some(template(CAMERA_TEMPLATE)),
+            dry_run: false,
+            on_collision,
+            delete_empty_dirs: false,
+        }
+    }
+
+    /// Writes a Porch clip starting at `start` where `template` puts it, with
+    /// a sidecar recording its path.
+    fn clip(root: &Path, template: &PathTemplate, start: DateTime<Utc>) -> PathBuf {
+        let event = CameraEvent::new("device-1".to_string(), start, Duration::seconds(10));
+        let path = root.join(template.render(start, event.end_time(), "device-1", "Porch", &[]));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, b"video").unwrap();
+        let mut sidecar = archive::VideoMetadata::from(event).with_device_name("Porch");
+        sidecar.file_path = Some(path.clone());
+        archive::write_sidecar(&path, &sidecar).unwrap();
+        path
+    }
+
+    /// Every file under `root`, relative to it.
+    fn files(root: &Path) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.push(path.strip_prefix(root).unwrap().to_path_buf());
+                }
+            }
+        }
+        files.sort();
+        files
+    }
+
+    fn target(root: &Path, start: DateTime<Utc>) -> PathBuf {
+        root.join(template(CAMERA_TEMPLATE).render(
+            start,
+            start + Duration::seconds(10),
+            "device-1",
+            "Porch",
+            &[],
+        ))
+    }
+
+    #[test]
+    fn moves_clips_with_companions_and_keep_list() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let from = template(DEFAULT_PATH_TEMPLATE);
+        let old = clip(root, &from, at(12));
+        fs::write(archive::keep_marker_path(&old), "").unwrap();
+        let relative = old
+            .strip_prefix(root)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        fs::write(root.join("keep.txt"), format!("{relative}\n")).unwrap();
+        let mut args = args(CollisionPolicy::Skip);
+        args.delete_empty_dirs = true;
+
+        run(root, &args, &from).unwrap();
+
+        let new = target(root, at(12));
+        assert!(!old.exists() && new.exists());
+        assert!(archive::keep_marker_path(&new).exists());
+        assert_eq!(
+            archive::read_sidecar(&new).unwrap().file_path.as_deref(),
+            Some(new.as_path())
+        );
+        let keep_list = fs::read_to_string(root.join("keep.txt")).unwrap();
+        assert_eq!(keep_list.trim(), "Porch/2025/06/01/05-00-00.mp4");
+        assert!(!old.parent().unwrap().exists());
+        assert!(!root.join(JOURNAL_FILE).exists());
+    }
+
+    #[test]
+    fn dry_run_leaves_the_tree_untouched() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let from = template(DEFAULT_PATH_TEMPLATE);
+        clip(root, &from, at(12));
+        clip(root, &from, at(13));
+        let before = files(root);
+        let mut args = args(CollisionPolicy::Skip);
+        args.dry_run = true;
+
+        run(root, &args, &from).unwrap();
+
+        assert_eq!(files(root), before);
+    }
+
+    /// A clip at 12:00 whose destination is already taken by another file.
+    fn collision(root: &Path) -> PathBuf {
+        let old = clip(root, &template(DEFAULT_PATH_TEMPLATE), at(12));
+        let taken = target(root, at(12));
+        fs::create_dir_all(taken.parent().unwrap()).unwrap();
+        fs::write(&taken, b"other").unwrap();
+        old
+    }
+
+    #[test]
+    fn collision_skip_leaves_the_clip_in_place() {
+        let dir = TempDir::new().unwrap();
+        let old = collision(dir.path());
+
+        run(
+            dir.path(),
+            &args(CollisionPolicy::Skip),
+            &template(CAMERA_TEMPLATE),
+        )
+        .unwrap();
+
+        assert!(old.exists() && archive::sidecar_path(&old).exists());
+        assert_eq!(fs::read(target(dir.path(), at(12))).unwrap(), b"other");
+    }
+
+    #[test]
+    fn collision_suffix_moves_the_clip_beside_the_other() {
+        let dir = TempDir::new().unwrap();
+        let old = collision(dir.path());
+
+        run(
+            dir.path(),
+            &args(CollisionPolicy::Suffix),
+            &template(CAMERA_TEMPLATE),
+        )
+        .unwrap();
+
+        let suffixed = suffixed_path(&target(dir.path(), at(12)), 1);
+        assert!(!old.exists());
+        assert_eq!(fs::read(&suffixed).unwrap(), b"video");
+        assert!(archive::sidecar_path(&suffixed).exists());
+        assert_eq!(fs::read(target(dir.path(), at(12))).unwrap(), b"other");
+    }
+
+    #[test]
+    fn collision_error_moves_nothing() {
+        let dir = TempDir::new().unwrap();
+        clip(dir.path(), &template(DEFAULT_PATH_TEMPLATE), at(11));
+        collision(dir.path());
+        let before = files(dir.path());
+
+        assert!(
+            run(
+                dir.path(),
+                &args(CollisionPolicy::Error),
+                &template(CAMERA_TEMPLATE)
+            )
+            .is_err()
+        );
+
+        assert_eq!(files(dir.path()), before);
+    }
+
+    #[test]
+    fn resumes_from_journal_after_partial_move() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let from = template(DEFAULT_PATH_TEMPLATE);
+        let to = template(CAMERA_TEMPLATE);
+        let first = clip(root, &from, at(12));
+        let second = clip(root, &from, at(13));
+        let moves = plan_moves(root, &from, &to, CollisionPolicy::Skip).unwrap();
+        save_journal(
+            &root.join(JOURNAL_FILE),
+            &Journal {
+                from_template: from.to_string(),
+                to_template: to.to_string(),
+                moves,
+            },
+        )
+        .unwrap();
+        // Interrupted after moving the first clip but before its sidecar
+        let first_target = target(root, at(12));
+        fs::create_dir_all(first_target.parent().unwrap()).unwrap();
+        fs::rename(&first, &first_target).unwrap();
+
+        run(root, &args(CollisionPolicy::Skip), &from).unwrap();
+
+        for (old, new) in [(first, first_target), (second, target(root, at(13)))] {
+            assert!(!old.exists() && !archive::sidecar_path(&old).exists());
+            assert!(new.exists() && archive::sidecar_path(&new).exists());
+        }
+        assert!(!root.join(JOURNAL_FILE).exists());
+    }
+
+    #[test]
+    fn rejects_journal_for_other_templates() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let old = clip(root, &template(DEFAULT_PATH_TEMPLATE), at(12));
+        save_journal(
+            &root.join(JOURNAL_FILE),
+            &Journal {
+                from_template: DEFAULT_PATH_TEMPLATE.to_string(),
+                to_template: "{device_id}/{year}/{month}/{day}/{hour}-{minute}-{second}.mp4"
+                    .to_string(),
+                moves: Vec::new(),
+            },
+        )
+        .unwrap();
+
+        let error = run(
+            root,
+            &args(CollisionPolicy::Skip),
+            &template(CAMERA_TEMPLATE),
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(error.contains("unfinished migration"), "{error}");
+        assert!(old.exists());
+        assert!(root.join(JOURNAL_FILE).exists());
+    }
+}
@@ -1,14 +1,148 @@
+use std::{fmt, str::FromStr};
+
 use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use tracing::warn;
 
-const MAX_EVENT_DURATION_SECS: i64 = 10 * 60;
+/// Longest window downloaded for one event; longer events are clipped.
+pub const MAX_EVENT_DURATION_SECS: i64 = 10 * 60;
+
+#[derive(Debug, Error)]
+pub enum DurationParseError {
+    #[error("invalid ISO 8601 duration '{input}': {reason}")]
+    Invalid { input: String, reason: String },
+    /// Months and years have no fixed length in seconds.
+    #[error("duration '{0}' uses months or years, which have no fixed length")]
+    NonFixedLength(String),
+    #[error("duration '{0}' is negative or out of range")]
+    OutOfRange(String),
+}
+
+/// Parses the `duration` attribute of a Nest event, keeping millisecond
+/// precision.
+pub fn parse_event_duration(duration_str: &str) -> Result<Duration, DurationParseError> {
+    let parsed = iso8601_duration::Duration::parse(duration_str).map_err(|e| {
+        DurationParseError::Invalid {
+            input: duration_str.to_string(),
+            reason: format!("{e:?}"),
+        }
+    })?;
+    let secs = parsed
+        .num_seconds()
+        .ok_or_else(|| DurationParseError::NonFixedLength(duration_str.to_string()))?;
+
+    let millis = (f64::from(secs) * 1000.0).round();
+    if !millis.is_finite() || millis < 0.0 || millis > i64::MAX as f64 {
+        return Err(DurationParseError::OutOfRange(duration_str.to_string()));
+    }
+
+    Ok(Duration::milliseconds(millis as i64))
+}
+
+/// A classification the API attached to an event.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventType {
+    Motion,
+    Person,
+    Sound,
+    Vehicle,
+    Animal,
+    Package,
+    Face,
+    #[serde(untagged)]
+    Other(String),
+}
+
+impl fmt::Display for EventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Motion => "motion",
+            Self::Person => "person",
+            Self::Sound => "sound",
+            Self::Vehicle => "vehicle",
+            Self::Animal => "animal",
+            Self::Package => "package",
+            Self::Face => "face",
+            Self::Other(other) => other,
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for EventType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_ascii_lowercase();
+        Ok(match name.as_str() {
+            "" => return Err("empty event type".to_string()),
+            "motion" => Self::Motion,
+            "person" => Self::Person,
+            "sound" => Self::Sound,
+            "vehicle" => Self::Vehicle,
+            "animal" => Self::Animal,
+            "package" => Self::Package,
+            "face" => Self::Face,
+            _ => Self::Other(name),
+        })
+    }
+}
+
+/// Which events to download, e.g. `person+motion,sound`: `,` separates
+/// alternatives (OR), `+` joins types that must all be present (AND).
+#[derive(Debug, Clone)]
+pub struct EventTypeFilter {
+    alternatives: Vec<Vec<EventType>>,
+}
+
+impl FromStr for EventTypeFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let alternatives = s
+            .split(',')
+            .map(|alternative| {
+                alternative
+                    .split('+')
+                    .map(EventType::from_str)
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self { alternatives })
+    }
+}
+
+impl EventTypeFilter {
+    pub fn matches(&self, event: &CameraEvent) -> bool {
+        self.alternatives.iter().any(|required| {
+            required
+                .iter()
+                .all(|event_type| event.event_types.contains(event_type))
+        })
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CameraEvent {
     pub device_id: String,
     pub start_time: DateTime<Utc>,
     pub duration: Duration,
+    /// The event's real length when it exceeded the cap and `duration` was
+    /// clipped to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_duration: Option<Duration>,
+    /// Every classification reported for the event's `<Period>`.
+    #[serde(default)]
+    pub event_types: Vec<EventType>,
+    /// The `<Period>`'s `id`, when the manifest gives one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip_id: Option<String>,
+    /// Ids of the events `--merge-gap-seconds` merged into this one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged_event_ids: Vec<String>,
 }
 
 impl CameraEvent {
@@ -17,6 +151,10 @@ impl CameraEvent {
             device_id,
             start_time,
             duration,
+            original_duration: None,
+            event_types: Vec::new(),
+            clip_id: None,
+            merged_event_ids: Vec::new(),
         }
     }
 
@@ -24,6 +162,11 @@ impl CameraEvent {
         self.start_time + self.duration
     }
 
+    /// Whether `dt` falls within the half-open window `[start_time, end_time())`.
+    pub fn window_contains(&self, dt: DateTime<Utc>) -> bool {
+        dt >= self.start_time && dt < self.end_time()
+    }
+
     pub fn event_id(&self) -> String {
         format!(
             "{}->{}|{}",
@@ -37,6 +180,7 @@ impl CameraEvent {
         device_id: String,
         program_date_time: &str,
         duration_str: &str,
+        event_types: Vec<EventType>,
     ) -> anyhow::Result<Self> {
         let start_time = DateTime::parse_from_rfc3339(program_date_time)
             .map(|dt| dt.with_timezone(&Utc))
@@ -45,23 +189,197 @@ impl CameraEvent {
                     .map(|dt| dt.with_timezone(&Utc))
             })?;
 
-        let duration_parsed = iso8601_duration::Duration::parse(duration_str)
-            .map_err(|e| anyhow::anyhow!("Failed to parse duration: {:?}", e))?;
-        let duration_secs = duration_parsed.num_seconds().unwrap_or(0.0) as i64;
-        let capped_duration_secs = duration_secs.min(MAX_EVENT_DURATION_SECS);
+        let parsed_duration = parse_event_duration(duration_str)?;
+        let max_duration = Duration::seconds(MAX_EVENT_DURATION_SECS);
+        let duration = parsed_duration.min(max_duration);
 
-        if duration_secs > MAX_EVENT_DURATION_SECS {
+        if parsed_duration > max_duration {
             warn!(
                 %device_id,
                 %program_date_time,
-                duration_secs,
-                capped_duration_secs,
+                duration_secs = parsed_duration.num_seconds(),
+                capped_duration_secs = MAX_EVENT_DURATION_SECS,
                 "Event duration exceeded cap; clipping download window"
             );
         }
 
-        let duration = Duration::seconds(capped_duration_secs);
+        Ok(Self {
+            original_duration: (parsed_duration > max_duration).then_some(parsed_duration),
+            event_types,
+            ..Self::new(device_id, start_time, duration)
+        })
+    }
+}
+
+/// Merges runs of a device's events, sorted by start time, that are at most
+/// `max_gap` apart into single events spanning them, as long as the merged
+/// event stays within the download cap. Merged events keep the union of
+/// their constituents' types and list the constituents' ids.
+pub fn merge_adjacent(events: Vec<CameraEvent>, max_gap: Duration) -> Vec<CameraEvent> {
+    let max_duration = Duration::seconds(MAX_EVENT_DURATION_SECS);
+    let mut merged: Vec<CameraEvent> = Vec::new();
+
+    for event in events {
+        if let Some(last) = merged.last_mut()
+            && last.device_id == event.device_id
+            && last.original_duration.is_none()
+            && event.original_duration.is_none()
+            // Overlapping events merge whatever the gap allows
+            && (last.window_contains(event.start_time)
+                || event.start_time - last.end_time() <= max_gap)
+            && event.end_time().max(last.end_time()) - last.start_time <= max_duration
+        {
+            if last.merged_event_ids.is_empty() {
+                last.merged_event_ids.push(last.event_id());
+                last.clip_id = None;
+            }
+            last.merged_event_ids.push(event.event_id());
+            last.duration = event.end_time().max(last.end_time()) - last.start_time;
+            for event_type in event.event_types {
+                if !last.event_types.contains(&event_type) {
+                    last.event_types.push(event_type);
+                }
+            }
+            continue;
+        }
+        merged.push(event);
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn event(duration_secs: i64) -> CameraEvent {
+        CameraEvent::new(
+            "device".to_string(),
+            Utc.with_ymd_and_hms(2025, 6, 1, 12, 0, 0).unwrap(),
+            Duration::seconds(duration_secs),
+        )
+    }
+
+    #[test]
+    fn parses_durations_exactly() {
+        for (input, millis) in [
+            ("PT0S", 0),
+            ("PT15S", 15_000),
+            ("PT12.345S", 12_345),
+            ("PT0.5S", 500),
+            ("PT1M30S", 90_000),
+            ("PT1H2M3S", 3_723_000),
+            ("P1DT1S", 86_401_000),
+        ] {
+            assert_eq!(
+                parse_event_duration(input).unwrap(),
+                Duration::milliseconds(millis),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_months_and_years() {
+        for input in ["P1M", "P1Y", "P1Y2M3DT4H"] {
+            assert!(
+                matches!(
+                    parse_event_duration(input),
+                    Err(DurationParseError::NonFixedLength(ref s)) if s == input
+                ),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_durations() {
+        for input in ["", "15", "PT", "fifteen seconds"] {
+            assert!(
+                matches!(
+                    parse_event_duration(input),
+                    Err(DurationParseError::Invalid { .. })
+                ),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn caps_long_events_and_keeps_original_duration() {
+        let event = CameraEvent::from_xml_attributes(
+            "device".to_string(),
+            "2025-06-01T12:00:00.000Z",
+            "PT15M",
+            Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(event.duration, Duration::seconds(MAX_EVENT_DURATION_SECS));
+        assert_eq!(event.original_duration, Some(Duration::minutes(15)));
+    }
+
+    #[test]
+    fn window_contains_start() {
+        let event = event(30);
+        assert!(event.window_contains(event.start_time));
+    }
+
+    #[test]
+    fn window_excludes_end() {
+        let event = event(30);
+        assert!(!event.window_contains(event.end_time()));
+    }
+
+    #[test]
+    fn window_contains_middle() {
+        let event = event(30);
+        assert!(event.window_contains(event.start_time + Duration::seconds(15)));
+    }
+
+    #[test]
+    fn window_excludes_before_start() {
+        let event = event(30);
+        assert!(!event.window_contains(event.start_time - Duration::milliseconds(1)));
+    }
+
+    #[test]
+    fn zero_duration_window_contains_nothing() {
+        let event = event(0);
+        assert!(!event.window_contains(event.start_time));
+        assert!(!event.window_contains(event.start_time - Duration::seconds(1)));
+        assert!(!event.window_contains(event.start_time + Duration::seconds(1)));
+    }
+
+    #[test]
+    fn merges_overlapping_and_close_events() {
+        let first = event(30);
+        let overlapping = CameraEvent {
+            start_time: first.start_time + Duration::seconds(20),
+            ..event(30)
+        };
+        let close = CameraEvent {
+            start_time: overlapping.end_time() + Duration::seconds(5),
+            ..event(10)
+        };
+        let far = CameraEvent {
+            start_time: close.end_time() + Duration::seconds(6),
+            ..event(10)
+        };
+
+        let merged = merge_adjacent(
+            vec![first.clone(), overlapping.clone(), close.clone(), far],
+            Duration::seconds(5),
+        );
 
-        Ok(Self::new(device_id, start_time, duration))
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].start_time, first.start_time);
+        assert_eq!(merged[0].end_time(), close.end_time());
+        assert_eq!(
+            merged[0].merged_event_ids,
+            [first.event_id(), overlapping.event_id(), close.event_id()]
+        );
+        assert!(merged[1].merged_event_ids.is_empty());
     }
 }
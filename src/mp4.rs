@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+
+/// Seconds from the MP4 epoch (1904-01-01) to the Unix epoch.
+const MP4_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// Anything smaller can't hold a playable clip.
+const MIN_CLIP_SIZE: u64 = 1024;
+
+#[derive(Debug, Error)]
+pub enum LayoutError {
+    #[error("box at offset {offset} has an invalid size")]
+    InvalidBoxSize { offset: usize },
+    #[error("no {0} box")]
+    MissingBox(&'static str),
+    #[error("{name} box at offset {offset} is too short")]
+    ShortHeader { name: &'static str, offset: usize },
+    #[error("unsupported {name} version {version}")]
+    UnsupportedVersion { name: &'static str, version: u8 },
+    #[error("time doesn't fit a version 0 header")]
+    TimeOutOfRange,
+    #[error("only {size} bytes long")]
+    TooSmall { size: u64 },
+    #[error("unreadable: {0}")]
+    Unreadable(#[from] io::Error),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BoxRef {
+    kind: [u8; 4],
+    offset: usize,
+    payload: usize,
+    end: usize,
+}
+
+/// Splits `data[start..end]` into boxes, rejecting sizes that overrun it.
+fn children(data: &[u8], start: usize, end: usize) -> Result<Vec<BoxRef>, LayoutError> {
+    let mut boxes = Vec::new();
+    let mut offset = start;
+
+    while offset < end {
+        let invalid = LayoutError::InvalidBoxSize { offset };
+        let header = data.get(offset..offset + 8).ok_or(invalid)?;
+        let size32 = u32::from_be_bytes(header[..4].try_into().unwrap());
+        let kind = header[4..8].try_into().unwrap();
+
+        let (size, payload) = match size32 {
+            0 => (end - offset, offset + 8),
+            1 => {
+                let large = data
+                    .get(offset + 8..offset + 16)
+                    .ok_or(LayoutError::InvalidBoxSize { offset })?;
+                let size = u64::from_be_bytes(large.try_into().unwrap());
+                let size =
+                    usize::try_from(size).map_err(|_| LayoutError::InvalidBoxSize { offset })?;
+                (size, offset + 16)
+            }
+            size => (size as usize, offset + 8),
+        };
+        let box_end = offset
+            .checked_add(size)
+            .filter(|&box_end| size >= payload - offset && box_end <= end)
+            .ok_or(LayoutError::InvalidBoxSize { offset })?;
+
+        boxes.push(BoxRef {
+            kind,
+            offset,
+            payload,
+            end: box_end,
+        });
+        offset = box_end;
+    }
+
+    Ok(boxes)
+}
+
+/// Checks that the file at `path` looks like a whole clip: big enough, with
+/// top-level boxes that end exactly at the end of the file and a `moov` box.
+/// Only box headers are read, so this is cheap even for long clips.
+pub fn check_file(path: &Path) -> Result<(), LayoutError> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if len < MIN_CLIP_SIZE {
+        return Err(LayoutError::TooSmall { size: len });
+    }
+
+    let mut has_moov = false;
+    let mut offset = 0u64;
+    while offset < len {
+        // Offsets past usize only matter on 32-bit targets, where the clip
+        // couldn't be loaded to check anyway
+        let invalid = move || LayoutError::InvalidBoxSize {
+            offset: offset as usize,
+        };
+        let mut header = [0u8; 8];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header).map_err(|_| invalid())?;
+        let size32 = u32::from_be_bytes(header[..4].try_into().unwrap());
+        has_moov |= &header[4..8] == b"moov";
+
+        let (size, header_len) = match size32 {
+            0 => (len - offset, 8),
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large).map_err(|_| invalid())?;
+                (u64::from_be_bytes(large), 16)
+            }
+            size => (u64::from(size), 8),
+        };
+        offset = offset
+            .checked_add(size)
+            .filter(|&box_end| size >= header_len && box_end <= len)
+            .ok_or_else(invalid)?;
+    }
+
+    if has_moov {
+        Ok(())
+    } else {
+        Err(LayoutError::MissingBox("moov"))
+    }
+}
+
+/// Clips [`check_file`] found whole, with the size and modification time they
+/// had then, so an unchanged clip isn't parsed again on every check.
+#[derive(Debug, Default)]
+pub struct VerifiedClips(HashMap<PathBuf, (u64, SystemTime)>);
+
+impl VerifiedClips {
+    /// Checks the clips at `paths`, returning the problems of those that
+    /// aren't whole. Only these paths are remembered afterwards, so clips that
+    /// have left the query window are forgotten.
+    pub fn check(
+        &mut self,
+        paths: impl IntoIterator<Item = PathBuf>,
+    ) -> HashMap<PathBuf, LayoutError> {
+        let mut verified = HashMap::new();
+        let mut problems = HashMap::new();
+        for path in paths {
+            let stamp = std::fs::metadata(&path)
+                .and_then(|metadata| Ok((metadata.len(), metadata.modified()?)));
+            if let Ok(stamp) = stamp
+                && self.0.get(&path) == Some(&stamp)
+            {
+                verified.insert(path, stamp);
+                continue;
+            }
+            match (check_file(&path), stamp) {
+                (Ok(()), Ok(stamp)) => {
+                    verified.insert(path, stamp);
+                }
+                (Ok(()), Err(_)) => {}
+                (Err(problem), _) => {
+                    problems.insert(path, problem);
+                }
+            }
+        }
+        self.0 = verified;
+        problems
+    }
+}
+
+fn find(boxes: &[BoxRef], kind: &[u8; 4]) -> Option<BoxRef> {
+    boxes.iter().find(|b| &b.kind == kind).copied()
+}
+
+/// Sets the creation and modification times in the movie header, and in each
+/// track's track and media headers, to `time`. The whole layout is checked
+/// before anything is written, so on error `data` is untouched. Returns the
+/// number of headers updated.
+pub fn set_creation_time(data: &mut [u8], time: DateTime<Utc>) -> Result<usize, LayoutError> {
+    let top = children(data, 0, data.len())?;
+    let moov = find(&top, b"moov").ok_or(LayoutError::MissingBox("moov"))?;
+    let moov_children = children(data, moov.payload, moov.end)?;
+
+    let mut headers = vec![(
+        "mvhd",
+        find(&moov_children, b"mvhd").ok_or(LayoutError::MissingBox("mvhd"))?,
+    )];
+    for trak in moov_children.iter().filter(|b| &b.kind == b"trak") {
+        let trak_children = children(data, trak.payload, trak.end)?;
+        if let Some(tkhd) = find(&trak_children, b"tkhd") {
+            headers.push(("tkhd", tkhd));
+        }
+        if let Some(mdia) = find(&trak_children, b"mdia")
+            && let Some(mdhd) = find(&children(data, mdia.payload, mdia.end)?, b"mdhd")
+        {
+            headers.push(("mdhd", mdhd));
+        }
+    }
+
+    let mp4_time = time.timestamp() + MP4_EPOCH_OFFSET;
+    let mut writes: Vec<(usize, Vec<u8>)> = Vec::new();
+    for (name, header) in &headers {
+        let version = *data.get(header.payload).ok_or(LayoutError::ShortHeader {
+            name,
+            offset: header.offset,
+        })?;
+        // Full box header (version + flags), then creation and modification time
+        let fields = match version {
+            0 => {
+                let value = u32::try_from(mp4_time).map_err(|_| LayoutError::TimeOutOfRange)?;
+                [value.to_be_bytes(), value.to_be_bytes()].concat()
+            }
+            1 => {
+                let value = mp4_time as u64;
+                [value.to_be_bytes(), value.to_be_bytes()].concat()
+            }
+            version => return Err(LayoutError::UnsupportedVersion { name, version }),
+        };
+        let start = header.payload + 4;
+        if start + fields.len() > header.end {
+            return Err(LayoutError::ShortHeader {
+                name,
+                offset: header.offset,
+            });
+        }
+        writes.push((start, fields));
+    }
+
+    for (start, fields) in &writes {
+        data[*start..start + fields.len()].copy_from_slice(fields);
+    }
+    Ok(writes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// 2025-06-01T12:00:00Z in seconds since 1904-01-01.
+    const EXPECTED_MP4_TIME: u64 = 3_831_624_000;
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let size = (8 + payload.len()) as u32;
+        [&size.to_be_bytes()[..], kind, payload].concat()
+    }
+
+    /// A full box header with zeroed creation and modification times,
+    /// followed by `rest` bytes of other fields.
+    fn header_box(kind: &[u8; 4], version: u8, rest: usize) -> Vec<u8> {
+        let time_len = if version == 1 { 16 } else { 8 };
+        let mut payload = vec![version, 0, 0, 0];
+        payload.resize(4 + time_len + rest, 0);
+        mp4_box(kind, &payload)
+    }
+
+    /// ftyp, then a moov with a version 0 movie header and one track whose
+    /// track header is version 0 and media header version 1, then mdat.
+    fn fixture() -> Vec<u8> {
+        let mdia = mp4_box(b"mdia", &header_box(b"mdhd", 1, 8));
+        let trak = mp4_box(b"trak", &[header_box(b"tkhd", 0, 68), mdia].concat());
+        let moov = mp4_box(b"moov", &[header_box(b"mvhd", 0, 88), trak].concat());
+        [
+            mp4_box(b"ftyp", b"isom\0\0\0\0isomiso2"),
+            moov,
+            mp4_box(b"mdat", &[0; 1024]),
+        ]
+        .concat()
+    }
+
+    /// Offset of the first box named `kind`, found by its name bytes.
+    fn offset_of(data: &[u8], kind: &[u8; 4]) -> usize {
+        data.windows(4).position(|w| w == kind).unwrap() - 4
+    }
+
+    fn start_time() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 6, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn writes_1904_epoch_times() {
+        let mut data = fixture();
+
+        assert_eq!(set_creation_time(&mut data, start_time()).unwrap(), 3);
+
+        for kind in [b"mvhd", b"tkhd"] {
+            let fields = offset_of(&data, kind) + 12;
+            let expected = (EXPECTED_MP4_TIME as u32).to_be_bytes();
+            assert_eq!(data[fields..fields + 4], expected, "{kind:?} creation");
+            assert_eq!(
+                data[fields + 4..fields + 8],
+                expected,
+                "{kind:?} modification"
+            );
+        }
+        let fields = offset_of(&data, b"mdhd") + 12;
+        let expected = EXPECTED_MP4_TIME.to_be_bytes();
+        assert_eq!(data[fields..fields + 8], expected);
+        assert_eq!(data[fields + 8..fields + 16], expected);
+    }
+
+    #[test]
+    fn leaves_data_untouched_on_unexpected_layout() {
+        let mut data = fixture();
+        let mvhd = offset_of(&data, b"mvhd");
+        data[mvhd + 8] = 7;
+        let before = data.clone();
+
+        assert!(matches!(
+            set_creation_time(&mut data, start_time()),
+            Err(LayoutError::UnsupportedVersion {
+                name: "mvhd",
+                version: 7
+            })
+        ));
+        assert_eq!(data, before);
+    }
+
+    #[test]
+    fn rejects_clip_without_moov() {
+        let mut data = [mp4_box(b"ftyp", b"isom"), mp4_box(b"mdat", &[0; 16])].concat();
+        assert!(matches!(
+            set_creation_time(&mut data, start_time()),
+            Err(LayoutError::MissingBox("moov"))
+        ));
+    }
+
+    #[test]
+    fn rejects_box_overrunning_its_parent() {
+        let mut data = fixture();
+        let moov = offset_of(&data, b"moov");
+        data[moov..moov + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(
+            set_creation_time(&mut data, start_time()),
+            Err(LayoutError::InvalidBoxSize { .. })
+        ));
+    }
+
+    #[test]
+    fn verified_clip_is_not_parsed_again_until_it_changes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("clip.mp4");
+        let clip = fixture();
+        std::fs::write(&path, &clip).unwrap();
+        let mut verified = VerifiedClips::default();
+        assert!(verified.check([path.clone()]).is_empty());
+
+        // Same size and modification time: taken on trust
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::write(&path, vec![0; clip.len()]).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(verified.check([path.clone()]).is_empty());
+
+        std::fs::write(&path, &clip[..clip.len() - 10]).unwrap();
+        assert!(verified.check([path.clone()]).contains_key(&path));
+    }
+
+    #[test]
+    fn check_file_accepts_whole_clip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("clip.mp4");
+        std::fs::write(&path, fixture()).unwrap();
+        assert!(check_file(&path).is_ok());
+
+        let truncated = fixture();
+        std::fs::write(&path, &truncated[..truncated.len() - 10]).unwrap();
+        assert!(matches!(
+            check_file(&path),
+            Err(LayoutError::InvalidBoxSize { .. })
+        ));
+    }
+}
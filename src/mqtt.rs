@@ -0,0 +1,120 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+use crate::models::CameraEvent;
+
+/// Outgoing requests buffered while the broker is unreachable. Publishes
+/// beyond this are dropped rather than stalling downloads.
+const REQUEST_CAPACITY: usize = 100;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Checks that `url` is a usable broker URL without connecting to it.
+pub fn validate_url(url: &str) -> Result<()> {
+    parse_url(url).map(|_| ())
+}
+
+fn parse_url(url: &str) -> Result<MqttOptions> {
+    let url = if url.contains("client_id=") {
+        url.to_string()
+    } else {
+        let separator = if url.contains('?') { '&' } else { '?' };
+        format!(
+            "{url}{separator}client_id=nest-sync-{:08x}",
+            rand::random::<u32>()
+        )
+    };
+    MqttOptions::parse_url(&url).context("Invalid MQTT URL")
+}
+
+#[derive(Debug, Serialize)]
+struct EventNotification<'a> {
+    device_id: &'a str,
+    device_name: &'a str,
+    event_id: String,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    path: String,
+}
+
+/// Publishes a message for every downloaded clip. The connection is driven by
+/// a background task that reconnects on its own; publishing never waits on
+/// the broker.
+#[derive(Clone)]
+pub struct MqttPublisher {
+    client: AsyncClient,
+    topic_template: String,
+}
+
+impl MqttPublisher {
+    /// Connects to `url` (e.g. `mqtt://broker:1883`). A random `client_id` is
+    /// added when the URL doesn't specify one.
+    pub fn connect(url: &str, topic_template: String) -> Result<Self> {
+        let options = parse_url(url)?;
+        let (host, port) = options.broker_address();
+        let (client, mut eventloop) = AsyncClient::new(options, REQUEST_CAPACITY);
+
+        tokio::spawn(async move {
+            let mut connected = false;
+            loop {
+                match eventloop.poll().await {
+                    Ok(event) => {
+                        if !connected {
+                            info!(%host, port, "Connected to MQTT broker");
+                            connected = true;
+                        }
+                        debug!(?event, "MQTT event");
+                    }
+                    Err(e) => {
+                        if connected {
+                            warn!(%host, port, error = %e, "MQTT connection lost; reconnecting");
+                            connected = false;
+                        } else {
+                            debug!(%host, port, error = %e, "MQTT connection attempt failed");
+                        }
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            client,
+            topic_template,
+        })
+    }
+
+    pub fn publish_download(&self, event: &CameraEvent, device_name: &str, path: &Path) {
+        let topic = self
+            .topic_template
+            .replace("{device_id}", &event.device_id)
+            .replace("{device_name}", device_name);
+        let notification = EventNotification {
+            device_id: &event.device_id,
+            device_name,
+            event_id: event.event_id(),
+            start_time: event.start_time,
+            end_time: event.end_time(),
+            path: path.display().to_string(),
+        };
+
+        let payload = match serde_json::to_vec(&notification) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!(error = %e, "Failed to serialize MQTT notification");
+                return;
+            }
+        };
+
+        if let Err(e) = self
+            .client
+            .try_publish(topic.as_str(), QoS::AtLeastOnce, false, payload)
+        {
+            warn!(%topic, error = %e, "Failed to queue MQTT notification");
+        }
+    }
+}
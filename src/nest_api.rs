@@ -1,16 +1,85 @@
+use std::str::FromStr;
+
 use anyhow::Result;
 use chrono::{DateTime, Duration, Utc};
-use quick_xml::{Reader, events::Event};
+use quick_xml::{
+    Reader,
+    events::{BytesStart, Event},
+};
+use tracing::{debug, warn, warn_span};
+
+use crate::{
+    google_auth::{ApiError, GoogleConnection, api_error},
+    metrics::METRICS,
+    models::{CameraEvent, EventType, MAX_EVENT_DURATION_SECS},
+};
+
+const EVENTS_URI: &str = "{origin}/dashmanifest/namespace/{namespace}/device/{device_id}";
+const DOWNLOAD_VIDEO_URI: &str = "{origin}/mp4clip/namespace/{namespace}/device/{device_id}";
+/// How far a Period's segment timelines may stray from its own window before
+/// they're distrusted, e.g. when a manifest lists only some segments.
+const SEGMENT_WINDOW_TOLERANCE_SECS: i64 = 2;
+/// The manifest `variant` requested when no `--quality` is set.
+const DEFAULT_VARIANT: u32 = 2;
+/// More unparseable Periods than this in one manifest save it to a file for
+/// inspection, as the format has likely changed.
+const MANIFEST_DUMP_FAILURE_THRESHOLD: usize = 2;
+
+/// A per-device `--quality` override.
+#[derive(Debug, Clone)]
+pub struct DeviceQuality {
+    /// Device name or ID.
+    pub device: String,
+    pub variant: u32,
+}
+
+/// Parses `DEVICE=VARIANT`, e.g. `Attic=1`.
+impl FromStr for DeviceQuality {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (device, variant) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("expected DEVICE=VARIANT, got '{s}'"))?;
+        if device.is_empty() {
+            return Err(format!("missing device in '{s}'"));
+        }
+        Ok(Self {
+            device: device.to_string(),
+            variant: variant
+                .trim()
+                .parse()
+                .map_err(|e| format!("invalid variant '{variant}': {e}"))?,
+        })
+    }
+}
 
-use crate::{google_auth::GoogleConnection, models::CameraEvent};
+/// A manifest `<Period>` that couldn't be turned into an event, with the raw
+/// attribute values it had.
+#[derive(Debug, Clone)]
+pub struct PeriodParseFailure {
+    pub byte_offset: u64,
+    pub program_date_time: Option<String>,
+    pub duration: Option<String>,
+    pub reason: String,
+}
 
-const EVENTS_URI: &str = "https://nest-camera-frontend.googleapis.com/dashmanifest/namespace/nest-phoenix-prod/device/{device_id}";
-const DOWNLOAD_VIDEO_URI: &str = "https://nest-camera-frontend.googleapis.com/mp4clip/namespace/nest-phoenix-prod/device/{device_id}";
+impl std::fmt::Display for PeriodParseFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Period at byte {} (programDateTime={:?}, duration={:?}): {}",
+            self.byte_offset, self.program_date_time, self.duration, self.reason
+        )
+    }
+}
 
 pub struct NestDevice {
     pub device_id: String,
-    #[allow(dead_code)]
     pub device_name: String,
+    /// The `--quality` variant, sent with manifest and clip requests. Unset,
+    /// manifests ask for the default variant and clip requests name none.
+    pub variant: Option<u32>,
 }
 
 impl Clone for NestDevice {
@@ -18,6 +87,7 @@ impl Clone for NestDevice {
         Self {
             device_id: self.device_id.clone(),
             device_name: self.device_name.clone(),
+            variant: self.variant,
         }
     }
 }
@@ -27,64 +97,153 @@ impl NestDevice {
         Self {
             device_id,
             device_name,
+            variant: None,
         }
     }
 
+    pub fn with_variant(mut self, variant: Option<u32>) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    /// Fetches the device's events in the window ending at `end_time`, oldest
+    /// first.
     pub async fn get_events(
         &self,
-        connection: &mut GoogleConnection,
+        connection: &GoogleConnection,
+        end_time: DateTime<Utc>,
+        duration_minutes: i64,
+    ) -> Result<Vec<CameraEvent>> {
+        self.query_events(
+            connection,
+            end_time,
+            duration_minutes,
+            connection.event_query_types(),
+        )
+        .await
+    }
+
+    /// [`Self::get_events`] with an explicit `types` query parameter.
+    pub async fn query_events(
+        &self,
+        connection: &GoogleConnection,
         end_time: DateTime<Utc>,
         duration_minutes: i64,
+        query_types: &str,
     ) -> Result<Vec<CameraEvent>> {
         let start_time = end_time - Duration::minutes(duration_minutes);
 
         let start_str = format_datetime_for_api(&start_time);
         let end_str = format_datetime_for_api(&end_time);
 
+        let variant = self.variant.unwrap_or(DEFAULT_VARIANT);
         let params = [
             ("start_time", start_str),
             ("end_time", end_str),
-            ("types", "4".to_string()),
-            ("variant", "2".to_string()),
+            ("types", query_types.to_string()),
+            ("variant", variant.to_string()),
         ];
+        debug!(device_id = %self.device_id, variant, "Requesting event manifest");
 
         let xml_data = connection
             .make_nest_get_request(&self.device_id, EVENTS_URI, &params)
             .await?;
 
-        self.parse_events(&xml_data)
+        let (mut events, failures) = self.parse_events(&xml_data)?;
+        if !failures.is_empty() {
+            self.report_parse_failures(&failures, &xml_data);
+        }
+        events.sort_by_key(|e| e.start_time);
+        Ok(events)
+    }
+
+    /// Counts a manifest's unparseable Periods, saving the raw manifest to
+    /// the temp directory when there are too many to be a one-off.
+    fn report_parse_failures(&self, failures: &[PeriodParseFailure], xml_data: &[u8]) {
+        METRICS.record_manifest_parse_failures(failures.len());
+
+        let dump_path = (failures.len() > MANIFEST_DUMP_FAILURE_THRESHOLD).then(|| {
+            std::env::temp_dir().join(format!("nest-sync-manifest-{}.xml", self.device_id))
+        });
+        if let Some(path) = &dump_path
+            && let Err(e) = std::fs::write(path, xml_data)
+        {
+            warn!(path = %path.display(), error = %e, "Failed to save event manifest");
+        }
+
+        warn!(
+            device_id = %self.device_id,
+            failed_count = failures.len(),
+            first_failure = %failures[0],
+            manifest_dump = ?dump_path,
+            "Event manifest had Periods that couldn't be parsed; their events were dropped"
+        );
+    }
+
+    /// Synthesizes fixed-size windows covering the lookback period for
+    /// continuous recording. Windows are aligned to multiples of the chunk
+    /// size so repeated runs produce identical windows, and only windows that
+    /// have fully elapsed by `end_time` are returned.
+    pub fn timeline_chunks(
+        &self,
+        end_time: DateTime<Utc>,
+        duration_minutes: i64,
+        chunk_minutes: i64,
+    ) -> Vec<CameraEvent> {
+        let chunk = Duration::minutes(chunk_minutes);
+        let chunk_secs = chunk.num_seconds();
+        let start_secs = (end_time - Duration::minutes(duration_minutes)).timestamp();
+        let aligned_start = start_secs - start_secs.rem_euclid(chunk_secs);
+
+        let mut chunks = Vec::new();
+        let mut chunk_start = DateTime::from_timestamp(aligned_start, 0).unwrap_or(end_time);
+        while chunk_start + chunk <= end_time {
+            chunks.push(CameraEvent::new(self.device_id.clone(), chunk_start, chunk));
+            chunk_start += chunk;
+        }
+
+        chunks
     }
 
-    fn parse_events(&self, xml_data: &[u8]) -> Result<Vec<CameraEvent>> {
+    /// Parses a manifest's Periods into events, alongside the Periods that
+    /// couldn't be parsed.
+    fn parse_events(&self, xml_data: &[u8]) -> Result<(Vec<CameraEvent>, Vec<PeriodParseFailure>)> {
         let mut reader = Reader::from_reader(xml_data);
         reader.config_mut().trim_text(true);
         let mut events = Vec::new();
+        let mut failures = Vec::new();
+        let mut collect = |result| match result {
+            Ok(event) => events.push(event),
+            Err(failure) => failures.push(failure),
+        };
         let mut buf = Vec::new();
+        let mut period: Option<PeriodBuilder> = None;
 
         loop {
+            let byte_offset = reader.buffer_position();
             match reader.read_event_into(&mut buf) {
-                Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e)) => {
+                Ok(Event::Start(ref e)) => {
                     if e.name().as_ref() == b"Period" {
-                        let mut program_date_time = None;
-                        let mut duration = None;
-
-                        for attr in e.attributes().flatten() {
-                            let key = attr.key.as_ref();
-                            let value = String::from_utf8_lossy(&attr.value).to_string();
-
-                            if key == b"programDateTime" {
-                                program_date_time = Some(value);
-                            } else if key == b"duration" {
-                                duration = Some(value);
-                            }
-                        }
-
-                        if let (Some(pdt), Some(dur)) = (program_date_time, duration)
-                            && let Ok(event) =
-                                CameraEvent::from_xml_attributes(self.device_id.clone(), &pdt, &dur)
-                        {
-                            events.push(event);
+                        period = Some(PeriodBuilder::new(e, byte_offset));
+                    } else if let Some(period) = &mut period {
+                        period.start_element(e);
+                    }
+                }
+                Ok(Event::Empty(ref e)) => {
+                    if e.name().as_ref() == b"Period" {
+                        collect(PeriodBuilder::new(e, byte_offset).finish(&self.device_id));
+                    } else if let Some(period) = &mut period {
+                        period.start_element(e);
+                        period.end_element(e.name().as_ref());
+                    }
+                }
+                Ok(Event::End(ref e)) => {
+                    if e.name().as_ref() == b"Period" {
+                        if let Some(period) = period.take() {
+                            collect(period.finish(&self.device_id));
                         }
+                    } else if let Some(period) = &mut period {
+                        period.end_element(e.name().as_ref());
                     }
                 }
                 Ok(Event::Eof) => break,
@@ -94,25 +253,256 @@ impl NestDevice {
             buf.clear();
         }
 
-        Ok(events)
+        Ok((events, failures))
     }
 
+    /// Downloads the clip from `start_time` to `end_time`, an event's window
+    /// or that window padded. The clip endpoint only takes a time range, not
+    /// the manifest's Period `id`, so the window is sent with the millisecond
+    /// precision the manifest gave it.
     pub async fn download_camera_event(
         &self,
-        connection: &mut GoogleConnection,
-        event: &CameraEvent,
+        connection: &GoogleConnection,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
     ) -> Result<Vec<u8>> {
-        let start_ms = event.start_time.timestamp_millis();
-        let end_ms = event.end_time().timestamp_millis();
+        let start_ms = start_time.timestamp_millis();
+        let end_ms = end_time.timestamp_millis();
 
-        let params = [
+        let mut params = vec![
             ("start_time", start_ms.to_string()),
             ("end_time", end_ms.to_string()),
         ];
+        if let Some(variant) = self.variant {
+            params.push(("variant", variant.to_string()));
+        }
+        debug!(device_id = %self.device_id, variant = ?self.variant, "Requesting clip");
 
-        connection
+        // The manifest listed this event, so a 404 can't be a wrong namespace
+        let video_data = connection
             .make_nest_get_request(&self.device_id, DOWNLOAD_VIDEO_URI, &params)
             .await
+            .map_err(|e| match api_error(&e) {
+                Some(ApiError::NotFound { .. }) => ApiError::ClipExpired {
+                    response: "404 Not Found",
+                }
+                .into(),
+                Some(ApiError::Gone { .. }) => ApiError::ClipExpired {
+                    response: "410 Gone",
+                }
+                .into(),
+                _ => e,
+            })?;
+        // Past the camera's history the endpoint may answer with no footage
+        // rather than an error
+        if video_data.is_empty() {
+            return Err(ApiError::ClipExpired {
+                response: "empty response",
+            }
+            .into());
+        }
+        Ok(video_data)
+    }
+}
+
+/// A `<Period>` being read. Besides the Period's own attributes, it collects
+/// the media ranges its `SegmentTimeline`s cover, one per AdaptationSet or
+/// Representation, which pin the event window down more precisely than the
+/// Period's rounded duration.
+struct PeriodBuilder {
+    byte_offset: u64,
+    program_date_time: Option<String>,
+    duration: Option<String>,
+    event_types: Vec<EventType>,
+    id: Option<String>,
+    /// Ticks per second and start offset of the enclosing `SegmentTemplate`.
+    timescale: u64,
+    presentation_time_offset: u64,
+    /// First and end tick of the timeline being read.
+    timeline: Option<(Option<u64>, u64)>,
+    /// Completed timelines as offsets from the Period start, `None` where an
+    /// offset is out of range.
+    media_ranges: Vec<Option<(Duration, Duration)>>,
+}
+
+impl PeriodBuilder {
+    fn new(period: &BytesStart, byte_offset: u64) -> Self {
+        let mut builder = Self {
+            byte_offset,
+            program_date_time: None,
+            duration: None,
+            event_types: Vec::new(),
+            id: None,
+            timescale: 1,
+            presentation_time_offset: 0,
+            timeline: None,
+            media_ranges: Vec::new(),
+        };
+
+        for attr in period.attributes().flatten() {
+            let key = attr.key.as_ref();
+            let value = String::from_utf8_lossy(&attr.value).to_string();
+
+            if key == b"programDateTime" {
+                builder.program_date_time = Some(value);
+            } else if key == b"id" {
+                builder.id = Some(value);
+            } else if key == b"duration" {
+                builder.duration = Some(value);
+            } else if key == b"type" || key == b"types" {
+                for event_type in value
+                    .split([',', ' '])
+                    .filter_map(|t| t.parse::<EventType>().ok())
+                {
+                    if !builder.event_types.contains(&event_type) {
+                        builder.event_types.push(event_type);
+                    }
+                }
+            }
+        }
+
+        builder
+    }
+
+    fn start_element(&mut self, element: &BytesStart) {
+        let attr = |name: &[u8]| -> Option<u64> {
+            element
+                .attributes()
+                .flatten()
+                .find(|a| a.key.as_ref() == name)
+                .and_then(|a| String::from_utf8_lossy(&a.value).parse().ok())
+        };
+
+        match element.name().as_ref() {
+            b"SegmentTemplate" => {
+                self.timescale = attr(b"timescale").filter(|&t| t > 0).unwrap_or(1);
+                self.presentation_time_offset = attr(b"presentationTimeOffset").unwrap_or(0);
+            }
+            b"SegmentTimeline" => self.timeline = Some((None, 0)),
+            b"S" => {
+                let Some((first, end)) = &mut self.timeline else {
+                    return;
+                };
+                let Some(segment_duration) = attr(b"d") else {
+                    return;
+                };
+                // Segments without `t` follow on from the previous one; a
+                // negative (open-ended) repeat count isn't resolvable here
+                // and counts as a single segment
+                let start = attr(b"t").unwrap_or(*end);
+                let count = attr(b"r").map_or(1, |r| r.saturating_add(1));
+                first.get_or_insert(start);
+                *end = start.saturating_add(segment_duration.saturating_mul(count));
+            }
+            _ => {}
+        }
+    }
+
+    fn end_element(&mut self, name: &[u8]) {
+        if name == b"SegmentTimeline"
+            && let Some((Some(first), end)) = self.timeline.take()
+        {
+            let to_offset = |ticks: u64| {
+                let ticks = i128::from(ticks) - i128::from(self.presentation_time_offset);
+                let millis = ticks * 1000 / i128::from(self.timescale);
+                i64::try_from(millis)
+                    .ok()
+                    .and_then(Duration::try_milliseconds)
+            };
+            self.media_ranges.push(to_offset(first).zip(to_offset(end)));
+        }
+    }
+
+    /// Builds the event, narrowing the Period window to the span its segment
+    /// timelines cover when the two agree to within
+    /// `SEGMENT_WINDOW_TOLERANCE`.
+    fn finish(self, device_id: &str) -> Result<CameraEvent, PeriodParseFailure> {
+        let byte_offset = self.byte_offset;
+        let _span = warn_span!("parse_period", byte_offset).entered();
+
+        let (Some(pdt), Some(dur)) = (&self.program_date_time, &self.duration) else {
+            warn!(
+                byte_offset,
+                program_date_time = ?self.program_date_time,
+                duration = ?self.duration,
+                "Skipping Period missing programDateTime or duration"
+            );
+            return Err(PeriodParseFailure {
+                byte_offset,
+                program_date_time: self.program_date_time,
+                duration: self.duration,
+                reason: "missing programDateTime or duration".to_string(),
+            });
+        };
+        let mut event = match CameraEvent::from_xml_attributes(
+            device_id.to_string(),
+            pdt,
+            dur,
+            self.event_types,
+        ) {
+            Ok(event) => event,
+            Err(e) => {
+                warn!(
+                    byte_offset,
+                    program_date_time = %pdt,
+                    duration = %dur,
+                    error = %e,
+                    "Skipping malformed Period"
+                );
+                return Err(PeriodParseFailure {
+                    byte_offset,
+                    program_date_time: Some(pdt.clone()),
+                    duration: Some(dur.clone()),
+                    reason: format!("{e:#}"),
+                });
+            }
+        };
+
+        event.clip_id = self.id;
+
+        if !self.media_ranges.is_empty() {
+            let tolerance = Duration::seconds(SEGMENT_WINDOW_TOLERANCE_SECS);
+            let ranges: Option<Vec<_>> = self.media_ranges.iter().copied().collect();
+            let media_start = ranges
+                .as_ref()
+                .and_then(|r| r.iter().map(|&(start, _)| start).min());
+            let media_end = ranges
+                .as_ref()
+                .and_then(|r| r.iter().map(|&(_, end)| end).max());
+            // An offset past what a timestamp can hold can't agree with the
+            // Period, so it falls back like any other disagreement
+            let narrowed = media_start.zip(media_end).and_then(|(start, end)| {
+                let duration = end.checked_sub(&start)?;
+                let start_time = event.start_time.checked_add_signed(start)?;
+                Some((start, end, duration, start_time))
+            });
+            match narrowed {
+                Some((media_start, media_end, media_duration, start_time))
+                    if media_start >= -tolerance
+                        && media_end <= event.duration + tolerance
+                        && (media_duration - event.duration).abs() <= tolerance =>
+                {
+                    // The tolerance mustn't take the window past the cap
+                    let max_duration = Duration::seconds(MAX_EVENT_DURATION_SECS);
+                    if media_duration > max_duration {
+                        event.original_duration.get_or_insert(media_duration);
+                    }
+                    event.start_time = start_time;
+                    event.duration = media_duration.min(max_duration);
+                }
+                _ => {
+                    warn!(
+                        byte_offset,
+                        period_duration_ms = event.duration.num_milliseconds(),
+                        media_start_ms = ?media_start.map(|d| d.num_milliseconds()),
+                        media_end_ms = ?media_end.map(|d| d.num_milliseconds()),
+                        "Segment timeline disagrees with the Period; using the Period window"
+                    );
+                }
+            }
+        }
+
+        Ok(event)
     }
 }
 
@@ -120,3 +510,245 @@ fn format_datetime_for_api(dt: &DateTime<Utc>) -> String {
     let formatted = dt.format("%Y-%m-%dT%H:%M:%S").to_string();
     format!("{}.000Z", formatted)
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// A manifest in the shape the API returns: each event a Period holding
+    /// video and audio AdaptationSets whose SegmentTimelines cover the clip.
+    const FULL_DASH_MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static">
+  <Period id="event-1" programDateTime="2025-06-01T12:00:00.000Z" duration="PT15S" type="person,motion">
+    <AdaptationSet mimeType="video/mp4">
+      <SegmentTemplate timescale="90000" presentationTimeOffset="0">
+        <SegmentTimeline>
+          <S t="45000" d="180000" r="6"/>
+        </SegmentTimeline>
+      </SegmentTemplate>
+      <Representation id="video-2" bandwidth="1500000"/>
+    </AdaptationSet>
+    <AdaptationSet mimeType="audio/mp4">
+      <SegmentTemplate timescale="48000" presentationTimeOffset="0">
+        <SegmentTimeline>
+          <S t="24000" d="96000" r="6"/>
+        </SegmentTimeline>
+      </SegmentTemplate>
+      <Representation id="audio-1" bandwidth="64000"/>
+    </AdaptationSet>
+  </Period>
+  <Period id="event-2" programDateTime="2025-06-01T12:05:00.000Z" duration="PT10S" type="motion">
+    <AdaptationSet mimeType="video/mp4">
+      <Representation id="video-low">
+        <SegmentTemplate timescale="1000">
+          <SegmentTimeline>
+            <S t="0" d="2000"/>
+            <S d="2000" r="1"/>
+          </SegmentTimeline>
+        </SegmentTemplate>
+      </Representation>
+      <Representation id="video-high">
+        <SegmentTemplate timescale="1000">
+          <SegmentTimeline>
+            <S t="0" d="2000" r="4"/>
+          </SegmentTimeline>
+        </SegmentTemplate>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+
+    fn device() -> NestDevice {
+        NestDevice::new("device-1".to_string(), "Porch".to_string())
+    }
+
+    fn parse(xml: &str) -> (Vec<CameraEvent>, Vec<PeriodParseFailure>) {
+        device().parse_events(xml.as_bytes()).unwrap()
+    }
+
+    fn manifest(periods: &str) -> String {
+        format!(r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011">{periods}</MPD>"#)
+    }
+
+    fn at(h: u32, m: u32, s: u32, ms: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 6, 1, h, m, s).unwrap() + Duration::milliseconds(ms)
+    }
+
+    #[test]
+    fn narrows_window_to_segment_timelines() {
+        let (events, failures) = parse(FULL_DASH_MANIFEST);
+        assert!(failures.is_empty());
+        assert_eq!(events.len(), 2);
+
+        let first = &events[0];
+        assert_eq!(first.device_id, "device-1");
+        assert_eq!(first.clip_id.as_deref(), Some("event-1"));
+        assert_eq!(first.event_types, [EventType::Person, EventType::Motion]);
+        // Both tracks start 0.5s into the Period and run 7 two-second segments
+        assert_eq!(first.start_time, at(12, 0, 0, 500));
+        assert_eq!(first.duration, Duration::seconds(14));
+    }
+
+    #[test]
+    fn takes_widest_span_across_representations() {
+        let (events, _) = parse(FULL_DASH_MANIFEST);
+        let second = &events[1];
+        assert_eq!(second.start_time, at(12, 5, 0, 0));
+        assert_eq!(second.duration, Duration::seconds(10));
+    }
+
+    #[test]
+    fn keeps_period_window_without_timelines() {
+        let (events, _) = parse(&manifest(
+            r#"<Period programDateTime="2025-06-01T12:00:00.000Z" duration="PT12.5S"/>"#,
+        ));
+        assert_eq!(events[0].start_time, at(12, 0, 0, 0));
+        assert_eq!(events[0].duration, Duration::milliseconds(12_500));
+        assert_eq!(events[0].clip_id, None);
+    }
+
+    #[test]
+    fn distrusts_timeline_far_from_period() {
+        let (events, _) = parse(&manifest(
+            r#"<Period programDateTime="2025-06-01T12:00:00.000Z" duration="PT30S">
+                 <AdaptationSet><SegmentTemplate timescale="1">
+                   <SegmentTimeline><S t="0" d="2" r="4"/></SegmentTimeline>
+                 </SegmentTemplate></AdaptationSet>
+               </Period>"#,
+        ));
+        assert_eq!(events[0].start_time, at(12, 0, 0, 0));
+        assert_eq!(events[0].duration, Duration::seconds(30));
+    }
+
+    #[test]
+    fn honours_presentation_time_offset() {
+        let (events, _) = parse(&manifest(
+            r#"<Period programDateTime="2025-06-01T12:00:00.000Z" duration="PT10S">
+                 <AdaptationSet><SegmentTemplate timescale="1000" presentationTimeOffset="5000">
+                   <SegmentTimeline><S t="6000" d="9000"/></SegmentTimeline>
+                 </SegmentTemplate></AdaptationSet>
+               </Period>"#,
+        ));
+        assert_eq!(events[0].start_time, at(12, 0, 1, 0));
+        assert_eq!(events[0].duration, Duration::seconds(9));
+    }
+
+    #[test]
+    fn out_of_range_timeline_falls_back_to_period_window() {
+        for template in [
+            r#"<SegmentTemplate timescale="1" presentationTimeOffset="18446744073709551615">
+                 <SegmentTimeline><S t="0" d="10"/></SegmentTimeline>
+               </SegmentTemplate>"#,
+            r#"<SegmentTemplate timescale="1">
+                 <SegmentTimeline><S t="18446744073709551000" d="10"/></SegmentTimeline>
+               </SegmentTemplate>"#,
+            // Fits a duration, but not once added to the Period start
+            r#"<SegmentTemplate timescale="1000">
+                 <SegmentTimeline><S t="9000000000000000" d="10000"/></SegmentTimeline>
+               </SegmentTemplate>"#,
+        ] {
+            let (events, failures) = parse(&manifest(&format!(
+                r#"<Period programDateTime="2025-06-01T12:00:00.000Z" duration="PT10S">
+                     <AdaptationSet>{template}</AdaptationSet>
+                   </Period>"#
+            )));
+            assert!(failures.is_empty());
+            assert_eq!(events[0].start_time, at(12, 0, 0, 0));
+            assert_eq!(events[0].duration, Duration::seconds(10));
+        }
+    }
+
+    #[test]
+    fn tolerance_never_exceeds_duration_cap() {
+        let (events, _) = parse(&manifest(
+            r#"<Period programDateTime="2025-06-01T12:00:00.000Z" duration="PT10M">
+                 <AdaptationSet><SegmentTemplate timescale="1000">
+                   <SegmentTimeline><S t="0" d="601500"/></SegmentTimeline>
+                 </SegmentTemplate></AdaptationSet>
+               </Period>"#,
+        ));
+        let event = &events[0];
+        assert_eq!(event.duration, Duration::seconds(MAX_EVENT_DURATION_SECS));
+        assert_eq!(
+            event.original_duration,
+            Some(Duration::milliseconds(601_500))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_xml() {
+        assert!(device().parse_events(b"<MPD><Period></MPD>").is_err());
+    }
+
+    const MALFORMED_PERIODS: &str = r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011">
+  <Period id="good-1" programDateTime="2025-06-01T12:00:00.000Z" duration="PT10S"/>
+  <Period id="no-duration" programDateTime="2025-06-01T12:01:00.000Z"/>
+  <Period id="bad-time" programDateTime="yesterday" duration="PT10S"/>
+  <Period id="bad-duration" programDateTime="2025-06-01T12:03:00.000Z" duration="ten seconds">
+    <AdaptationSet mimeType="video/mp4"/>
+  </Period>
+  <Period id="good-2" programDateTime="2025-06-01T12:04:00.000Z" duration="PT5S"/>
+</MPD>"#;
+
+    #[test]
+    fn malformed_periods_drop_only_their_own_events() {
+        let (events, failures) = parse(MALFORMED_PERIODS);
+
+        let starts: Vec<_> = events.iter().map(|e| e.start_time).collect();
+        assert_eq!(starts, [at(12, 0, 0, 0), at(12, 4, 0, 0)]);
+        assert_eq!(events[0].clip_id.as_deref(), Some("good-1"));
+        assert_eq!(events[1].clip_id.as_deref(), Some("good-2"));
+        assert_eq!(failures.len(), 3);
+    }
+
+    #[test]
+    fn malformed_period_failures_keep_the_raw_attributes() {
+        let (_, failures) = parse(MALFORMED_PERIODS);
+
+        let attributes: Vec<_> = failures
+            .iter()
+            .map(|f| (f.program_date_time.as_deref(), f.duration.as_deref()))
+            .collect();
+        assert_eq!(
+            attributes,
+            [
+                (Some("2025-06-01T12:01:00.000Z"), None),
+                (Some("yesterday"), Some("PT10S")),
+                (Some("2025-06-01T12:03:00.000Z"), Some("ten seconds")),
+            ]
+        );
+        assert_eq!(failures[0].reason, "missing programDateTime or duration");
+        for (failure, id) in failures
+            .iter()
+            .zip(["no-duration", "bad-time", "bad-duration"])
+        {
+            // The offset is where reading the Period began, before any
+            // whitespace ahead of it
+            let at_offset = MALFORMED_PERIODS[failure.byte_offset as usize..].trim_start();
+            assert!(
+                at_offset.starts_with(&format!(r#"<Period id="{id}""#)),
+                "{failure}"
+            );
+        }
+    }
+
+    #[test]
+    fn many_malformed_periods_save_the_manifest() {
+        let device = NestDevice::new(
+            format!("parse-failure-test-{}", std::process::id()),
+            "Porch".to_string(),
+        );
+        let (_, failures) = device.parse_events(MALFORMED_PERIODS.as_bytes()).unwrap();
+        assert!(failures.len() > MANIFEST_DUMP_FAILURE_THRESHOLD);
+
+        device.report_parse_failures(&failures, MALFORMED_PERIODS.as_bytes());
+
+        let dump =
+            std::env::temp_dir().join(format!("nest-sync-manifest-{}.xml", device.device_id));
+        let saved = std::fs::read_to_string(&dump).unwrap();
+        std::fs::remove_file(&dump).unwrap();
+        assert_eq!(saved, MALFORMED_PERIODS);
+    }
+}
@@ -0,0 +1,105 @@
+use std::{fmt::Write as _, fs, path::Path};
+
+use anyhow::{Context, Result};
+use chrono_tz::America::Vancouver;
+use quick_xml::escape::escape;
+use tracing::info;
+
+use crate::archive::{self, VideoMetadata};
+
+/// Renders a Kodi/Jellyfin movie NFO for a clip from its sidecar.
+pub fn render(metadata: &VideoMetadata) -> String {
+    let start = metadata.start_time.with_timezone(&Vancouver);
+    let device = if metadata.device_name.is_empty() {
+        metadata.device_id.as_str()
+    } else {
+        metadata.device_name.as_str()
+    };
+    let event_types: Vec<&str> = metadata
+        .event_type
+        .as_deref()
+        .map(|types| types.split('+').collect())
+        .unwrap_or_default();
+
+    let what = if event_types.is_empty() {
+        "Event".to_string()
+    } else {
+        let what = event_types.join(" and ");
+        let mut chars = what.chars();
+        chars
+            .next()
+            .map(|first| first.to_uppercase().chain(chars).collect())
+            .unwrap_or(what)
+    };
+    let plot = format!(
+        "{what} recorded by {device} on {} for {} seconds.",
+        start.format("%A %-d %B %Y at %H:%M:%S %Z"),
+        metadata.duration_secs
+    );
+    // Runtime is whole minutes in the NFO schema; round short clips up
+    let runtime_minutes = (metadata.duration_secs.max(1) + 59) / 60;
+
+    let mut nfo =
+        String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<movie>\n");
+    let mut element = |name: &str, value: &str| {
+        let _ = writeln!(nfo, "  <{name}>{}</{name}>", escape(value));
+    };
+    element(
+        "title",
+        &format!("{device} {}", start.format("%Y-%m-%d %H:%M:%S")),
+    );
+    element("plot", &plot);
+    element("runtime", &runtime_minutes.to_string());
+    element("premiered", &start.format("%Y-%m-%d").to_string());
+    element("year", &start.format("%Y").to_string());
+    element("studio", device);
+    for tag in event_types
+        .iter()
+        .copied()
+        .chain(metadata.tags.iter().map(String::as_str))
+    {
+        element("tag", tag);
+    }
+    if let Some(downloaded_at) = metadata.downloaded_at {
+        element(
+            "dateadded",
+            &downloaded_at
+                .with_timezone(&Vancouver)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+        );
+    }
+    let _ = writeln!(
+        nfo,
+        "  <uniqueid type=\"nest\" default=\"true\">{}</uniqueid>",
+        escape(metadata.event_id.as_str())
+    );
+    nfo.push_str("</movie>\n");
+    nfo
+}
+
+pub fn write(clip: &Path, metadata: &VideoMetadata) -> Result<()> {
+    fs::write(archive::nfo_path(clip), render(metadata)).context("Failed to write NFO")
+}
+
+/// Rewrites the NFO of every clip that has a sidecar.
+pub fn regenerate(output_path: &Path) -> Result<()> {
+    let mut written_count = 0;
+    let mut skipped_count = 0;
+
+    for clip in archive::walk_clips(output_path) {
+        let Some(metadata) = &clip.sidecar else {
+            skipped_count += 1;
+            continue;
+        };
+        write(&clip.path, metadata)
+            .with_context(|| format!("Failed to write NFO for {}", clip.path.display()))?;
+        written_count += 1;
+    }
+
+    info!(
+        written_count,
+        skipped_count, "Regenerated NFO files; clips without a sidecar were skipped"
+    );
+    Ok(())
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use chrono_tz::America::Vancouver;
+use tracing::info;
+
+use crate::archive::{self, VideoMetadata};
+
+/// Playlist written into each folder of clips.
+pub const PLAYLIST_FILE: &str = "playlist.m3u";
+
+/// Serializes playlist updates from concurrent downloads into one folder.
+static PLAYLIST_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, clap::Args)]
+pub struct PlaylistArgs {
+    /// Folder to rebuild playlists under (default: the whole output directory)
+    dir: Option<PathBuf>,
+}
+
+/// One clip in a playlist.
+#[derive(Debug, Clone)]
+struct Entry {
+    start_time: DateTime<Utc>,
+    duration_secs: i64,
+    title: String,
+    /// File name, relative to the playlist.
+    file: String,
+}
+
+impl Entry {
+    fn new(clip: &Path, metadata: Option<&VideoMetadata>, fallback_start: DateTime<Utc>) -> Self {
+        let start_time = metadata.map_or(fallback_start, |m| m.start_time);
+        let device = metadata
+            .map(|m| {
+                if m.device_name.is_empty() {
+                    m.device_id.as_str()
+                } else {
+                    m.device_name.as_str()
+                }
+            })
+            .unwrap_or(archive::UNATTRIBUTED);
+        let local_time = start_time.with_timezone(&Vancouver).format("%H:%M:%S");
+
+        Self {
+            start_time,
+            // -1 is M3U for an unknown length
+            duration_secs: metadata.map_or(-1, |m| m.duration_secs),
+            title: format!("{device} {local_time}"),
+            file: clip
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Renders an extended M3U. Each entry's `#EXT-X-PROGRAM-DATE-TIME` records
+/// its start time, so later updates can insert clips in order without
+/// re-reading every sidecar.
+fn render(entries: &[Entry]) -> String {
+    let mut out = String::from("#EXTM3U\n");
+    for entry in entries {
+        let _ = writeln!(
+            out,
+            "#EXT-X-PROGRAM-DATE-TIME:{}",
+            entry.start_time.to_rfc3339()
+        );
+        let _ = writeln!(out, "#EXTINF:{},{}", entry.duration_secs, entry.title);
+        let _ = writeln!(out, "{}", entry.file);
+    }
+    out
+}
+
+/// Parses a playlist written by [`render`]. Entries without a start time
+/// can't be ordered and are dropped.
+fn parse(contents: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut start_time = None;
+    let mut extinf = None;
+
+    for line in contents.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(time) = line.strip_prefix("#EXT-X-PROGRAM-DATE-TIME:") {
+            start_time = DateTime::parse_from_rfc3339(time)
+                .ok()
+                .map(|t| t.with_timezone(&Utc));
+        } else if let Some(info) = line.strip_prefix("#EXTINF:") {
+            extinf = info
+                .split_once(',')
+                .map(|(duration, title)| (duration.parse().unwrap_or(-1), title.to_string()));
+        } else if !line.starts_with('#') {
+            if let Some(start_time) = start_time.take() {
+                let (duration_secs, title) = extinf.take().unwrap_or((-1, String::new()));
+                entries.push(Entry {
+                    start_time,
+                    duration_secs,
+                    title,
+                    file: line.to_string(),
+                });
+            }
+            extinf = None;
+        }
+    }
+
+    entries
+}
+
+/// Writes the playlist atomically (temp file + rename).
+fn write(path: &Path, entries: &[Entry]) -> Result<()> {
+    let tmp_path = path.with_extension("m3u.tmp");
+    fs::write(&tmp_path, render(entries))
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+/// Adds a newly downloaded clip to its folder's playlist, in start time order.
+/// Entries whose clips have since been pruned are dropped along the way.
+pub fn add_clip(clip: &Path, metadata: &VideoMetadata) -> Result<()> {
+    let Some(dir) = clip.parent() else {
+        return Ok(());
+    };
+    let path = dir.join(PLAYLIST_FILE);
+    let _lock = PLAYLIST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut entries = fs::read_to_string(&path)
+        .map(|contents| parse(&contents))
+        .unwrap_or_default();
+    let entry = Entry::new(clip, Some(metadata), metadata.start_time);
+    entries.retain(|e| e.file != entry.file && dir.join(&e.file).exists());
+    let index = entries.partition_point(|e| e.start_time <= entry.start_time);
+    entries.insert(index, entry);
+
+    write(&path, &entries)
+}
+
+/// Drops deleted clips from the playlists of the folders they were in.
+/// Folders without a playlist are left without one.
+pub fn remove_clips(clips: &[&Path]) -> Result<()> {
+    let mut removed: BTreeMap<&Path, Vec<String>> = BTreeMap::new();
+    for clip in clips {
+        if let (Some(dir), Some(name)) = (clip.parent(), clip.file_name()) {
+            removed
+                .entry(dir)
+                .or_default()
+                .push(name.to_string_lossy().into_owned());
+        }
+    }
+
+    let _lock = PLAYLIST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    for (dir, files) in removed {
+        let path = dir.join(PLAYLIST_FILE);
+        let Ok(contents) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let mut entries = parse(&contents);
+        let count = entries.len();
+        entries.retain(|e| !files.contains(&e.file));
+        if entries.len() != count {
+            write(&path, &entries)?;
+        }
+    }
+    Ok(())
+}
+
+/// Rebuilds the playlist of every folder of clips under `dir`. Clips without
+/// a sidecar are ordered by modification time.
+pub fn run(output_path: &Path, args: &PlaylistArgs) -> Result<()> {
+    let dir = args.dir.as_deref().unwrap_or(output_path);
+    let mut folders: BTreeMap<PathBuf, Vec<Entry>> = BTreeMap::new();
+    for clip in archive::walk_clips(dir) {
+        let Some(folder) = clip.path.parent() else {
+            continue;
+        };
+        let entry = Entry::new(&clip.path, clip.sidecar.as_ref(), clip.modified.into());
+        folders.entry(folder.to_path_buf()).or_default().push(entry);
+    }
+
+    let clip_count: usize = folders.values().map(Vec::len).sum();
+    for (folder, entries) in &mut folders {
+        entries.sort_by(|a, b| (a.start_time, &a.file).cmp(&(b.start_time, &b.file)));
+        write(&folder.join(PLAYLIST_FILE), entries)?;
+    }
+
+    info!(
+        playlist_count = folders.len(),
+        clip_count, "Rebuilt playlists"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::models::CameraEvent;
+
+    fn metadata(minute: u32) -> VideoMetadata {
+        let start = Utc.with_ymd_and_hms(2025, 6, 1, 18, minute, 0).unwrap();
+        let event = CameraEvent::new("porch".to_string(), start, chrono::Duration::seconds(10));
+        VideoMetadata::from(event).with_device_name("Porch")
+    }
+
+    /// Writes a clip and adds it to its folder's playlist.
+    fn add(dir: &TempDir, name: &str, minute: u32) -> PathBuf {
+        let clip = dir.path().join(name);
+        fs::write(&clip, b"video").unwrap();
+        add_clip(&clip, &metadata(minute)).unwrap();
+        clip
+    }
+
+    fn files(dir: &TempDir) -> Vec<String> {
+        let contents = fs::read_to_string(dir.path().join(PLAYLIST_FILE)).unwrap();
+        parse(&contents).into_iter().map(|e| e.file).collect()
+    }
+
+    #[test]
+    fn parse_reads_back_what_render_writes() {
+        let entries = vec![
+            Entry::new(Path::new("a.mp4"), Some(&metadata(0)), Utc::now()),
+            Entry::new(Path::new("b.mp4"), None, metadata(5).start_time),
+        ];
+
+        let parsed = parse(&render(&entries));
+
+        assert_eq!(parsed.len(), 2);
+        for (parsed, entry) in parsed.iter().zip(&entries) {
+            assert_eq!(parsed.start_time, entry.start_time);
+            assert_eq!(parsed.duration_secs, entry.duration_secs);
+            assert_eq!(parsed.title, entry.title);
+            assert_eq!(parsed.file, entry.file);
+        }
+        assert_eq!(parsed[0].title, "Porch 11:00:00");
+        assert_eq!(parsed[1].title, "(unattributed) 11:05:00");
+        assert_eq!(parsed[1].duration_secs, -1);
+    }
+
+    #[test]
+    fn adds_clips_in_start_time_order() {
+        let dir = TempDir::new().unwrap();
+        add(&dir, "b.mp4", 20);
+        add(&dir, "c.mp4", 40);
+        add(&dir, "a.mp4", 0);
+        add(&dir, "b2.mp4", 20);
+        // Adding a clip again replaces its entry
+        add(&dir, "c.mp4", 40);
+
+        assert_eq!(files(&dir), ["a.mp4", "b.mp4", "b2.mp4", "c.mp4"]);
+    }
+
+    #[test]
+    fn removes_deleted_clips() {
+        let dir = TempDir::new().unwrap();
+        let first = add(&dir, "a.mp4", 0);
+        add(&dir, "b.mp4", 20);
+        fs::remove_file(&first).unwrap();
+
+        remove_clips(&[first.as_path()]).unwrap();
+
+        assert_eq!(files(&dir), ["b.mp4"]);
+    }
+
+    #[test]
+    fn removing_clips_leaves_folders_without_a_playlist_alone() {
+        let dir = TempDir::new().unwrap();
+
+        remove_clips(&[dir.path().join("a.mp4").as_path()]).unwrap();
+
+        assert!(!dir.path().join(PLAYLIST_FILE).exists());
+    }
+}
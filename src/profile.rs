@@ -0,0 +1,96 @@
+use clap::{ArgMatches, ValueEnum, parser::ValueSource};
+
+use crate::{Args, google_auth::DEFAULT_EVENT_QUERY_TYPES, models::EventTypeFilter};
+
+/// A bundle of defaults for a kind of camera, chosen with `--profile`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Profile {
+    /// Every event, with a little extra footage around each ring or visitor
+    Doorbell,
+    /// People, vehicles, animals and packages; short bursts from wind or rain skipped
+    OutdoorCam,
+    /// People, sounds and animals indoors; short bursts skipped
+    IndoorCam,
+}
+
+/// Values a profile gives flags that weren't passed explicitly.
+struct ProfileDefaults {
+    event_query_types: &'static str,
+    quality: Option<u32>,
+    event_types: Option<&'static str>,
+    clip_padding_before: u64,
+    clip_padding_after: u64,
+    min_event_secs: u64,
+}
+
+impl Profile {
+    // Every profile keeps the query types and variant known to work; they're
+    // here so a camera model that needs others can get its own profile
+    fn defaults(self) -> ProfileDefaults {
+        match self {
+            Self::Doorbell => ProfileDefaults {
+                event_query_types: DEFAULT_EVENT_QUERY_TYPES,
+                quality: None,
+                event_types: None,
+                clip_padding_before: 5,
+                clip_padding_after: 10,
+                min_event_secs: 0,
+            },
+            Self::OutdoorCam => ProfileDefaults {
+                event_query_types: DEFAULT_EVENT_QUERY_TYPES,
+                quality: None,
+                event_types: Some("person,vehicle,animal,package"),
+                clip_padding_before: 3,
+                clip_padding_after: 5,
+                min_event_secs: 3,
+            },
+            Self::IndoorCam => ProfileDefaults {
+                event_query_types: DEFAULT_EVENT_QUERY_TYPES,
+                quality: None,
+                event_types: Some("person,sound,animal"),
+                clip_padding_before: 2,
+                clip_padding_after: 5,
+                min_event_secs: 2,
+            },
+        }
+    }
+}
+
+/// Fills in `args.profile`'s defaults for every flag `matches` didn't get
+/// from the command line or the environment.
+pub fn apply(mut args: Args, matches: &ArgMatches) -> Args {
+    let Some(profile) = args.profile else {
+        return args;
+    };
+    let defaults = profile.defaults();
+    let unset = |id: &str| {
+        matches!(
+            matches.value_source(id),
+            None | Some(ValueSource::DefaultValue)
+        )
+    };
+
+    if unset("event_query_types") {
+        args.event_query_types = defaults.event_query_types.to_string();
+    }
+    if unset("quality") {
+        args.quality = defaults.quality;
+    }
+    if unset("event_types") {
+        args.event_types = defaults.event_types.map(|types| {
+            types
+                .parse::<EventTypeFilter>()
+                .expect("valid profile event types")
+        });
+    }
+    if unset("clip_padding_before") {
+        args.clip_padding_before = defaults.clip_padding_before;
+    }
+    if unset("clip_padding_after") {
+        args.clip_padding_after = defaults.clip_padding_after;
+    }
+    if unset("min_event_secs") {
+        args.min_event_secs = defaults.min_event_secs;
+    }
+    args
+}
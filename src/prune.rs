@@ -0,0 +1,1071 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    Args,
+    archive::{self, ArchiveClip, ArchiveWalk, DeviceAttribution, VideoMetadata},
+    dedup::{self, DedupMode},
+    layout::PathTemplate,
+    playlist,
+    retention::{self, FreeSpaceTier},
+    thinning::{self, ThinTier},
+};
+
+#[derive(Debug, clap::Args)]
+pub struct PruneArgs {
+    /// Number of days (or hours with --retention-hours) to keep videos; overrides --retention-days
+    #[arg(long)]
+    retention_days: Option<u64>,
+
+    /// Log what pruning would delete without deleting anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Log every clip that is kept, and why
+    #[arg(long)]
+    verbose: bool,
+
+    /// Delete the oldest clips until the archive is at most this many GB; overrides --max-disk-gb
+    #[arg(long)]
+    max_disk_gb: Option<f64>,
+
+    /// Keep at most this many clips per device; overrides --max-clips-per-device
+    #[arg(long)]
+    max_clips_per_device: Option<usize>,
+}
+
+#[derive(Clone)]
+pub struct PrunePolicy {
+    pub retention_period: u64,
+    pub use_hours: bool,
+    pub keep_min_per_device: usize,
+    pub thin_tiers: Vec<ThinTier>,
+    pub free_space_tiers: Vec<FreeSpaceTier>,
+    pub max_disk_bytes: Option<u64>,
+    pub max_clips_per_device: Option<usize>,
+    /// What to do with byte-identical clips after pruning.
+    pub dedup: Option<DedupMode>,
+    pub dry_run: bool,
+    pub verbose: bool,
+    /// Layout of the archive, used to skip day directories too recent to
+    /// hold prunable clips.
+    pub path_template: PathTemplate,
+}
+
+impl PrunePolicy {
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            retention_period: args.retention_days,
+            use_hours: args.retention_hours,
+            keep_min_per_device: args.keep_min_per_device,
+            thin_tiers: args.thin.clone(),
+            free_space_tiers: args.retention_free_tier.clone(),
+            max_disk_bytes: args.max_disk_gb.map(gb_to_bytes),
+            max_clips_per_device: args.max_clips_per_device,
+            dedup: args.dedup_identical,
+            dry_run: args.prune_dry_run,
+            verbose: false,
+            path_template: args.path_template.clone(),
+        }
+    }
+
+    /// The daemon's policy with the `prune` subcommand's overrides applied.
+    pub fn for_subcommand(args: &Args, prune_args: &PruneArgs) -> Self {
+        let mut policy = Self::from_args(args);
+        if let Some(retention_days) = prune_args.retention_days {
+            policy.retention_period = retention_days;
+        }
+        if let Some(max_disk_gb) = prune_args.max_disk_gb {
+            policy.max_disk_bytes = Some(gb_to_bytes(max_disk_gb));
+        }
+        if prune_args.max_clips_per_device.is_some() {
+            policy.max_clips_per_device = prune_args.max_clips_per_device;
+        }
+        policy.dry_run |= prune_args.dry_run;
+        policy.verbose = prune_args.verbose;
+        policy
+    }
+
+    /// Whether only the age cutoff can delete clips, so clips newer than it
+    /// only matter for the per-device minimum. Verbose runs list every kept
+    /// clip, and deduplication compares them, so they see them all.
+    fn is_age_only(&self) -> bool {
+        !self.verbose
+            && self.dedup.is_none()
+            && self.thin_tiers.is_empty()
+            && self.max_disk_bytes.is_none()
+            && self.max_clips_per_device.is_none()
+    }
+
+    fn is_disabled(&self) -> bool {
+        self.retention_period == 0
+            && self.thin_tiers.is_empty()
+            && self.free_space_tiers.is_empty()
+            && self.max_disk_bytes.is_none()
+            && self.max_clips_per_device.is_none()
+            && self.dedup.is_none()
+    }
+}
+
+fn gb_to_bytes(gb: f64) -> u64 {
+    (gb * 1_000_000_000.0) as u64
+}
+
+/// Free space on the output filesystem, in bytes, below which the daemon
+/// prunes the oldest clips regardless of age, and up to which it then frees.
+#[derive(Debug, Clone, Copy)]
+pub struct EmergencyThresholds {
+    pub floor: u64,
+    pub recovery: u64,
+}
+
+impl EmergencyThresholds {
+    pub fn from_args(args: &Args) -> Option<Self> {
+        let floor_gb = args.emergency_free_gb?;
+        Some(Self {
+            floor: gb_to_bytes(floor_gb),
+            recovery: gb_to_bytes(args.emergency_recovery_gb.unwrap_or(floor_gb * 2.0)),
+        })
+    }
+}
+
+/// What an emergency prune pass did.
+#[derive(Debug, Clone, Copy)]
+pub struct EmergencyOutcome {
+    pub deleted_count: usize,
+    pub freed_bytes: u64,
+    /// Whether free space reached the recovery threshold.
+    pub recovered: bool,
+}
+
+/// Files modified strictly before the returned instant are prunable; a file
+/// whose age equals the retention period exactly is kept.
+fn retention_cutoff(now: SystemTime, retention_period: u64, use_hours: bool) -> SystemTime {
+    let unit_seconds = if use_hours { 60 * 60 } else { 24 * 60 * 60 };
+    let retention = Duration::from_secs(retention_period.saturating_mul(unit_seconds));
+    now.checked_sub(retention).unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// Date directories starting on or after the returned day only hold clips
+/// newer than `cutoff`. The two days' margin covers the archive's local time
+/// zone, whatever it is, and clips whose time is their end rather than their
+/// start.
+fn first_recent_day(cutoff: SystemTime) -> NaiveDate {
+    DateTime::<Utc>::from(cutoff).date_naive() + Days::new(2)
+}
+
+/// The archive's clips for an age-only policy. Year, month and day
+/// directories too recent to hold prunable clips aren't walked, except newest
+/// first until each device with older clips has `keep_min_per_device` newer
+/// ones, which decide what the minimum protects. Falls back to walking
+/// everything when the template has no date directories.
+fn walk_for_cutoff(output_path: &Path, policy: &PrunePolicy, cutoff: SystemTime) -> ArchiveWalk {
+    walk_for_cutoff_with(output_path, policy, cutoff, |dir, skip| {
+        archive::walk_clips_skipping(output_path, dir, skip)
+    })
+}
+
+/// Which subdirectories a walk leaves unopened.
+type SkipDir<'a> = dyn Fn(&Path) -> bool + Sync + 'a;
+
+/// [`walk_for_cutoff`] walking each directory with `walk`.
+fn walk_for_cutoff_with(
+    output_path: &Path,
+    policy: &PrunePolicy,
+    cutoff: SystemTime,
+    walk: impl Fn(&Path, &SkipDir) -> ArchiveWalk,
+) -> ArchiveWalk {
+    let first_recent = first_recent_day(cutoff);
+    let dir_start_date = |dir: &Path| {
+        dir.strip_prefix(output_path)
+            .ok()
+            .and_then(|relative| policy.path_template.dir_start_date(relative))
+    };
+    // Inside a recent directory, every date directory is recent too
+    let recent = |dir: &Path| dir_start_date(dir).is_some_and(|date| date >= first_recent);
+    let dated = |dirs: Vec<PathBuf>| {
+        dirs.into_iter()
+            .filter_map(|dir| Some((dir_start_date(&dir)?, dir)))
+            .collect::<Vec<_>>()
+    };
+
+    let mut archive = walk(output_path, &recent);
+    let mut recent_dirs = dated(std::mem::take(&mut archive.skipped));
+    let skipped_dirs = recent_dirs.len();
+
+    if policy.keep_min_per_device > 0 {
+        let attribution =
+            DeviceAttribution::new(output_path, &policy.path_template, &archive.clips);
+        let mut newer_counts: HashMap<String, usize> = archive
+            .clips
+            .iter()
+            .filter_map(|clip| attribution.device(clip))
+            .map(|device| (device.into_owned(), 0))
+            .collect();
+        let mut newer_clips = Vec::new();
+        let mut newer_duplicates = Vec::new();
+        // Date directories are nested or disjoint, so the one starting latest
+        // holds only clips newer than any other left. A year or month is
+        // opened one level at a time so only as much is walked as needed.
+        loop {
+            if newer_counts
+                .values()
+                .all(|count| *count >= policy.keep_min_per_device)
+            {
+                break;
+            }
+            recent_dirs.sort_by(|a, b| a.0.cmp(&b.0));
+            let Some((_, dir)) = recent_dirs.pop() else {
+                break;
+            };
+            let dir_walk = walk(&dir, &recent);
+            recent_dirs.extend(dated(dir_walk.skipped));
+            for device in dir_walk
+                .clips
+                .iter()
+                .filter_map(|clip| attribution.device(clip))
+            {
+                if let Some(count) = newer_counts.get_mut(device.as_ref()) {
+                    *count += 1;
+                }
+            }
+            newer_clips.extend(dir_walk.clips);
+            newer_duplicates.extend(dir_walk.duplicate_sidecars);
+        }
+        archive.clips.extend(newer_clips);
+        archive.duplicate_sidecars.extend(newer_duplicates);
+    }
+
+    debug!(
+        skipped_dirs,
+        %first_recent,
+        "Skipped date directories newer than the retention cutoff"
+    );
+    archive
+}
+
+/// Each attributed device's clips, newest first. Clips neither a sidecar nor
+/// their path attributes are left out.
+fn clips_by_device<'a>(
+    clips: &'a [ArchiveClip],
+    attribution: &'a DeviceAttribution,
+) -> Vec<Vec<&'a ArchiveClip>> {
+    let mut by_device: HashMap<Cow<str>, Vec<&ArchiveClip>> = HashMap::new();
+    for clip in clips {
+        if let Some(device) = attribution.device(clip) {
+            by_device.entry(device).or_default().push(clip);
+        }
+    }
+
+    by_device
+        .into_values()
+        .map(|mut device_clips| {
+            device_clips.sort_by(|a, b| b.modified.cmp(&a.modified));
+            device_clips
+        })
+        .collect()
+}
+
+/// The newest `keep` clips of each attributed device.
+fn newest_per_device(
+    clips: &[ArchiveClip],
+    attribution: &DeviceAttribution,
+    keep: usize,
+) -> HashSet<PathBuf> {
+    clips_by_device(clips, attribution)
+        .into_iter()
+        .flat_map(|device_clips| device_clips.into_iter().take(keep).map(|c| c.path.clone()))
+        .collect()
+}
+
+/// Each attributed device's unpinned clips beyond its newest `max`.
+fn over_device_limit(
+    clips: &[ArchiveClip],
+    attribution: &DeviceAttribution,
+    max: usize,
+) -> HashSet<PathBuf> {
+    clips_by_device(clips, attribution)
+        .into_iter()
+        .flat_map(|device_clips| {
+            device_clips
+                .into_iter()
+                .filter(|c| !c.pinned)
+                .skip(max)
+                .map(|c| c.path.clone())
+        })
+        .collect()
+}
+
+/// Deletes the clips `policy` no longer keeps: those past the retention
+/// period, thinned out, over the per-device limit, and finally the oldest
+/// remaining ones until the archive fits the disk limit. Pinned clips and each
+/// device's newest `keep_min_per_device` are never deleted.
+pub async fn prune_old_videos(output_path: &Path, policy: &PrunePolicy) -> Result<()> {
+    prune_at(output_path, policy, SystemTime::now()).await
+}
+
+async fn prune_at(output_path: &Path, policy: &PrunePolicy, now: SystemTime) -> Result<()> {
+    if policy.is_disabled() {
+        // No pruning
+        return Ok(());
+    }
+
+    let mut retention_period = policy.retention_period;
+    let unit = if policy.use_hours { "hours" } else { "days" };
+    if !policy.free_space_tiers.is_empty() {
+        match retention::free_space_percent(output_path) {
+            Ok(free_percent) => {
+                let (effective, tier) = retention::effective_retention(
+                    retention_period,
+                    &policy.free_space_tiers,
+                    free_percent,
+                );
+                if let Some(tier) = tier {
+                    warn!(
+                        free_percent = format!("{free_percent:.1}"),
+                        %tier,
+                        retention_period = effective,
+                        unit,
+                        "Free space is low; shortening retention"
+                    );
+                }
+                retention_period = effective;
+            }
+            Err(e) => {
+                warn!(error = %e, "Failed to read free space; using base retention");
+            }
+        }
+    }
+
+    if retention_period > 0 {
+        info!(
+            retention_period,
+            unit, "Pruning videos older than specified period"
+        );
+    }
+
+    let cutoff_time =
+        (retention_period > 0).then(|| retention_cutoff(now, retention_period, policy.use_hours));
+
+    // The walk, the deletions and dedup's hashing can each take minutes on a
+    // large archive; keep them off the runtime's worker threads
+    let prune = {
+        let output_path = output_path.to_path_buf();
+        let policy = policy.clone();
+        tokio::task::spawn_blocking(move || {
+            let walk_started = Instant::now();
+            let walk = match cutoff_time {
+                Some(cutoff) if policy.is_age_only() => {
+                    walk_for_cutoff(&output_path, &policy, cutoff)
+                }
+                _ => archive::walk_clips_skipping(&output_path, &output_path, |_| false),
+            };
+            info!(
+                clip_count = walk.clips.len(),
+                walk_secs = format!("{:.1}", walk_started.elapsed().as_secs_f64()),
+                "Walked archive"
+            );
+            prune_clips(&output_path, &policy, &walk, cutoff_time, now);
+        })
+    };
+    prune.await.context("Pruning failed")
+}
+
+/// Deletes the walked clips that `policy` doesn't keep and the sidecars of
+/// deleted duplicates that no longer matter, then deduplicates the rest.
+fn prune_clips(
+    output_path: &Path,
+    policy: &PrunePolicy,
+    walk: &ArchiveWalk,
+    cutoff_time: Option<SystemTime>,
+    now: SystemTime,
+) {
+    let clips = walk.clips.as_slice();
+    let mut protected_count = 0;
+    let mut pinned_count = 0;
+    let mut pinned_bytes = 0;
+
+    let attribution = DeviceAttribution::new(output_path, &policy.path_template, clips);
+    let protected = newest_per_device(clips, &attribution, policy.keep_min_per_device);
+    let over_limit = policy
+        .max_clips_per_device
+        .map(|max| over_device_limit(clips, &attribution, max))
+        .unwrap_or_default();
+
+    // Thinning only considers clips that survive the age cutoff and aren't
+    // otherwise protected
+    let thinnable: Vec<&ArchiveClip> = clips
+        .iter()
+        .filter(|c| !c.pinned && !protected.contains(&c.path))
+        .filter(|c| cutoff_time.is_none_or(|cutoff| c.modified >= cutoff))
+        .collect();
+    let thinned = thinning::select_thinned(&thinnable, &policy.thin_tiers, now);
+
+    let mut deletions: Vec<(&ArchiveClip, String)> = Vec::new();
+    let mut survivors: Vec<&ArchiveClip> = Vec::new();
+    for clip in clips {
+        let path = &clip.path;
+
+        let reason = if clip.pinned {
+            pinned_count += 1;
+            pinned_bytes += clip.size;
+            None
+        } else if protected.contains(path) {
+            let past_cutoff = cutoff_time.is_some_and(|cutoff| clip.modified < cutoff);
+            if past_cutoff || over_limit.contains(path) {
+                debug!(path = %path.display(), "Keeping video to satisfy per-device minimum");
+                protected_count += 1;
+            }
+            None
+        } else if cutoff_time.is_some_and(|cutoff| clip.modified < cutoff) {
+            Some("retention".to_string())
+        } else if let Some(&tier_index) = thinned.get(path) {
+            Some(format!(
+                "thin tier {} ({})",
+                tier_index + 1,
+                policy.thin_tiers[tier_index]
+            ))
+        } else if over_limit.contains(path) {
+            Some("per-device clip limit".to_string())
+        } else {
+            None
+        };
+
+        match reason {
+            Some(reason) => deletions.push((clip, reason)),
+            None => survivors.push(clip),
+        }
+    }
+
+    if let Some(max_disk_bytes) = policy.max_disk_bytes {
+        let mut total_bytes: u64 = survivors.iter().map(|c| c.size).sum();
+        if total_bytes > max_disk_bytes {
+            survivors.sort_by_key(|c| c.modified);
+            let mut still_kept = Vec::new();
+            for clip in survivors {
+                if total_bytes > max_disk_bytes && !clip.pinned && !protected.contains(&clip.path) {
+                    total_bytes -= clip.size;
+                    deletions.push((clip, "disk limit".to_string()));
+                } else {
+                    still_kept.push(clip);
+                }
+            }
+            if total_bytes > max_disk_bytes {
+                warn!(
+                    total_bytes,
+                    max_disk_bytes,
+                    "Archive still exceeds the disk limit; the rest is pinned or protected"
+                );
+            }
+            survivors = still_kept;
+        }
+    }
+
+    if policy.verbose {
+        for clip in &survivors {
+            let reason = if clip.pinned {
+                "pinned"
+            } else if protected.contains(&clip.path) {
+                "per-device minimum"
+            } else {
+                "within policy"
+            };
+            info!(path = %clip.path.display(), reason, "Keeping video");
+        }
+    }
+    // Clips the per-device minimum saved are reported separately
+    let kept_count = survivors.iter().filter(|c| !c.pinned).count() - protected_count;
+
+    let mut deleted_count = 0;
+    let mut deleted_paths = HashSet::new();
+    for (clip, reason) in deletions {
+        let path = &clip.path;
+        if policy.dry_run {
+            info!(path = %path.display(), %reason, "Would delete video (dry run)");
+            deleted_count += 1;
+            deleted_paths.insert(path);
+            continue;
+        }
+
+        match archive::remove_clip(path) {
+            Ok(_) => {
+                info!(path = %path.display(), %reason, "Deleted old video");
+                deleted_count += 1;
+                deleted_paths.insert(path);
+            }
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "Failed to delete video");
+            }
+        }
+    }
+
+    if !policy.dry_run {
+        update_playlists(&deleted_paths);
+    }
+
+    let stale_duplicate_count = remove_stale_duplicate_sidecars(
+        &walk.duplicate_sidecars,
+        &deleted_paths,
+        cutoff_time,
+        policy.dry_run,
+    );
+
+    let dedup = policy
+        .dedup
+        .map(|mode| dedup::dedup_identical(&survivors, mode, policy.dry_run))
+        .unwrap_or_default();
+
+    info!(
+        deleted_count,
+        kept_count,
+        protected_count,
+        pinned_count,
+        pinned_bytes,
+        stale_duplicate_count,
+        duplicate_count = dedup.duplicate_count,
+        dedup_reclaimed_bytes = dedup.reclaimed_bytes,
+        dry_run = policy.dry_run,
+        "Pruning complete"
+    );
+}
+
+/// Deletes the sidecars `--dedup-identical skip` left for deleted duplicates
+/// once they no longer keep an event from being downloaded again: when the
+/// original is gone, including when this pass deleted it, or the duplicate is
+/// itself past `cutoff_time`. Returns how many were deleted.
+fn remove_stale_duplicate_sidecars(
+    duplicate_sidecars: &[(PathBuf, VideoMetadata)],
+    deleted_paths: &HashSet<&PathBuf>,
+    cutoff_time: Option<SystemTime>,
+    dry_run: bool,
+) -> usize {
+    let mut removed_count = 0;
+    for (path, sidecar) in duplicate_sidecars {
+        let Some(original) = &sidecar.duplicate_of else {
+            continue;
+        };
+        let reason = if deleted_paths.contains(original) || !original.exists() {
+            "original deleted"
+        } else if cutoff_time.is_some_and(|cutoff| SystemTime::from(sidecar.start_time) < cutoff) {
+            "retention"
+        } else {
+            continue;
+        };
+
+        if dry_run {
+            info!(path = %path.display(), reason, "Would delete sidecar of removed duplicate (dry run)");
+            removed_count += 1;
+            continue;
+        }
+        match fs::remove_file(archive::sidecar_path(path)) {
+            Ok(()) => {
+                info!(path = %path.display(), reason, "Deleted sidecar of removed duplicate");
+                removed_count += 1;
+            }
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "Failed to delete sidecar of removed duplicate");
+            }
+        }
+    }
+    removed_count
+}
+
+/// Deletes the oldest clips, whatever their age, until the output filesystem
+/// has `thresholds.recovery` bytes free, starting from `available`. Pinned
+/// clips and each device's newest `keep_min_per_device` are never deleted,
+/// and nothing outside the archive's clips is, so the pass stops short when
+/// those are all that's left.
+pub async fn emergency_prune(
+    output_path: &Path,
+    policy: &PrunePolicy,
+    thresholds: EmergencyThresholds,
+    available: u64,
+) -> Result<EmergencyOutcome> {
+    let needed = thresholds.recovery.saturating_sub(available);
+    // Walking and deleting both wait on the disk; keep them off the runtime's
+    // worker threads
+    let prune = {
+        let output_path = output_path.to_path_buf();
+        let policy = policy.clone();
+        tokio::task::spawn_blocking(move || {
+            let clips = archive::walk_clips(&output_path);
+            delete_oldest(&output_path, &policy, &clips, needed)
+        })
+    };
+    let (deleted_count, freed_bytes) = prune.await.context("Emergency prune failed")?;
+
+    // Other writers may have used or freed space meanwhile
+    let recovered = if policy.dry_run {
+        freed_bytes >= needed
+    } else {
+        fs4::available_space(output_path)? >= thresholds.recovery
+    };
+    Ok(EmergencyOutcome {
+        deleted_count,
+        freed_bytes,
+        recovered,
+    })
+}
+
+/// Deletes the oldest of the walked `clips` that aren't pinned or protected
+/// until `needed` bytes are freed. Returns how many were deleted and the
+/// bytes they freed.
+fn delete_oldest(
+    output_path: &Path,
+    policy: &PrunePolicy,
+    clips: &[ArchiveClip],
+    needed: u64,
+) -> (usize, u64) {
+    let attribution = DeviceAttribution::new(output_path, &policy.path_template, clips);
+    let protected = newest_per_device(clips, &attribution, policy.keep_min_per_device);
+    let mut candidates: Vec<&ArchiveClip> = clips
+        .iter()
+        .filter(|c| !c.pinned && !protected.contains(&c.path))
+        .collect();
+    candidates.sort_by_key(|c| c.modified);
+
+    let mut freed_bytes = 0;
+    let mut deleted_count = 0;
+    let mut deleted_paths = HashSet::new();
+    for clip in candidates {
+        if freed_bytes >= needed {
+            break;
+        }
+        let path = &clip.path;
+        if policy.dry_run {
+            info!(path = %path.display(), reason = "emergency", "Would delete video (dry run)");
+        } else if let Err(e) = archive::remove_clip(path) {
+            error!(path = %path.display(), error = %e, "Failed to delete video");
+            continue;
+        } else {
+            info!(path = %path.display(), reason = "emergency", "Deleted old video");
+            deleted_paths.insert(path);
+        }
+        freed_bytes += clip.size;
+        deleted_count += 1;
+    }
+    update_playlists(&deleted_paths);
+    (deleted_count, freed_bytes)
+}
+
+/// Drops deleted clips from their folders' playlists, which otherwise only
+/// catch up when another clip is downloaded into the same folder.
+fn update_playlists(deleted_paths: &HashSet<&PathBuf>) {
+    let deleted: Vec<&Path> = deleted_paths.iter().map(|path| path.as_path()).collect();
+    if let Err(e) = playlist::remove_clips(&deleted) {
+        warn!(error = %format!("{e:#}"), "Failed to update playlists of deleted clips");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use filetime::FileTime;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{archive::VideoMetadata, layout::DEFAULT_PATH_TEMPLATE, models::CameraEvent};
+
+    const HOUR: u64 = 60 * 60;
+    const DAY: u64 = 24 * HOUR;
+
+    fn now() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_750_000_000)
+    }
+
+    fn policy(retention_period: u64, use_hours: bool) -> PrunePolicy {
+        PrunePolicy {
+            retention_period,
+            use_hours,
+            keep_min_per_device: 0,
+            thin_tiers: Vec::new(),
+            free_space_tiers: Vec::new(),
+            max_disk_bytes: None,
+            max_clips_per_device: None,
+            dedup: None,
+            dry_run: false,
+            verbose: false,
+            path_template: DEFAULT_PATH_TEMPLATE.parse().unwrap(),
+        }
+    }
+
+    /// Creates `name` in `dir`, last modified `age` before `now()`.
+    fn clip(dir: &TempDir, name: &str, age: Duration) -> PathBuf {
+        let path = dir.path().join(name);
+        fs::write(&path, b"clip").unwrap();
+        let mtime = FileTime::from_system_time(now() - age);
+        filetime::set_file_mtime(&path, mtime).unwrap();
+        path
+    }
+
+    #[test]
+    fn cutoff_counts_hours() {
+        assert_eq!(
+            retention_cutoff(now(), 6, true),
+            now() - Duration::from_secs(6 * HOUR)
+        );
+    }
+
+    #[test]
+    fn cutoff_counts_days() {
+        assert_eq!(
+            retention_cutoff(now(), 6, false),
+            now() - Duration::from_secs(6 * DAY)
+        );
+    }
+
+    #[test]
+    fn cutoff_before_epoch_saturates() {
+        assert_eq!(
+            retention_cutoff(now(), u64::MAX, false),
+            SystemTime::UNIX_EPOCH
+        );
+    }
+
+    #[tokio::test]
+    async fn prunes_by_hours() {
+        let dir = TempDir::new().unwrap();
+        let newer = clip(&dir, "newer.mp4", Duration::from_secs(3 * HOUR - 60));
+        let boundary = clip(&dir, "boundary.mp4", Duration::from_secs(3 * HOUR));
+        let older = clip(&dir, "older.mp4", Duration::from_secs(3 * HOUR + 1));
+        let day_old = clip(&dir, "day-old.mp4", Duration::from_secs(DAY));
+
+        prune_at(dir.path(), &policy(3, true), now()).await.unwrap();
+
+        assert!(newer.exists());
+        assert!(boundary.exists(), "a clip exactly at the cutoff is kept");
+        assert!(!older.exists());
+        assert!(!day_old.exists());
+    }
+
+    #[tokio::test]
+    async fn prunes_by_days() {
+        let dir = TempDir::new().unwrap();
+        let hours_old = clip(&dir, "hours-old.mp4", Duration::from_secs(5 * HOUR));
+        let newer = clip(&dir, "newer.mp4", Duration::from_secs(3 * DAY - 60));
+        let boundary = clip(&dir, "boundary.mp4", Duration::from_secs(3 * DAY));
+        let older = clip(&dir, "older.mp4", Duration::from_secs(3 * DAY + 1));
+
+        prune_at(dir.path(), &policy(3, false), now())
+            .await
+            .unwrap();
+
+        assert!(hours_old.exists());
+        assert!(newer.exists());
+        assert!(boundary.exists(), "a clip exactly at the cutoff is kept");
+        assert!(!older.exists());
+    }
+
+    #[tokio::test]
+    async fn keeps_minimum_of_clips_attributed_by_path() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("Porch")).unwrap();
+        fs::create_dir_all(dir.path().join("Garage")).unwrap();
+        let porch_newest = clip(
+            &dir,
+            "Porch/2025-05-01T10-00-00.mp4",
+            Duration::from_secs(40 * DAY),
+        );
+        let porch_older = clip(
+            &dir,
+            "Porch/2025-04-01T10-00-00.mp4",
+            Duration::from_secs(70 * DAY),
+        );
+        let garage_newest = clip(
+            &dir,
+            "Garage/2025-03-01T10-00-00.mp4",
+            Duration::from_secs(100 * DAY),
+        );
+        let mut policy = policy(30, false);
+        policy.keep_min_per_device = 1;
+        policy.path_template = "{device_name}/{year}-{month}-{day}T{hour}-{minute}-{second}.mp4"
+            .parse()
+            .unwrap();
+
+        prune_at(dir.path(), &policy, now()).await.unwrap();
+
+        assert!(porch_newest.exists());
+        assert!(!porch_older.exists());
+        assert!(garage_newest.exists());
+    }
+
+    #[tokio::test]
+    async fn path_attributed_clips_join_their_cameras_sidecar_clips() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("Porch")).unwrap();
+        let with_sidecar = clip(
+            &dir,
+            "Porch/2025-05-01T10-00-00.mp4",
+            Duration::from_secs(40 * DAY),
+        );
+        let event = crate::models::CameraEvent::new(
+            "porch-id".to_string(),
+            DateTime::<Utc>::from(now() - Duration::from_secs(40 * DAY)),
+            chrono::Duration::seconds(10),
+        );
+        let sidecar = archive::VideoMetadata::from(event).with_device_name("Porch");
+        archive::write_sidecar(&with_sidecar, &sidecar).unwrap();
+        let without_sidecar = clip(
+            &dir,
+            "Porch/2025-04-01T10-00-00.mp4",
+            Duration::from_secs(70 * DAY),
+        );
+        let mut policy = policy(30, false);
+        policy.keep_min_per_device = 1;
+        policy.path_template = "{device_name}/{year}-{month}-{day}T{hour}-{minute}-{second}.mp4"
+            .parse()
+            .unwrap();
+
+        prune_at(dir.path(), &policy, now()).await.unwrap();
+
+        assert!(with_sidecar.exists());
+        assert!(!without_sidecar.exists());
+    }
+
+    /// Leaves the sidecar `--dedup-identical skip` writes for a deleted
+    /// duplicate of `original`, recorded `age` before `now()`.
+    fn duplicate_sidecar(dir: &TempDir, name: &str, original: &Path, age: Duration) -> PathBuf {
+        let path = dir.path().join(name);
+        let event = CameraEvent::new(
+            "porch".to_string(),
+            DateTime::from(now() - age),
+            chrono::Duration::seconds(10),
+        );
+        let mut sidecar = VideoMetadata::from(event);
+        sidecar.duplicate_of = Some(original.to_path_buf());
+        archive::write_sidecar(&path, &sidecar).unwrap();
+        archive::sidecar_path(&path)
+    }
+
+    #[tokio::test]
+    async fn removes_sidecars_of_duplicates_once_stale() {
+        let dir = TempDir::new().unwrap();
+        let pruned = clip(&dir, "pruned.mp4", Duration::from_secs(40 * DAY));
+        let pruned_duplicate = duplicate_sidecar(
+            &dir,
+            "pruned-copy.mp4",
+            &pruned,
+            Duration::from_secs(40 * DAY),
+        );
+        let pinned = clip(&dir, "pinned.mp4", Duration::from_secs(50 * DAY));
+        fs::write(archive::keep_marker_path(&pinned), "").unwrap();
+        let old_duplicate = duplicate_sidecar(
+            &dir,
+            "pinned-copy.mp4",
+            &pinned,
+            Duration::from_secs(50 * DAY),
+        );
+        let recent = clip(&dir, "recent.mp4", Duration::from_secs(DAY));
+        let recent_duplicate =
+            duplicate_sidecar(&dir, "recent-copy.mp4", &recent, Duration::from_secs(DAY));
+
+        prune_at(dir.path(), &policy(30, false), now())
+            .await
+            .unwrap();
+
+        assert!(!pruned.exists());
+        assert!(!pruned_duplicate.exists(), "original deleted");
+        assert!(pinned.exists());
+        assert!(!old_duplicate.exists(), "past retention");
+        assert!(recent.exists());
+        assert!(recent_duplicate.exists(), "still stops a download");
+    }
+
+    #[tokio::test]
+    async fn drops_pruned_clips_from_playlists() {
+        let dir = TempDir::new().unwrap();
+        let old = clip(&dir, "old.mp4", Duration::from_secs(40 * DAY));
+        let new = clip(&dir, "new.mp4", Duration::from_secs(DAY));
+        for (path, age) in [(&old, 40 * DAY), (&new, DAY)] {
+            let event = CameraEvent::new(
+                "porch".to_string(),
+                DateTime::from(now() - Duration::from_secs(age)),
+                chrono::Duration::seconds(10),
+            );
+            playlist::add_clip(path, &VideoMetadata::from(event)).unwrap();
+        }
+
+        prune_at(dir.path(), &policy(30, false), now())
+            .await
+            .unwrap();
+
+        let playlist = fs::read_to_string(dir.path().join(playlist::PLAYLIST_FILE)).unwrap();
+        assert!(!playlist.contains("old.mp4"));
+        assert!(playlist.contains("new.mp4"));
+    }
+
+    #[tokio::test]
+    async fn unattributed_clips_get_no_minimum() {
+        let dir = TempDir::new().unwrap();
+        let old = clip(&dir, "old.mp4", Duration::from_secs(40 * DAY));
+        let mut policy = policy(30, false);
+        policy.keep_min_per_device = 5;
+
+        prune_at(dir.path(), &policy, now()).await.unwrap();
+
+        assert!(!old.exists());
+    }
+
+    #[test]
+    fn walk_for_cutoff_finds_clips_on_both_sides_of_cutoff() {
+        let dir = TempDir::new().unwrap();
+        clip(&dir, "newer.mp4", Duration::from_secs(HOUR));
+        clip(&dir, "older.mp4", Duration::from_secs(5 * HOUR));
+        let cutoff = retention_cutoff(now(), 3, true);
+
+        let clips = walk_for_cutoff(dir.path(), &policy(3, true), cutoff).clips;
+        let old: Vec<_> = clips
+            .iter()
+            .filter(|c| c.modified < cutoff)
+            .map(|c| c.path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(clips.len(), 2);
+        assert_eq!(old, ["older.mp4"]);
+    }
+
+    /// Creates the clip at `relative` in the archive, attributed to `device`
+    /// by a sidecar.
+    fn dated_clip(dir: &TempDir, relative: &str, age: Duration, device: &str) -> PathBuf {
+        fs::create_dir_all(dir.path().join(relative).parent().unwrap()).unwrap();
+        let path = clip(dir, relative, age);
+        let event = CameraEvent::new(
+            device.to_string(),
+            DateTime::from(now() - age),
+            chrono::Duration::seconds(10),
+        );
+        archive::write_sidecar(&path, &VideoMetadata::from(event)).unwrap();
+        path
+    }
+
+    /// Walks for `cutoff` like prune does, also returning every directory
+    /// the walk opened.
+    fn counting_walk(
+        dir: &TempDir,
+        policy: &PrunePolicy,
+        cutoff: SystemTime,
+    ) -> (Vec<ArchiveClip>, HashSet<PathBuf>) {
+        let root = dir.path();
+        let opened = std::sync::Mutex::new(HashSet::new());
+        let clips = walk_for_cutoff_with(root, policy, cutoff, |walked, skip| {
+            opened.lock().unwrap().insert(walked.to_path_buf());
+            archive::walk_clips_skipping(root, walked, |subdir: &Path| {
+                let skipped = skip(subdir);
+                if !skipped {
+                    opened.lock().unwrap().insert(subdir.to_path_buf());
+                }
+                skipped
+            })
+        })
+        .clips;
+        let opened = opened.into_inner().unwrap();
+        let relative = opened
+            .into_iter()
+            .map(|path| path.strip_prefix(root).unwrap().to_path_buf())
+            .collect();
+        (clips, relative)
+    }
+
+    /// Clips either side of a 3-day cutoff, 2025-06-12 15:06 UTC, so the day
+    /// directories from 2025-06-14 on can't hold anything prunable.
+    fn archive_around_cutoff(dir: &TempDir) {
+        dated_clip(
+            dir,
+            "2025/05/20/2025-05-20T08-00-00.mp4",
+            Duration::from_secs(26 * DAY),
+            "porch",
+        );
+        dated_clip(
+            dir,
+            "2025/06/10/2025-06-10T08-00-00.mp4",
+            Duration::from_secs(5 * DAY),
+            "porch",
+        );
+        dated_clip(
+            dir,
+            "2025/06/13/2025-06-13T08-00-00.mp4",
+            Duration::from_secs(2 * DAY),
+            "porch",
+        );
+        dated_clip(
+            dir,
+            "2025/06/14/2025-06-14T08-00-00.mp4",
+            Duration::from_secs(DAY),
+            "porch",
+        );
+        dated_clip(
+            dir,
+            "2025/06/15/2025-06-15T08-00-00.mp4",
+            Duration::from_secs(HOUR),
+            "porch",
+        );
+        dated_clip(
+            dir,
+            "2025/07/01/2025-07-01T08-00-00.mp4",
+            Duration::ZERO,
+            "porch",
+        );
+        dated_clip(
+            dir,
+            "2026/01/01/2026-01-01T08-00-00.mp4",
+            Duration::ZERO,
+            "porch",
+        );
+    }
+
+    #[test]
+    fn walk_for_cutoff_never_opens_recent_directories() {
+        let dir = TempDir::new().unwrap();
+        archive_around_cutoff(&dir);
+        let policy = policy(3, false);
+        let cutoff = retention_cutoff(now(), 3, false);
+
+        let (clips, opened) = counting_walk(&dir, &policy, cutoff);
+
+        for recent in [
+            "2025/06/14",
+            "2025/06/15",
+            "2025/07",
+            "2025/07/01",
+            "2026",
+            "2026/01",
+        ] {
+            assert!(!opened.contains(Path::new(recent)), "opened {recent}");
+        }
+        assert!(opened.contains(Path::new("2025/06/13")));
+        assert_eq!(clips.len(), 3);
+        assert!(
+            clips
+                .iter()
+                .all(|clip| clip.modified < now() - Duration::from_secs(DAY))
+        );
+    }
+
+    #[test]
+    fn walk_for_cutoff_opens_only_enough_recent_directories_for_keep_min() {
+        let dir = TempDir::new().unwrap();
+        archive_around_cutoff(&dir);
+        let mut policy = policy(3, false);
+        policy.keep_min_per_device = 2;
+        let cutoff = retention_cutoff(now(), 3, false);
+
+        let (clips, opened) = counting_walk(&dir, &policy, cutoff);
+
+        // Newest first, one level at a time: 2026 and 2025/07 hold the two
+        // newest clips, so June's recent days stay closed
+        for walked in ["2026", "2026/01", "2026/01/01", "2025/07", "2025/07/01"] {
+            assert!(opened.contains(Path::new(walked)), "didn't open {walked}");
+        }
+        for recent in ["2025/06/14", "2025/06/15"] {
+            assert!(!opened.contains(Path::new(recent)), "opened {recent}");
+        }
+        assert_eq!(clips.len(), 5);
+    }
+}
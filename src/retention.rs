@@ -0,0 +1,80 @@
+use std::{fmt, path::Path, str::FromStr};
+
+/// Below `free_percent` free space on the archive's filesystem, shorten
+/// retention to `retention_period` (days, or hours with `--retention-hours`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FreeSpaceTier {
+    pub free_percent: f64,
+    pub retention_period: u64,
+}
+
+impl fmt::Display for FreeSpaceTier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "below {}% free: {}",
+            self.free_percent, self.retention_period
+        )
+    }
+}
+
+/// Parses `FREE_PERCENT:PERIOD`, e.g. `20:30`.
+impl FromStr for FreeSpaceTier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (free, period) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected FREE_PERCENT:PERIOD, got '{s}'"))?;
+        let free_percent: f64 = free
+            .trim_end_matches('%')
+            .parse()
+            .map_err(|_| format!("invalid free space percentage '{free}'"))?;
+        if !(0.0..=100.0).contains(&free_percent) {
+            return Err(format!("free space percentage '{free}' must be 0-100"));
+        }
+        let retention_period = period
+            .parse()
+            .map_err(|_| format!("invalid retention period '{period}'"))?;
+        if retention_period == 0 {
+            return Err("tier retention period must be positive".to_string());
+        }
+
+        Ok(Self {
+            free_percent,
+            retention_period,
+        })
+    }
+}
+
+/// Percentage of the filesystem holding `path` that is available.
+pub fn free_space_percent(path: &Path) -> std::io::Result<f64> {
+    let total = fs4::total_space(path)?;
+    let available = fs4::available_space(path)?;
+    if total == 0 {
+        return Ok(100.0);
+    }
+    Ok(available as f64 * 100.0 / total as f64)
+}
+
+/// The retention period in force at `free_percent` free space: the shortest
+/// of the base period (0 = forever) and every tier whose threshold is crossed.
+/// Returns the tier that applied, if any.
+pub fn effective_retention(
+    base: u64,
+    tiers: &[FreeSpaceTier],
+    free_percent: f64,
+) -> (u64, Option<FreeSpaceTier>) {
+    let tier = tiers
+        .iter()
+        .filter(|tier| free_percent < tier.free_percent)
+        .min_by_key(|tier| tier.retention_period)
+        .copied();
+
+    match tier {
+        Some(tier) if base == 0 || tier.retention_period < base => {
+            (tier.retention_period, Some(tier))
+        }
+        _ => (base, None),
+    }
+}
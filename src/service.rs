@@ -0,0 +1,263 @@
+//! Running the daemon as a Windows service. The service takes no command
+//! line: its flags and credentials come from files in the configuration
+//! directory, so they can change without re-registering it, and it logs to
+//! a daily file there since it has no console.
+
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use clap::CommandFactory;
+use tokio::sync::watch;
+use tracing::{error, info};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+        ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+    service_dispatcher,
+    service_manager::{ServiceManager, ServiceManagerAccess},
+};
+
+use crate::Args;
+
+const SERVICE_NAME: &str = "nest-sync";
+const SERVICE_DISPLAY_NAME: &str = "Nest Sync";
+const SERVICE_DESCRIPTION: &str = "Downloads Google Nest camera events";
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+/// Flags for the service, one per line as they'd be typed, e.g. `--output=D:\Nest`.
+const ARGS_FILE: &str = "nest-sync.args";
+/// How long the service manager is told to wait for a stop: long enough for
+/// a running cycle's downloads to finish.
+const STOP_WAIT_HINT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, clap::Args)]
+pub struct ServiceArgs {
+    #[command(subcommand)]
+    action: ServiceAction,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum ServiceAction {
+    /// Register the service to start automatically with Windows
+    Install,
+    /// Stop the service if it's running and remove it
+    Uninstall,
+    /// Run as the service; only the service control manager should call this
+    Run,
+}
+
+pub fn run(service_args: &ServiceArgs) -> Result<()> {
+    match service_args.action {
+        ServiceAction::Install => install(),
+        ServiceAction::Uninstall => uninstall(),
+        ServiceAction::Run => service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+            .context("Failed to connect to the service control manager"),
+    }
+}
+
+/// `%ProgramData%\nest-sync`, holding the service's flags, `.env` and logs.
+fn config_dir() -> PathBuf {
+    std::env::var_os("ProgramData")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(r"C:\ProgramData"))
+        .join(SERVICE_NAME)
+}
+
+fn install() -> Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .context("Failed to open the service control manager; run this as Administrator")?;
+
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from(SERVICE_DISPLAY_NAME),
+        service_type: SERVICE_TYPE,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe().context("Failed to locate nest-sync.exe")?,
+        launch_arguments: vec![OsString::from("service"), OsString::from("run")],
+        dependencies: Vec::new(),
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .context("Failed to create the service")?;
+    service
+        .set_description(SERVICE_DESCRIPTION)
+        .context("Failed to set the service description")?;
+
+    let config_dir = config_dir();
+    fs::create_dir_all(&config_dir)
+        .with_context(|| format!("Failed to create {}", config_dir.display()))?;
+    println!("Installed the {SERVICE_NAME} service. Before starting it, put:");
+    println!(
+        "  its flags in {}, one per line, e.g. --output=D:\\Nest",
+        config_dir.join(ARGS_FILE).display()
+    );
+    println!(
+        "  GOOGLE_MASTER_TOKEN and GOOGLE_USERNAME in {}",
+        config_dir.join(".env").display()
+    );
+    println!("Logs go to {}", config_dir.join("logs").display());
+    Ok(())
+}
+
+fn uninstall() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .context("Failed to open the service control manager; run this as Administrator")?;
+    let service = manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .context("Failed to open the service")?;
+
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop().context("Failed to stop the service")?;
+        println!("Stopping the service; it finishes the running cycle first");
+    }
+    // Removal completes once the service has stopped
+    service.delete().context("Failed to remove the service")?;
+    println!("Removed the {SERVICE_NAME} service");
+    Ok(())
+}
+
+/// Parses the flags in the args file. Blank lines and lines starting with
+/// `#` are skipped.
+fn load_args(config_dir: &Path) -> Result<Args> {
+    let path = config_dir.join(ARGS_FILE);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let flags = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+    let args = Args::command()
+        .try_get_matches_from(std::iter::once(SERVICE_NAME).chain(flags))
+        .and_then(|matches| Args::from_matches(&matches))
+        .with_context(|| format!("Invalid flags in {}", path.display()))?;
+    if args.command.is_some() {
+        bail!("{} can't name a subcommand", path.display());
+    }
+    Ok(args)
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    let config_dir = config_dir();
+    // Credentials and environment-backed flags; the system environment is
+    // used when there's no file
+    dotenvy::from_path(config_dir.join(".env")).ok();
+    let args = load_args(&config_dir);
+
+    #[cfg(feature = "sentry")]
+    let _sentry = args.as_ref().ok().and_then(crate::init_sentry);
+    let appender = tracing_appender::rolling::daily(config_dir.join("logs"), "nest-sync.log");
+    let (writer, _writer_guard) = tracing_appender::non_blocking(appender);
+    crate::logging::init(args.as_ref().ok(), BoxMakeWriter::new(writer), false);
+
+    if let Err(e) = run_service(args) {
+        error!(error = %format!("{e:#}"), "Service failed");
+    }
+}
+
+/// Runs the daemon until the service manager stops it. Bad flags are
+/// reported as a failed start rather than left to time out.
+fn run_service(args: Result<Args>) -> Result<()> {
+    let (stop_tx, mut stop_rx) = watch::channel(false);
+    let status_handle =
+        service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                info!("Stop requested by the service control manager");
+                stop_tx.send_replace(true);
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })
+        .context("Failed to register the service control handler")?;
+
+    #[cfg(feature = "insecure-tls")]
+    let args = args.and_then(|args| crate::confirm_insecure_tls(&args).map(|()| args));
+    let args = match args {
+        Ok(args) => args,
+        Err(e) => {
+            set_status(
+                &status_handle,
+                ServiceState::Stopped,
+                ServiceExitCode::Win32(1),
+            )?;
+            return Err(e);
+        }
+    };
+
+    set_status(
+        &status_handle,
+        ServiceState::Running,
+        ServiceExitCode::Win32(0),
+    )?;
+    let runtime = tokio::runtime::Runtime::new().context("Failed to start the async runtime")?;
+    // The same graceful shutdown as SIGTERM: the running cycle and prune
+    // pass finish and the state is saved
+    let stopped = async move {
+        let _ = stop_rx.wait_for(|stop| *stop).await;
+        if let Err(e) = set_status(
+            &status_handle,
+            ServiceState::StopPending,
+            ServiceExitCode::Win32(0),
+        ) {
+            error!(error = %format!("{e:#}"), "Failed to report stop pending");
+        }
+    };
+    runtime.block_on(crate::run_daemon(Arc::new(args), stopped));
+
+    set_status(
+        &status_handle,
+        ServiceState::Stopped,
+        ServiceExitCode::Win32(0),
+    )?;
+    info!("Service stopped");
+    Ok(())
+}
+
+fn set_status(
+    status_handle: &ServiceStatusHandle,
+    state: ServiceState,
+    exit_code: ServiceExitCode,
+) -> Result<()> {
+    let controls_accepted = match state {
+        ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        _ => ServiceControlAccept::empty(),
+    };
+    let wait_hint = match state {
+        ServiceState::StopPending => STOP_WAIT_HINT,
+        _ => Duration::default(),
+    };
+    status_handle
+        .set_service_status(ServiceStatus {
+            service_type: SERVICE_TYPE,
+            current_state: state,
+            controls_accepted,
+            exit_code,
+            checkpoint: 0,
+            wait_hint,
+            process_id: None,
+        })
+        .context("Failed to report the service status")
+}
@@ -0,0 +1,105 @@
+use std::{collections::HashSet, fmt, str::FromStr};
+
+use chrono::{DateTime, Duration, Utc};
+use tracing::{info, warn};
+
+use crate::{
+    google_auth::DiscoveredDevice,
+    metrics::{DeviceSilence, METRICS},
+    thinning::parse_duration_spec,
+};
+
+/// A per-device override of how long a camera may go without events.
+#[derive(Debug, Clone)]
+pub struct DeviceSilenceThreshold {
+    /// Device name or ID.
+    pub device: String,
+    pub threshold: Duration,
+}
+
+impl fmt::Display for DeviceSilenceThreshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}s", self.device, self.threshold.num_seconds())
+    }
+}
+
+/// Parses `DEVICE=DURATION`, e.g. `Side Gate=7d`.
+impl FromStr for DeviceSilenceThreshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (device, threshold) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("expected DEVICE=DURATION, got '{s}'"))?;
+        if device.is_empty() {
+            return Err(format!("missing device in '{s}'"));
+        }
+        Ok(Self {
+            device: device.to_string(),
+            threshold: parse_duration_spec(threshold)?,
+        })
+    }
+}
+
+/// Warns once when a camera's manifests have shown no events for longer than
+/// its threshold, and again when events resume. A threshold of zero disables
+/// the check for that device.
+pub struct SilenceMonitor {
+    default_threshold: Duration,
+    overrides: Vec<DeviceSilenceThreshold>,
+    alerting: HashSet<String>,
+}
+
+impl SilenceMonitor {
+    pub fn new(default_threshold: Duration, overrides: Vec<DeviceSilenceThreshold>) -> Self {
+        Self {
+            default_threshold,
+            overrides,
+            alerting: HashSet::new(),
+        }
+    }
+
+    fn threshold(&self, device: &DiscoveredDevice) -> Duration {
+        self.overrides
+            .iter()
+            .rev()
+            .find(|o| o.device == device.device_name || o.device == device.device_id)
+            .map_or(self.default_threshold, |o| o.threshold)
+    }
+
+    /// Checks `device`, whose newest event started at `last_event` (or whose
+    /// tracking began then, before any event was seen).
+    pub fn check(&mut self, device: &DiscoveredDevice, last_event: DateTime<Utc>) {
+        let device_name = &device.device_name;
+        let silent_for = Utc::now() - last_event;
+        let threshold = self.threshold(device);
+        let silent = threshold > Duration::zero() && silent_for > threshold;
+
+        if silent && self.alerting.insert(device.device_id.clone()) {
+            warn!(
+                %device_name,
+                silent_since = %last_event,
+                silent_hours = silent_for.num_hours(),
+                threshold_hours = threshold.num_hours(),
+                "Camera has reported no events for unusually long; check that it is online"
+            );
+        } else if !silent && self.alerting.remove(&device.device_id) {
+            info!(%device_name, "Camera is reporting events again");
+        }
+
+        METRICS.record_device_silence(
+            &device.device_id,
+            device_name,
+            DeviceSilence {
+                silent_since: last_event,
+                threshold_secs: threshold.num_seconds(),
+                alerting: silent,
+            },
+        );
+    }
+
+    /// Drops `device`'s alert, e.g. once it is no longer polled.
+    pub fn forget(&mut self, device_id: &str) {
+        self.alerting.remove(device_id);
+    }
+}
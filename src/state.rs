@@ -0,0 +1,627 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+const STATE_FILE: &str = ".nest-sync-state.json";
+/// Days of download history kept for growth projections.
+const DAILY_HISTORY_DAYS: i64 = 90;
+/// Ignored events are forgotten after this many days; by then they have long
+/// left the event query window.
+const IGNORED_MAX_AGE_DAYS: i64 = 30;
+/// The oldest ignored events are forgotten beyond this many.
+const IGNORED_MAX_ENTRIES: usize = 1000;
+/// Compaction forgets a device's positions only once it hasn't been checked
+/// for this long, so a host that was down a while still catches up.
+const DEVICE_MAX_IDLE_DAYS: i64 = 90;
+/// Merged events are forgotten this many days after they started, when Nest
+/// no longer lists them.
+const MERGED_MAX_AGE_DAYS: i64 = 60;
+/// How far an event's start may be from an imported clip's, whose file name
+/// only keeps whole seconds.
+const IMPORT_MATCH_TOLERANCE_SECS: i64 = 1;
+
+/// Download bookkeeping that must survive restarts, persisted as JSON at the
+/// root of the output directory.
+pub struct StateStore {
+    path: PathBuf,
+    data: StateData,
+    /// Changed since the last save.
+    dirty: bool,
+}
+
+/// The daemon's one [`StateStore`], shared by the check cycle, device refresh
+/// and the periodic flush. All mutation goes through [`Self::lock`], and
+/// changes are written in batches by [`Self::flush`] rather than after each
+/// one. The lock is synchronous, so it must not be held across an `.await`.
+#[derive(Clone)]
+pub struct SharedStateStore(Arc<Mutex<StateStore>>);
+
+impl SharedStateStore {
+    pub fn load(output_path: &Path) -> Self {
+        Self(Arc::new(Mutex::new(StateStore::load(output_path))))
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, StateStore> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Saves the state if it changed since the last flush, logging failures;
+    /// unsaved changes are retried on the next flush.
+    pub fn flush(&self) {
+        let mut store = self.lock();
+        if !store.dirty {
+            return;
+        }
+        if let Err(e) = store.save() {
+            error!(path = %store.path.display(), error = %format!("{e:#}"), "Failed to save state");
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StateData {
+    #[serde(default)]
+    failures: HashMap<String, FailureRecord>,
+    /// Bytes downloaded per UTC day.
+    #[serde(default)]
+    daily_bytes: BTreeMap<NaiveDate, u64>,
+    /// Start of each device's newest event seen in a manifest, or when the
+    /// device was first checked if it has had none since.
+    #[serde(default)]
+    last_event_at: HashMap<String, DateTime<Utc>>,
+    /// How far each device's events have been downloaded, so checks missed
+    /// while the host was down can be caught up on.
+    #[serde(default)]
+    checked_until: HashMap<String, DateTime<Utc>>,
+    /// Events that failed with an error retrying can't fix, skipped from then on.
+    #[serde(default)]
+    ignored: BTreeMap<String, IgnoredEvent>,
+    /// Events fetched on the latest local day with a download, counted
+    /// against `--max-downloads-per-day`.
+    #[serde(default)]
+    downloads: Option<DownloadDay>,
+    /// Events downloaded as part of a `--merge-gap-seconds` clip, keyed by
+    /// their own id, with the merged clip's event id.
+    #[serde(default)]
+    merged: BTreeMap<String, String>,
+    /// Clips `import-existing` found in an archive from another tool, which
+    /// aren't where the path template would put their events.
+    #[serde(default)]
+    imported: BTreeSet<ImportedClip>,
+}
+
+/// An already archived clip that events starting at the same time are
+/// treated as downloaded to.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ImportedClip {
+    pub start_time: DateTime<Utc>,
+    /// The camera's name or ID; any camera's event matches when unknown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+}
+
+/// The events whose clips were fetched on one local calendar day.
+#[derive(Debug, Serialize, Deserialize)]
+struct DownloadDay {
+    date: NaiveDate,
+    event_ids: BTreeSet<String>,
+}
+
+/// Repeated download failures of one event, with the earliest time it may be
+/// retried.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailureRecord {
+    pub count: u32,
+    pub retry_after: DateTime<Utc>,
+    pub last_error: String,
+    /// Set once the failure limit is reached; the event is never retried.
+    #[serde(default)]
+    pub gave_up: bool,
+}
+
+/// An event given up on because it keeps failing with a non-retryable error.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IgnoredEvent {
+    pub reason: String,
+    pub failures: u32,
+    pub ignored_at: DateTime<Utc>,
+}
+
+/// What [`StateStore::compact`] removed.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Compaction {
+    pub failures: usize,
+    pub ignored: usize,
+    pub merged: usize,
+    pub imported: usize,
+    pub devices: usize,
+}
+
+impl Compaction {
+    pub fn total(self) -> usize {
+        self.failures + self.ignored + self.merged + self.imported + self.devices
+    }
+}
+
+/// Exponential backoff parameters for failed downloads.
+#[derive(Debug, Clone, Copy)]
+pub struct FailureBackoff {
+    pub base: Duration,
+    pub max: Duration,
+    /// Give up on an event permanently after this many failures.
+    pub max_failures: Option<u32>,
+}
+
+impl StateStore {
+    /// Loads the state for `output_path`. A missing file starts empty; an
+    /// unreadable one is logged and replaced on the next save.
+    pub fn load(output_path: &Path) -> Self {
+        let path = output_path.join(STATE_FILE);
+        let data = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                warn!(path = %path.display(), error = %e, "Ignoring unreadable state file");
+                StateData::default()
+            }),
+            Err(_) => StateData::default(),
+        };
+
+        Self {
+            path,
+            data,
+            dirty: false,
+        }
+    }
+
+    /// Writes the state atomically (temp file + rename).
+    pub fn save(&mut self) -> Result<()> {
+        let json = serde_json::to_vec_pretty(&self.data).context("Failed to serialize state")?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, json).context("Failed to write state file")?;
+        fs::rename(&tmp_path, &self.path).context("Failed to replace state file")?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// The state for changing, marking it to be saved on the next flush.
+    fn data_mut(&mut self) -> &mut StateData {
+        self.dirty = true;
+        &mut self.data
+    }
+
+    pub fn failure(&self, event_id: &str) -> Option<&FailureRecord> {
+        self.data.failures.get(event_id)
+    }
+
+    /// Records a failed download and returns the updated record.
+    pub fn record_failure(
+        &mut self,
+        event_id: &str,
+        error: &str,
+        backoff: &FailureBackoff,
+    ) -> &FailureRecord {
+        let record = self
+            .data_mut()
+            .failures
+            .entry(event_id.to_string())
+            .or_insert_with(|| FailureRecord {
+                count: 0,
+                retry_after: Utc::now(),
+                last_error: String::new(),
+                gave_up: false,
+            });
+
+        record.count += 1;
+        record.last_error = error.to_string();
+        let exponent = record.count.saturating_sub(1).min(16);
+        let delay = backoff
+            .base
+            .checked_mul(2i32.pow(exponent))
+            .map_or(backoff.max, |d| d.min(backoff.max));
+        record.retry_after = Utc::now() + delay;
+        record.gave_up = backoff.max_failures.is_some_and(|max| record.count >= max);
+
+        record
+    }
+
+    /// Records a completed download of `bytes` bytes.
+    pub fn record_success(&mut self, event_id: &str, bytes: u64) {
+        let data = self.data_mut();
+        data.failures.remove(event_id);
+
+        let today = Utc::now().date_naive();
+        *data.daily_bytes.entry(today).or_default() += bytes;
+        let oldest = today - Duration::days(DAILY_HISTORY_DAYS);
+        data.daily_bytes.retain(|date, _| *date > oldest);
+    }
+
+    pub fn is_ignored(&self, event_id: &str) -> bool {
+        self.data.ignored.contains_key(event_id)
+    }
+
+    pub fn ignored(&self) -> &BTreeMap<String, IgnoredEvent> {
+        &self.data.ignored
+    }
+
+    /// Moves an event from the failure records to the ignore list, dropping
+    /// entries that are too old or too many.
+    pub fn ignore_event(&mut self, event_id: &str, reason: &str) {
+        let data = self.data_mut();
+        let failures = data.failures.remove(event_id).map_or(0, |r| r.count);
+        let now = Utc::now();
+        data.ignored.insert(
+            event_id.to_string(),
+            IgnoredEvent {
+                reason: reason.to_string(),
+                failures,
+                ignored_at: now,
+            },
+        );
+
+        let oldest = now - Duration::days(IGNORED_MAX_AGE_DAYS);
+        data.ignored.retain(|_, e| e.ignored_at > oldest);
+        while data.ignored.len() > IGNORED_MAX_ENTRIES {
+            let Some(oldest_id) = data
+                .ignored
+                .iter()
+                .min_by_key(|(_, e)| e.ignored_at)
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            data.ignored.remove(&oldest_id);
+        }
+    }
+
+    /// Removes the given events, or every event if none are given, from the
+    /// ignore list so they are retried. Returns how many were removed.
+    pub fn clear_ignored(&mut self, event_ids: &[String]) -> usize {
+        let data = self.data_mut();
+        let before = data.ignored.len();
+        if event_ids.is_empty() {
+            data.ignored.clear();
+        } else {
+            for event_id in event_ids {
+                data.ignored.remove(event_id);
+            }
+        }
+        before - data.ignored.len()
+    }
+
+    fn downloads(&self, date: NaiveDate) -> Option<&BTreeSet<String>> {
+        self.data
+            .downloads
+            .as_ref()
+            .filter(|day| day.date == date)
+            .map(|day| &day.event_ids)
+    }
+
+    /// How many events' clips were fetched on local day `date`.
+    pub fn downloads_on(&self, date: NaiveDate) -> usize {
+        self.downloads(date).map_or(0, BTreeSet::len)
+    }
+
+    /// Whether `event_id` was already fetched on local day `date`, so
+    /// fetching it again doesn't count towards the day's quota.
+    pub fn downloaded_on(&self, event_id: &str, date: NaiveDate) -> bool {
+        self.downloads(date)
+            .is_some_and(|event_ids| event_ids.contains(event_id))
+    }
+
+    /// Counts a fetch of `event_id` on local day `date`, once per event,
+    /// forgetting any earlier day.
+    pub fn count_download(&mut self, event_id: &str, date: NaiveDate) {
+        let data = self.data_mut();
+        let day = match &mut data.downloads {
+            Some(day) if day.date == date => day,
+            downloads => downloads.insert(DownloadDay {
+                date,
+                event_ids: BTreeSet::new(),
+            }),
+        };
+        day.event_ids.insert(event_id.to_string());
+    }
+
+    /// Whether `event_id` was already downloaded as part of a merged clip.
+    pub fn is_merged(&self, event_id: &str) -> bool {
+        self.data.merged.contains_key(event_id)
+    }
+
+    /// Records that the events in `event_ids` were downloaded as the merged
+    /// clip `merged_event_id`, forgetting entries too old to be listed again.
+    pub fn record_merged(&mut self, merged_event_id: &str, event_ids: &[String]) {
+        let data = self.data_mut();
+        for event_id in event_ids {
+            data.merged
+                .insert(event_id.clone(), merged_event_id.to_string());
+        }
+        let oldest = Utc::now() - Duration::days(MERGED_MAX_AGE_DAYS);
+        data.merged
+            .retain(|event_id, _| event_start(event_id).is_none_or(|start| start > oldest));
+    }
+
+    /// Records a clip found by `import-existing`. Returns whether it's new.
+    pub fn import_clip(&mut self, clip: ImportedClip) -> bool {
+        if self.data.imported.contains(&clip) {
+            return false;
+        }
+        self.data_mut().imported.insert(clip)
+    }
+
+    /// Whether a clip for the event starting at `start_time` on this device
+    /// was imported from an existing archive.
+    pub fn is_imported(
+        &self,
+        start_time: DateTime<Utc>,
+        device_id: &str,
+        device_name: &str,
+    ) -> bool {
+        let tolerance = Duration::seconds(IMPORT_MATCH_TOLERANCE_SECS);
+        let earliest = ImportedClip {
+            start_time: start_time - tolerance,
+            device: None,
+        };
+        self.data
+            .imported
+            .range(earliest..)
+            .take_while(|clip| clip.start_time <= start_time + tolerance)
+            .any(|clip| {
+                clip.device
+                    .as_deref()
+                    .is_none_or(|device| device == device_id || device == device_name)
+            })
+    }
+
+    /// Removes entries that can no longer matter: failure records, ignored
+    /// events, merged events and imported clips for events that started
+    /// before `cutoff`, and the positions of
+    /// devices not checked since then nor for 90 days, e.g. cameras removed
+    /// from the account.
+    pub fn compact(&mut self, cutoff: DateTime<Utc>) -> Compaction {
+        let stale = |event_id: &str| event_start(event_id).is_some_and(|start| start < cutoff);
+        let device_cutoff = cutoff.min(Utc::now() - Duration::days(DEVICE_MAX_IDLE_DAYS));
+        let mut compaction = Compaction::default();
+
+        let failures_before = self.data.failures.len();
+        let ignored_before = self.data.ignored.len();
+        let merged_before = self.data.merged.len();
+        let imported_before = self.data.imported.len();
+        let stale_devices: BTreeSet<String> = self
+            .data
+            .checked_until
+            .keys()
+            .chain(self.data.last_event_at.keys())
+            .filter(|device_id| {
+                [&self.data.checked_until, &self.data.last_event_at]
+                    .iter()
+                    .all(|positions| {
+                        positions
+                            .get(*device_id)
+                            .is_none_or(|&at| at < device_cutoff)
+                    })
+            })
+            .cloned()
+            .collect();
+        if self.data.failures.keys().any(|id| stale(id))
+            || self.data.ignored.keys().any(|id| stale(id))
+            || self.data.merged.keys().any(|id| stale(id))
+            || self
+                .data
+                .imported
+                .first()
+                .is_some_and(|clip| clip.start_time < cutoff)
+            || !stale_devices.is_empty()
+        {
+            let data = self.data_mut();
+            data.failures.retain(|event_id, _| !stale(event_id));
+            data.ignored.retain(|event_id, _| !stale(event_id));
+            data.merged.retain(|event_id, _| !stale(event_id));
+            data.imported.retain(|clip| clip.start_time >= cutoff);
+            for device_id in &stale_devices {
+                data.checked_until.remove(device_id);
+                data.last_event_at.remove(device_id);
+            }
+        }
+        compaction.devices = stale_devices.len();
+        compaction.failures = failures_before - self.data.failures.len();
+        compaction.ignored = ignored_before - self.data.ignored.len();
+        compaction.merged = merged_before - self.data.merged.len();
+        compaction.imported = imported_before - self.data.imported.len();
+        compaction
+    }
+
+    pub fn daily_bytes(&self) -> &BTreeMap<NaiveDate, u64> {
+        &self.data.daily_bytes
+    }
+
+    /// Records the newest event a device's manifest listed, if any, and
+    /// returns the device's last event time.
+    pub fn record_last_event(
+        &mut self,
+        device_id: &str,
+        newest_event: Option<DateTime<Utc>>,
+    ) -> DateTime<Utc> {
+        let last = self
+            .data_mut()
+            .last_event_at
+            .entry(device_id.to_string())
+            .or_insert_with(|| newest_event.unwrap_or_else(Utc::now));
+        if let Some(newest_event) = newest_event {
+            *last = (*last).max(newest_event);
+        }
+        *last
+    }
+
+    pub fn checked_until(&self, device_id: &str) -> Option<DateTime<Utc>> {
+        self.data.checked_until.get(device_id).copied()
+    }
+
+    pub fn set_checked_until(&mut self, device_id: &str, position: DateTime<Utc>) {
+        self.data_mut()
+            .checked_until
+            .insert(device_id.to_string(), position);
+    }
+
+    /// Forgets a device's last event time and check position, so tracking
+    /// starts afresh at its next check.
+    pub fn reset_device(&mut self, device_id: &str) {
+        let data = self.data_mut();
+        data.last_event_at.remove(device_id);
+        data.checked_until.remove(device_id);
+    }
+}
+
+/// The start of an event from its ID, `<start>-><end>|<device>`.
+fn event_start(event_id: &str) -> Option<DateTime<Utc>> {
+    let (start, _) = event_id.split_once("->")?;
+    DateTime::parse_from_rfc3339(start)
+        .ok()
+        .map(|start| start.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const EVENT_ID: &str = "2025-06-01T12:00:00+00:00->2025-06-01T12:00:10+00:00|porch";
+
+    fn store() -> (TempDir, StateStore) {
+        let dir = TempDir::new().unwrap();
+        let store = StateStore::load(dir.path());
+        (dir, store)
+    }
+
+    fn backoff(base: Duration, max: Duration) -> FailureBackoff {
+        FailureBackoff {
+            base,
+            max,
+            max_failures: None,
+        }
+    }
+
+    /// Records `count` failures and returns how long the last one backs off.
+    fn delay_after(store: &mut StateStore, count: u32, backoff: &FailureBackoff) -> Duration {
+        for _ in 1..count {
+            store.record_failure(EVENT_ID, "error", backoff);
+        }
+        let before = Utc::now();
+        let retry_after = store.record_failure(EVENT_ID, "error", backoff).retry_after;
+        // Rounded to whole seconds, as recording takes a moment
+        Duration::seconds((retry_after - before).num_seconds())
+    }
+
+    #[test]
+    fn first_failure_backs_off_by_the_base_delay() {
+        let (_dir, mut store) = store();
+        let backoff = backoff(Duration::minutes(5), Duration::hours(24));
+
+        assert_eq!(delay_after(&mut store, 1, &backoff), Duration::minutes(5));
+        assert_eq!(store.failure(EVENT_ID).unwrap().count, 1);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let (_dir, mut store) = store();
+        let backoff = backoff(Duration::minutes(5), Duration::hours(1));
+
+        assert_eq!(delay_after(&mut store, 3, &backoff), Duration::minutes(20));
+        assert_eq!(delay_after(&mut store, 2, &backoff), Duration::hours(1));
+        assert_eq!(store.failure(EVENT_ID).unwrap().count, 5);
+    }
+
+    #[test]
+    fn backoff_exponent_saturates() {
+        let (_dir, mut store) = store();
+        let backoff = backoff(Duration::seconds(1), Duration::days(365));
+
+        assert_eq!(
+            delay_after(&mut store, 40, &backoff),
+            Duration::seconds(1 << 16)
+        );
+    }
+
+    fn ignored_at(ignored_at: DateTime<Utc>) -> IgnoredEvent {
+        IgnoredEvent {
+            reason: "expired".to_string(),
+            failures: 1,
+            ignored_at,
+        }
+    }
+
+    #[test]
+    fn ignoring_an_event_drops_its_failure_record() {
+        let (_dir, mut store) = store();
+        let backoff = backoff(Duration::minutes(5), Duration::hours(1));
+        store.record_failure(EVENT_ID, "error", &backoff);
+        store.record_failure(EVENT_ID, "error", &backoff);
+
+        store.ignore_event(EVENT_ID, "expired");
+
+        assert!(store.is_ignored(EVENT_ID));
+        assert!(store.failure(EVENT_ID).is_none());
+        assert_eq!(store.ignored()[EVENT_ID].failures, 2);
+    }
+
+    #[test]
+    fn ignored_events_age_out() {
+        let (_dir, mut store) = store();
+        let now = Utc::now();
+        let ignored = &mut store.data_mut().ignored;
+        ignored.insert(
+            "old".to_string(),
+            ignored_at(now - Duration::days(IGNORED_MAX_AGE_DAYS + 1)),
+        );
+        ignored.insert(
+            "recent".to_string(),
+            ignored_at(now - Duration::days(IGNORED_MAX_AGE_DAYS - 1)),
+        );
+
+        store.ignore_event(EVENT_ID, "expired");
+
+        assert!(!store.is_ignored("old"));
+        assert!(store.is_ignored("recent"));
+        assert!(store.is_ignored(EVENT_ID));
+    }
+
+    #[test]
+    fn ignore_list_forgets_the_oldest_beyond_its_bound() {
+        let (_dir, mut store) = store();
+        let now = Utc::now();
+        let ignored = &mut store.data_mut().ignored;
+        for i in 0..IGNORED_MAX_ENTRIES {
+            ignored.insert(
+                format!("event-{i}"),
+                ignored_at(now - Duration::minutes(i as i64 + 1)),
+            );
+        }
+
+        store.ignore_event(EVENT_ID, "expired");
+
+        assert_eq!(store.ignored().len(), IGNORED_MAX_ENTRIES);
+        assert!(store.is_ignored(EVENT_ID));
+        let oldest = format!("event-{}", IGNORED_MAX_ENTRIES - 1);
+        assert!(!store.is_ignored(&oldest));
+        assert!(store.is_ignored("event-0"));
+    }
+
+    #[test]
+    fn gives_up_at_the_failure_limit() {
+        let (_dir, mut store) = store();
+        let backoff = FailureBackoff {
+            max_failures: Some(2),
+            ..backoff(Duration::minutes(5), Duration::hours(1))
+        };
+
+        assert!(!store.record_failure(EVENT_ID, "error", &backoff).gave_up);
+        assert!(store.record_failure(EVENT_ID, "error", &backoff).gave_up);
+    }
+}
@@ -0,0 +1,265 @@
+use std::{collections::BTreeMap, fmt::Write as _, path::Path};
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::America::Vancouver;
+use clap::ValueEnum;
+
+use crate::{
+    archive::{self, ArchiveClip, PinnedTotals, UNATTRIBUTED},
+    du::format_bytes,
+    layout::PathTemplate,
+    thinning::parse_duration_spec,
+};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum GroupBy {
+    Day,
+    Hour,
+    Device,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct StatsArgs {
+    /// How to bucket clips
+    #[arg(long, value_enum, default_value = "day")]
+    group_by: GroupBy,
+
+    /// Only include clips that started within this long ago, e.g. 30d or 12h
+    #[arg(long, value_parser = parse_duration_spec)]
+    since: Option<Duration>,
+
+    /// Only include clips from this device (name or ID)
+    #[arg(long)]
+    device: Option<String>,
+
+    /// Only include clips with this event type
+    #[arg(long)]
+    event_type: Option<String>,
+
+    /// Print CSV instead of a table
+    #[arg(long)]
+    csv: bool,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct BucketStats {
+    pub clips: u64,
+    pub recorded_secs: i64,
+    /// Clips without a sidecar, whose start time was derived from the file
+    /// path and whose duration is unknown.
+    pub low_confidence_clips: u64,
+    /// Clips pinned against pruning, and their size.
+    pub pinned: PinnedTotals,
+}
+
+/// Start time and attribution of a clip, from its sidecar when present.
+struct ClipActivity<'a> {
+    start_time: DateTime<Utc>,
+    duration_secs: i64,
+    device: &'a str,
+    low_confidence: bool,
+}
+
+impl<'a> ClipActivity<'a> {
+    fn from_clip(clip: &'a ArchiveClip, root: &Path, template: &PathTemplate) -> Self {
+        match &clip.sidecar {
+            Some(sidecar) => Self {
+                start_time: sidecar.start_time,
+                duration_secs: sidecar.duration_secs,
+                device: &sidecar.device_name,
+                low_confidence: false,
+            },
+            None => Self {
+                start_time: path_start_time(&clip.path, root, template)
+                    .unwrap_or_else(|| clip.modified.into()),
+                duration_secs: 0,
+                device: UNATTRIBUTED,
+                low_confidence: true,
+            },
+        }
+    }
+
+    /// Clips spanning a bucket boundary count towards their start bucket.
+    fn bucket(&self, group_by: GroupBy) -> String {
+        let local = self.start_time.with_timezone(&Vancouver);
+        match group_by {
+            GroupBy::Day => local.format("%Y-%m-%d").to_string(),
+            GroupBy::Hour => local.format("%Y-%m-%d %H:00").to_string(),
+            GroupBy::Device => self.device.to_string(),
+        }
+    }
+}
+
+fn path_start_time(path: &Path, root: &Path, template: &PathTemplate) -> Option<DateTime<Utc>> {
+    let relative = path.strip_prefix(root).ok()?.to_str()?;
+    template.parse(relative)?.start_time
+}
+
+fn matches_filters(clip: &ArchiveClip, args: &StatsArgs) -> bool {
+    let sidecar = clip.sidecar.as_ref();
+    let device_ok = args.device.as_deref().is_none_or(|device| {
+        sidecar.is_some_and(|s| s.device_name == device || s.device_id == device)
+    });
+    let event_type_ok = args.event_type.as_deref().is_none_or(|event_type| {
+        sidecar
+            .and_then(|s| s.event_type.as_deref())
+            .is_some_and(|types| types.split('+').any(|t| t == event_type))
+    });
+    device_ok && event_type_ok
+}
+
+/// Summarizes clip counts and recorded time per bucket from the sidecars.
+pub fn summarize(
+    output_path: &Path,
+    args: &StatsArgs,
+    template: &PathTemplate,
+) -> BTreeMap<String, BucketStats> {
+    let since = args.since.map(|since| Utc::now() - since);
+    let mut buckets: BTreeMap<String, BucketStats> = BTreeMap::new();
+
+    for clip in archive::walk_clips(output_path) {
+        if !matches_filters(&clip, args) {
+            continue;
+        }
+
+        let activity = ClipActivity::from_clip(&clip, output_path, template);
+        if since.is_some_and(|since| activity.start_time < since) {
+            continue;
+        }
+
+        let stats = buckets.entry(activity.bucket(args.group_by)).or_default();
+        stats.clips += 1;
+        stats.recorded_secs += activity.duration_secs;
+        if activity.low_confidence {
+            stats.low_confidence_clips += 1;
+        }
+        stats.pinned.add(&clip);
+    }
+
+    buckets
+}
+
+pub fn run(output_path: &Path, args: &StatsArgs, template: &PathTemplate) -> Result<()> {
+    let buckets = summarize(output_path, args, template);
+
+    if args.csv {
+        print!("{}", render_csv(&buckets));
+        return Ok(());
+    }
+
+    let width = buckets
+        .keys()
+        .map(|k| k.chars().count())
+        .chain(["Bucket".len()])
+        .max()
+        .unwrap_or(0);
+    println!(
+        "{:<width$}  {:>8}  {:>10}  {:>8}",
+        "Bucket", "Clips", "Minutes", "Pinned"
+    );
+    for (bucket, stats) in &buckets {
+        let marker = if stats.low_confidence_clips > 0 {
+            " *"
+        } else {
+            ""
+        };
+        println!(
+            "{bucket:<width$}  {:>8}  {:>10.1}  {:>8}{marker}",
+            stats.clips,
+            stats.recorded_secs as f64 / 60.0,
+            stats.pinned.count
+        );
+    }
+
+    let pinned = buckets
+        .values()
+        .fold(PinnedTotals::default(), |total, stats| PinnedTotals {
+            count: total.count + stats.pinned.count,
+            bytes: total.bytes + stats.pinned.bytes,
+        });
+    println!();
+    println!(
+        "{} pinned clips, {}",
+        pinned.count,
+        format_bytes(pinned.bytes)
+    );
+
+    if buckets.values().any(|s| s.low_confidence_clips > 0) {
+        println!("* low confidence: includes clips without a sidecar, timed from the file name");
+    }
+
+    Ok(())
+}
+
+fn render_csv(buckets: &BTreeMap<String, BucketStats>) -> String {
+    let mut out = String::from(
+        "bucket,clips,recorded_minutes,low_confidence_clips,pinned_clips,pinned_bytes\n",
+    );
+    for (bucket, stats) in buckets {
+        let _ = writeln!(
+            out,
+            "{},{},{:.1},{},{},{}",
+            csv_field(bucket),
+            stats.clips,
+            stats.recorded_secs as f64 / 60.0,
+            stats.low_confidence_clips,
+            stats.pinned.count,
+            stats.pinned.bytes
+        );
+    }
+    out
+}
+
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::layout::DEFAULT_PATH_TEMPLATE;
+
+    #[test]
+    fn counts_pinned_clips_and_bytes() {
+        let dir = TempDir::new().unwrap();
+        let day = dir.path().join("2025/06/01");
+        fs::create_dir_all(&day).unwrap();
+        fs::write(day.join("2025-06-01T10-00-00.mp4"), [0; 100]).unwrap();
+        fs::write(day.join("2025-06-01T10-00-00.keep"), b"").unwrap();
+        fs::write(day.join("2025-06-01T11-00-00.mp4"), [0; 40]).unwrap();
+        fs::write(day.join("2025-06-01T12-00-00.mp4"), [0; 7]).unwrap();
+        fs::write(
+            dir.path().join("keep.txt"),
+            "# pinned\n2025/06/01/2025-06-01T11-00-00.mp4\n",
+        )
+        .unwrap();
+        let args = StatsArgs {
+            group_by: GroupBy::Day,
+            since: None,
+            device: None,
+            event_type: None,
+            csv: false,
+        };
+
+        let buckets = summarize(dir.path(), &args, &DEFAULT_PATH_TEMPLATE.parse().unwrap());
+
+        let stats = &buckets["2025-06-01"];
+        assert_eq!(stats.clips, 3);
+        assert_eq!(
+            stats.pinned,
+            PinnedTotals {
+                count: 2,
+                bytes: 140
+            }
+        );
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+
+use crate::metrics::METRICS;
+
+/// Consecutive filesystem-level write errors after which the output
+/// filesystem counts as unavailable rather than one clip having failed.
+const FAILURE_THRESHOLD: usize = 3;
+/// Written to and deleted from the output directory to test it.
+const PROBE_FILE: &str = ".nest-sync-probe";
+/// A probe still waiting after this long, e.g. on a hard-mounted NFS export
+/// whose server is down, counts as failed.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+/// `EIO`, which a soft-mounted NFS export returns once its server stops
+/// answering.
+#[cfg(unix)]
+const EIO: i32 = 5;
+
+/// Whether `error` is about the output filesystem as a whole rather than one
+/// file: read-only, full, over quota, a stale or disconnected network mount,
+/// or an I/O error.
+pub fn is_storage_error(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|e| e.downcast_ref::<io::Error>())
+        .any(is_storage_io_error)
+}
+
+fn is_storage_io_error(error: &io::Error) -> bool {
+    #[cfg(unix)]
+    if error.raw_os_error() == Some(EIO) {
+        return true;
+    }
+    matches!(
+        error.kind(),
+        io::ErrorKind::ReadOnlyFilesystem
+            | io::ErrorKind::StorageFull
+            | io::ErrorKind::QuotaExceeded
+            | io::ErrorKind::StaleNetworkFileHandle
+            | io::ErrorKind::NotConnected
+    )
+}
+
+/// Tracks whether clips can be written. After `FAILURE_THRESHOLD` storage
+/// errors in a row, or a failed probe, downloads pause until a probe at the
+/// start of a later check succeeds.
+pub struct StorageMonitor {
+    output_path: PathBuf,
+    consecutive_failures: usize,
+    unavailable: bool,
+    /// A probe that timed out and is still blocked on the filesystem.
+    stuck_probe: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl StorageMonitor {
+    pub fn new(output_path: &Path) -> Self {
+        Self {
+            output_path: output_path.to_path_buf(),
+            consecutive_failures: 0,
+            unavailable: false,
+            stuck_probe: None,
+        }
+    }
+
+    pub fn is_unavailable(&self) -> bool {
+        self.unavailable
+    }
+
+    /// Probes the output directory, pausing downloads when it can't be
+    /// written and resuming them once it can again.
+    pub async fn check(&mut self) {
+        // Each stuck probe would hold a blocking thread; wait for the last
+        if let Some(probe) = &self.stuck_probe
+            && !probe.is_finished()
+        {
+            debug!("Previous storage probe is still waiting; not probing again");
+            return;
+        }
+        self.stuck_probe = None;
+
+        let path = self.output_path.join(PROBE_FILE);
+        let mut probe = tokio::task::spawn_blocking(move || {
+            fs::write(&path, b"nest-sync")?;
+            fs::remove_file(&path)
+        });
+        let result = match tokio::time::timeout(PROBE_TIMEOUT, &mut probe).await {
+            Ok(Ok(result)) => result.map_err(|e| format!("{e}")),
+            Ok(Err(e)) => Err(format!("probe task failed: {e}")),
+            Err(_) => {
+                self.stuck_probe = Some(probe);
+                Err(format!(
+                    "probe write timed out after {}s",
+                    PROBE_TIMEOUT.as_secs()
+                ))
+            }
+        };
+
+        match result {
+            Ok(()) if self.unavailable => {
+                self.unavailable = false;
+                self.consecutive_failures = 0;
+                METRICS.set_storage_unavailable(false);
+                info!(
+                    path = %self.output_path.display(),
+                    "Output storage is writable again; resuming downloads"
+                );
+            }
+            Ok(()) => {}
+            Err(reason) if self.unavailable => {
+                debug!(%reason, "Output storage is still unavailable");
+            }
+            Err(reason) => self.pause(&reason),
+        }
+    }
+
+    /// Counts a failed write if it was a storage error, pausing downloads
+    /// after `FAILURE_THRESHOLD` in a row.
+    pub fn record_failure(&mut self, error: &anyhow::Error) {
+        if !is_storage_error(error) {
+            return;
+        }
+        self.consecutive_failures += 1;
+        if self.consecutive_failures >= FAILURE_THRESHOLD && !self.unavailable {
+            self.pause(&format!("{error:#}"));
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+    }
+
+    fn pause(&mut self, reason: &str) {
+        self.unavailable = true;
+        METRICS.set_storage_unavailable(true);
+        error!(
+            path = %self.output_path.display(),
+            %reason,
+            "Output storage is unavailable; pausing downloads until it can be written again"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn storage_error() -> anyhow::Error {
+        anyhow::Error::new(io::Error::from(io::ErrorKind::StorageFull))
+            .context("Failed to write clip")
+    }
+
+    fn clip_error() -> anyhow::Error {
+        anyhow::anyhow!("Request returned error status")
+    }
+
+    #[test]
+    fn classifies_filesystem_wide_io_errors() {
+        for kind in [
+            io::ErrorKind::ReadOnlyFilesystem,
+            io::ErrorKind::StorageFull,
+            io::ErrorKind::QuotaExceeded,
+            io::ErrorKind::StaleNetworkFileHandle,
+            io::ErrorKind::NotConnected,
+        ] {
+            assert!(is_storage_io_error(&io::Error::from(kind)), "{kind:?}");
+        }
+        #[cfg(unix)]
+        assert!(is_storage_io_error(&io::Error::from_raw_os_error(EIO)));
+
+        for kind in [io::ErrorKind::NotFound, io::ErrorKind::PermissionDenied] {
+            assert!(!is_storage_io_error(&io::Error::from(kind)), "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn finds_storage_errors_under_context() {
+        assert!(is_storage_error(&storage_error()));
+        let not_found = Err::<(), _>(io::Error::from(io::ErrorKind::NotFound))
+            .context("Failed to write clip")
+            .unwrap_err();
+        assert!(!is_storage_error(&not_found));
+        assert!(!is_storage_error(&clip_error()));
+    }
+
+    #[test]
+    fn pauses_after_threshold_of_consecutive_storage_errors() {
+        let dir = TempDir::new().unwrap();
+        let mut storage = StorageMonitor::new(dir.path());
+
+        for _ in 1..FAILURE_THRESHOLD {
+            storage.record_failure(&storage_error());
+            // Other failures neither count nor reset the run
+            storage.record_failure(&clip_error());
+        }
+        assert!(!storage.is_unavailable());
+
+        storage.record_failure(&storage_error());
+        assert!(storage.is_unavailable());
+    }
+
+    #[test]
+    fn success_resets_the_count() {
+        let dir = TempDir::new().unwrap();
+        let mut storage = StorageMonitor::new(dir.path());
+
+        for _ in 1..FAILURE_THRESHOLD {
+            storage.record_failure(&storage_error());
+        }
+        storage.record_success();
+        storage.record_failure(&storage_error());
+
+        assert!(!storage.is_unavailable());
+    }
+
+    #[tokio::test]
+    async fn probe_pauses_and_resumes() {
+        let dir = TempDir::new().unwrap();
+        let output = dir.path().join("mount");
+        let mut storage = StorageMonitor::new(&output);
+
+        // Unmounted: the directory isn't there to write to
+        storage.check().await;
+        assert!(storage.is_unavailable());
+
+        fs::create_dir(&output).unwrap();
+        storage.check().await;
+        assert!(!storage.is_unavailable());
+        assert!(!output.join(PROBE_FILE).exists());
+    }
+
+    #[tokio::test]
+    async fn resume_clears_earlier_failures() {
+        let dir = TempDir::new().unwrap();
+        let mut storage = StorageMonitor::new(dir.path());
+        for _ in 0..FAILURE_THRESHOLD {
+            storage.record_failure(&storage_error());
+        }
+        assert!(storage.is_unavailable());
+
+        storage.check().await;
+        assert!(!storage.is_unavailable());
+
+        // A fresh run of failures is needed to pause again
+        storage.record_failure(&storage_error());
+        assert!(!storage.is_unavailable());
+    }
+
+    #[tokio::test]
+    async fn waits_for_stuck_probe_before_probing_again() {
+        let dir = TempDir::new().unwrap();
+        let mut storage = StorageMonitor::new(&dir.path().join("missing"));
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        storage.stuck_probe = Some(tokio::task::spawn_blocking(move || {
+            let _ = released.recv();
+            Ok(())
+        }));
+
+        // A new probe would fail and pause downloads
+        storage.check().await;
+        assert!(!storage.is_unavailable());
+
+        release.send(()).unwrap();
+        while !storage.stuck_probe.as_ref().unwrap().is_finished() {
+            tokio::task::yield_now().await;
+        }
+        storage.check().await;
+        assert!(storage.is_unavailable());
+        assert!(storage.stuck_probe.is_none());
+    }
+}
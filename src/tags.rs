@@ -0,0 +1,247 @@
+//! User-defined event tags from a `--tag-rules` file: a JSON list of rules,
+//! each naming a tag and the conditions an event must meet to get it, e.g.
+//!
+//! ```json
+//! [
+//!   { "tag": "night", "between": "22:00-06:00" },
+//!   { "tag": "driveway", "device": "Driveway" },
+//!   { "tag": "late-visitor", "device": "Front Door", "between": "23:30-05:00" }
+//! ]
+//! ```
+//!
+//! A rule's conditions must all hold; a rule without any tags every event.
+
+use std::{fs, path::Path, str::FromStr};
+
+use anyhow::{Context, Result, bail};
+use chrono::NaiveTime;
+use chrono_tz::America::Vancouver;
+use serde::Deserialize;
+
+use crate::models::CameraEvent;
+
+/// A local time-of-day range `HH:MM-HH:MM`, including its start and excluding
+/// its end. It runs past midnight when the end is earlier than the start.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+struct TimeRange {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimeRange {
+    fn contains(self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+impl FromStr for TimeRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expected = || format!("expected HH:MM-HH:MM, got '{s}'");
+        let (start, end) = s.split_once('-').ok_or_else(expected)?;
+        let parse =
+            |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| expected());
+        let range = Self {
+            start: parse(start)?,
+            end: parse(end)?,
+        };
+        if range.start == range.end {
+            return Err(format!("time range '{s}' is empty"));
+        }
+        Ok(range)
+    }
+}
+
+impl TryFrom<String> for TimeRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TagRule {
+    tag: String,
+    /// Device name or ID.
+    #[serde(default)]
+    device: Option<String>,
+    #[serde(default)]
+    between: Option<TimeRange>,
+}
+
+impl TagRule {
+    fn matches(&self, event: &CameraEvent, device_id: &str, device_name: &str) -> bool {
+        let device_matches = self
+            .device
+            .as_deref()
+            .is_none_or(|device| device == device_name || device == device_id);
+        let time = event.start_time.with_timezone(&Vancouver).time();
+        let time_matches = self.between.is_none_or(|range| range.contains(time));
+        device_matches && time_matches
+    }
+}
+
+/// The rules from a `--tag-rules` file, in file order.
+#[derive(Debug, Clone, Default)]
+pub struct TagRules(Vec<TagRule>);
+
+impl TagRules {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read tag rules {}", path.display()))?;
+        let rules: Vec<TagRule> = serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid tag rules in {}", path.display()))?;
+        for rule in &rules {
+            if rule.tag.trim().is_empty() {
+                bail!("Invalid tag rules in {}: a tag is empty", path.display());
+            }
+        }
+        Ok(Self(rules))
+    }
+
+    /// The tags for an event, in rule order without duplicates.
+    pub fn tags_for(&self, event: &CameraEvent, device_id: &str, device_name: &str) -> Vec<String> {
+        let mut tags: Vec<String> = Vec::new();
+        for rule in &self.0 {
+            if rule.matches(event, device_id, device_name) && !tags.contains(&rule.tag) {
+                tags.push(rule.tag.clone());
+            }
+        }
+        tags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDate, TimeZone, Utc};
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn time(s: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(s, "%H:%M").unwrap()
+    }
+
+    /// An event on the porch camera starting at local time `at` on 2025-06-01.
+    fn event(at: &str) -> CameraEvent {
+        let start = Vancouver
+            .from_local_datetime(
+                &NaiveDate::from_ymd_opt(2025, 6, 1)
+                    .unwrap()
+                    .and_time(time(at)),
+            )
+            .unwrap();
+        CameraEvent::new(
+            "device-1".to_string(),
+            start.with_timezone(&Utc),
+            Duration::seconds(10),
+        )
+    }
+
+    fn load(json: &str) -> Result<TagRules> {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("tags.json");
+        fs::write(&path, json).unwrap();
+        TagRules::load(&path)
+    }
+
+    #[test]
+    fn parses_time_ranges() {
+        for (range, expected) in [
+            ("22:00-06:00", Some(("22:00", "06:00"))),
+            ("08:30 - 17:00", Some(("08:30", "17:00"))),
+            ("00:00-23:59", Some(("00:00", "23:59"))),
+            ("22:00", None),
+            ("22:00-6", None),
+            ("25:00-06:00", None),
+            ("10:00-10:00", None),
+        ] {
+            let parsed = range.parse::<TimeRange>().ok();
+            assert_eq!(
+                parsed.map(|r| (r.start, r.end)),
+                expected.map(|(start, end)| (time(start), time(end))),
+                "{range}"
+            );
+        }
+    }
+
+    #[test]
+    fn time_ranges_include_start_exclude_end_and_wrap_midnight() {
+        for (range, at, expected) in [
+            ("08:00-17:00", "08:00", true),
+            ("08:00-17:00", "12:00", true),
+            ("08:00-17:00", "17:00", false),
+            ("08:00-17:00", "07:59", false),
+            ("22:00-06:00", "22:00", true),
+            ("22:00-06:00", "23:59", true),
+            ("22:00-06:00", "00:00", true),
+            ("22:00-06:00", "05:59", true),
+            ("22:00-06:00", "06:00", false),
+            ("22:00-06:00", "12:00", false),
+            ("22:00-06:00", "21:59", false),
+        ] {
+            let range: TimeRange = range.parse().unwrap();
+            assert_eq!(range.contains(time(at)), expected, "{at} in {range:?}");
+        }
+    }
+
+    #[test]
+    fn tags_events_by_device_and_local_time() {
+        let rules = load(
+            r#"[
+                { "tag": "night", "between": "22:00-06:00" },
+                { "tag": "porch", "device": "Porch" },
+                { "tag": "porch", "device": "device-1" },
+                { "tag": "garage", "device": "Garage" },
+                { "tag": "late-visitor", "device": "device-1", "between": "23:30-05:00" },
+                { "tag": "everything" }
+            ]"#,
+        )
+        .unwrap();
+
+        for (at, device_name, expected) in [
+            ("12:00", "Porch", &["porch", "everything"][..]),
+            ("23:00", "Porch", &["night", "porch", "everything"]),
+            (
+                "00:15",
+                "Porch",
+                &["night", "porch", "late-visitor", "everything"],
+            ),
+            ("05:30", "Porch", &["night", "porch", "everything"]),
+            // Matched by ID whatever the camera is called now
+            ("12:00", "Garage", &["porch", "garage", "everything"]),
+        ] {
+            assert_eq!(
+                rules.tags_for(&event(at), "device-1", device_name),
+                expected,
+                "{at} on {device_name}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for json in [
+            r#"[{ "tag": "night", "between": "22:00" }]"#,
+            r#"[{ "tag": " " }]"#,
+            r#"[{ "tag": "night", "hours": "22:00-06:00" }]"#,
+            r#"{ "tag": "night" }"#,
+        ] {
+            assert!(load(json).is_err(), "{json}");
+        }
+        assert!(
+            load("[]")
+                .unwrap()
+                .tags_for(&event("12:00"), "device-1", "Porch")
+                .is_empty()
+        );
+    }
+}
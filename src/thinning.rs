@@ -0,0 +1,307 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::archive::ArchiveClip;
+
+/// Past `min_age`, keep at most `max_per_bucket` clips per device in each
+/// `bucket`-sized time slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThinTier {
+    pub min_age: Duration,
+    pub bucket: Duration,
+    pub max_per_bucket: usize,
+}
+
+impl fmt::Display for ThinTier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "older than {}s: {} per {}s",
+            self.min_age.num_seconds(),
+            self.max_per_bucket,
+            self.bucket.num_seconds()
+        )
+    }
+}
+
+/// Parses `AGE:BUCKET[:MAX]`, e.g. `14d:1h` or `60d:1d:2`.
+impl FromStr for ThinTier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let (Some(age), Some(bucket)) = (parts.next(), parts.next()) else {
+            return Err(format!("expected AGE:BUCKET[:MAX], got '{s}'"));
+        };
+        let max_per_bucket = match parts.next() {
+            Some(max) => max
+                .parse()
+                .map_err(|_| format!("invalid max clips per bucket '{max}'"))?,
+            None => 1,
+        };
+        if parts.next().is_some() {
+            return Err(format!("expected AGE:BUCKET[:MAX], got '{s}'"));
+        }
+
+        let bucket = parse_duration_spec(bucket)?;
+        if bucket <= Duration::zero() {
+            return Err("bucket size must be positive".to_string());
+        }
+
+        Ok(Self {
+            min_age: parse_duration_spec(age)?,
+            bucket,
+            max_per_bucket,
+        })
+    }
+}
+
+/// Parses durations like `30m`, `12h`, `14d` or `2w`.
+pub fn parse_duration_spec(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let value: i64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{s}'"))?;
+
+    let duration = match unit {
+        "s" => Duration::try_seconds(value),
+        "m" => Duration::try_minutes(value),
+        "h" => Duration::try_hours(value),
+        "d" => Duration::try_days(value),
+        "w" => Duration::try_weeks(value),
+        _ => {
+            return Err(format!(
+                "invalid duration unit in '{s}' (use s, m, h, d or w)"
+            ));
+        }
+    };
+    duration.ok_or_else(|| format!("duration '{s}' is out of range"))
+}
+
+/// Picks clips to delete under the thinning tiers. Each clip falls under the
+/// tier with the greatest `min_age` it has reached; within each device, tier
+/// and bucket the earliest clips (ties broken by path) are kept, so repeated
+/// runs make the same choice. Unattributed clips are left alone.
+///
+/// Returns each doomed clip with the index of the tier that selected it.
+pub fn select_thinned(
+    clips: &[&ArchiveClip],
+    tiers: &[ThinTier],
+    now: SystemTime,
+) -> HashMap<PathBuf, usize> {
+    let now: DateTime<Utc> = now.into();
+    let mut buckets: BTreeMap<(&str, usize, i64), Vec<&ArchiveClip>> = BTreeMap::new();
+
+    for clip in clips {
+        let Some(device_id) = clip.device_id() else {
+            continue;
+        };
+        let modified: DateTime<Utc> = clip.modified.into();
+        let age = now - modified;
+
+        let tier = tiers
+            .iter()
+            .enumerate()
+            .filter(|(_, tier)| age >= tier.min_age)
+            .max_by_key(|(_, tier)| tier.min_age);
+        let Some((tier_index, tier)) = tier else {
+            continue;
+        };
+
+        let bucket = modified.timestamp().div_euclid(tier.bucket.num_seconds());
+        buckets
+            .entry((device_id, tier_index, bucket))
+            .or_default()
+            .push(clip);
+    }
+
+    let mut doomed = HashMap::new();
+    for ((_, tier_index, _), mut bucket_clips) in buckets {
+        bucket_clips.sort_by(|a, b| a.modified.cmp(&b.modified).then(a.path.cmp(&b.path)));
+        for clip in bucket_clips
+            .into_iter()
+            .skip(tiers[tier_index].max_per_bucket)
+        {
+            doomed.insert(clip.path.clone(), tier_index);
+        }
+    }
+
+    doomed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration as StdDuration;
+
+    use super::*;
+    use crate::{archive::VideoMetadata, models::CameraEvent};
+
+    const HOUR: u64 = 60 * 60;
+    const DAY: u64 = 24 * HOUR;
+
+    /// An instant on an hour boundary, so bucket edges are easy to reason
+    /// about.
+    fn now() -> SystemTime {
+        SystemTime::UNIX_EPOCH + StdDuration::from_secs(20_000 * DAY)
+    }
+
+    fn clip(name: &str, device_id: Option<&str>, age_secs: u64) -> ArchiveClip {
+        let modified = now() - StdDuration::from_secs(age_secs);
+        ArchiveClip {
+            path: PathBuf::from(name),
+            modified,
+            size: 1,
+            sidecar: device_id.map(|device_id| {
+                VideoMetadata::from(CameraEvent::new(
+                    device_id.to_string(),
+                    modified.into(),
+                    Duration::seconds(10),
+                ))
+            }),
+            pinned: false,
+        }
+    }
+
+    fn tiers(specs: &[&str]) -> Vec<ThinTier> {
+        specs.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    fn thinned(clips: &[ArchiveClip], tiers: &[ThinTier]) -> BTreeMap<String, usize> {
+        let refs: Vec<&ArchiveClip> = clips.iter().collect();
+        select_thinned(&refs, tiers, now())
+            .into_iter()
+            .map(|(path, tier)| (path.display().to_string(), tier))
+            .collect()
+    }
+
+    #[test]
+    fn parses_tiers() {
+        assert_eq!(
+            "14d:1h".parse::<ThinTier>().unwrap(),
+            ThinTier {
+                min_age: Duration::days(14),
+                bucket: Duration::hours(1),
+                max_per_bucket: 1,
+            }
+        );
+        assert_eq!("60d:1d:2".parse::<ThinTier>().unwrap().max_per_bucket, 2);
+        assert!("14d".parse::<ThinTier>().is_err());
+        assert!("14d:0h".parse::<ThinTier>().is_err());
+        assert!("14d:1h:2:3".parse::<ThinTier>().is_err());
+        assert!("14x:1h".parse::<ThinTier>().is_err());
+    }
+
+    #[test]
+    fn parses_duration_units() {
+        assert_eq!(parse_duration_spec("30s"), Ok(Duration::seconds(30)));
+        assert_eq!(parse_duration_spec("30m"), Ok(Duration::minutes(30)));
+        assert_eq!(parse_duration_spec("12h"), Ok(Duration::hours(12)));
+        assert_eq!(parse_duration_spec("14d"), Ok(Duration::days(14)));
+        assert_eq!(parse_duration_spec("2w"), Ok(Duration::weeks(2)));
+        assert!(parse_duration_spec("h").is_err());
+        assert!(parse_duration_spec("5y").is_err());
+        assert_eq!(
+            parse_duration_spec("9223372036854775807w"),
+            Err("duration '9223372036854775807w' is out of range".to_string())
+        );
+    }
+
+    #[test]
+    fn keeps_clips_younger_than_every_tier() {
+        let clips = [
+            clip("a.mp4", Some("cam"), HOUR),
+            clip("b.mp4", Some("cam"), HOUR + 60),
+        ];
+        assert!(thinned(&clips, &tiers(&["14d:1h"])).is_empty());
+    }
+
+    #[test]
+    fn keeps_earliest_clip_per_bucket() {
+        let age = 20 * DAY;
+        let clips = [
+            clip("first.mp4", Some("cam"), age + 50 * 60),
+            clip("second.mp4", Some("cam"), age + 30 * 60),
+            clip("third.mp4", Some("cam"), age + 10 * 60),
+            clip("next-hour.mp4", Some("cam"), age - 10 * 60),
+        ];
+        assert_eq!(
+            thinned(&clips, &tiers(&["14d:1h"])),
+            BTreeMap::from([("second.mp4".to_string(), 0), ("third.mp4".to_string(), 0)])
+        );
+    }
+
+    #[test]
+    fn keeps_up_to_max_per_bucket() {
+        let age = 20 * DAY;
+        let clips = [
+            clip("first.mp4", Some("cam"), age + 50 * 60),
+            clip("second.mp4", Some("cam"), age + 30 * 60),
+            clip("third.mp4", Some("cam"), age + 10 * 60),
+        ];
+        assert_eq!(
+            thinned(&clips, &tiers(&["14d:1h:2"])),
+            BTreeMap::from([("third.mp4".to_string(), 0)])
+        );
+    }
+
+    #[test]
+    fn oldest_reached_tier_applies() {
+        // Same day, different hours: hourly thinning keeps both, daily one
+        let base = 100 * DAY + 12 * HOUR;
+        let clips = [
+            clip("morning.mp4", Some("cam"), base + 3 * HOUR),
+            clip("noon.mp4", Some("cam"), base),
+        ];
+        let tiers = tiers(&["60d:1d", "14d:1h"]);
+        assert_eq!(
+            thinned(&clips, &tiers),
+            BTreeMap::from([("noon.mp4".to_string(), 0)])
+        );
+
+        let recent = [
+            clip("morning.mp4", Some("cam"), 20 * DAY + 3 * HOUR),
+            clip("noon.mp4", Some("cam"), 20 * DAY),
+        ];
+        assert!(thinned(&recent, &tiers).is_empty());
+    }
+
+    #[test]
+    fn buckets_are_per_device() {
+        let age = 20 * DAY + 30 * 60;
+        let clips = [
+            clip("porch.mp4", Some("porch"), age),
+            clip("garage.mp4", Some("garage"), age),
+        ];
+        assert!(thinned(&clips, &tiers(&["14d:1h"])).is_empty());
+    }
+
+    #[test]
+    fn leaves_unattributed_clips_alone() {
+        let age = 20 * DAY + 30 * 60;
+        let clips = [clip("a.mp4", None, age), clip("b.mp4", None, age + 60)];
+        assert!(thinned(&clips, &tiers(&["14d:1h"])).is_empty());
+    }
+
+    #[test]
+    fn selection_is_independent_of_input_order() {
+        let age = 20 * DAY + 30 * 60;
+        let mut clips = vec![
+            clip("c.mp4", Some("cam"), age),
+            clip("a.mp4", Some("cam"), age),
+            clip("b.mp4", Some("cam"), age + 60),
+        ];
+        let tiers = tiers(&["14d:1h"]);
+        let expected = BTreeMap::from([("a.mp4".to_string(), 0), ("c.mp4".to_string(), 0)]);
+        assert_eq!(thinned(&clips, &tiers), expected);
+        clips.reverse();
+        assert_eq!(thinned(&clips, &tiers), expected);
+    }
+}
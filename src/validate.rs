@@ -0,0 +1,309 @@
+use std::{fmt, path::Path};
+
+use anyhow::{Result, bail};
+use chrono::Duration;
+
+use crate::{
+    Args, google_auth, heartbeat::HeartbeatMethod, layout::DeviceCollisionPolicy,
+    models::MAX_EVENT_DURATION_SECS, mqtt, tags::TagRules,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{label}: {}", self.message)
+    }
+}
+
+#[derive(Default)]
+struct Findings(Vec<Finding>);
+
+impl Findings {
+    fn error(&mut self, message: impl Into<String>) {
+        self.0.push(Finding {
+            severity: Severity::Error,
+            message: message.into(),
+        });
+    }
+
+    fn warning(&mut self, message: impl Into<String>) {
+        self.0.push(Finding {
+            severity: Severity::Warning,
+            message: message.into(),
+        });
+    }
+}
+
+/// Checks the flags and environment for mistakes clap can't catch on its own.
+/// Purely local: nothing here touches the network.
+pub fn check(args: &Args, output_path: &Path) -> Vec<Finding> {
+    let mut findings = Findings::default();
+
+    for var in ["GOOGLE_MASTER_TOKEN", "GOOGLE_USERNAME"] {
+        match std::env::var(var) {
+            Ok(value) if !value.trim().is_empty() => {}
+            Ok(_) => findings.error(format!("{var} is empty")),
+            Err(_) => findings.error(format!("{var} is not set")),
+        }
+    }
+
+    if output_path.exists() && !output_path.is_dir() {
+        findings.error(format!(
+            "output path {} is not a directory",
+            output_path.display()
+        ));
+    } else if !output_path.exists() {
+        findings.warning(format!(
+            "output directory {} doesn't exist yet and will be created",
+            output_path.display()
+        ));
+    }
+
+    if !args.path_template.resolves_seconds() {
+        findings.warning(format!(
+            "path template '{}' doesn't include every field down to {{second}}; clips that map to \
+             the same path are skipped as already downloaded",
+            args.path_template
+        ));
+    }
+
+    if args.device_collisions == DeviceCollisionPolicy::Refuse
+        && !args.path_template.separates_devices()
+    {
+        findings.error(format!(
+            "path template '{}' can give clips from different cameras the same path; add \
+             {{device_id}} or {{device_name}}, or use --device-collisions suffix",
+            args.path_template
+        ));
+    }
+
+    if args.concurrency == 0 {
+        findings.error("--concurrency must be at least 1");
+    }
+    if args.discovery_concurrency == 0 {
+        findings.error("--discovery-concurrency must be at least 1");
+    }
+    if args.check_interval == 0 {
+        findings.error("--check-interval must be at least 1 minute");
+    }
+    if args.check_interval > 0 && args.check_jitter_secs >= args.check_interval * 60 {
+        findings.error("--check-jitter-secs must be shorter than --check-interval");
+    }
+    if args.prune_interval == 0 {
+        findings.error("--prune-interval must be at least 1 minute");
+    }
+    if args.state_flush_interval_secs == 0 {
+        findings.error("--state-flush-interval-secs must be at least 1");
+    }
+    if args.retry_backoff_minutes <= 0 {
+        findings.error("--retry-backoff-minutes must be positive");
+    }
+    if args.max_download_failures == Some(0) {
+        findings.error("--max-download-failures must be at least 1");
+    }
+    if args.max_events_per_cycle == Some(0) {
+        findings.error("--max-events-per-cycle must be at least 1");
+    }
+    if args.max_downloads_per_day == Some(0) {
+        findings.error("--max-downloads-per-day must be at least 1");
+    }
+    for (flag, padding) in [
+        ("--clip-padding-before", args.clip_padding_before),
+        ("--clip-padding-after", args.clip_padding_after),
+    ] {
+        if padding > MAX_EVENT_DURATION_SECS as u64 {
+            findings.error(format!(
+                "{flag} must be at most {MAX_EVENT_DURATION_SECS} seconds, the longest clip"
+            ));
+        }
+    }
+    if args.catch_up_budget.is_some_and(|budget| budget < 2) {
+        findings.error("--catch-up-budget must be at least 2, one event list and one download");
+    }
+
+    check_retention(args, &mut findings);
+
+    if args.incremental_by_filename && !args.path_template.separates_devices() {
+        findings.error(
+            "--incremental-by-filename needs a --path-template with {device_id} or {device_name}",
+        );
+    }
+
+    if args.continuous && args.event_types.is_some() {
+        findings.warning("--event-types is ignored with --continuous");
+    }
+
+    if let Some(url) = &args.mqtt_url
+        && let Err(e) = mqtt::validate_url(url)
+    {
+        findings.error(format!("--mqtt-url: {e:#}"));
+    }
+    if args.mqtt_topic.contains(['#', '+']) {
+        findings.error("--mqtt-topic can't contain the wildcards '#' or '+'");
+    }
+    if args.heartbeat_summary && args.heartbeat_method == HeartbeatMethod::Get {
+        findings.warning("--heartbeat-summary is only sent with --heartbeat-method post");
+    }
+
+    if let Some(ca_cert) = &args.ca_cert {
+        match std::fs::read(ca_cert) {
+            Ok(pem) => {
+                if let Err(e) = google_auth::check_ca_cert(&pem) {
+                    findings.error(format!("--ca-cert {}: {e:#}", ca_cert.display()));
+                }
+            }
+            Err(e) => findings.error(format!("--ca-cert {}: {e}", ca_cert.display())),
+        }
+    }
+
+    if let Some(android_id) = &args.android_id
+        && let Err(e) = google_auth::check_android_id(android_id)
+    {
+        findings.error(format!("--android-id: {e:#}"));
+    }
+
+    if let Some(tag_rules) = &args.tag_rules
+        && let Err(e) = TagRules::load(tag_rules)
+    {
+        findings.error(format!("--tag-rules: {e:#}"));
+    }
+
+    findings.0
+}
+
+fn check_retention(args: &Args, findings: &mut Findings) {
+    let unit = if args.retention_hours {
+        "hours"
+    } else {
+        "days"
+    };
+    let period_to_duration = |period: u64| {
+        let period = i64::try_from(period).unwrap_or(i64::MAX);
+        if args.retention_hours {
+            Duration::try_hours(period)
+        } else {
+            Duration::try_days(period)
+        }
+    };
+
+    if args.retention_hours {
+        findings.warning("--retention-hours is meant for testing; retention is counted in hours");
+    }
+    if args.retention_days > 0 && period_to_duration(args.retention_days).is_none() {
+        findings.error(format!(
+            "--retention-days {} {unit} is out of range",
+            args.retention_days
+        ));
+    }
+
+    if args.retention_days == 0 && !args.thin.is_empty() {
+        findings
+            .warning("--thin tiers apply even though retention is disabled (--retention-days 0)");
+    }
+    let retention = (args.retention_days > 0)
+        .then(|| period_to_duration(args.retention_days))
+        .flatten();
+    for (i, tier) in args.thin.iter().enumerate() {
+        if let Some(retention) = retention
+            && tier.min_age >= retention
+        {
+            findings.warning(format!(
+                "--thin tier '{tier}' never applies: clips are pruned after {} {unit}",
+                args.retention_days
+            ));
+        }
+        if tier.max_per_bucket == 0 {
+            findings.warning(format!(
+                "--thin tier '{tier}' keeps no clips; it acts like a shorter retention period"
+            ));
+        }
+        if let Some(other) = args.thin[..i]
+            .iter()
+            .find(|other| other.min_age == tier.min_age)
+        {
+            findings.warning(format!(
+                "--thin tiers '{other}' and '{tier}' start at the same age"
+            ));
+        }
+    }
+
+    if let Some(max_disk_gb) = args.max_disk_gb
+        && max_disk_gb <= 0.0
+    {
+        findings.error("--max-disk-gb must be positive");
+    }
+    if let Some(floor_gb) = args.emergency_free_gb {
+        if floor_gb <= 0.0 {
+            findings.error("--emergency-free-gb must be positive");
+        }
+        if let Some(recovery_gb) = args.emergency_recovery_gb
+            && recovery_gb <= floor_gb
+        {
+            findings.error("--emergency-recovery-gb must be more than --emergency-free-gb");
+        }
+        if args.once {
+            findings.warning("--emergency-free-gb is ignored with --once");
+        }
+    }
+    if let Some(max_clips) = args.max_clips_per_device
+        && max_clips < args.keep_min_per_device
+    {
+        findings.warning(format!(
+            "--max-clips-per-device {max_clips} is below --keep-min-per-device {}, which wins",
+            args.keep_min_per_device
+        ));
+    }
+
+    for (i, tier) in args.retention_free_tier.iter().enumerate() {
+        if args.retention_days > 0 && tier.retention_period >= args.retention_days {
+            findings.warning(format!(
+                "--retention-free-tier '{tier}' never shortens the {} {unit} retention period",
+                args.retention_days
+            ));
+        }
+        if args.retention_free_tier[..i]
+            .iter()
+            .any(|other| other.free_percent == tier.free_percent)
+        {
+            findings.warning(format!(
+                "--retention-free-tier thresholds of {}% are repeated; the shortest period wins",
+                tier.free_percent
+            ));
+        }
+    }
+}
+
+/// Prints every finding and fails when any of them is an error.
+pub fn run(args: &Args, output_path: &Path) -> Result<()> {
+    let findings = check(args, output_path);
+    for finding in &findings {
+        println!("{finding}");
+    }
+
+    let error_count = findings
+        .iter()
+        .filter(|f| f.severity == Severity::Error)
+        .count();
+    let warning_count = findings.len() - error_count;
+    println!("{error_count} errors, {warning_count} warnings");
+
+    if error_count > 0 {
+        bail!("Configuration has {error_count} errors");
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use std::path::Path;
+
+use anyhow::{Result, bail};
+
+use crate::{
+    archive::{self, PinnedTotals},
+    du::format_bytes,
+    xattrs,
+};
+
+#[derive(Debug, clap::Args)]
+pub struct VerifyArgs {
+    /// Also compare each clip's extended attributes with its sidecar
+    #[arg(long)]
+    xattrs: bool,
+}
+
+/// Compares clips on disk with what their sidecars recorded and prints every
+/// mismatch, then how many clips are pinned. Fails when any clip has drifted.
+pub fn run(output_path: &Path, args: &VerifyArgs) -> Result<()> {
+    let mut checked_count = 0;
+    let mut drifted_count = 0;
+    let mut pinned = PinnedTotals::default();
+
+    for clip in archive::walk_clips(output_path) {
+        pinned.add(&clip);
+        let Some(metadata) = &clip.sidecar else {
+            continue;
+        };
+        checked_count += 1;
+
+        let mut drift = Vec::new();
+        if let Some(expected_size) = metadata.file_size_bytes
+            && expected_size != clip.size
+        {
+            drift.push(format!(
+                "size: expected {expected_size} bytes, found {}",
+                clip.size
+            ));
+        }
+
+        if args.xattrs {
+            for (name, expected) in xattrs::expected(metadata) {
+                match xattrs::read(&clip.path, name) {
+                    Ok(Some(actual)) if actual == expected => {}
+                    Ok(Some(actual)) => drift.push(format!(
+                        "xattr {name}: expected '{expected}', found '{actual}'"
+                    )),
+                    Ok(None) => drift.push(format!("xattr {name}: missing")),
+                    Err(e) => {
+                        drift.push(format!("xattrs unreadable: {e}"));
+                        break;
+                    }
+                }
+            }
+        }
+
+        if !drift.is_empty() {
+            drifted_count += 1;
+            for problem in drift {
+                println!("{}: {problem}", clip.path.display());
+            }
+        }
+    }
+
+    println!("{checked_count} clips checked, {drifted_count} drifted");
+    println!(
+        "{} pinned clips, {}",
+        pinned.count,
+        format_bytes(pinned.bytes)
+    );
+    if drifted_count > 0 {
+        bail!("{drifted_count} clips don't match their sidecars");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use serde::Serialize;
+
+/// Build metadata for the running binary, captured by `build.rs`.
+#[derive(Debug, Clone, Serialize)]
+pub struct NestSyncVersion {
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    pub build_date: &'static str,
+    pub rust_version: &'static str,
+}
+
+pub const NEST_SYNC_VERSION: NestSyncVersion = NestSyncVersion {
+    version: env!("CARGO_PKG_VERSION"),
+    git_commit: env!("NEST_SYNC_GIT_COMMIT"),
+    build_date: env!("NEST_SYNC_BUILD_DATE"),
+    rust_version: env!("NEST_SYNC_RUST_VERSION"),
+};
+
+/// Text shown by `--version`.
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    "\ncommit: ",
+    env!("NEST_SYNC_GIT_COMMIT"),
+    "\nbuilt: ",
+    env!("NEST_SYNC_BUILD_DATE"),
+    "\nrustc: ",
+    env!("NEST_SYNC_RUST_VERSION"),
+);
@@ -0,0 +1,76 @@
+use std::{
+    io,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use tracing::warn;
+
+use crate::archive::VideoMetadata;
+
+const PREFIX: &str = "user.nest.";
+
+/// Set after the first failure so unsupported filesystems warn once per run.
+static WRITE_FAILURE_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// The attributes written for a clip, without the `user.nest.` prefix.
+pub fn expected(metadata: &VideoMetadata) -> Vec<(&'static str, String)> {
+    vec![
+        ("device_name", metadata.device_name.clone()),
+        ("event_start", metadata.start_time.to_rfc3339()),
+        ("duration_secs", metadata.duration_secs.to_string()),
+        ("event_id", metadata.event_id.clone()),
+    ]
+}
+
+pub fn write(path: &Path, metadata: &VideoMetadata) -> io::Result<()> {
+    for (name, value) in expected(metadata) {
+        set(path, &format!("{PREFIX}{name}"), value.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Writes the attributes, warning on the first failure only.
+pub fn write_or_warn(path: &Path, metadata: &VideoMetadata) {
+    if let Err(e) = write(path, metadata)
+        && !WRITE_FAILURE_WARNED.swap(true, Ordering::Relaxed)
+    {
+        warn!(
+            path = %path.display(),
+            error = %e,
+            "Failed to write extended attributes; further failures this run won't be logged"
+        );
+    }
+}
+
+/// Reads one attribute, without the `user.nest.` prefix.
+pub fn read(path: &Path, name: &str) -> io::Result<Option<String>> {
+    get(path, &format!("{PREFIX}{name}"))
+        .map(|value| value.map(|v| String::from_utf8_lossy(&v).into_owned()))
+}
+
+#[cfg(unix)]
+fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    xattr::set(path, name, value)
+}
+
+#[cfg(unix)]
+fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+    xattr::get(path, name)
+}
+
+#[cfg(not(unix))]
+fn set(_path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "extended attributes are not supported on this platform",
+    ))
+}
+
+#[cfg(not(unix))]
+fn get(_path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "extended attributes are not supported on this platform",
+    ))
+}